@@ -0,0 +1,46 @@
+extern crate glfw;
+extern crate gust;
+
+use gust::prelude::*;
+use std::error::Error;
+use std::rc::Rc;
+
+fn main() -> Result<(), Box<Error>> {
+    let mut window = Window::new(gust::WIDTH, gust::HEIGHT, "Hello");
+    let tex_leave = Rc::new(Texture::from_path("examples/texture/Z.png").unwrap());
+    let mut leave = Sprite::from(&tex_leave);
+    let mut minimap = RenderTexture::new(400, 400)?;
+    let mut map = Sprite::from(minimap.texture());
+    let event_handler = EventHandler::new(&window);
+
+    leave.set_position(Point::new(200.0, 200.0));
+    leave.set_scale(Vector::new(0.5, 0.5));
+    leave.set_origin_to_center()?;
+    map.set_position(Point::new(gust::WIDTH as f32 - 400.0, 0.0));
+    minimap.set_clear_color(Color::new(0.2, 0.2, 0.2));
+    window.set_clear_color(Color::new(0.45, 0.0, 1.0));
+    window.poll(None);
+
+    while window.is_open() {
+        window.poll_events();
+        leave.rotate(1.0);
+        leave.update();
+        map.update();
+
+        for event in event_handler.fetch() {
+            if let Events::Key(Key::Escape, _, _, _) = event.1 {
+                window.close();
+            }
+        }
+
+        minimap.clear();
+        minimap.draw(&leave);
+        minimap.display();
+
+        window.clear();
+        window.draw(&leave);
+        window.draw(&map);
+        window.display();
+    }
+    Ok(())
+}
@@ -0,0 +1,224 @@
+//
+//  Rust file | 2018
+//  Author: Alexandre Fourcat
+//  batch.rs
+//  module:
+//! Frame batching system.
+//! Drawables sharing the same texture, shader, blend mode and projection are merged
+//! inside one dynamic buffer and drawn with a single draw call.
+//! The batch is flushed when the state change, before a drawable that can't be batched
+//! and when the window is displayed.
+//! ```no_run
+//! use gust::prelude::*;
+//! use std::rc::Rc;
+//!
+//! let mut window = Window::new(800, 600, "Batch");
+//! let texture = Rc::new(Texture::from_path("texture.png").unwrap());
+//! let sprites: Vec<Sprite> = (0..500).map(|_| Sprite::from(&texture)).collect();
+//!
+//! window.clear();
+//! for sprite in &sprites {
+//!     window.draw(sprite);
+//! }
+//! // One draw call for the 500 sprites
+//! window.display();
+//! ```
+
+use draw::{BlendMode, Context, Drawable, IDENTITY};
+use nalgebra::{Matrix4, Vector4};
+use resources::Resource;
+use shader::Shader;
+use texture::Texture;
+use vertex::{Vertex, VertexArray};
+use vertex_buffer::{BufferUsage, Primitive, VertexBuffer};
+use Vector;
+
+/// State shared by every quad of a batch.
+struct State {
+    texture: Resource<Texture>,
+    shader: Resource<Shader>,
+    blend_mode: BlendMode,
+    projection: Matrix4<f32>,
+}
+
+impl State {
+    fn is_same(
+        &self,
+        texture: &Resource<Texture>,
+        shader: &Resource<Shader>,
+        blend_mode: BlendMode,
+        projection: &Matrix4<f32>,
+    ) -> bool {
+        Resource::ptr_eq(&self.texture, texture)
+            && Resource::ptr_eq(&self.shader, shader)
+            && self.blend_mode == blend_mode
+            && self.projection == *projection
+    }
+}
+
+/// Dynamic buffer receiving the quads of the drawables until a flush.
+pub struct Batch {
+    buffer: VertexBuffer,
+    state: Option<State>,
+    draw_calls: usize,
+}
+
+impl Batch {
+    pub fn new() -> Batch {
+        Batch {
+            // Rewritten at each flush
+            buffer: VertexBuffer::with_usage(
+                Primitive::Triangles,
+                VertexArray::new(),
+                BufferUsage::Stream,
+            ),
+            state: None,
+            draw_calls: 0,
+        }
+    }
+
+    /// Push a quad given as a triangle strip of 4 vertices transformed by model.
+    /// The batch is flushed first if the state is different.
+    pub fn push_quad(
+        &mut self,
+        quad: &[Vertex],
+        model: &Matrix4<f32>,
+        texture: &Resource<Texture>,
+        shader: Resource<Shader>,
+        blend_mode: BlendMode,
+        projection: &Matrix4<f32>,
+    ) {
+        let same = match self.state {
+            Some(ref state) => state.is_same(texture, &shader, blend_mode, projection),
+            None => false,
+        };
+
+        if !same {
+            self.flush();
+            self.state = Some(State {
+                texture: Resource::clone(texture),
+                shader,
+                blend_mode,
+                projection: *projection,
+            });
+        }
+
+        let transform = |vertex: &Vertex| {
+            let pos = model * Vector4::new(vertex.pos.x, vertex.pos.y, 0.0, 1.0);
+            Vertex::new(Vector::new(pos.x, pos.y), vertex.tex, vertex.color)
+        };
+
+        // The strip 0 1 2 3 become the triangles 0 1 2 and 2 1 3
+        let (a, b, c, d) = (
+            transform(&quad[0]),
+            transform(&quad[1]),
+            transform(&quad[2]),
+            transform(&quad[3]),
+        );
+        self.buffer.append(&[a, b, c, c, b, d]);
+    }
+
+    /// Draw everything pushed since the last flush.
+    pub fn flush(&mut self) {
+        if let Some(state) = self.state.take() {
+            if self.buffer.array().len() == 0 {
+                return;
+            }
+
+            let mut context = Context::new(
+                Some(state.texture.as_ref()),
+                &state.shader,
+                vec![
+                    ("transform".to_string(), &*IDENTITY),
+                    ("projection".to_string(), &state.projection),
+                ],
+                state.blend_mode,
+            );
+
+            self.buffer.update();
+            self.buffer.draw_with_context(&mut context);
+            self.buffer.clear();
+            self.draw_calls += 1;
+        }
+    }
+
+    /// Number of quads waiting for the next flush.
+    pub fn pending(&self) -> usize {
+        self.buffer.array().len() / 6
+    }
+
+    /// Number of draw calls made by the batch since its creation.
+    pub fn draw_calls(&self) -> usize {
+        self.draw_calls
+    }
+}
+
+impl Default for Batch {
+    fn default() -> Batch {
+        Batch::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Batch;
+    use color::Color;
+    use device::{self, NullDevice};
+    use draw::{BlendMode, Drawable};
+    use nalgebra::Matrix4;
+    use shader::Shader;
+    use sprite::Sprite;
+    use std::rc::Rc;
+    use texture::Texture;
+    use Vector;
+
+    #[test]
+    fn sprites_sharing_the_state_are_one_draw_call() {
+        let null = Rc::new(NullDevice::new());
+        let _device = device::scoped(null.clone());
+        let texture = Rc::new(Texture::from_color(Color::white(), Vector::new(4, 4)));
+        let projection = Matrix4::identity();
+        let mut batch = Batch::new();
+
+        for _ in 0..100 {
+            assert!(Sprite::from(&texture).batch(&mut batch, &projection));
+        }
+        assert_eq!((batch.pending(), null.draw_calls()), (100, 0));
+
+        // What Window::display does before swapping the buffers
+        batch.flush();
+        assert_eq!((batch.pending(), null.draw_calls()), (0, 1));
+        batch.flush();
+        assert_eq!((null.draw_calls(), batch.draw_calls()), (1, 1));
+    }
+
+    #[test]
+    fn state_change_flushes_the_batch() {
+        let null = Rc::new(NullDevice::new());
+        let _device = device::scoped(null.clone());
+        let texture = Rc::new(Texture::from_color(Color::white(), Vector::new(4, 4)));
+        let other = Rc::new(Texture::from_color(Color::red(), Vector::new(4, 4)));
+        let projection = Matrix4::identity();
+        let mut batch = Batch::new();
+        let mut added = Sprite::from(&other);
+        let mut custom = Sprite::from(&other);
+
+        added.set_blend_mode(BlendMode::Add);
+        custom.set_shader(Rc::new(Shader::default()));
+        Sprite::from(&texture).batch(&mut batch, &projection);
+        Sprite::from(&texture).batch(&mut batch, &projection);
+        assert_eq!(null.draw_calls(), 0);
+
+        // Texture, blend mode then projection change
+        Sprite::from(&other).batch(&mut batch, &projection);
+        assert_eq!(null.draw_calls(), 1);
+        added.batch(&mut batch, &projection);
+        assert_eq!(null.draw_calls(), 2);
+        added.batch(&mut batch, &Matrix4::new_scaling(2.0));
+        assert_eq!((null.draw_calls(), batch.pending()), (3, 1));
+
+        // A sprite with its own shader is drawn by the target after a flush
+        assert!(!custom.batch(&mut batch, &projection));
+        assert_eq!(batch.pending(), 1);
+    }
+}
@@ -0,0 +1,1347 @@
+//
+//  Rust file | 2018
+//  Author: Alexandre Fourcat
+//  command.rs
+//  module:
+//! Command recording system.
+//! While recording, the draws are not executed but stored inside a CommandList with
+//! everything needed to run them again: vertex range, textures, shader, blend mode and uniforms.
+//! Gpu objects are still created and updated normally so a list can be replayed later,
+//! and the bindings are still made so the reads see the targets drawn on (see Recorder).
+//! ```no_run
+//! use gust::prelude::*;
+//! use std::rc::Rc;
+//!
+//! let mut window = Window::new(800, 600, "Record");
+//! let texture = Rc::new(Texture::from_path("texture.png").unwrap());
+//! let sprite = Sprite::from(&texture);
+//!
+//! let frame = window.record(|window| window.draw(&sprite));
+//! assert_eq!(frame.draw_calls(), 1);
+//! println!("{}", frame);
+//!
+//! frame.replay();
+//! window.display();
+//! ```
+//! A list can be saved with CommandList::write and loaded back with CommandList::parse.
+
+use color::Color;
+use device;
+use device::{ActiveVariable, DepthTest, RenderDevice, ShaderStage, StencilTest};
+use draw::{BlendEquation, BlendFactor, BlendFunc, BlendMode};
+use state_cache::StateStats;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::ffi::CStr;
+use std::fmt;
+use std::io;
+use std::rc::Rc;
+use std::str::{FromStr, Split};
+use texture::{Filter, RgbMode, Wrap};
+use vertex::{Instance, Vertex};
+use vertex_buffer::{BufferUsage, Primitive};
+use Vector;
+
+/// Record every draw made inside the closure on the current thread.
+pub fn record<F: FnOnce()>(draw: F) -> CommandList {
+    let recorder = Rc::new(Recorder::new(device::current()));
+    {
+        // The previous device is given back even if the closure panic
+        let _guard = device::scoped(recorder.clone());
+        draw();
+    }
+    recorder.finish()
+}
+
+//----------------------------------------------------------------------------
+//
+//
+//                             COMMAND : ENUM
+//
+//
+//----------------------------------------------------------------------------
+
+/// Value given to an uniform.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Uniform {
+    Floats(Vec<f32>),
+    Ints(Vec<i32>),
+    Matrix(Vec<f32>),
+}
+
+/// Everything needed by a draw call.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DrawCommand {
+    /// Framebuffer drawn on, 0 being the window.
+    pub framebuffer: u32,
+    pub vertex_array: u32,
+    pub primitive: Primitive,
+    /// First vertex drawn.
+    pub first: usize,
+    /// Number of vertices drawn.
+    pub count: usize,
+    /// Number of instances, None for a draw without instancing.
+    pub instances: Option<usize>,
+    /// Textures bound as (unit, texture), ordered by unit.
+    pub textures: Vec<(u32, u32)>,
+    pub program: u32,
+    /// None if blending is disabled.
+    pub blend: Option<BlendMode>,
+    pub scissor: Option<[i32; 4]>,
+    pub stencil: Option<StencilTest>,
+    pub depth: Option<DepthTest>,
+    pub viewport: [i32; 4],
+    /// Clockwise faces are the front ones, see RenderDevice::front_face.
+    pub clockwise: bool,
+    /// Uniforms of the program at the time of the draw.
+    /// The ones set through a location without name are named `@location`.
+    pub uniforms: Vec<(String, Uniform)>,
+}
+
+impl DrawCommand {
+    /// Texture bound on the unit 0.
+    pub fn texture(&self) -> Option<u32> {
+        self.textures
+            .iter()
+            .find(|texture| texture.0 == 0)
+            .map(|texture| texture.1)
+    }
+}
+
+/// A recorded command.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Clear { framebuffer: u32, color: Color },
+    ClearStencil { framebuffer: u32, bits: u8 },
+    Draw(DrawCommand),
+}
+
+impl Command {
+    /// Execute the command on the current device.
+    pub fn execute(&self) {
+        let device = device::current();
+
+        match self {
+            Command::Clear { framebuffer, color } => {
+                device.bind_framebuffer(*framebuffer);
+                device.clear(*color);
+            }
+            Command::ClearStencil { framebuffer, bits } => {
+                device.bind_framebuffer(*framebuffer);
+                device.clear_stencil(*bits);
+            }
+            Command::Draw(draw) => {
+                device.bind_framebuffer(draw.framebuffer);
+                device.set_viewport(draw.viewport);
+                device.front_face(draw.clockwise);
+                device.scissor(draw.scissor);
+                device.stencil(draw.stencil);
+                device.depth(draw.depth);
+                device.bind_texture(0, draw.texture().unwrap_or(0));
+                for &(unit, texture) in &draw.textures {
+                    device.bind_texture(unit, texture);
+                }
+                match draw.blend {
+                    Some(ref mode) => device.blend(mode),
+                    None => device.disable_blend(),
+                }
+                device.use_program(draw.program);
+                for (name, value) in &draw.uniforms {
+                    let location = match name.strip_prefix('@') {
+                        Some(number) => number.parse().unwrap_or(-1),
+                        None => device.uniform_location(draw.program, name),
+                    };
+                    match value {
+                        Uniform::Floats(values) => device.uniform_floats(location, values),
+                        Uniform::Ints(values) => device.uniform_ints(location, values),
+                        Uniform::Matrix(values) => device.uniform_matrix(location, values),
+                    }
+                }
+                match draw.instances {
+                    Some(instances) => device.draw_arrays_instanced(
+                        draw.vertex_array,
+                        draw.primitive,
+                        draw.first,
+                        draw.count,
+                        instances,
+                    ),
+                    None => device.draw_arrays(
+                        draw.vertex_array,
+                        draw.primitive,
+                        draw.first,
+                        draw.count,
+                    ),
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Command::Clear { framebuffer, color } => write!(
+                f,
+                "clear fb={} color=({}, {}, {}, {})",
+                framebuffer, color.0, color.1, color.2, color.3
+            ),
+            Command::ClearStencil { framebuffer, bits } => {
+                write!(f, "clear_stencil fb={} bits={:#010b}", framebuffer, bits)
+            }
+            Command::Draw(draw) => {
+                write!(
+                    f,
+                    "draw fb={} vao={} {:?} [{}..{}] textures={:?} program={} blend={:?} \
+                     viewport={:?}",
+                    draw.framebuffer,
+                    draw.vertex_array,
+                    draw.primitive,
+                    draw.first,
+                    draw.first + draw.count,
+                    draw.textures,
+                    draw.program,
+                    draw.blend,
+                    draw.viewport,
+                )?;
+                if draw.clockwise {
+                    write!(f, " clockwise")?;
+                }
+                if let Some(instances) = draw.instances {
+                    write!(f, " instances={}", instances)?;
+                }
+                if let Some(scissor) = draw.scissor {
+                    write!(f, " scissor={:?}", scissor)?;
+                }
+                if let Some(stencil) = draw.stencil {
+                    write!(f, " stencil={:?}", stencil)?;
+                }
+                if let Some(depth) = draw.depth {
+                    write!(f, " depth={:?}", depth)?;
+                }
+                for (name, value) in &draw.uniforms {
+                    write!(f, " {}={:?}", name, value)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+//----------------------------------------------------------------------------
+//
+//
+//                             COMMANDLIST : STRUCT
+//
+//
+//----------------------------------------------------------------------------
+
+/// List of recorded commands, it only hold ids and values so it can be sent to another thread.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct CommandList {
+    commands: Vec<Command>,
+}
+
+impl CommandList {
+    pub fn new() -> CommandList {
+        CommandList::default()
+    }
+
+    pub fn commands(&self) -> &[Command] {
+        &self.commands
+    }
+
+    pub fn push(&mut self, command: Command) {
+        self.commands.push(command);
+    }
+
+    /// Iterate over the draw commands only.
+    pub fn draws(&self) -> impl Iterator<Item = &DrawCommand> {
+        self.commands.iter().filter_map(|command| match command {
+            Command::Draw(draw) => Some(draw),
+            _ => None,
+        })
+    }
+
+    /// Number of draw calls inside the list.
+    pub fn draw_calls(&self) -> usize {
+        self.draws().count()
+    }
+
+    /// Number of vertices submitted by all the draws, instances included.
+    pub fn vertices(&self) -> usize {
+        self.draws()
+            .map(|draw| draw.count * draw.instances.unwrap_or(1))
+            .sum()
+    }
+
+    pub fn len(&self) -> usize {
+        self.commands.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.commands.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.commands.clear();
+    }
+
+    /// Execute all the commands on the current device.
+    pub fn replay(&self) {
+        for command in &self.commands {
+            command.execute();
+        }
+    }
+
+    /// Write the list in a text format read back by parse.
+    /// One command per line, its fields are separated by tabs and `-` is an absent value:
+    /// ```text
+    /// clear       framebuffer r g b a
+    /// clear_stencil framebuffer bits
+    /// draw        framebuffer vertex_array primitive first count instances unit:texture,...
+    ///             program blend scissor stencil depth viewport cw|ccw name=kind:values...
+    /// ```
+    pub fn write<W: io::Write>(&self, out: &mut W) -> io::Result<()> {
+        for command in &self.commands {
+            command.write(out)?;
+        }
+        Ok(())
+    }
+
+    /// Read a list written by write.
+    pub fn parse(text: &str) -> Result<CommandList, ParseError> {
+        let mut list = CommandList::new();
+
+        for (line, content) in text.lines().enumerate() {
+            if !content.is_empty() {
+                list.push(Command::parse(line + 1, content)?);
+            }
+        }
+        Ok(list)
+    }
+}
+
+/// One command per line.
+impl fmt::Display for CommandList {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for command in &self.commands {
+            writeln!(f, "{}", command)?;
+        }
+        Ok(())
+    }
+}
+
+//----------------------------------------------------------------------------
+//
+//
+//                             TEXT FORMAT
+//
+//
+//----------------------------------------------------------------------------
+
+static PRIMITIVES: [(Primitive, &str); 6] = [
+    (Primitive::Triangles, "triangles"),
+    (Primitive::Quads, "quads"),
+    (Primitive::TrianglesStrip, "triangles_strip"),
+    (Primitive::TriangleFan, "triangle_fan"),
+    (Primitive::Points, "points"),
+    (Primitive::Lines, "lines"),
+];
+
+static BLEND_MODES: [(BlendMode, &str); 6] = [
+    (BlendMode::None, "none"),
+    (BlendMode::Alpha, "alpha"),
+    (BlendMode::PremultipliedAlpha, "premultiplied_alpha"),
+    (BlendMode::Add, "add"),
+    (BlendMode::Multiply, "multiply"),
+    (BlendMode::Screen, "screen"),
+];
+
+static BLEND_FACTORS: [(BlendFactor, &str); 10] = [
+    (BlendFactor::Zero, "zero"),
+    (BlendFactor::One, "one"),
+    (BlendFactor::SrcColor, "src_color"),
+    (BlendFactor::OneMinusSrcColor, "one_minus_src_color"),
+    (BlendFactor::DstColor, "dst_color"),
+    (BlendFactor::OneMinusDstColor, "one_minus_dst_color"),
+    (BlendFactor::SrcAlpha, "src_alpha"),
+    (BlendFactor::OneMinusSrcAlpha, "one_minus_src_alpha"),
+    (BlendFactor::DstAlpha, "dst_alpha"),
+    (BlendFactor::OneMinusDstAlpha, "one_minus_dst_alpha"),
+];
+
+static BLEND_EQUATIONS: [(BlendEquation, &str); 5] = [
+    (BlendEquation::Add, "add"),
+    (BlendEquation::Subtract, "subtract"),
+    (BlendEquation::ReverseSubtract, "reverse_subtract"),
+    (BlendEquation::Min, "min"),
+    (BlendEquation::Max, "max"),
+];
+
+fn name_of<T: PartialEq>(table: &[(T, &'static str)], value: &T) -> &'static str {
+    table
+        .iter()
+        .find(|entry| entry.0 == *value)
+        .map_or("?", |entry| entry.1)
+}
+
+fn value_of<T: Clone>(table: &[(T, &str)], name: &str) -> Option<T> {
+    table
+        .iter()
+        .find(|entry| entry.1 == name)
+        .map(|entry| entry.0.clone())
+}
+
+/// Values separated by commas.
+fn join<T: fmt::Display>(values: &[T]) -> String {
+    values
+        .iter()
+        .map(|value| value.to_string())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn blend_name(mode: &BlendMode) -> String {
+    match mode {
+        BlendMode::Custom(func) => format!(
+            "custom:{},{},{},{},{},{}",
+            name_of(&BLEND_FACTORS, &func.color_src),
+            name_of(&BLEND_FACTORS, &func.color_dst),
+            name_of(&BLEND_FACTORS, &func.alpha_src),
+            name_of(&BLEND_FACTORS, &func.alpha_dst),
+            name_of(&BLEND_EQUATIONS, &func.color_equation),
+            name_of(&BLEND_EQUATIONS, &func.alpha_equation),
+        ),
+        mode => name_of(&BLEND_MODES, mode).to_string(),
+    }
+}
+
+fn optional<T: fmt::Display>(value: Option<T>) -> String {
+    value.map_or("-".to_string(), |value| value.to_string())
+}
+
+/// Fields of a line, every error give the line and the field that failed.
+struct Fields<'a> {
+    line: usize,
+    fields: Split<'a, char>,
+}
+
+impl<'a> Fields<'a> {
+    fn error(&self, field: &str) -> ParseError {
+        ParseError {
+            line: self.line,
+            field: field.to_string(),
+        }
+    }
+
+    fn next(&mut self) -> Result<&'a str, ParseError> {
+        match self.fields.next() {
+            Some(field) => Ok(field),
+            None => Err(self.error("")),
+        }
+    }
+
+    fn value<T: FromStr>(&mut self) -> Result<T, ParseError> {
+        let field = self.next()?;
+
+        field.parse().map_err(|_| self.error(field))
+    }
+
+    fn optional<T: FromStr>(&mut self) -> Result<Option<T>, ParseError> {
+        match self.next()? {
+            "-" => Ok(None),
+            field => field.parse().map(Some).map_err(|_| self.error(field)),
+        }
+    }
+
+    /// Comma separated values of a field.
+    fn list<T: FromStr>(&self, field: &str) -> Result<Vec<T>, ParseError> {
+        if field.is_empty() {
+            return Ok(Vec::new());
+        }
+        field
+            .split(',')
+            .map(|value| value.parse().map_err(|_| self.error(field)))
+            .collect()
+    }
+
+    fn blend(&mut self) -> Result<Option<BlendMode>, ParseError> {
+        let field = self.next()?;
+
+        if field == "-" {
+            return Ok(None);
+        }
+        if let Some(mode) = value_of(&BLEND_MODES, field) {
+            return Ok(Some(mode));
+        }
+        let names: Vec<&str> = match field.splitn(2, ':').collect::<Vec<_>>()[..] {
+            ["custom", names] => names.split(',').collect(),
+            _ => return Err(self.error(field)),
+        };
+        if names.len() != 6 {
+            return Err(self.error(field));
+        }
+        let factor = |name| value_of(&BLEND_FACTORS, name).ok_or_else(|| self.error(field));
+        let equation = |name| value_of(&BLEND_EQUATIONS, name).ok_or_else(|| self.error(field));
+
+        Ok(Some(BlendMode::Custom(BlendFunc {
+            color_src: factor(names[0])?,
+            color_dst: factor(names[1])?,
+            alpha_src: factor(names[2])?,
+            alpha_dst: factor(names[3])?,
+            color_equation: equation(names[4])?,
+            alpha_equation: equation(names[5])?,
+        })))
+    }
+
+    /// Textures bound as unit:texture pairs.
+    fn textures(&mut self) -> Result<Vec<(u32, u32)>, ParseError> {
+        let field = self.next()?;
+        let mut textures = Vec::new();
+
+        for texture in self.list::<String>(field)? {
+            let (unit, texture) = match texture.splitn(2, ':').collect::<Vec<_>>()[..] {
+                [unit, texture] => (unit.parse(), texture.parse()),
+                _ => return Err(self.error(field)),
+            };
+            match (unit, texture) {
+                (Ok(unit), Ok(texture)) => textures.push((unit, texture)),
+                _ => return Err(self.error(field)),
+            }
+        }
+        Ok(textures)
+    }
+
+    fn uniform(&self, field: &str) -> Result<(String, Uniform), ParseError> {
+        let (name, kind, values) = match field.splitn(2, '=').collect::<Vec<_>>()[..] {
+            [name, value] => match value.splitn(2, ':').collect::<Vec<_>>()[..] {
+                [kind, values] => (name, kind, values),
+                _ => return Err(self.error(field)),
+            },
+            _ => return Err(self.error(field)),
+        };
+        let uniform = match kind {
+            "floats" => Uniform::Floats(self.list(values)?),
+            "ints" => Uniform::Ints(self.list(values)?),
+            "matrix" => Uniform::Matrix(self.list(values)?),
+            _ => return Err(self.error(field)),
+        };
+        Ok((name.to_string(), uniform))
+    }
+}
+
+impl Command {
+    fn write<W: io::Write>(&self, out: &mut W) -> io::Result<()> {
+        match self {
+            Command::Clear { framebuffer, color } => writeln!(
+                out,
+                "clear\t{}\t{}\t{}\t{}\t{}",
+                framebuffer, color.0, color.1, color.2, color.3
+            ),
+            Command::ClearStencil { framebuffer, bits } => {
+                writeln!(out, "clear_stencil\t{}\t{}", framebuffer, bits)
+            }
+            Command::Draw(draw) => {
+                write!(
+                    out,
+                    "draw\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                    draw.framebuffer,
+                    draw.vertex_array,
+                    name_of(&PRIMITIVES, &draw.primitive),
+                    draw.first,
+                    draw.count,
+                    optional(draw.instances),
+                    join(
+                        &draw
+                            .textures
+                            .iter()
+                            .map(|texture| format!("{}:{}", texture.0, texture.1))
+                            .collect::<Vec<_>>()
+                    ),
+                    draw.program,
+                    draw.blend.as_ref().map_or("-".to_string(), blend_name),
+                    optional(draw.scissor.map(|scissor| join(&scissor))),
+                    optional(draw.stencil.map(|stencil| {
+                        join(&[stencil.reference, stencil.mask, stencil.write_mask])
+                    })),
+                    optional(
+                        draw.depth
+                            .map(|depth| format!("{},{}", depth.depth, depth.write))
+                    ),
+                    join(&draw.viewport),
+                    if draw.clockwise { "cw" } else { "ccw" },
+                )?;
+                for (name, value) in &draw.uniforms {
+                    let (kind, values) = match value {
+                        Uniform::Floats(values) => ("floats", join(values)),
+                        Uniform::Ints(values) => ("ints", join(values)),
+                        Uniform::Matrix(values) => ("matrix", join(values)),
+                    };
+                    write!(out, "\t{}={}:{}", name, kind, values)?;
+                }
+                writeln!(out)
+            }
+        }
+    }
+
+    fn parse(line: usize, content: &str) -> Result<Command, ParseError> {
+        let mut fields = Fields {
+            line,
+            fields: content.split('\t'),
+        };
+
+        match fields.next()? {
+            "clear" => Ok(Command::Clear {
+                framebuffer: fields.value()?,
+                color: Color(
+                    fields.value()?,
+                    fields.value()?,
+                    fields.value()?,
+                    fields.value()?,
+                ),
+            }),
+            "clear_stencil" => Ok(Command::ClearStencil {
+                framebuffer: fields.value()?,
+                bits: fields.value()?,
+            }),
+            "draw" => {
+                let framebuffer = fields.value()?;
+                let vertex_array = fields.value()?;
+                let primitive = fields.next()?;
+                let primitive =
+                    value_of(&PRIMITIVES, primitive).ok_or_else(|| fields.error(primitive))?;
+                let first = fields.value()?;
+                let count = fields.value()?;
+                let instances = fields.optional()?;
+                let textures = fields.textures()?;
+                let program = fields.value()?;
+                let blend = fields.blend()?;
+                let scissor = match fields.next()? {
+                    "-" => None,
+                    field => match fields.list::<i32>(field)?[..] {
+                        [x, y, width, height] => Some([x, y, width, height]),
+                        _ => return Err(fields.error(field)),
+                    },
+                };
+                let stencil = match fields.next()? {
+                    "-" => None,
+                    field => match fields.list::<u8>(field)?[..] {
+                        [reference, mask, write_mask] => Some(StencilTest {
+                            reference,
+                            mask,
+                            write_mask,
+                        }),
+                        _ => return Err(fields.error(field)),
+                    },
+                };
+                let depth = match fields.next()? {
+                    "-" => None,
+                    field => match field.splitn(2, ',').collect::<Vec<_>>()[..] {
+                        [depth, write] => Some(DepthTest {
+                            depth: depth.parse().map_err(|_| fields.error(field))?,
+                            write: write.parse().map_err(|_| fields.error(field))?,
+                        }),
+                        _ => return Err(fields.error(field)),
+                    },
+                };
+                let field = fields.next()?;
+                let viewport = match fields.list::<i32>(field)?[..] {
+                    [x, y, width, height] => [x, y, width, height],
+                    _ => return Err(fields.error(field)),
+                };
+                let clockwise = match fields.next()? {
+                    "cw" => true,
+                    "ccw" => false,
+                    field => return Err(fields.error(field)),
+                };
+                let mut uniforms = Vec::new();
+                while let Some(field) = fields.fields.next() {
+                    uniforms.push(fields.uniform(field)?);
+                }
+
+                Ok(Command::Draw(DrawCommand {
+                    framebuffer,
+                    vertex_array,
+                    primitive,
+                    first,
+                    count,
+                    instances,
+                    textures,
+                    program,
+                    blend,
+                    scissor,
+                    stencil,
+                    depth,
+                    viewport,
+                    clockwise,
+                    uniforms,
+                }))
+            }
+            field => Err(fields.error(field)),
+        }
+    }
+}
+
+/// A line of a written CommandList that couldn't be read.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    /// Line of the error, starting from 1.
+    pub line: usize,
+    /// Field that couldn't be read, empty if the line is too short.
+    pub field: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.field.is_empty() {
+            write!(f, "Line {}: missing fields.", self.line)
+        } else {
+            write!(f, "Line {}: can't read `{}`.", self.line, self.field)
+        }
+    }
+}
+
+impl Error for ParseError {
+    fn cause(&self) -> Option<&Error> {
+        None
+    }
+}
+
+//----------------------------------------------------------------------------
+//
+//
+//                             RECORDER : STRUCT
+//
+//
+//----------------------------------------------------------------------------
+
+#[derive(Debug, Default)]
+struct State {
+    framebuffer: u32,
+    /// Texture bound on each unit, 0 if none.
+    textures: Vec<u32>,
+    program: u32,
+    blend: Option<BlendMode>,
+    scissor: Option<[i32; 4]>,
+    stencil: Option<StencilTest>,
+    depth: Option<DepthTest>,
+    viewport: [i32; 4],
+    clockwise: bool,
+    /// Names of the locations asked to the device or given by the programs.
+    names: HashMap<(u32, i32), String>,
+    /// Programs whose active uniforms are inside names.
+    known_programs: HashSet<u32>,
+    /// Name of the last location asked, used when the device doesn't give real locations.
+    pending: Option<String>,
+    uniforms: HashMap<u32, Vec<(String, Uniform)>>,
+}
+
+impl State {
+    fn set_uniform(&mut self, location: i32, value: Uniform) {
+        let pending = self.pending.take();
+        let name = match (self.names.get(&(self.program, location)), pending) {
+            (Some(name), _) => name.clone(),
+            (None, Some(name)) => name,
+            // Ignored by the driver too
+            (None, None) if location == -1 => return,
+            // Only valid for this program, a glsl name can't start with @
+            (None, None) => format!("@{}", location),
+        };
+        let uniforms = self.uniforms.entry(self.program).or_default();
+
+        match uniforms.iter_mut().find(|uniform| uniform.0 == name) {
+            Some(uniform) => uniform.1 = value,
+            None => uniforms.push((name, value)),
+        }
+    }
+}
+
+/// Device recording the draws and the clears instead of sending them to another device.
+/// Every other call is forwarded: the bindings and the state are recorded with the draws
+/// and set on the inner device too, so what is read while recording (read_pixels,
+/// read_texture) comes from the targets of the recorded commands.
+pub struct Recorder {
+    inner: Rc<RenderDevice>,
+    state: RefCell<State>,
+    commands: RefCell<CommandList>,
+}
+
+impl Recorder {
+    /// Create a recorder on top of the device that will hold the gpu objects.
+    pub fn new(inner: Rc<RenderDevice>) -> Recorder {
+        let state = State {
+            framebuffer: inner.framebuffer(),
+            viewport: inner.viewport(),
+            clockwise: inner.is_clockwise(),
+            ..State::default()
+        };
+
+        Recorder {
+            inner,
+            state: RefCell::new(state),
+            commands: RefCell::new(CommandList::new()),
+        }
+    }
+
+    /// Take the commands recorded until now.
+    pub fn finish(&self) -> CommandList {
+        self.commands.replace(CommandList::new())
+    }
+
+    fn record_draw(
+        &self,
+        vertex_array: u32,
+        primitive: Primitive,
+        first: usize,
+        count: usize,
+        instances: Option<usize>,
+    ) {
+        let state = self.state.borrow();
+
+        self.commands.borrow_mut().push(Command::Draw(DrawCommand {
+            framebuffer: state.framebuffer,
+            vertex_array,
+            primitive,
+            first,
+            count,
+            instances,
+            textures: state
+                .textures
+                .iter()
+                .enumerate()
+                .filter(|texture| *texture.1 != 0)
+                .map(|(unit, texture)| (unit as u32, *texture))
+                .collect(),
+            program: state.program,
+            blend: state.blend,
+            scissor: state.scissor,
+            stencil: state.stencil,
+            depth: state.depth,
+            viewport: state.viewport,
+            clockwise: state.clockwise,
+            uniforms: state
+                .uniforms
+                .get(&state.program)
+                .cloned()
+                .unwrap_or_default(),
+        }));
+    }
+}
+
+impl RenderDevice for Recorder {
+    fn create_buffer(&self) -> u32 {
+        self.inner.create_buffer()
+    }
+
+    fn buffer_data(&self, buffer: u32, data: &[Vertex], usage: BufferUsage) {
+        self.inner.buffer_data(buffer, data, usage);
+    }
+
+    fn buffer_sub_data(&self, buffer: u32, offset: usize, data: &[Vertex]) {
+        self.inner.buffer_sub_data(buffer, offset, data);
+    }
+
+    fn orphan_buffer(&self, buffer: u32, len: usize, usage: BufferUsage) {
+        self.inner.orphan_buffer(buffer, len, usage);
+    }
+
+    fn buffer_write_unsynchronized(&self, buffer: u32, offset: usize, data: &[Vertex]) {
+        self.inner.buffer_write_unsynchronized(buffer, offset, data);
+    }
+
+    fn instance_data(&self, buffer: u32, data: &[Instance], usage: BufferUsage) {
+        self.inner.instance_data(buffer, data, usage);
+    }
+
+    fn instance_sub_data(&self, buffer: u32, offset: usize, data: &[Instance]) {
+        self.inner.instance_sub_data(buffer, offset, data);
+    }
+
+    fn texture_index_data(&self, buffer: u32, data: &[f32]) {
+        self.inner.texture_index_data(buffer, data);
+    }
+
+    fn bind_buffer(&self, buffer: u32) {
+        self.inner.bind_buffer(buffer);
+    }
+
+    fn delete_buffer(&self, buffer: u32) {
+        self.inner.delete_buffer(buffer);
+    }
+
+    fn create_vertex_array(&self) -> u32 {
+        self.inner.create_vertex_array()
+    }
+
+    fn vertex_layout(&self, vertex_array: u32, buffer: u32) {
+        self.inner.vertex_layout(vertex_array, buffer);
+    }
+
+    fn texture_index_layout(&self, vertex_array: u32, buffer: u32) {
+        self.inner.texture_index_layout(vertex_array, buffer);
+    }
+
+    fn instance_layout(&self, vertex_array: u32, buffer: u32, first: usize) {
+        self.inner.instance_layout(vertex_array, buffer, first);
+    }
+
+    fn bind_vertex_array(&self, vertex_array: u32) {
+        self.inner.bind_vertex_array(vertex_array);
+    }
+
+    fn delete_vertex_array(&self, vertex_array: u32) {
+        self.inner.delete_vertex_array(vertex_array);
+    }
+
+    fn fence(&self) -> usize {
+        self.inner.fence()
+    }
+
+    fn wait_fence(&self, fence: usize, timeout: u64) -> bool {
+        self.inner.wait_fence(fence, timeout)
+    }
+
+    fn delete_fence(&self, fence: usize) {
+        self.inner.delete_fence(fence);
+    }
+
+    fn create_texture(&self, data: &[u8], mode: RgbMode, width: u32, height: u32) -> u32 {
+        self.inner.create_texture(data, mode, width, height)
+    }
+
+    fn update_texture(
+        &self,
+        texture: u32,
+        data: &[u8],
+        mode: RgbMode,
+        pos: Vector<u32>,
+        sizes: Vector<u32>,
+    ) {
+        self.inner.update_texture(texture, data, mode, pos, sizes);
+    }
+
+    fn read_texture(&self, texture: u32, mode: RgbMode, data: &mut [u8]) {
+        self.inner.read_texture(texture, mode, data);
+    }
+
+    fn copy_texture(&self, src: u32, dst: u32, mode: RgbMode, width: u32, height: u32) {
+        self.inner.copy_texture(src, dst, mode, width, height);
+    }
+
+    fn texture_wrap(&self, texture: u32, wrap: Wrap) {
+        self.inner.texture_wrap(texture, wrap);
+    }
+
+    fn texture_filter(&self, texture: u32, filter: Filter) {
+        self.inner.texture_filter(texture, filter);
+    }
+
+    fn bind_texture(&self, unit: u32, texture: u32) {
+        let textures = &mut self.state.borrow_mut().textures;
+
+        if textures.len() <= unit as usize {
+            textures.resize(unit as usize + 1, 0);
+        }
+        textures[unit as usize] = texture;
+        self.inner.bind_texture(unit, texture);
+    }
+
+    fn max_texture_units(&self) -> usize {
+        self.inner.max_texture_units()
+    }
+
+    fn delete_texture(&self, texture: u32) {
+        self.inner.delete_texture(texture);
+    }
+
+    fn create_shader(&self, stage: ShaderStage, source: &CStr) -> u32 {
+        self.inner.create_shader(stage, source)
+    }
+
+    fn shader_status(&self, shader: u32) -> Result<(), String> {
+        self.inner.shader_status(shader)
+    }
+
+    fn create_program(&self, vert: u32, frag: u32) -> u32 {
+        self.inner.create_program(vert, frag)
+    }
+
+    fn program_status(&self, program: u32) -> Result<(), String> {
+        self.inner.program_status(program)
+    }
+
+    fn use_program(&self, program: u32) {
+        let mut state = self.state.borrow_mut();
+
+        // The shaders cache their locations, they may never be asked while recording
+        if state.known_programs.insert(program) {
+            for uniform in self.inner.active_uniforms(program) {
+                // Arrays are given by their first element, the others are asked by name
+                if uniform.size > 1 && uniform.name.ends_with("[0]") {
+                    let base = &uniform.name[..uniform.name.len() - 3];
+
+                    for index in 1..uniform.size {
+                        let name = format!("{}[{}]", base, index);
+                        let location = self.inner.uniform_location(program, &name);
+
+                        if location != -1 {
+                            state.names.insert((program, location), name);
+                        }
+                    }
+                }
+                state
+                    .names
+                    .insert((program, uniform.location), uniform.name);
+            }
+        }
+        state.program = program;
+        self.inner.use_program(program);
+    }
+
+    fn uniform_location(&self, program: u32, name: &str) -> i32 {
+        let location = self.inner.uniform_location(program, name);
+        let mut state = self.state.borrow_mut();
+
+        if location != -1 {
+            state.names.insert((program, location), name.to_string());
+        }
+        state.pending = Some(name.to_string());
+        location
+    }
+
+    fn active_uniforms(&self, program: u32) -> Vec<ActiveVariable> {
+        self.inner.active_uniforms(program)
+    }
+
+    fn active_attributes(&self, program: u32) -> Vec<ActiveVariable> {
+        self.inner.active_attributes(program)
+    }
+
+    fn uniform_floats(&self, location: i32, values: &[f32]) {
+        self.state
+            .borrow_mut()
+            .set_uniform(location, Uniform::Floats(values.to_vec()));
+        self.inner.uniform_floats(location, values);
+    }
+
+    fn uniform_ints(&self, location: i32, values: &[i32]) {
+        self.state
+            .borrow_mut()
+            .set_uniform(location, Uniform::Ints(values.to_vec()));
+        self.inner.uniform_ints(location, values);
+    }
+
+    fn uniform_matrix(&self, location: i32, values: &[f32]) {
+        self.state
+            .borrow_mut()
+            .set_uniform(location, Uniform::Matrix(values.to_vec()));
+        self.inner.uniform_matrix(location, values);
+    }
+
+    fn delete_shader(&self, shader: u32) {
+        self.inner.delete_shader(shader);
+    }
+
+    fn delete_program(&self, program: u32) {
+        let mut state = self.state.borrow_mut();
+
+        // The id can be given again to another program
+        state.known_programs.remove(&program);
+        state.names.retain(|key, _| key.0 != program);
+        self.inner.delete_program(program);
+    }
+
+    fn create_framebuffer(&self, texture: u32) -> Result<u32, u32> {
+        self.inner.create_framebuffer(texture)
+    }
+
+    fn bind_framebuffer(&self, framebuffer: u32) {
+        self.state.borrow_mut().framebuffer = framebuffer;
+        self.inner.bind_framebuffer(framebuffer);
+    }
+
+    fn framebuffer(&self) -> u32 {
+        self.state.borrow().framebuffer
+    }
+
+    fn read_pixels(&self, width: u32, height: u32, data: &mut [u8]) {
+        self.inner.read_pixels(width, height, data);
+    }
+
+    fn delete_framebuffer(&self, framebuffer: u32) {
+        self.inner.delete_framebuffer(framebuffer);
+    }
+
+    fn init(&self, width: u32, height: u32) {
+        self.inner.init(width, height);
+    }
+
+    fn viewport(&self) -> [i32; 4] {
+        self.state.borrow().viewport
+    }
+
+    fn set_viewport(&self, viewport: [i32; 4]) {
+        self.state.borrow_mut().viewport = viewport;
+        self.inner.set_viewport(viewport);
+    }
+
+    fn front_face(&self, clockwise: bool) {
+        self.state.borrow_mut().clockwise = clockwise;
+        self.inner.front_face(clockwise);
+    }
+
+    fn is_clockwise(&self) -> bool {
+        self.state.borrow().clockwise
+    }
+
+    fn blend(&self, mode: &BlendMode) {
+        self.state.borrow_mut().blend = Some(*mode);
+        self.inner.blend(mode);
+    }
+
+    fn disable_blend(&self) {
+        self.state.borrow_mut().blend = None;
+        self.inner.disable_blend();
+    }
+
+    fn scissor(&self, rect: Option<[i32; 4]>) {
+        self.state.borrow_mut().scissor = rect;
+        self.inner.scissor(rect);
+    }
+
+    fn stencil(&self, test: Option<StencilTest>) {
+        self.state.borrow_mut().stencil = test;
+        self.inner.stencil(test);
+    }
+
+    fn depth(&self, test: Option<DepthTest>) {
+        self.state.borrow_mut().depth = test;
+        self.inner.depth(test);
+    }
+
+    fn clear_stencil(&self, bits: u8) {
+        let framebuffer = self.state.borrow().framebuffer;
+
+        self.commands
+            .borrow_mut()
+            .push(Command::ClearStencil { framebuffer, bits });
+    }
+
+    fn clear(&self, color: Color) {
+        let framebuffer = self.state.borrow().framebuffer;
+
+        self.commands
+            .borrow_mut()
+            .push(Command::Clear { framebuffer, color });
+    }
+
+    fn draw_arrays(&self, vertex_array: u32, primitive: Primitive, first: usize, count: usize) {
+        self.record_draw(vertex_array, primitive, first, count, None);
+    }
+
+    fn draw_arrays_instanced(
+        &self,
+        vertex_array: u32,
+        primitive: Primitive,
+        first: usize,
+        count: usize,
+        instances: usize,
+    ) {
+        self.record_draw(vertex_array, primitive, first, count, Some(instances));
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+
+    fn reset_state(&self) {
+        self.inner.reset_state();
+    }
+
+    fn stats(&self) -> StateStats {
+        self.inner.stats()
+    }
+
+    fn reset_stats(&self) {
+        self.inner.reset_stats();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{record, Command, CommandList, DrawCommand, Uniform};
+    use color::Color;
+    use device::{self, DepthTest, NullDevice, RenderDevice, StencilTest};
+    use draw::{BlendEquation, BlendFunc, BlendMode};
+    use render_texture::RenderTexture;
+    use std::rc::Rc;
+    use texture::read_framebuffer;
+    use vertex_buffer::Primitive;
+
+    #[test]
+    fn reads_see_the_targets_of_the_recorded_commands() {
+        let null = Rc::new(NullDevice::new());
+        let _device = device::scoped(null.clone());
+        let mut target = RenderTexture::new(4, 2).unwrap();
+        let framebuffer = target.framebuffer();
+        let mut read = None;
+
+        let list = record(|| {
+            target.active();
+            device::current().clear(Color::red());
+            assert_eq!(null.framebuffer(), framebuffer);
+            assert_eq!(null.viewport(), [0, 0, 4, 2]);
+            read = Some(read_framebuffer(4, 2));
+            target.release();
+        });
+
+        assert_eq!(null.framebuffer(), 0);
+        assert_eq!(read.unwrap().dimensions(), (4, 2));
+        assert_eq!(target.capture().unwrap().dimensions(), (4, 2));
+        assert_eq!(
+            list.commands(),
+            &[Command::Clear {
+                framebuffer,
+                color: Color::red(),
+            }][..]
+        );
+    }
+
+    #[test]
+    fn draws_are_recorded_not_executed() {
+        let null = Rc::new(NullDevice::new());
+        let _device = device::scoped(null.clone());
+
+        let list = record(|| {
+            let device = device::current();
+            device.bind_texture(0, 7);
+            device.bind_texture(2, 9);
+            device.set_viewport([0, 0, 64, 32]);
+            device.front_face(true);
+            device.blend(&BlendMode::Alpha);
+            device.use_program(3);
+            let location = device.uniform_location(3, "transform");
+            device.uniform_matrix(location, &[1.0; 16]);
+            device.draw_arrays(5, Primitive::Triangles, 0, 6);
+        });
+
+        assert_eq!(null.draw_calls(), 0);
+        assert_eq!(list.draw_calls(), 1);
+        assert_eq!(list.vertices(), 6);
+        match &list.commands()[0] {
+            Command::Draw(draw) => {
+                assert_eq!(draw.textures, vec![(0, 7), (2, 9)]);
+                assert_eq!(draw.program, 3);
+                assert_eq!(draw.blend, Some(BlendMode::Alpha));
+                assert_eq!((draw.viewport, draw.clockwise), ([0, 0, 64, 32], true));
+                assert_eq!(
+                    draw.uniforms,
+                    vec![("transform".to_string(), Uniform::Matrix(vec![1.0; 16]))]
+                );
+            }
+            command => panic!("Unexpected command {}", command),
+        }
+
+        list.replay();
+        assert_eq!(null.draw_calls(), 1);
+    }
+
+    #[test]
+    fn location_without_name_is_recorded_by_number() {
+        let _device = device::scoped(Rc::new(NullDevice::new()));
+
+        // Like a location cached by a shader before the recording
+        let list = record(|| {
+            let device = device::current();
+            device.use_program(3);
+            device.uniform_ints(5, &[1]);
+            device.uniform_ints(-1, &[2]);
+            device.draw_arrays(5, Primitive::Triangles, 0, 6);
+        });
+
+        match &list.commands()[0] {
+            Command::Draw(draw) => assert_eq!(
+                draw.uniforms,
+                vec![("@5".to_string(), Uniform::Ints(vec![1]))]
+            ),
+            command => panic!("Unexpected command {}", command),
+        }
+        let mut text = Vec::new();
+        list.write(&mut text).unwrap();
+        assert_eq!(
+            CommandList::parse(&String::from_utf8(text).unwrap()),
+            Ok(list)
+        );
+    }
+
+    #[test]
+    fn written_list_is_parsed_back() {
+        let mut list = CommandList::new();
+        let draw = DrawCommand {
+            framebuffer: 0,
+            vertex_array: 2,
+            primitive: Primitive::TrianglesStrip,
+            first: 4,
+            count: 8,
+            instances: None,
+            textures: Vec::new(),
+            program: 1,
+            blend: None,
+            scissor: None,
+            stencil: None,
+            depth: None,
+            viewport: [0, 0, 800, 600],
+            clockwise: false,
+            uniforms: Vec::new(),
+        };
+
+        list.push(Command::Clear {
+            framebuffer: 3,
+            color: Color(0.1, 0.25, 1.0 / 3.0, 1.0),
+        });
+        list.push(Command::ClearStencil {
+            framebuffer: 3,
+            bits: 0b1010,
+        });
+        list.push(Command::Draw(draw.clone()));
+        list.push(Command::Draw(DrawCommand {
+            instances: Some(100),
+            textures: vec![(0, 7), (3, 12)],
+            viewport: [-1, 2, 16, 16],
+            clockwise: true,
+            blend: Some(BlendMode::Custom(BlendFunc {
+                color_equation: BlendEquation::Min,
+                ..BlendFunc::from(BlendMode::Alpha)
+            })),
+            scissor: Some([-2, 0, 64, 32]),
+            stencil: Some(StencilTest {
+                reference: 1,
+                mask: 3,
+                write_mask: 0,
+            }),
+            depth: Some(DepthTest {
+                depth: -0.5,
+                write: true,
+            }),
+            uniforms: vec![
+                ("offset".to_string(), Uniform::Floats(vec![0.5, 1e-7])),
+                ("offsets[1]".to_string(), Uniform::Ints(vec![-1])),
+                ("transform".to_string(), Uniform::Matrix(vec![2.0; 16])),
+                ("empty".to_string(), Uniform::Floats(Vec::new())),
+            ],
+            ..draw
+        }));
+
+        let mut text = Vec::new();
+        list.write(&mut text).unwrap();
+        let text = String::from_utf8(text).unwrap();
+
+        assert_eq!(text.lines().count(), 4);
+        assert_eq!(CommandList::parse(&text), Ok(list));
+        let error = CommandList::parse("clear\t0\t1\t1\n\ndraw\t0").unwrap_err();
+        assert_eq!((error.line, error.field.as_str()), (1, ""));
+        let error = CommandList::parse("draw\t0\t1\thexagons").unwrap_err();
+        assert_eq!(error.field, "hexagons");
+    }
+}
@@ -0,0 +1,1712 @@
+//
+//  Rust file | 2018
+//  Author: Alexandre Fourcat
+//  device.rs
+//  module:
+//! Render device system.
+//! Every call to the graphic api go through a RenderDevice. Drawables never talk to
+//! OpenGl directly, so another backend (a mock, a recorder...) can be plugged without
+//! touching them.
+//! Each thread has its own current device, OpenGl by default.
+//! ```no_run
+//! use gust::device::{self, NullDevice};
+//! use std::rc::Rc;
+//!
+//! // Everything created after this line on this thread will go through the NullDevice.
+//! let null = Rc::new(NullDevice::new());
+//! device::set_current(null.clone());
+//! assert_eq!(null.draw_calls(), 0);
+//! ```
+
+use color::Color;
+use draw::{BlendEquation, BlendFactor, BlendMode};
+use gl;
+use gl::types::*;
+use gl_error;
+use shader::Shader;
+use state_cache::{StateCache, StateStats};
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::ffi::{CStr, CString};
+use std::mem;
+use std::os::raw::c_void;
+use std::ptr;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use texture::{Filter, RgbMode, Wrap};
+use vertex::{Instance, Vertex};
+use vertex_buffer::{BufferUsage, Primitive};
+use Vector;
+
+/// Stage of a shader inside a program.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ShaderStage {
+    Vertex,
+    Fragment,
+}
+
+/// Glsl type of a shader variable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum GlslType {
+    Float,
+    Vec2,
+    Vec3,
+    Vec4,
+    Int,
+    IVec2,
+    IVec3,
+    IVec4,
+    Bool,
+    Mat2,
+    Mat3,
+    Mat4,
+    Sampler2D,
+    /// Any other type, with its gl enum.
+    Other(u32),
+}
+
+/// Uniform or attribute used by a linked program.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ActiveVariable {
+    pub name: String,
+    pub kind: GlslType,
+    /// Number of elements, more than 1 for arrays.
+    pub size: i32,
+    pub location: i32,
+}
+
+/// Depth test of the next draws, every fragment get the same depth (0 being the nearest).
+/// Fragments behind the ones already drawn are discarded.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DepthTest {
+    pub depth: f32,
+    /// Write the depth of the fragments drawn.
+    pub write: bool,
+}
+
+/// Stencil test of the next draws.
+/// Pass where `stencil & mask == reference & mask`, then `reference` is written
+/// in the bits of `write_mask`. Nothing is written in the color buffer if `write_mask` isn't 0.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct StencilTest {
+    pub reference: u8,
+    pub mask: u8,
+    pub write_mask: u8,
+}
+
+/// Trait owning the creation of every gpu object and the draw calls.
+/// Ids returned by a device are only meaningful for this device.
+pub trait RenderDevice {
+    // Buffers ----------------------------------------------------------------
+
+    /// Create a new vertex buffer.
+    fn create_buffer(&self) -> u32;
+
+    /// Allocate the buffer and fill it with vertices.
+    fn buffer_data(&self, buffer: u32, data: &[Vertex], usage: BufferUsage);
+
+    /// Update the vertices of the buffer from `offset` (in vertices).
+    fn buffer_sub_data(&self, buffer: u32, offset: usize, data: &[Vertex]);
+
+    /// Give a new storage of `len` vertices to the buffer, the old one is released by the
+    /// driver once the draws using it are done instead of stalling the next writes.
+    fn orphan_buffer(&self, buffer: u32, len: usize, usage: BufferUsage);
+
+    /// Write the vertices from `offset` (in vertices) without waiting for the gpu,
+    /// the caller must know the range isn't read anymore (see fence). Falls back to
+    /// buffer_sub_data when the range can't be mapped.
+    fn buffer_write_unsynchronized(&self, buffer: u32, offset: usize, data: &[Vertex]);
+
+    /// Allocate the buffer and fill it with instances.
+    fn instance_data(&self, buffer: u32, data: &[Instance], usage: BufferUsage);
+
+    /// Update the instances of the buffer from `offset` (in instances).
+    fn instance_sub_data(&self, buffer: u32, offset: usize, data: &[Instance]);
+
+    /// Allocate the buffer and fill it with the texture index of each vertex.
+    fn texture_index_data(&self, buffer: u32, data: &[f32]);
+
+    fn bind_buffer(&self, buffer: u32);
+
+    fn delete_buffer(&self, buffer: u32);
+
+    // Vertex arrays ----------------------------------------------------------
+
+    /// Create a new vertex array (layout of a buffer).
+    fn create_vertex_array(&self) -> u32;
+
+    /// Link the buffer to the vertex array with the Vertex layout
+    /// | pos | texCoord | color |
+    fn vertex_layout(&self, vertex_array: u32, buffer: u32);
+
+    /// Link the buffer of texture indices to the vertex array, one float per vertex
+    /// at the location 9. Only the multi-texture sprite batches have one.
+    fn texture_index_layout(&self, vertex_array: u32, buffer: u32);
+
+    /// Link the buffer to the vertex array as one Instance per instance drawn, starting
+    /// from the instance first,
+    /// from the location 3: | pos | origin | scale | rotation | texRect | color | textureIndex |
+    fn instance_layout(&self, vertex_array: u32, buffer: u32, first: usize);
+
+    fn bind_vertex_array(&self, vertex_array: u32);
+
+    fn delete_vertex_array(&self, vertex_array: u32);
+
+    // Fences -----------------------------------------------------------------
+
+    /// Insert a fence signaled when the commands sent before it are done by the gpu.
+    fn fence(&self) -> usize;
+
+    /// Wait at most timeout nanoseconds for the fence, return true if it's signaled.
+    fn wait_fence(&self, fence: usize, timeout: u64) -> bool;
+
+    fn delete_fence(&self, fence: usize);
+
+    // Textures ---------------------------------------------------------------
+
+    /// Create a texture of width * height filled with data.
+    /// An empty size only reserve the id.
+    fn create_texture(&self, data: &[u8], mode: RgbMode, width: u32, height: u32) -> u32;
+
+    /// Update a block of the texture.
+    fn update_texture(
+        &self,
+        texture: u32,
+        data: &[u8],
+        mode: RgbMode,
+        pos: Vector<u32>,
+        sizes: Vector<u32>,
+    );
+
+    /// Read all the pixels of the texture inside data.
+    fn read_texture(&self, texture: u32, mode: RgbMode, data: &mut [u8]);
+
+    /// Copy the pixels of src inside dst, both have the given sizes and mode.
+    fn copy_texture(&self, src: u32, dst: u32, mode: RgbMode, width: u32, height: u32);
+
+    fn texture_wrap(&self, texture: u32, wrap: Wrap);
+
+    fn texture_filter(&self, texture: u32, filter: Filter);
+
+    /// Bind the texture to the texture unit.
+    fn bind_texture(&self, unit: u32, texture: u32);
+
+    /// Number of texture units readable by a fragment shader.
+    fn max_texture_units(&self) -> usize;
+
+    fn delete_texture(&self, texture: u32);
+
+    // Shaders ----------------------------------------------------------------
+
+    /// Create and compile a shader.
+    fn create_shader(&self, stage: ShaderStage, source: &CStr) -> u32;
+
+    /// Return the info log if the compilation failed.
+    fn shader_status(&self, shader: u32) -> Result<(), String>;
+
+    /// Create a program and link the two shaders.
+    fn create_program(&self, vert: u32, frag: u32) -> u32;
+
+    /// Return the info log if the link failed.
+    fn program_status(&self, program: u32) -> Result<(), String>;
+
+    fn use_program(&self, program: u32);
+
+    /// Location of a uniform of the program, -1 if it has none with this name.
+    fn uniform_location(&self, program: u32, name: &str) -> i32;
+
+    /// Uniforms used by the program, the unused ones are removed by the driver.
+    fn active_uniforms(&self, program: u32) -> Vec<ActiveVariable>;
+
+    /// Vertex attributes used by the program.
+    fn active_attributes(&self, program: u32) -> Vec<ActiveVariable>;
+
+    /// Set a float, vec2, vec3 or vec4 uniform depending of the length of values.
+    fn uniform_floats(&self, location: i32, values: &[f32]);
+
+    /// Set an int, ivec2, ivec3 or ivec4 uniform depending of the length of values.
+    fn uniform_ints(&self, location: i32, values: &[i32]);
+
+    /// Set a mat2, mat3 or mat4 uniform depending of the length of values.
+    fn uniform_matrix(&self, location: i32, values: &[f32]);
+
+    fn delete_shader(&self, shader: u32);
+
+    fn delete_program(&self, program: u32);
+
+    // Framebuffers -----------------------------------------------------------
+
+    /// Create a framebuffer drawing inside the texture.
+    /// Return the framebuffer status on failure.
+    fn create_framebuffer(&self, texture: u32) -> Result<u32, u32>;
+
+    /// Bind a framebuffer, 0 being the window one.
+    fn bind_framebuffer(&self, framebuffer: u32);
+
+    /// Framebuffer bound, 0 being the window one.
+    fn framebuffer(&self) -> u32;
+
+    /// Read pixels (RGBA) of the bound framebuffer from the bottom left corner.
+    fn read_pixels(&self, width: u32, height: u32, data: &mut [u8]);
+
+    fn delete_framebuffer(&self, framebuffer: u32);
+
+    // States and draw --------------------------------------------------------
+
+    /// Setup the default states of a new context.
+    fn init(&self, width: u32, height: u32);
+
+    fn viewport(&self) -> [i32; 4];
+
+    fn set_viewport(&self, viewport: [i32; 4]);
+
+    /// Change the winding of the front faces.
+    fn front_face(&self, clockwise: bool);
+
+    /// Return true if the front faces are wound clockwise.
+    fn is_clockwise(&self) -> bool;
+
+    /// Enable the blending with the mode, BlendMode::None disable it.
+    fn blend(&self, mode: &BlendMode);
+
+    fn disable_blend(&self);
+
+    /// Clip the draws to [x, y, width, height] from the bottom left, None disable it.
+    fn scissor(&self, rect: Option<[i32; 4]>);
+
+    /// Test the stencil buffer while drawing, None disable it.
+    fn stencil(&self, test: Option<StencilTest>);
+
+    /// Test the depth buffer while drawing, None disable it.
+    fn depth(&self, test: Option<DepthTest>);
+
+    /// Set the bits to 0 in the whole stencil buffer.
+    fn clear_stencil(&self, bits: u8);
+
+    /// Clear the color, depth and stencil buffers, the scissor and the masks are ignored.
+    fn clear(&self, color: Color);
+
+    /// Draw `count` vertices from `first` of the vertex array.
+    fn draw_arrays(&self, vertex_array: u32, primitive: Primitive, first: usize, count: usize);
+
+    /// Draw `instances` times the `count` vertices from `first` of the vertex array.
+    fn draw_arrays_instanced(
+        &self,
+        vertex_array: u32,
+        primitive: Primitive,
+        first: usize,
+        count: usize,
+        instances: usize,
+    );
+
+    fn flush(&self);
+
+    // State cache ------------------------------------------------------------
+
+    /// Forget every state known, needed when the gl context change.
+    fn reset_state(&self) {}
+
+    /// Counters of the calls saved by a StateCache.
+    fn stats(&self) -> StateStats {
+        StateStats::default()
+    }
+
+    fn reset_stats(&self) {}
+}
+
+thread_local! {
+    static DEVICE: RefCell<Rc<RenderDevice>> =
+        RefCell::new(Rc::new(StateCache::new(Rc::new(GlDevice))));
+    /// Gl context current on the thread, 0 before any window.
+    static CONTEXT: Cell<usize> = Cell::new(0);
+    /// Objects made by gust in each context used by the thread, by context.
+    static OBJECTS: RefCell<ObjectTable> = RefCell::new(ObjectTable::default());
+}
+
+/// Ids given to the gl contexts, never reused.
+static NEXT_CONTEXT: AtomicUsize = AtomicUsize::new(1);
+
+/// Return the device of the current thread, a StateCache over a GlDevice by default.
+pub fn current() -> Rc<RenderDevice> {
+    DEVICE.with(|device| Rc::clone(&device.borrow()))
+}
+
+/// Change the device of the current thread and return the previous one.
+pub fn set_current(device: Rc<RenderDevice>) -> Rc<RenderDevice> {
+    DEVICE.with(|current| current.replace(device))
+}
+
+/// Make device the current one until the guard is dropped, the previous device is given
+/// back even on a panic. Objects made through device must be dropped before the guard,
+/// declaring them after it is enough.
+/// ```no_run
+/// use gust::device::{self, NullDevice};
+/// use std::rc::Rc;
+///
+/// let null = Rc::new(NullDevice::new());
+/// let _device = device::scoped(null.clone());
+/// ```
+pub fn scoped(device: Rc<RenderDevice>) -> DeviceGuard {
+    DeviceGuard {
+        previous: set_current(device),
+    }
+}
+
+/// Id of the gl context current on this thread, the gpu objects gust keeps for itself
+/// (like the built-in shaders) are made once per context.
+pub fn context() -> usize {
+    CONTEXT.with(|context| context.get())
+}
+
+/// Give an id to a new gl context.
+pub(crate) fn new_context() -> usize {
+    NEXT_CONTEXT.fetch_add(1, Ordering::Relaxed)
+}
+
+/// Tell which context has been made current on this thread.
+pub(crate) fn set_context(id: usize) {
+    CONTEXT.with(|context| context.set(id));
+}
+
+/// Objects gust keeps for itself inside a gl context, they can't be used by another one.
+#[derive(Default)]
+pub(crate) struct ContextObjects {
+    /// Built-in shaders, by builtin.
+    pub shaders: HashMap<usize, Rc<Shader>>,
+    /// Texture units used by the batches, queried once from the driver.
+    pub texture_units: Option<usize>,
+}
+
+/// Objects of the contexts used by a thread, by context.
+#[derive(Default)]
+struct ObjectTable(HashMap<usize, ContextObjects>);
+
+impl Drop for ObjectTable {
+    /// The contexts still there when the thread ends are given up with their objects,
+    /// the device they were made on may already be gone.
+    fn drop(&mut self) {
+        for (_, objects) in self.0.drain() {
+            mem::forget(objects);
+        }
+    }
+}
+
+/// Give the objects of the current context to f.
+/// f must not call it again, the objects are made outside of it.
+pub(crate) fn with_objects<F, R>(f: F) -> R
+where
+    F: FnOnce(&mut ContextObjects) -> R,
+{
+    OBJECTS.with(|objects| f(objects.borrow_mut().0.entry(context()).or_default()))
+}
+
+/// Delete the objects of a context, it has to be current.
+/// Called by the window owning the context when it's dropped.
+pub(crate) fn drop_context(id: usize) {
+    let objects = OBJECTS.with(|objects| objects.borrow_mut().0.remove(&id));
+    // Dropped outside of the table, the shaders delete their programs through the device
+    drop(objects);
+}
+
+/// Give back the previous device of the thread when dropped, see scoped.
+pub struct DeviceGuard {
+    previous: Rc<RenderDevice>,
+}
+
+impl Drop for DeviceGuard {
+    fn drop(&mut self) {
+        set_current(Rc::clone(&self.previous));
+    }
+}
+
+//----------------------------------------------------------------------------
+//
+//
+//                             GLDEVICE : STRUCT
+//
+//
+//----------------------------------------------------------------------------
+
+/// OpenGl 3.3 backend, the gl functions have to be loaded (by the window).
+/// In debug builds every call is checked, see gl_error.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct GlDevice;
+
+impl GlDevice {
+    unsafe fn info_log(id: u32, program: bool) -> String {
+        let mut len = 0;
+        if program {
+            gl::GetProgramiv(id, gl::INFO_LOG_LENGTH, &mut len);
+        } else {
+            gl::GetShaderiv(id, gl::INFO_LOG_LENGTH, &mut len);
+        }
+        let mut log: Vec<u8> = vec![0; len.max(1) as usize];
+        if program {
+            gl::GetProgramInfoLog(id, len, ptr::null_mut(), log.as_mut_ptr() as *mut _);
+        } else {
+            gl::GetShaderInfoLog(id, len, ptr::null_mut(), log.as_mut_ptr() as *mut _);
+        }
+        log.retain(|c| *c != 0);
+        String::from_utf8_lossy(&log).into_owned()
+    }
+
+    unsafe fn bound_framebuffer() -> u32 {
+        let mut bound = 0;
+        gl::GetIntegerv(gl::FRAMEBUFFER_BINDING, &mut bound);
+        bound as u32
+    }
+
+    fn blend_factor(factor: BlendFactor) -> GLenum {
+        match factor {
+            BlendFactor::Zero => gl::ZERO,
+            BlendFactor::One => gl::ONE,
+            BlendFactor::SrcColor => gl::SRC_COLOR,
+            BlendFactor::OneMinusSrcColor => gl::ONE_MINUS_SRC_COLOR,
+            BlendFactor::DstColor => gl::DST_COLOR,
+            BlendFactor::OneMinusDstColor => gl::ONE_MINUS_DST_COLOR,
+            BlendFactor::SrcAlpha => gl::SRC_ALPHA,
+            BlendFactor::OneMinusSrcAlpha => gl::ONE_MINUS_SRC_ALPHA,
+            BlendFactor::DstAlpha => gl::DST_ALPHA,
+            BlendFactor::OneMinusDstAlpha => gl::ONE_MINUS_DST_ALPHA,
+        }
+    }
+
+    fn blend_equation(equation: BlendEquation) -> GLenum {
+        match equation {
+            BlendEquation::Add => gl::FUNC_ADD,
+            BlendEquation::Subtract => gl::FUNC_SUBTRACT,
+            BlendEquation::ReverseSubtract => gl::FUNC_REVERSE_SUBTRACT,
+            BlendEquation::Min => gl::MIN,
+            BlendEquation::Max => gl::MAX,
+        }
+    }
+
+    fn internal_format(mode: RgbMode) -> GLenum {
+        match mode {
+            RgbMode::RGBA => gl::RGBA8,
+            RgbMode::RGB => gl::RGB8,
+            RgbMode::RED => gl::R8,
+        }
+    }
+
+    fn glsl_type(kind: GLenum) -> GlslType {
+        match kind {
+            gl::FLOAT => GlslType::Float,
+            gl::FLOAT_VEC2 => GlslType::Vec2,
+            gl::FLOAT_VEC3 => GlslType::Vec3,
+            gl::FLOAT_VEC4 => GlslType::Vec4,
+            gl::INT => GlslType::Int,
+            gl::INT_VEC2 => GlslType::IVec2,
+            gl::INT_VEC3 => GlslType::IVec3,
+            gl::INT_VEC4 => GlslType::IVec4,
+            gl::BOOL => GlslType::Bool,
+            gl::FLOAT_MAT2 => GlslType::Mat2,
+            gl::FLOAT_MAT3 => GlslType::Mat3,
+            gl::FLOAT_MAT4 => GlslType::Mat4,
+            gl::SAMPLER_2D => GlslType::Sampler2D,
+            kind => GlslType::Other(kind),
+        }
+    }
+
+    unsafe fn active_variables(program: u32, attributes: bool) -> Vec<ActiveVariable> {
+        let (mut count, mut max_len) = (0, 0);
+        if attributes {
+            gl::GetProgramiv(program, gl::ACTIVE_ATTRIBUTES, &mut count);
+            gl::GetProgramiv(program, gl::ACTIVE_ATTRIBUTE_MAX_LENGTH, &mut max_len);
+        } else {
+            gl::GetProgramiv(program, gl::ACTIVE_UNIFORMS, &mut count);
+            gl::GetProgramiv(program, gl::ACTIVE_UNIFORM_MAX_LENGTH, &mut max_len);
+        }
+
+        (0..count.max(0) as u32)
+            .map(|index| {
+                let mut name: Vec<u8> = vec![0; max_len.max(1) as usize];
+                let (mut len, mut size, mut kind) = (0, 0, 0);
+                let get = if attributes {
+                    gl::GetActiveAttrib
+                } else {
+                    gl::GetActiveUniform
+                };
+                get(
+                    program,
+                    index,
+                    max_len,
+                    &mut len,
+                    &mut size,
+                    &mut kind,
+                    name.as_mut_ptr() as *mut GLchar,
+                );
+                name.truncate(len.max(0) as usize);
+
+                let c_name = CString::new(name.clone()).unwrap();
+                let location = if attributes {
+                    gl::GetAttribLocation(program, c_name.as_ptr())
+                } else {
+                    gl::GetUniformLocation(program, c_name.as_ptr())
+                };
+                ActiveVariable {
+                    name: String::from_utf8_lossy(&name).into_owned(),
+                    kind: Self::glsl_type(kind),
+                    size,
+                    location,
+                }
+            })
+            .collect()
+    }
+}
+
+impl RenderDevice for GlDevice {
+    fn create_buffer(&self) -> u32 {
+        let mut id = 0;
+        unsafe {
+            gl::GenBuffers(1, &mut id);
+        }
+        gl_error::check("create_buffer");
+        id
+    }
+
+    fn buffer_data(&self, buffer: u32, data: &[Vertex], usage: BufferUsage) {
+        unsafe {
+            gl::BindBuffer(gl::ARRAY_BUFFER, buffer);
+            gl::BufferData(
+                gl::ARRAY_BUFFER,
+                mem::size_of_val(data) as GLsizeiptr,
+                data.as_ptr() as *const GLvoid,
+                usage.into(),
+            );
+            gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+        }
+        gl_error::check("buffer_data");
+    }
+
+    fn buffer_sub_data(&self, buffer: u32, offset: usize, data: &[Vertex]) {
+        unsafe {
+            gl::BindBuffer(gl::ARRAY_BUFFER, buffer);
+            gl::BufferSubData(
+                gl::ARRAY_BUFFER,
+                (mem::size_of::<Vertex>() * offset) as GLintptr,
+                mem::size_of_val(data) as GLsizeiptr,
+                data.as_ptr() as *const GLvoid,
+            );
+            gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+        }
+        gl_error::check("buffer_sub_data");
+    }
+
+    fn orphan_buffer(&self, buffer: u32, len: usize, usage: BufferUsage) {
+        unsafe {
+            gl::BindBuffer(gl::ARRAY_BUFFER, buffer);
+            gl::BufferData(
+                gl::ARRAY_BUFFER,
+                (mem::size_of::<Vertex>() * len) as GLsizeiptr,
+                ptr::null(),
+                usage.into(),
+            );
+            gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+        }
+        gl_error::check("orphan_buffer");
+    }
+
+    fn buffer_write_unsynchronized(&self, buffer: u32, offset: usize, data: &[Vertex]) {
+        if data.is_empty() {
+            return;
+        }
+        let size = mem::size_of_val(data);
+
+        unsafe {
+            gl::BindBuffer(gl::ARRAY_BUFFER, buffer);
+            let target = gl::MapBufferRange(
+                gl::ARRAY_BUFFER,
+                (mem::size_of::<Vertex>() * offset) as GLintptr,
+                size as GLsizeiptr,
+                gl::MAP_WRITE_BIT | gl::MAP_INVALIDATE_RANGE_BIT | gl::MAP_UNSYNCHRONIZED_BIT,
+            );
+            if target.is_null() {
+                gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+                gl_error::check("buffer_write_unsynchronized");
+                return self.buffer_sub_data(buffer, offset, data);
+            }
+            ptr::copy_nonoverlapping(data.as_ptr() as *const u8, target as *mut u8, size);
+            gl::UnmapBuffer(gl::ARRAY_BUFFER);
+            gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+        }
+        gl_error::check("buffer_write_unsynchronized");
+    }
+
+    fn instance_data(&self, buffer: u32, data: &[Instance], usage: BufferUsage) {
+        unsafe {
+            gl::BindBuffer(gl::ARRAY_BUFFER, buffer);
+            gl::BufferData(
+                gl::ARRAY_BUFFER,
+                mem::size_of_val(data) as GLsizeiptr,
+                data.as_ptr() as *const GLvoid,
+                usage.into(),
+            );
+            gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+        }
+        gl_error::check("instance_data");
+    }
+
+    fn instance_sub_data(&self, buffer: u32, offset: usize, data: &[Instance]) {
+        unsafe {
+            gl::BindBuffer(gl::ARRAY_BUFFER, buffer);
+            gl::BufferSubData(
+                gl::ARRAY_BUFFER,
+                (mem::size_of::<Instance>() * offset) as GLintptr,
+                mem::size_of_val(data) as GLsizeiptr,
+                data.as_ptr() as *const GLvoid,
+            );
+            gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+        }
+        gl_error::check("instance_sub_data");
+    }
+
+    fn texture_index_data(&self, buffer: u32, data: &[f32]) {
+        unsafe {
+            gl::BindBuffer(gl::ARRAY_BUFFER, buffer);
+            gl::BufferData(
+                gl::ARRAY_BUFFER,
+                mem::size_of_val(data) as GLsizeiptr,
+                data.as_ptr() as *const GLvoid,
+                gl::DYNAMIC_DRAW,
+            );
+            gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+        }
+        gl_error::check("texture_index_data");
+    }
+
+    fn bind_buffer(&self, buffer: u32) {
+        unsafe {
+            gl::BindBuffer(gl::ARRAY_BUFFER, buffer);
+        }
+        gl_error::check("bind_buffer");
+    }
+
+    fn delete_buffer(&self, buffer: u32) {
+        unsafe {
+            gl::DeleteBuffers(1, &buffer);
+        }
+        gl_error::check("delete_buffer");
+    }
+
+    fn create_vertex_array(&self) -> u32 {
+        let mut id = 0;
+        unsafe {
+            gl::GenVertexArrays(1, &mut id);
+        }
+        gl_error::check("create_vertex_array");
+        id
+    }
+
+    fn vertex_layout(&self, vertex_array: u32, buffer: u32) {
+        let stride = mem::size_of::<Vertex>() as GLsizei;
+        unsafe {
+            gl::BindVertexArray(vertex_array);
+            gl::BindBuffer(gl::ARRAY_BUFFER, buffer);
+
+            // Position (Of each vertex)
+            gl::VertexAttribPointer(0, 2, gl::FLOAT, gl::FALSE, stride, ptr::null());
+            gl::EnableVertexAttribArray(0);
+            // Texture Coord (Of each vertex)
+            gl::VertexAttribPointer(
+                1,
+                2,
+                gl::FLOAT,
+                gl::FALSE,
+                stride,
+                (2 * mem::size_of::<GLfloat>()) as *const _,
+            );
+            gl::EnableVertexAttribArray(1);
+            // Color (of each vertex)
+            gl::VertexAttribPointer(
+                2,
+                3,
+                gl::FLOAT,
+                gl::FALSE,
+                stride,
+                (4 * mem::size_of::<GLfloat>()) as *const _,
+            );
+            gl::EnableVertexAttribArray(2);
+
+            gl::BindVertexArray(0);
+            gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+        }
+        gl_error::check("vertex_layout");
+    }
+
+    fn texture_index_layout(&self, vertex_array: u32, buffer: u32) {
+        unsafe {
+            gl::BindVertexArray(vertex_array);
+            gl::BindBuffer(gl::ARRAY_BUFFER, buffer);
+            gl::VertexAttribPointer(9, 1, gl::FLOAT, gl::FALSE, 0, ptr::null());
+            gl::EnableVertexAttribArray(9);
+            gl::BindVertexArray(0);
+            gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+        }
+        gl_error::check("texture_index_layout");
+    }
+
+    fn instance_layout(&self, vertex_array: u32, buffer: u32, first: usize) {
+        let stride = mem::size_of::<Instance>() as GLsizei;
+        // Gl 3.3 has no base instance, the attributes start at the first instance instead
+        let start = first * mem::size_of::<Instance>();
+        // Location, number of floats and offset in floats of each attribute
+        let attributes = [
+            (3, 2, 0),
+            (4, 2, 2),
+            (5, 2, 4),
+            (6, 1, 6),
+            (7, 4, 7),
+            (8, 4, 11),
+            (10, 1, 15),
+        ];
+
+        unsafe {
+            gl::BindVertexArray(vertex_array);
+            gl::BindBuffer(gl::ARRAY_BUFFER, buffer);
+            for &(location, size, offset) in &attributes {
+                gl::VertexAttribPointer(
+                    location,
+                    size,
+                    gl::FLOAT,
+                    gl::FALSE,
+                    stride,
+                    (start + offset * mem::size_of::<GLfloat>()) as *const _,
+                );
+                gl::EnableVertexAttribArray(location);
+                // Move to the next instance and not to the next vertex
+                gl::VertexAttribDivisor(location, 1);
+            }
+            gl::BindVertexArray(0);
+            gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+        }
+        gl_error::check("instance_layout");
+    }
+
+    fn bind_vertex_array(&self, vertex_array: u32) {
+        unsafe {
+            gl::BindVertexArray(vertex_array);
+        }
+        gl_error::check("bind_vertex_array");
+    }
+
+    fn delete_vertex_array(&self, vertex_array: u32) {
+        unsafe {
+            gl::DeleteVertexArrays(1, &vertex_array);
+        }
+        gl_error::check("delete_vertex_array");
+    }
+
+    fn fence(&self) -> usize {
+        let fence = unsafe { gl::FenceSync(gl::SYNC_GPU_COMMANDS_COMPLETE, 0) };
+        gl_error::check("fence");
+        fence as usize
+    }
+
+    fn wait_fence(&self, fence: usize, timeout: u64) -> bool {
+        let status =
+            unsafe { gl::ClientWaitSync(fence as GLsync, gl::SYNC_FLUSH_COMMANDS_BIT, timeout) };
+        gl_error::check("wait_fence");
+        status == gl::ALREADY_SIGNALED || status == gl::CONDITION_SATISFIED
+    }
+
+    fn delete_fence(&self, fence: usize) {
+        unsafe {
+            gl::DeleteSync(fence as GLsync);
+        }
+        gl_error::check("delete_fence");
+    }
+
+    fn create_texture(&self, data: &[u8], mode: RgbMode, width: u32, height: u32) -> u32 {
+        let mut id = 0;
+        unsafe {
+            gl::GenTextures(1, &mut id);
+            if width == 0 || height == 0 {
+                return id;
+            }
+            gl::BindTexture(gl::TEXTURE_2D, id);
+            // Create the storage
+            gl::TexStorage2D(
+                gl::TEXTURE_2D,
+                1,
+                Self::internal_format(mode),
+                width as i32,
+                height as i32,
+            );
+            // Put pixel inside the storage
+            gl::PixelStorei(gl::UNPACK_ALIGNMENT, 1);
+            gl::TexSubImage2D(
+                gl::TEXTURE_2D,
+                0,
+                0,
+                0,
+                width as i32,
+                height as i32,
+                mode.as_gl(),
+                gl::UNSIGNED_BYTE,
+                data.as_ptr() as *const c_void,
+            );
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::REPEAT as i32);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::REPEAT as i32);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as i32);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as i32);
+            gl::GenerateMipmap(gl::TEXTURE_2D);
+            gl::BindTexture(gl::TEXTURE_2D, 0);
+        }
+        gl_error::check("create_texture");
+        id
+    }
+
+    fn update_texture(
+        &self,
+        texture: u32,
+        data: &[u8],
+        mode: RgbMode,
+        pos: Vector<u32>,
+        sizes: Vector<u32>,
+    ) {
+        unsafe {
+            gl::BindTexture(gl::TEXTURE_2D, texture);
+            gl::PixelStorei(gl::UNPACK_ALIGNMENT, 1);
+            gl::TexSubImage2D(
+                gl::TEXTURE_2D,
+                0,
+                pos.x as i32,
+                pos.y as i32,
+                sizes.x as i32,
+                sizes.y as i32,
+                mode.as_gl(),
+                gl::UNSIGNED_BYTE,
+                data.as_ptr() as *const c_void,
+            );
+            gl::BindTexture(gl::TEXTURE_2D, 0);
+            gl::Flush();
+        }
+        gl_error::check("update_texture");
+    }
+
+    fn read_texture(&self, texture: u32, mode: RgbMode, data: &mut [u8]) {
+        unsafe {
+            gl::BindTexture(gl::TEXTURE_2D, texture);
+            gl::PixelStorei(gl::PACK_ALIGNMENT, 1);
+            gl::GetTexImage(
+                gl::TEXTURE_2D,
+                0,
+                mode.as_gl(),
+                gl::UNSIGNED_BYTE,
+                data.as_mut_ptr() as *mut c_void,
+            );
+            gl::BindTexture(gl::TEXTURE_2D, 0);
+        }
+        gl_error::check("read_texture");
+    }
+
+    fn copy_texture(&self, src: u32, dst: u32, mode: RgbMode, width: u32, height: u32) {
+        if gl::CopyImageSubData::is_loaded() {
+            unsafe {
+                gl::CopyImageSubData(
+                    src,
+                    gl::TEXTURE_2D,
+                    0,
+                    0,
+                    0,
+                    0,
+                    dst,
+                    gl::TEXTURE_2D,
+                    0,
+                    0,
+                    0,
+                    0,
+                    width as i32,
+                    height as i32,
+                    1,
+                );
+            }
+        } else {
+            // glCopyImageSubData is gl 4.3, go through the cpu before
+            let mut data = vec![0; (width * height) as usize * mode.channels()];
+            self.read_texture(src, mode, &mut data);
+            self.update_texture(
+                dst,
+                &data,
+                mode,
+                Vector::new(0, 0),
+                Vector::new(width, height),
+            );
+        }
+        gl_error::check("copy_texture");
+    }
+
+    fn texture_wrap(&self, texture: u32, wrap: Wrap) {
+        let wrap = match wrap {
+            Wrap::Repeat => gl::REPEAT,
+            Wrap::MirroredRepeat => gl::MIRRORED_REPEAT,
+            Wrap::ClampToEdge => gl::CLAMP_TO_EDGE,
+        };
+        unsafe {
+            gl::BindTexture(gl::TEXTURE_2D, texture);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, wrap as i32);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, wrap as i32);
+            gl::BindTexture(gl::TEXTURE_2D, 0);
+        }
+        gl_error::check("texture_wrap");
+    }
+
+    fn texture_filter(&self, texture: u32, filter: Filter) {
+        let filter = match filter {
+            Filter::Linear => gl::LINEAR,
+            Filter::Nearest => gl::NEAREST,
+        };
+        unsafe {
+            gl::BindTexture(gl::TEXTURE_2D, texture);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, filter as i32);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, filter as i32);
+            gl::BindTexture(gl::TEXTURE_2D, 0);
+        }
+        gl_error::check("texture_filter");
+    }
+
+    fn bind_texture(&self, unit: u32, texture: u32) {
+        unsafe {
+            gl::ActiveTexture(gl::TEXTURE0 + unit);
+            gl::BindTexture(gl::TEXTURE_2D, texture);
+        }
+        gl_error::check("bind_texture");
+    }
+
+    fn max_texture_units(&self) -> usize {
+        let mut units = 0;
+        unsafe {
+            gl::GetIntegerv(gl::MAX_TEXTURE_IMAGE_UNITS, &mut units);
+        }
+        gl_error::check("max_texture_units");
+        units as usize
+    }
+
+    fn delete_texture(&self, texture: u32) {
+        unsafe {
+            gl::DeleteTextures(1, &texture);
+        }
+        gl_error::check("delete_texture");
+    }
+
+    fn create_shader(&self, stage: ShaderStage, source: &CStr) -> u32 {
+        unsafe {
+            let id = gl::CreateShader(match stage {
+                ShaderStage::Vertex => gl::VERTEX_SHADER,
+                ShaderStage::Fragment => gl::FRAGMENT_SHADER,
+            });
+            gl::ShaderSource(id, 1, &source.as_ptr(), ptr::null());
+            gl::CompileShader(id);
+            gl_error::check("create_shader");
+            id
+        }
+    }
+
+    fn shader_status(&self, shader: u32) -> Result<(), String> {
+        let mut success = 0;
+        unsafe {
+            gl::GetShaderiv(shader, gl::COMPILE_STATUS, &mut success);
+            if success == 0 {
+                return Err(Self::info_log(shader, false));
+            }
+        }
+        gl_error::check("shader_status");
+        Ok(())
+    }
+
+    fn create_program(&self, vert: u32, frag: u32) -> u32 {
+        unsafe {
+            let id = gl::CreateProgram();
+            gl::AttachShader(id, vert);
+            gl::AttachShader(id, frag);
+            gl::LinkProgram(id);
+            gl_error::check("create_program");
+            id
+        }
+    }
+
+    fn program_status(&self, program: u32) -> Result<(), String> {
+        let mut status = 0;
+        unsafe {
+            gl::GetProgramiv(program, gl::LINK_STATUS, &mut status);
+            if status == 0 {
+                return Err(Self::info_log(program, true));
+            }
+        }
+        gl_error::check("program_status");
+        Ok(())
+    }
+
+    fn use_program(&self, program: u32) {
+        unsafe {
+            gl::UseProgram(program);
+        }
+        gl_error::check("use_program");
+    }
+
+    fn uniform_location(&self, program: u32, name: &str) -> i32 {
+        // A name holding a nul can't be a glsl identifier
+        let name = match CString::new(name.as_bytes()) {
+            Ok(name) => name,
+            Err(_) => return -1,
+        };
+        let location = unsafe { gl::GetUniformLocation(program, name.as_ptr()) };
+        gl_error::check("uniform_location");
+        location
+    }
+
+    fn active_uniforms(&self, program: u32) -> Vec<ActiveVariable> {
+        let uniforms = unsafe { Self::active_variables(program, false) };
+        gl_error::check("active_uniforms");
+        uniforms
+    }
+
+    fn active_attributes(&self, program: u32) -> Vec<ActiveVariable> {
+        let attributes = unsafe { Self::active_variables(program, true) };
+        gl_error::check("active_attributes");
+        attributes
+    }
+
+    fn uniform_floats(&self, location: i32, values: &[f32]) {
+        unsafe {
+            match values.len() {
+                1 => gl::Uniform1f(location, values[0]),
+                2 => gl::Uniform2f(location, values[0], values[1]),
+                3 => gl::Uniform3f(location, values[0], values[1], values[2]),
+                4 => gl::Uniform4f(location, values[0], values[1], values[2], values[3]),
+                _ => {}
+            }
+        }
+        gl_error::check("uniform_floats");
+    }
+
+    fn uniform_ints(&self, location: i32, values: &[i32]) {
+        unsafe {
+            match values.len() {
+                1 => gl::Uniform1i(location, values[0]),
+                2 => gl::Uniform2i(location, values[0], values[1]),
+                3 => gl::Uniform3i(location, values[0], values[1], values[2]),
+                4 => gl::Uniform4i(location, values[0], values[1], values[2], values[3]),
+                _ => {}
+            }
+        }
+        gl_error::check("uniform_ints");
+    }
+
+    fn uniform_matrix(&self, location: i32, values: &[f32]) {
+        unsafe {
+            match values.len() {
+                4 => gl::UniformMatrix2fv(location, 1, gl::FALSE, values.as_ptr()),
+                9 => gl::UniformMatrix3fv(location, 1, gl::FALSE, values.as_ptr()),
+                16 => gl::UniformMatrix4fv(location, 1, gl::FALSE, values.as_ptr()),
+                _ => {}
+            }
+        }
+        gl_error::check("uniform_matrix");
+    }
+
+    fn delete_shader(&self, shader: u32) {
+        unsafe {
+            gl::DeleteShader(shader);
+        }
+        gl_error::check("delete_shader");
+    }
+
+    fn delete_program(&self, program: u32) {
+        unsafe {
+            gl::DeleteProgram(program);
+        }
+        gl_error::check("delete_program");
+    }
+
+    fn create_framebuffer(&self, texture: u32) -> Result<u32, u32> {
+        let mut fbo = 0;
+        unsafe {
+            // The target drawn on is given back once the framebuffer is made
+            let previous = GlDevice::bound_framebuffer();
+            gl::GenFramebuffers(1, &mut fbo);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, fbo);
+            gl::FramebufferTexture2D(
+                gl::FRAMEBUFFER,
+                gl::COLOR_ATTACHMENT0,
+                gl::TEXTURE_2D,
+                texture,
+                0,
+            );
+
+            // Stencil buffer used by the masks of draw::Context
+            let (mut width, mut height, mut stencil) = (0, 0, 0);
+            gl::BindTexture(gl::TEXTURE_2D, texture);
+            gl::GetTexLevelParameteriv(gl::TEXTURE_2D, 0, gl::TEXTURE_WIDTH, &mut width);
+            gl::GetTexLevelParameteriv(gl::TEXTURE_2D, 0, gl::TEXTURE_HEIGHT, &mut height);
+            gl::BindTexture(gl::TEXTURE_2D, 0);
+            gl::GenRenderbuffers(1, &mut stencil);
+            gl::BindRenderbuffer(gl::RENDERBUFFER, stencil);
+            gl::RenderbufferStorage(gl::RENDERBUFFER, gl::DEPTH24_STENCIL8, width, height);
+            gl::BindRenderbuffer(gl::RENDERBUFFER, 0);
+            gl::FramebufferRenderbuffer(
+                gl::FRAMEBUFFER,
+                gl::DEPTH_STENCIL_ATTACHMENT,
+                gl::RENDERBUFFER,
+                stencil,
+            );
+
+            let status = gl::CheckFramebufferStatus(gl::FRAMEBUFFER);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, previous);
+
+            if status != gl::FRAMEBUFFER_COMPLETE {
+                gl::DeleteFramebuffers(1, &fbo);
+                gl::DeleteRenderbuffers(1, &stencil);
+                return Err(status);
+            }
+        }
+        gl_error::check("create_framebuffer");
+        Ok(fbo)
+    }
+
+    fn bind_framebuffer(&self, framebuffer: u32) {
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, framebuffer);
+        }
+        gl_error::check("bind_framebuffer");
+    }
+
+    fn framebuffer(&self) -> u32 {
+        let framebuffer = unsafe { GlDevice::bound_framebuffer() };
+        gl_error::check("framebuffer");
+        framebuffer
+    }
+
+    fn read_pixels(&self, width: u32, height: u32, data: &mut [u8]) {
+        unsafe {
+            gl::PixelStorei(gl::PACK_ALIGNMENT, 1);
+            gl::ReadPixels(
+                0,
+                0,
+                width as i32,
+                height as i32,
+                gl::RGBA,
+                gl::UNSIGNED_BYTE,
+                data.as_mut_ptr() as *mut c_void,
+            );
+        }
+        gl_error::check("read_pixels");
+    }
+
+    fn delete_framebuffer(&self, framebuffer: u32) {
+        let mut stencil = 0;
+        unsafe {
+            // Deleting the bound framebuffer give back the window one, like gl does
+            let previous = match GlDevice::bound_framebuffer() {
+                bound if bound == framebuffer => 0,
+                bound => bound,
+            };
+            // The stencil renderbuffer isn't deleted with the framebuffer
+            gl::BindFramebuffer(gl::FRAMEBUFFER, framebuffer);
+            gl::GetFramebufferAttachmentParameteriv(
+                gl::FRAMEBUFFER,
+                gl::DEPTH_STENCIL_ATTACHMENT,
+                gl::FRAMEBUFFER_ATTACHMENT_OBJECT_NAME,
+                &mut stencil,
+            );
+            gl::BindFramebuffer(gl::FRAMEBUFFER, previous);
+            gl::DeleteRenderbuffers(1, &(stencil as u32));
+            gl::DeleteFramebuffers(1, &framebuffer);
+        }
+        gl_error::check("delete_framebuffer");
+    }
+
+    fn init(&self, width: u32, height: u32) {
+        unsafe {
+            gl::Viewport(0, 0, width as i32, height as i32);
+            gl::Enable(gl::CULL_FACE);
+        }
+        gl_error::check("init");
+        self.blend(&BlendMode::Alpha);
+    }
+
+    fn viewport(&self) -> [i32; 4] {
+        let mut viewport = [0; 4];
+        unsafe {
+            gl::GetIntegerv(gl::VIEWPORT, viewport.as_mut_ptr());
+        }
+        gl_error::check("viewport");
+        viewport
+    }
+
+    fn set_viewport(&self, viewport: [i32; 4]) {
+        unsafe {
+            gl::Viewport(viewport[0], viewport[1], viewport[2], viewport[3]);
+        }
+        gl_error::check("set_viewport");
+    }
+
+    fn front_face(&self, clockwise: bool) {
+        unsafe {
+            gl::FrontFace(if clockwise { gl::CW } else { gl::CCW });
+        }
+        gl_error::check("front_face");
+    }
+
+    fn is_clockwise(&self) -> bool {
+        let mut front_face = 0;
+        unsafe {
+            gl::GetIntegerv(gl::FRONT_FACE, &mut front_face);
+        }
+        gl_error::check("is_clockwise");
+        front_face as GLenum == gl::CW
+    }
+
+    fn blend(&self, mode: &BlendMode) {
+        let func = match mode.func() {
+            Some(func) => func,
+            None => return self.disable_blend(),
+        };
+
+        unsafe {
+            gl::Enable(gl::BLEND);
+            gl::BlendFuncSeparate(
+                Self::blend_factor(func.color_src),
+                Self::blend_factor(func.color_dst),
+                Self::blend_factor(func.alpha_src),
+                Self::blend_factor(func.alpha_dst),
+            );
+            gl::BlendEquationSeparate(
+                Self::blend_equation(func.color_equation),
+                Self::blend_equation(func.alpha_equation),
+            );
+        }
+        gl_error::check("blend");
+    }
+
+    fn disable_blend(&self) {
+        unsafe {
+            gl::Disable(gl::BLEND);
+        }
+        gl_error::check("disable_blend");
+    }
+
+    fn scissor(&self, rect: Option<[i32; 4]>) {
+        unsafe {
+            match rect {
+                Some(rect) => {
+                    gl::Enable(gl::SCISSOR_TEST);
+                    gl::Scissor(rect[0], rect[1], rect[2], rect[3]);
+                }
+                None => gl::Disable(gl::SCISSOR_TEST),
+            }
+        }
+        gl_error::check("scissor");
+    }
+
+    fn stencil(&self, test: Option<StencilTest>) {
+        unsafe {
+            let test = match test {
+                Some(test) => test,
+                None => {
+                    gl::Disable(gl::STENCIL_TEST);
+                    gl::ColorMask(gl::TRUE, gl::TRUE, gl::TRUE, gl::TRUE);
+                    return;
+                }
+            };
+            let writing = test.write_mask != 0;
+            let color = if writing { gl::FALSE } else { gl::TRUE };
+
+            gl::Enable(gl::STENCIL_TEST);
+            gl::ColorMask(color, color, color, color);
+            gl::StencilFunc(
+                gl::EQUAL,
+                GLint::from(test.reference),
+                GLuint::from(test.mask),
+            );
+            gl::StencilMask(GLuint::from(test.write_mask));
+            gl::StencilOp(
+                gl::KEEP,
+                gl::KEEP,
+                if writing { gl::REPLACE } else { gl::KEEP },
+            );
+        }
+        gl_error::check("stencil");
+    }
+
+    fn depth(&self, test: Option<DepthTest>) {
+        unsafe {
+            match test {
+                Some(test) => {
+                    let depth = GLdouble::from(test.depth);
+
+                    gl::Enable(gl::DEPTH_TEST);
+                    gl::DepthFunc(gl::LEQUAL);
+                    gl::DepthRange(depth, depth);
+                    gl::DepthMask(if test.write { gl::TRUE } else { gl::FALSE });
+                }
+                None => {
+                    gl::Disable(gl::DEPTH_TEST);
+                    gl::DepthRange(0.0, 1.0);
+                }
+            }
+        }
+        gl_error::check("depth");
+    }
+
+    fn clear_stencil(&self, bits: u8) {
+        unsafe {
+            gl::Disable(gl::SCISSOR_TEST);
+            gl::StencilMask(GLuint::from(bits));
+            gl::Clear(gl::STENCIL_BUFFER_BIT);
+        }
+        gl_error::check("clear_stencil");
+    }
+
+    fn clear(&self, color: Color) {
+        unsafe {
+            gl::Disable(gl::SCISSOR_TEST);
+            gl::ColorMask(gl::TRUE, gl::TRUE, gl::TRUE, gl::TRUE);
+            gl::StencilMask(0xFF);
+            gl::DepthMask(gl::TRUE);
+            gl::ClearColor(color.0, color.1, color.2, color.3);
+            gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT | gl::STENCIL_BUFFER_BIT);
+        }
+        gl_error::check("clear");
+    }
+
+    fn draw_arrays(&self, vertex_array: u32, primitive: Primitive, first: usize, count: usize) {
+        unsafe {
+            gl::BindVertexArray(vertex_array);
+            gl::DrawArrays(primitive.get_gl_type(), first as i32, count as i32);
+            gl::BindVertexArray(0);
+        }
+        gl_error::check("draw_arrays");
+    }
+
+    fn draw_arrays_instanced(
+        &self,
+        vertex_array: u32,
+        primitive: Primitive,
+        first: usize,
+        count: usize,
+        instances: usize,
+    ) {
+        unsafe {
+            gl::BindVertexArray(vertex_array);
+            gl::DrawArraysInstanced(
+                primitive.get_gl_type(),
+                first as i32,
+                count as i32,
+                instances as i32,
+            );
+            gl::BindVertexArray(0);
+        }
+        gl_error::check("draw_arrays_instanced");
+    }
+
+    fn flush(&self) {
+        unsafe {
+            gl::Flush();
+        }
+        gl_error::check("flush");
+    }
+}
+
+//----------------------------------------------------------------------------
+//
+//
+//                             NULLDEVICE : STRUCT
+//
+//
+//----------------------------------------------------------------------------
+
+/// A device that draw nothing, it only hand out ids and count draw calls and deletions.
+/// Useful to run drawables code on machines without any gpu.
+/// Pixels read back hold the index of their row in gl order, from the bottom.
+#[derive(Debug, Default)]
+pub struct NullDevice {
+    next_id: Cell<u32>,
+    draw_calls: Cell<usize>,
+    deleted: Cell<usize>,
+    /// Width of the textures, to know their rows when read.
+    widths: RefCell<HashMap<u32, u32>>,
+    viewport: Cell<[i32; 4]>,
+    framebuffer: Cell<u32>,
+    clockwise: Cell<bool>,
+    /// Fences are never signaled, as if the gpu was stuck on the draws.
+    busy: Cell<bool>,
+}
+
+impl NullDevice {
+    pub fn new() -> NullDevice {
+        NullDevice::default()
+    }
+
+    /// Number of draw_arrays called since the creation of the device.
+    pub fn draw_calls(&self) -> usize {
+        self.draw_calls.get()
+    }
+
+    /// Number of objects deleted since the creation of the device.
+    pub fn deleted(&self) -> usize {
+        self.deleted.get()
+    }
+
+    /// Keep the fences unsignaled until busy is set back to false.
+    pub fn set_busy(&self, busy: bool) {
+        self.busy.set(busy);
+    }
+
+    /// Framebuffer bound, the one read by read_pixels.
+    pub fn framebuffer(&self) -> u32 {
+        self.framebuffer.get()
+    }
+
+    fn delete(&self) {
+        self.deleted.set(self.deleted.get() + 1);
+    }
+
+    fn id(&self) -> u32 {
+        self.next_id.set(self.next_id.get() + 1);
+        self.next_id.get()
+    }
+
+    /// Write the index of each row of len bytes in all its bytes.
+    fn fill_rows(data: &mut [u8], len: usize) {
+        for (i, row) in data.chunks_mut(len.max(1)).enumerate() {
+            for byte in row {
+                *byte = i as u8;
+            }
+        }
+    }
+}
+
+impl RenderDevice for NullDevice {
+    fn create_buffer(&self) -> u32 {
+        self.id()
+    }
+
+    fn buffer_data(&self, _buffer: u32, _data: &[Vertex], _usage: BufferUsage) {}
+
+    fn buffer_sub_data(&self, _buffer: u32, _offset: usize, _data: &[Vertex]) {}
+
+    fn orphan_buffer(&self, _buffer: u32, _len: usize, _usage: BufferUsage) {}
+
+    fn buffer_write_unsynchronized(&self, _buffer: u32, _offset: usize, _data: &[Vertex]) {}
+
+    fn instance_data(&self, _buffer: u32, _data: &[Instance], _usage: BufferUsage) {}
+
+    fn instance_sub_data(&self, _buffer: u32, _offset: usize, _data: &[Instance]) {}
+
+    fn texture_index_data(&self, _buffer: u32, _data: &[f32]) {}
+
+    fn bind_buffer(&self, _buffer: u32) {}
+
+    fn delete_buffer(&self, _buffer: u32) {
+        self.delete();
+    }
+
+    fn create_vertex_array(&self) -> u32 {
+        self.id()
+    }
+
+    fn vertex_layout(&self, _vertex_array: u32, _buffer: u32) {}
+
+    fn texture_index_layout(&self, _vertex_array: u32, _buffer: u32) {}
+
+    fn instance_layout(&self, _vertex_array: u32, _buffer: u32, _first: usize) {}
+
+    fn bind_vertex_array(&self, _vertex_array: u32) {}
+
+    fn delete_vertex_array(&self, _vertex_array: u32) {
+        self.delete();
+    }
+
+    /// Nothing is drawn so every fence is already signaled, unless the device is busy.
+    fn fence(&self) -> usize {
+        self.id() as usize
+    }
+
+    fn wait_fence(&self, _fence: usize, _timeout: u64) -> bool {
+        !self.busy.get()
+    }
+
+    fn delete_fence(&self, _fence: usize) {
+        self.delete();
+    }
+
+    fn create_texture(&self, _data: &[u8], _mode: RgbMode, width: u32, _height: u32) -> u32 {
+        let id = self.id();
+
+        self.widths.borrow_mut().insert(id, width);
+        id
+    }
+
+    fn update_texture(
+        &self,
+        _texture: u32,
+        _data: &[u8],
+        _mode: RgbMode,
+        _pos: Vector<u32>,
+        _sizes: Vector<u32>,
+    ) {
+    }
+
+    fn read_texture(&self, texture: u32, mode: RgbMode, data: &mut [u8]) {
+        let width = self.widths.borrow().get(&texture).cloned().unwrap_or(0);
+
+        NullDevice::fill_rows(data, width as usize * mode.channels());
+    }
+
+    fn copy_texture(&self, _src: u32, _dst: u32, _mode: RgbMode, _width: u32, _height: u32) {}
+
+    fn texture_wrap(&self, _texture: u32, _wrap: Wrap) {}
+
+    fn texture_filter(&self, _texture: u32, _filter: Filter) {}
+
+    fn bind_texture(&self, _unit: u32, _texture: u32) {}
+
+    /// The minimum given by opengl 3.3.
+    fn max_texture_units(&self) -> usize {
+        16
+    }
+
+    fn delete_texture(&self, texture: u32) {
+        self.widths.borrow_mut().remove(&texture);
+        self.delete();
+    }
+
+    fn create_shader(&self, _stage: ShaderStage, _source: &CStr) -> u32 {
+        self.id()
+    }
+
+    fn shader_status(&self, _shader: u32) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn create_program(&self, _vert: u32, _frag: u32) -> u32 {
+        self.id()
+    }
+
+    fn program_status(&self, _program: u32) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn use_program(&self, _program: u32) {}
+
+    fn uniform_location(&self, _program: u32, _name: &str) -> i32 {
+        -1
+    }
+
+    fn active_uniforms(&self, _program: u32) -> Vec<ActiveVariable> {
+        Vec::new()
+    }
+
+    fn active_attributes(&self, _program: u32) -> Vec<ActiveVariable> {
+        Vec::new()
+    }
+
+    fn uniform_floats(&self, _location: i32, _values: &[f32]) {}
+
+    fn uniform_ints(&self, _location: i32, _values: &[i32]) {}
+
+    fn uniform_matrix(&self, _location: i32, _values: &[f32]) {}
+
+    fn delete_shader(&self, _shader: u32) {
+        self.delete();
+    }
+
+    fn delete_program(&self, _program: u32) {
+        self.delete();
+    }
+
+    fn create_framebuffer(&self, _texture: u32) -> Result<u32, u32> {
+        Ok(self.id())
+    }
+
+    fn bind_framebuffer(&self, framebuffer: u32) {
+        self.framebuffer.set(framebuffer);
+    }
+
+    fn framebuffer(&self) -> u32 {
+        self.framebuffer.get()
+    }
+
+    fn read_pixels(&self, width: u32, _height: u32, data: &mut [u8]) {
+        NullDevice::fill_rows(data, width as usize * 4);
+    }
+
+    fn delete_framebuffer(&self, framebuffer: u32) {
+        if self.framebuffer.get() == framebuffer {
+            self.framebuffer.set(0);
+        }
+        self.delete();
+    }
+
+    fn init(&self, width: u32, height: u32) {
+        self.viewport.set([0, 0, width as i32, height as i32]);
+    }
+
+    fn viewport(&self) -> [i32; 4] {
+        self.viewport.get()
+    }
+
+    fn set_viewport(&self, viewport: [i32; 4]) {
+        self.viewport.set(viewport);
+    }
+
+    fn front_face(&self, clockwise: bool) {
+        self.clockwise.set(clockwise);
+    }
+
+    fn is_clockwise(&self) -> bool {
+        self.clockwise.get()
+    }
+
+    fn blend(&self, _mode: &BlendMode) {}
+
+    fn disable_blend(&self) {}
+
+    fn scissor(&self, _rect: Option<[i32; 4]>) {}
+
+    fn stencil(&self, _test: Option<StencilTest>) {}
+
+    fn depth(&self, _test: Option<DepthTest>) {}
+
+    fn clear_stencil(&self, _bits: u8) {}
+
+    fn clear(&self, _color: Color) {}
+
+    fn draw_arrays(&self, _vertex_array: u32, _primitive: Primitive, _first: usize, _count: usize) {
+        self.draw_calls.set(self.draw_calls.get() + 1);
+    }
+
+    fn draw_arrays_instanced(
+        &self,
+        _vertex_array: u32,
+        _primitive: Primitive,
+        _first: usize,
+        _count: usize,
+        _instances: usize,
+    ) {
+        self.draw_calls.set(self.draw_calls.get() + 1);
+    }
+
+    fn flush(&self) {}
+}
+
+#[cfg(test)]
+mod test {
+    use super::{current, scoped, NullDevice};
+    use std::rc::Rc;
+    use vertex::VertexArray;
+    use vertex_buffer::{Primitive, VertexBuffer};
+
+    #[test]
+    fn null_device_counts_draw_calls() {
+        let null = Rc::new(NullDevice::new());
+        let _device = scoped(null.clone());
+
+        let buffer = VertexBuffer::new(Primitive::Triangles, VertexArray::new());
+        current().draw_arrays(buffer.array().id(), Primitive::Triangles, 0, 3);
+
+        assert_eq!(null.draw_calls(), 1);
+    }
+}
@@ -1,11 +1,19 @@
 //! Every traits needed by drawable object
 //!
 
-use gl;
+use batch::Batch;
+use command;
+use command::CommandList;
+use device;
+use device::StencilTest;
 use nalgebra::Matrix4;
-use nalgebra::Vector2;
-use shader::Shader;
-use shader::DEFAULT_SHADER;
+use nalgebra::{Vector2, Vector4};
+use rect::Rect;
+use shader::{Shader, Uniforms};
+use shader::{DEFAULT_SHADER, MASK_SHADER};
+use std::cell::RefCell;
+use std::error::Error;
+use std::fmt;
 use texture::Texture;
 
 //----------------------------------------------------------------------------
@@ -17,50 +25,141 @@ use texture::Texture;
 //----------------------------------------------------------------------------
 
 lazy_static! {
-    static ref DEFAULT_CONTEXT: Context<'static> = Context::default();
+    pub static ref IDENTITY: Matrix4<f32> = Matrix4::identity();
 }
 
-lazy_static! {
-    pub static ref IDENTITY: Matrix4<f32> = Matrix4::identity();
+thread_local! {
+    /// Clip of the context drawing a stencil mask. The mask is drawn by its own context
+    /// (texture, transform) that doesn't know about the clip of the pushing context.
+    static MASK: RefCell<Option<Context<'static>>> = RefCell::new(None);
 }
 
-#[derive(Debug)]
+/// True while a stencil mask is drawn, the targets musn't batch it.
+pub(crate) fn drawing_mask() -> bool {
+    MASK.with(|mask| mask.borrow().is_some())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 /// Blend mode needed to draw
+/// ```no_run
+/// use gust::draw::{BlendEquation, BlendFactor, BlendFunc, BlendMode};
+/// use gust::prelude::*;
+///
+/// let mut sprite = Sprite::new();
+/// sprite.set_blend_mode(BlendMode::Add);
+/// // Keep the darkest of the two colors
+/// sprite.set_blend_mode(BlendMode::Custom(BlendFunc {
+///     color_equation: BlendEquation::Min,
+///     ..BlendFunc::from(BlendMode::Alpha)
+/// }));
+/// ```
 pub enum BlendMode {
+    /// No blending, the source replace the destination.
+    None,
+    /// Classic transparency.
     Alpha,
-    Beta,
-    Ceta,
+    /// Transparency for colors already multiplied by their alpha.
+    PremultipliedAlpha,
+    /// The source is added to the destination, useful for lights and particles.
+    Add,
+    /// The destination is multiplied by the source.
+    Multiply,
+    /// Inverse of multiply, lighten the destination.
+    Screen,
+    /// Your own factors and equations.
+    Custom(BlendFunc),
 }
 
 impl BlendMode {
-    fn blend_to_gl(&self) {
-        match self {
-            BlendMode::Alpha => unsafe { gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA) },
-            _ => {}
-        }
-    }
-
     pub fn unactive(&self) {
-        unsafe {
-            gl::Disable(gl::BLEND);
-        }
+        device::current().disable_blend();
     }
 
     pub fn active(&self) {
-        unsafe {
-            gl::Enable(gl::BLEND);
-            match self {
-                BlendMode::Alpha => {
-                    gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
-                }
-                BlendMode::Beta => {
-                    gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
-                }
-                BlendMode::Ceta => {
-                    gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
-                }
-            }
-        }
+        device::current().blend(self);
+    }
+
+    /// Factors and equations of the mode, None if the blending is disabled.
+    pub fn func(&self) -> Option<BlendFunc> {
+        use self::BlendFactor::*;
+
+        let (color_src, color_dst, alpha_src, alpha_dst) = match self {
+            BlendMode::None => return None,
+            BlendMode::Custom(func) => return Some(*func),
+            BlendMode::Alpha => (SrcAlpha, OneMinusSrcAlpha, One, OneMinusSrcAlpha),
+            BlendMode::PremultipliedAlpha => (One, OneMinusSrcAlpha, One, OneMinusSrcAlpha),
+            BlendMode::Add => (SrcAlpha, One, One, One),
+            BlendMode::Multiply => (DstColor, Zero, DstAlpha, Zero),
+            BlendMode::Screen => (One, OneMinusSrcColor, One, OneMinusSrcAlpha),
+        };
+
+        Some(BlendFunc {
+            color_src,
+            color_dst,
+            alpha_src,
+            alpha_dst,
+            color_equation: BlendEquation::Add,
+            alpha_equation: BlendEquation::Add,
+        })
+    }
+}
+
+impl Default for BlendMode {
+    fn default() -> BlendMode {
+        BlendMode::Alpha
+    }
+}
+
+/// Factor applied to the source or the destination while blending.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BlendFactor {
+    Zero,
+    One,
+    SrcColor,
+    OneMinusSrcColor,
+    DstColor,
+    OneMinusDstColor,
+    SrcAlpha,
+    OneMinusSrcAlpha,
+    DstAlpha,
+    OneMinusDstAlpha,
+}
+
+/// Operation between the source and the destination once the factors are applied.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BlendEquation {
+    /// src + dst
+    Add,
+    /// src - dst
+    Subtract,
+    /// dst - src
+    ReverseSubtract,
+    Min,
+    Max,
+}
+
+/// Separate blending of the color and the alpha channels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BlendFunc {
+    pub color_src: BlendFactor,
+    pub color_dst: BlendFactor,
+    pub alpha_src: BlendFactor,
+    pub alpha_dst: BlendFactor,
+    pub color_equation: BlendEquation,
+    pub alpha_equation: BlendEquation,
+}
+
+/// Function of a mode, BlendMode::None give a function keeping only the source.
+impl From<BlendMode> for BlendFunc {
+    fn from(mode: BlendMode) -> BlendFunc {
+        mode.func().unwrap_or(BlendFunc {
+            color_src: BlendFactor::One,
+            color_dst: BlendFactor::Zero,
+            alpha_src: BlendFactor::One,
+            alpha_dst: BlendFactor::Zero,
+            color_equation: BlendEquation::Add,
+            alpha_equation: BlendEquation::Add,
+        })
     }
 }
 
@@ -78,9 +177,18 @@ impl BlendMode {
 /// process a default context can be use ether
 pub struct Context<'a> {
     texture: Option<&'a Texture>,
-    shader: &'a Shader,
+    /// The default shader of the current gl context when None.
+    shader: Option<&'a Shader>,
+    uniforms: Option<&'a Uniforms>,
     transform: Vec<(String, &'a Matrix4<f32>)>,
     blend_mode: BlendMode,
+    scissors: Vec<Rect<f32>>,
+    /// Projection of the target, to place the scissors.
+    projection: Option<Matrix4<f32>>,
+    masks: u8,
+    writing_mask: bool,
+    /// Texels of the masks less opaque than it are discarded.
+    mask_threshold: f32,
 }
 
 impl<'a> Context<'a> {
@@ -93,9 +201,15 @@ impl<'a> Context<'a> {
     ) -> Context<'a> {
         Context {
             texture,
-            shader,
+            shader: Some(shader),
+            uniforms: None,
             transform,
             blend_mode,
+            scissors: Vec::new(),
+            projection: None,
+            masks: 0,
+            writing_mask: false,
+            mask_threshold: 0.5,
         }
     }
 
@@ -111,12 +225,219 @@ impl<'a> Context<'a> {
         self.blend_mode.active();
     }
 
-    /// Apply final shader (transformation)
+    /// Shader drawn with, None for the default shader of the current gl context.
+    pub fn shader(&self) -> Option<&'a Shader> {
+        self.shader
+    }
+
+    /// Uniforms given to the shader before the transformations.
+    pub fn set_uniforms(&mut self, uniforms: &'a Uniforms) {
+        self.uniforms = Some(uniforms);
+    }
+
+    /// Apply final shader (uniforms then transformation)
+    /// Textured stencil masks are drawn with the mask shader instead.
     pub fn setup_shader(&self) {
-        self.shader.activate();
+        let threshold = MASK.with(|mask| mask.borrow().as_ref().map(|mask| mask.mask_threshold));
+        let builtin;
+        let shader = match threshold {
+            Some(threshold) if self.texture.is_some() => {
+                builtin = MASK_SHADER.get();
+                builtin.activate();
+                builtin.uniform_f("threshold", threshold);
+                &*builtin
+            }
+            _ => {
+                let shader = match self.shader {
+                    Some(shader) => shader,
+                    None => {
+                        builtin = DEFAULT_SHADER.get();
+                        &*builtin
+                    }
+                };
+                shader.activate();
+                if let Some(uniforms) = self.uniforms {
+                    uniforms.apply(shader);
+                }
+                shader
+            }
+        };
         for (name, mat) in &self.transform {
-            self.shader.uniform_mat4f(name.as_str(), mat);
+            shader.uniform_mat4f(name.as_str(), mat);
+        }
+    }
+
+    /// Projection of the target drawn on, set by the targets when drawing with the context.
+    pub fn set_projection(&mut self, projection: &Matrix4<f32>) {
+        self.projection = Some(*projection);
+    }
+
+    /// Alpha under which the texels of the stencil masks are discarded, 0.5 by default.
+    pub fn set_mask_threshold(&mut self, threshold: f32) {
+        self.mask_threshold = threshold;
+    }
+
+    /// Clip the next draws to a rectangle in view coordinates.
+    /// Nested scissors are intersected with the previous ones.
+    pub fn push_scissor(&mut self, rect: Rect<f32>) {
+        self.scissors.push(rect);
+    }
+
+    /// Remove the last scissor, return it if there was one.
+    pub fn pop_scissor(&mut self) -> Option<Rect<f32>> {
+        self.scissors.pop()
+    }
+
+    /// Draw the mask on the target inside the stencil buffer, the next draws are only visible
+    /// where the mask has been drawn. Masks keep their own texture and transform, only the
+    /// clip of this context is used, and nest up to 8 levels: past them nothing is drawn and
+    /// `StencilError::TooManyMasks` is returned.
+    /// ```no_run
+    /// use gust::prelude::*;
+    /// use std::rc::Rc;
+    ///
+    /// let mut window = Window::new(800, 600, "Minimap");
+    /// let circle = Rc::new(Texture::from_path("circle.png").unwrap());
+    /// let map = Rc::new(Texture::from_path("map.png").unwrap());
+    /// let mut context = Context::default();
+    ///
+    /// context.push_stencil(&mut window, &Sprite::from(&circle)).unwrap();
+    /// window.draw_with_context(&mut Sprite::from(&map), &mut context);
+    /// context.pop_stencil();
+    /// ```
+    pub fn push_stencil<D: Drawer, T: Drawable>(
+        &mut self,
+        target: &mut D,
+        mask: &T,
+    ) -> Result<(), StencilError> {
+        if self.masks >= 8 {
+            return Err(StencilError::TooManyMasks);
         }
+
+        // What the target held back musn't be drawn as a mask
+        target.flush();
+        self.set_projection(target.projection());
+        let clip = Context {
+            scissors: self.scissors.clone(),
+            projection: self.projection,
+            masks: self.masks + 1,
+            writing_mask: true,
+            mask_threshold: self.mask_threshold,
+            ..Context::default()
+        };
+        MASK.with(|mask| *mask.borrow_mut() = Some(clip));
+        target.draw(mask);
+        MASK.with(|mask| *mask.borrow_mut() = None);
+        self.masks += 1;
+        Ok(())
+    }
+
+    /// Remove the last mask from the stencil buffer.
+    pub fn pop_stencil(&mut self) {
+        if self.masks == 0 {
+            return;
+        }
+
+        device::current().clear_stencil(1 << (self.masks - 1));
+        self.masks -= 1;
+        self.apply_clip();
+    }
+
+    /// Number of nested stencil masks.
+    pub fn masks(&self) -> u8 {
+        self.masks
+    }
+
+    /// Apply the scissors and the stencil masks, the ones of the pushing context
+    /// while a mask is drawn.
+    pub fn apply_clip(&self) {
+        MASK.with(|mask| match *mask.borrow() {
+            Some(ref clip) => clip.clip(),
+            None => self.clip(),
+        });
+    }
+
+    fn clip(&self) {
+        let device = device::current();
+
+        device.scissor(self.scissor());
+        if self.masks == 0 {
+            device.stencil(None);
+            return;
+        }
+
+        // Each mask own a bit, drawing is allowed where every bit is set
+        let levels = ((1u16 << self.masks) - 1) as u8;
+        let last = 1 << (self.masks - 1);
+        device.stencil(Some(if self.writing_mask {
+            StencilTest {
+                reference: levels,
+                mask: levels & !last,
+                write_mask: last,
+            }
+        } else {
+            StencilTest {
+                reference: levels,
+                mask: levels,
+                write_mask: 0,
+            }
+        }));
+    }
+
+    /// Intersection of the scissors in pixels of the current viewport.
+    /// The rects are placed with the projection of the target, or of the context, or in
+    /// pixels from the top left of the viewport if none is known.
+    fn scissor(&self) -> Option<[i32; 4]> {
+        if self.scissors.is_empty() {
+            return None;
+        }
+
+        let viewport = device::current().viewport();
+        let projection = self.projection.unwrap_or_else(|| {
+            self.transform
+                .iter()
+                .find(|(name, _)| name == "projection")
+                .map_or_else(
+                    || {
+                        Matrix4::new_orthographic(
+                            0.0,
+                            viewport[2] as f32,
+                            viewport[3] as f32,
+                            0.0,
+                            -1.0,
+                            1.0,
+                        )
+                    },
+                    |(_, projection)| **projection,
+                )
+        });
+        let (mut left, mut bottom, mut right, mut top) = (
+            i32::MIN,
+            i32::MIN,
+            i32::MAX,
+            i32::MAX,
+        );
+
+        for rect in &self.scissors {
+            let corners = [
+                projection * Vector4::new(rect.left, rect.top, 0.0, 1.0),
+                projection * Vector4::new(rect.left + rect.width, rect.top + rect.height, 0.0, 1.0),
+            ];
+            let x: Vec<i32> = corners
+                .iter()
+                .map(|c| viewport[0] + ((c.x + 1.0) / 2.0 * viewport[2] as f32).round() as i32)
+                .collect();
+            let y: Vec<i32> = corners
+                .iter()
+                .map(|c| viewport[1] + ((c.y + 1.0) / 2.0 * viewport[3] as f32).round() as i32)
+                .collect();
+
+            left = left.max(x[0].min(x[1]));
+            right = right.min(x[0].max(x[1]));
+            bottom = bottom.max(y[0].min(y[1]));
+            top = top.min(y[0].max(y[1]));
+        }
+        Some([left, bottom, (right - left).max(0), (top - bottom).max(0)])
     }
 }
 
@@ -125,9 +446,15 @@ impl<'a> Default for Context<'a> {
     fn default() -> Context<'a> {
         Context {
             texture: None,
-            shader: &*DEFAULT_SHADER,
+            shader: None,
+            uniforms: None,
             transform: vec![("transform".to_string(), &*IDENTITY)],
             blend_mode: BlendMode::Alpha,
+            scissors: Vec::new(),
+            projection: None,
+            masks: 0,
+            writing_mask: false,
+            mask_threshold: 0.5,
         }
     }
 }
@@ -157,6 +484,7 @@ pub trait Drawer {
 
     /// Draw with context a Drawable.
     fn draw_with_context<T: Drawable>(&mut self, drawable: &mut T, context: &mut Context) {
+        context.set_projection(self.projection());
         drawable.draw_with_context(context);
     }
 
@@ -165,6 +493,18 @@ pub trait Drawer {
     fn get_sizes(&self) -> Vector2<f32>;
 
     fn projection(&self) -> &Matrix4<f32>;
+
+    /// Draw everything the target is holding back (like a batch).
+    fn flush(&mut self) {}
+
+    /// Record the draws made on the target instead of executing them.
+    fn record<F>(&mut self, draw: F) -> CommandList
+    where
+        F: FnOnce(&mut Self),
+        Self: Sized,
+    {
+        command::record(|| draw(self))
+    }
 }
 
 /// Trait that can be use to draw on window
@@ -179,10 +519,22 @@ pub trait Drawable {
     /// Should be call often so be carefull when implementing.
     fn update(&mut self);
 
+    /// Layer of the drawable, the higher are drawn on top by a DrawList.
+    fn layer(&self) -> f32 {
+        0.0
+    }
+
+    /// Push the drawable inside the batch of the target instead of drawing it.
+    /// Return false if the drawable can't be batched, which is the default.
+    fn batch(&self, _batch: &mut Batch, _projection: &Matrix4<f32>) -> bool {
+        false
+    }
+
     /// Setup the draw for the final system you don't have to implement it in a normal drawable
     fn setup_draw(&self, context: &mut Context) {
         context.apply_texture(0);
         context.apply_blendmode();
+        context.apply_clip();
         context.setup_shader();
     }
 }
@@ -198,3 +550,121 @@ pub trait DrawableMut: Drawable {
         self.draw_with_context(context);
     }
 }
+
+#[derive(Debug)]
+/// All error trigerable by the stencil masks of a Context
+pub enum StencilError {
+    /// The 8 bits of the stencil buffer are already used by nested masks
+    TooManyMasks,
+}
+
+impl fmt::Display for StencilError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StencilError::TooManyMasks => write!(f, "Only 8 stencil masks can be nested."),
+        }
+    }
+}
+
+impl Error for StencilError {
+    fn cause(&self) -> Option<&Error> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Context, Drawer};
+    use command::{record, Command};
+    use device::{self, NullDevice, StencilTest};
+    use rect::Rect;
+    use render_texture::RenderTexture;
+    use shader::{DEFAULT_SHADER, MASK_SHADER};
+    use sprite::Sprite;
+    use std::rc::Rc;
+    use texture::Texture;
+    use Vector;
+
+    #[test]
+    fn nested_scissors_are_intersected() {
+        let _device = device::scoped(Rc::new(NullDevice::new()));
+        let mut target = RenderTexture::new(100, 100).unwrap();
+        let texture = Rc::new(Texture::from_size(Vector::new(4, 4)));
+        let mut sprite = Sprite::from(&texture);
+        let mut context = Context::default();
+
+        context.push_scissor(Rect::new(10.0, 10.0, 50.0, 50.0));
+        context.push_scissor(Rect::new(30.0, 0.0, 50.0, 40.0));
+        let list = record(|| target.draw_with_context(&mut sprite, &mut context));
+        // The projection of a render texture is flipped, the rows start from the top
+        assert_eq!(list.draws().next().unwrap().scissor, Some([30, 10, 30, 30]));
+
+        context.pop_scissor();
+        let list = record(|| target.draw_with_context(&mut sprite, &mut context));
+        assert_eq!(list.draws().next().unwrap().scissor, Some([10, 10, 50, 50]));
+    }
+
+    #[test]
+    fn stencil_masks_own_a_bit() {
+        let _device = device::scoped(Rc::new(NullDevice::new()));
+        let mut target = RenderTexture::new(16, 16).unwrap();
+        let circle = Rc::new(Texture::from_size(Vector::new(4, 4)));
+        let map = Rc::new(Texture::from_size(Vector::new(8, 8)));
+        let mut content = Sprite::from(&map);
+        let mut context = Context::default();
+
+        let list = record(|| {
+            context
+                .push_stencil(&mut target, &Sprite::from(&circle))
+                .unwrap();
+            context
+                .push_stencil(&mut target, &Sprite::from(&circle))
+                .unwrap();
+            target.draw_with_context(&mut content, &mut context);
+            context.pop_stencil();
+            target.draw_with_context(&mut content, &mut context);
+        });
+        let draws: Vec<_> = list.draws().collect();
+        let test = |reference, mask, write_mask| {
+            Some(StencilTest {
+                reference,
+                mask,
+                write_mask,
+            })
+        };
+
+        // The masks keep their texture and go through the mask shader
+        assert_eq!(draws[0].texture(), Some(circle.id()));
+        assert_eq!(draws[0].program, MASK_SHADER.get().id());
+        assert_eq!(draws[2].program, DEFAULT_SHADER.get().id());
+        assert_eq!(
+            draws.iter().map(|draw| draw.stencil).collect::<Vec<_>>(),
+            vec![test(1, 0, 1), test(3, 1, 2), test(3, 3, 0), test(1, 1, 0)]
+        );
+        assert!(list.commands().iter().any(|command| match command {
+            Command::ClearStencil { bits, .. } => *bits == 2,
+            _ => false,
+        }));
+    }
+
+    #[test]
+    fn ninth_mask_is_refused() {
+        let _device = device::scoped(Rc::new(NullDevice::new()));
+        let mut target = RenderTexture::new(16, 16).unwrap();
+        let circle = Rc::new(Texture::from_size(Vector::new(4, 4)));
+        let mut context = Context::default();
+
+        let list = record(|| {
+            for _ in 0..8 {
+                context
+                    .push_stencil(&mut target, &Sprite::from(&circle))
+                    .unwrap();
+            }
+            assert!(context
+                .push_stencil(&mut target, &Sprite::from(&circle))
+                .is_err());
+        });
+        assert_eq!(context.masks(), 8);
+        assert_eq!(list.draws().count(), 8);
+    }
+}
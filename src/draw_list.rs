@@ -0,0 +1,282 @@
+//
+//  Rust file | 2018
+//  Author: Alexandre Fourcat
+//  draw_list.rs
+//  module:
+//! DrawList collect drawables during a frame and submit them sorted by layer.
+//! The sort is stable so drawables on the same layer keep the order they were pushed in.
+//! ```no_run
+//! use gust::draw_list::DrawList;
+//! use gust::prelude::*;
+//! use std::rc::Rc;
+//!
+//! let mut window = Window::new(800, 600, "Isometric");
+//! let texture = Rc::new(Texture::from_path("tile.png").unwrap());
+//! let tiles: Vec<Sprite> = (0..10).map(|_| Sprite::from(&texture)).collect();
+//! let mut list = DrawList::new();
+//!
+//! // Y-sorting: the lower a tile is on screen, the later it's drawn
+//! for tile in &tiles {
+//!     list.push_at(tile.get_position().y, tile);
+//! }
+//! window.clear();
+//! list.submit(&mut window);
+//! window.display();
+//! ```
+
+use device;
+use device::DepthTest;
+use draw::{Drawable, Drawer};
+use std::cmp::Ordering;
+
+struct Item<'a, D> {
+    layer: f32,
+    opaque: bool,
+    draw: Box<Fn(&mut D) + 'a>,
+}
+
+/// Collector of drawables sorted by layer before being drawn on a D.
+pub struct DrawList<'a, D: Drawer> {
+    items: Vec<Item<'a, D>>,
+    depth: bool,
+}
+
+impl<'a, D: Drawer> DrawList<'a, D> {
+    pub fn new() -> DrawList<'a, D> {
+        DrawList {
+            items: Vec::new(),
+            depth: false,
+        }
+    }
+
+    /// Push a drawable on its own layer.
+    pub fn push<T: Drawable>(&mut self, drawable: &'a T) {
+        self.push_at(drawable.layer(), drawable);
+    }
+
+    /// Push a drawable on the given layer, whatever its own layer is.
+    pub fn push_at<T: Drawable>(&mut self, layer: f32, drawable: &'a T) {
+        self.items.push(Item {
+            layer,
+            opaque: false,
+            draw: Box::new(move |target: &mut D| target.draw(drawable)),
+        });
+    }
+
+    /// Push a drawable without any transparency.
+    /// With the depth buffer enabled, opaque drawables are drawn front to back first
+    /// and hide what's behind them without any sorting cost on the gpu.
+    pub fn push_opaque<T: Drawable>(&mut self, drawable: &'a T) {
+        self.push_at(drawable.layer(), drawable);
+        if let Some(item) = self.items.last_mut() {
+            item.opaque = true;
+        }
+    }
+
+    /// Use the depth buffer for the opaque drawables, disabled by default.
+    pub fn set_depth(&mut self, depth: bool) {
+        self.depth = depth;
+    }
+
+    pub fn depth(&self) -> bool {
+        self.depth
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.items.clear();
+    }
+
+    /// Draw everything on the target from the lowest layer to the highest and empty the list.
+    pub fn submit(&mut self, target: &mut D) {
+        // Vec::sort_by is stable
+        self.items
+            .sort_by(|a, b| a.layer.partial_cmp(&b.layer).unwrap_or(Ordering::Equal));
+
+        if !self.depth || !self.items.iter().any(|item| item.opaque) {
+            for item in self.items.drain(..) {
+                (item.draw)(target);
+            }
+            return;
+        }
+
+        let device = device::current();
+        let (min, max) = (self.items[0].layer, self.items[self.items.len() - 1].layer);
+        let depth = |layer: f32| {
+            if max > min {
+                (max - layer) / (max - min)
+            } else {
+                0.0
+            }
+        };
+
+        // Front to back, a layer keeps its order so its last item stays on top
+        let mut opaque: Vec<_> = self.items.iter().filter(|item| item.opaque).collect();
+        opaque.sort_by(|a, b| b.layer.partial_cmp(&a.layer).unwrap_or(Ordering::Equal));
+        for item in opaque {
+            device.depth(Some(DepthTest {
+                depth: depth(item.layer),
+                write: true,
+            }));
+            (item.draw)(target);
+            target.flush();
+        }
+        for item in self.items.iter().filter(|item| !item.opaque) {
+            device.depth(Some(DepthTest {
+                depth: depth(item.layer),
+                write: false,
+            }));
+            (item.draw)(target);
+            target.flush();
+        }
+        device.depth(None);
+        self.items.clear();
+    }
+}
+
+impl<'a, D: Drawer> Default for DrawList<'a, D> {
+    fn default() -> DrawList<'a, D> {
+        DrawList::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::DrawList;
+    use command::record;
+    use device::{self, DepthTest, NullDevice};
+    use draw::{Context, Drawable, Drawer, IDENTITY};
+    use nalgebra::Matrix4;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use vertex_buffer::Primitive;
+    use Vector;
+
+    struct Target;
+
+    impl Drawer for Target {
+        fn draw<T: Drawable>(&mut self, drawable: &T) {
+            drawable.draw(self);
+        }
+
+        fn get_center(&self) -> Vector<f32> {
+            Vector::new(0.0, 0.0)
+        }
+
+        fn get_sizes(&self) -> Vector<f32> {
+            Vector::new(0.0, 0.0)
+        }
+
+        fn projection(&self) -> &Matrix4<f32> {
+            &*IDENTITY
+        }
+    }
+
+    struct Named<'a>(&'static str, f32, &'a RefCell<Vec<&'static str>>);
+
+    impl<'a> Drawable for Named<'a> {
+        fn draw<T: Drawer>(&self, _target: &mut T) {
+            self.2.borrow_mut().push(self.0);
+        }
+
+        fn draw_with_context(&self, _context: &mut Context) {}
+
+        fn update(&mut self) {}
+
+        fn layer(&self) -> f32 {
+            self.1
+        }
+    }
+
+    /// Draw call on its vertex array id.
+    struct Quad(u32, f32);
+
+    impl Drawable for Quad {
+        fn draw<T: Drawer>(&self, _target: &mut T) {
+            device::current().draw_arrays(self.0, Primitive::Triangles, 0, 6);
+        }
+
+        fn draw_with_context(&self, _context: &mut Context) {}
+
+        fn update(&mut self) {}
+
+        fn layer(&self) -> f32 {
+            self.1
+        }
+    }
+
+    #[test]
+    fn submit_sort_by_layer_and_keep_order() {
+        let drawn = RefCell::new(Vec::new());
+        let items = [
+            Named("b", 1.0, &drawn),
+            Named("c", 1.0, &drawn),
+            Named("a", 0.0, &drawn),
+            Named("d", 2.0, &drawn),
+        ];
+        let mut list = DrawList::new();
+
+        for item in &items {
+            list.push(item);
+        }
+        list.push_at(-1.0, &items[3]);
+        list.submit(&mut Target);
+
+        assert_eq!(*drawn.borrow(), vec!["d", "a", "b", "c", "d"]);
+        assert!(list.is_empty());
+    }
+
+    #[test]
+    fn opaque_items_are_drawn_front_to_back_first() {
+        let _device = device::scoped(Rc::new(NullDevice::new()));
+        let (back, front, glass, smoke) = (Quad(1, 0.0), Quad(2, 2.0), Quad(3, 1.0), Quad(4, 2.0));
+        let mut list = DrawList::new();
+
+        list.set_depth(true);
+        list.push(&glass);
+        list.push_opaque(&back);
+        list.push(&smoke);
+        list.push_opaque(&front);
+        let commands = record(|| list.submit(&mut Target));
+        let draws: Vec<_> = commands
+            .draws()
+            .map(|draw| (draw.vertex_array, draw.depth))
+            .collect();
+
+        // The depth goes from 0 on the highest layer to 1 on the lowest
+        let test = |depth, write| Some(DepthTest { depth, write });
+        assert_eq!(
+            draws,
+            vec![
+                (2, test(0.0, true)),
+                (1, test(1.0, true)),
+                (3, test(0.5, false)),
+                (4, test(0.0, false)),
+            ]
+        );
+    }
+
+    #[test]
+    fn opaque_items_of_a_layer_keep_their_order() {
+        let _device = device::scoped(Rc::new(NullDevice::new()));
+        let (floor, first, second) = (Quad(1, 0.0), Quad(2, 1.0), Quad(3, 1.0));
+        let mut list = DrawList::new();
+
+        list.set_depth(true);
+        list.push_opaque(&first);
+        list.push_opaque(&floor);
+        list.push_opaque(&second);
+        let commands = record(|| list.submit(&mut Target));
+        let draws: Vec<_> = commands.draws().map(|draw| draw.vertex_array).collect();
+
+        // Same depth, the last drawn passes the test: the last pushed is on top
+        assert_eq!(draws, vec![2, 3, 1]);
+    }
+}
@@ -4,9 +4,35 @@
 //  gl_error.rs
 //  module:
 //! gl error system
+//! In debug builds every gl call made by gust is checked, the failures are kept
+//! as GlError naming the gust operation until `take_errors` is called.
+//! The driver messages can also be received with the debug output.
+//! ```no_run
+//! use gust::gl_error;
+//! use gust::prelude::*;
+//!
+//! Window::request_debug_context(true);
+//! let mut window = Window::new(800, 600, "Debug");
+//! window.enable_debug(|message| println!("{}", message));
+//!
+//! window.clear();
+//! window.display();
+//! for error in gl_error::take_errors() {
+//!     eprintln!("{}", error);
+//! }
+//! ```
 
 use gl;
+use gl::types::*;
+use std::cell::RefCell;
 use std::error::Error;
+use std::ffi::CStr;
+use std::fmt;
+use std::os::raw::c_void;
+use std::ptr;
+
+/// Number of checked errors kept before the oldest ones are dropped.
+const MAX_ERRORS: usize = 256;
 
 #[derive(Debug)]
 pub enum GlError {
@@ -16,32 +42,46 @@ pub enum GlError {
     InvalidOperation,
     InvalidFramebufferOperation,
     OutOfMemory,
+    /// Error raised by the gust operation named.
+    Operation(&'static str, Box<GlError>),
 }
 
 impl GlError {
     pub fn new() -> Result<(), GlError> {
-        unsafe {
-            match gl::GetError() {
-                gl::NO_ERROR => Ok(()),
-                gl::INVALID_ENUM => Err(GlError::InvalidEnum),
-                gl::INVALID_VALUE => Err(GlError::InvalidValue),
-                gl::INVALID_OPERATION => Err(GlError::InvalidOperation),
-                gl::INVALID_FRAMEBUFFER_OPERATION => Err(GlError::InvalidFramebufferOperation),
-                gl::OUT_OF_MEMORY => Err(GlError::OutOfMemory),
-                _ => Ok(()),
-            }
+        GlError::from_gl(get_error())
+    }
+
+    /// Same as `new` but the error name the operation that raised it.
+    pub fn check(operation: &'static str) -> Result<(), GlError> {
+        GlError::new().map_err(|error| GlError::Operation(operation, Box::new(error)))
+    }
+
+    fn from_gl(error: GLenum) -> Result<(), GlError> {
+        match error {
+            gl::NO_ERROR => Ok(()),
+            gl::INVALID_ENUM => Err(GlError::InvalidEnum),
+            gl::INVALID_VALUE => Err(GlError::InvalidValue),
+            gl::INVALID_OPERATION => Err(GlError::InvalidOperation),
+            gl::INVALID_FRAMEBUFFER_OPERATION => Err(GlError::InvalidFramebufferOperation),
+            gl::OUT_OF_MEMORY => Err(GlError::OutOfMemory),
+            _ => Ok(()),
         }
     }
 }
 
+fn get_error() -> GLenum {
+    unsafe { gl::GetError() }
+}
+
 impl Error for GlError {
     fn cause(&self) -> Option<&Error> {
-        None
+        match self {
+            GlError::Operation(_, error) => Some(&**error),
+            _ => None,
+        }
     }
 }
 
-use std::fmt;
-
 impl fmt::Display for GlError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
@@ -51,6 +91,328 @@ impl fmt::Display for GlError {
             GlError::InvalidOperation => write!(f, "Bad operation"),
             GlError::InvalidFramebufferOperation => write!(f, "Bad framebuffer operation"),
             GlError::OutOfMemory => write!(f, "No more gl memory"),
+            GlError::Operation(operation, error) => write!(f, "{}: {}", operation, error),
+        }
+    }
+}
+
+thread_local! {
+    static ERRORS: RefCell<Vec<GlError>> = RefCell::new(Vec::new());
+    static DEBUG_CALLBACK: RefCell<Option<Box<Fn(&DebugMessage)>>> = RefCell::new(None);
+}
+
+/// Check the gl errors after a gust operation, only in debug builds.
+/// The failures are kept until `take_errors` is called.
+pub fn check(operation: &'static str) {
+    check_with(operation, get_error);
+}
+
+/// Same as `check` with the errors read from get_error instead of the gl context.
+fn check_with(operation: &'static str, get_error: fn() -> GLenum) {
+    if !cfg!(debug_assertions) {
+        return;
+    }
+    // gl::GetError return one error at a time
+    while let Err(error) = GlError::from_gl(get_error()) {
+        report(GlError::Operation(operation, Box::new(error)));
+    }
+}
+
+fn report(error: GlError) {
+    ERRORS.with(|errors| {
+        let mut errors = errors.borrow_mut();
+        if errors.len() == MAX_ERRORS {
+            errors.remove(0);
+        }
+        errors.push(error);
+    });
+}
+
+/// Return the errors found by the checked gl calls of this thread since the last call.
+pub fn take_errors() -> Vec<GlError> {
+    ERRORS.with(|errors| errors.replace(Vec::new()))
+}
+
+//----------------------------------------------------------------------------
+//
+//
+//                             DEBUGMESSAGE : STRUCT
+//
+//
+//----------------------------------------------------------------------------
+
+/// Part of the system that sent a debug message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DebugSource {
+    Api,
+    WindowSystem,
+    ShaderCompiler,
+    ThirdParty,
+    Application,
+    Other,
+}
+
+impl DebugSource {
+    fn from_gl(source: GLenum) -> DebugSource {
+        match source {
+            gl::DEBUG_SOURCE_API => DebugSource::Api,
+            gl::DEBUG_SOURCE_WINDOW_SYSTEM => DebugSource::WindowSystem,
+            gl::DEBUG_SOURCE_SHADER_COMPILER => DebugSource::ShaderCompiler,
+            gl::DEBUG_SOURCE_THIRD_PARTY => DebugSource::ThirdParty,
+            gl::DEBUG_SOURCE_APPLICATION => DebugSource::Application,
+            _ => DebugSource::Other,
+        }
+    }
+}
+
+/// Kind of a debug message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DebugType {
+    Error,
+    DeprecatedBehavior,
+    UndefinedBehavior,
+    Portability,
+    Performance,
+    Marker,
+    Other,
+}
+
+impl DebugType {
+    fn from_gl(kind: GLenum) -> DebugType {
+        match kind {
+            gl::DEBUG_TYPE_ERROR => DebugType::Error,
+            gl::DEBUG_TYPE_DEPRECATED_BEHAVIOR => DebugType::DeprecatedBehavior,
+            gl::DEBUG_TYPE_UNDEFINED_BEHAVIOR => DebugType::UndefinedBehavior,
+            gl::DEBUG_TYPE_PORTABILITY => DebugType::Portability,
+            gl::DEBUG_TYPE_PERFORMANCE => DebugType::Performance,
+            gl::DEBUG_TYPE_MARKER => DebugType::Marker,
+            _ => DebugType::Other,
+        }
+    }
+}
+
+/// Severity of a debug message, from the most to the least important.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum DebugSeverity {
+    High,
+    Medium,
+    Low,
+    Notification,
+}
+
+impl DebugSeverity {
+    fn from_gl(severity: GLenum) -> DebugSeverity {
+        match severity {
+            gl::DEBUG_SEVERITY_HIGH => DebugSeverity::High,
+            gl::DEBUG_SEVERITY_MEDIUM => DebugSeverity::Medium,
+            gl::DEBUG_SEVERITY_LOW => DebugSeverity::Low,
+            _ => DebugSeverity::Notification,
+        }
+    }
+}
+
+/// Message sent by the driver through the debug output.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DebugMessage {
+    pub source: DebugSource,
+    pub kind: DebugType,
+    pub severity: DebugSeverity,
+    pub id: u32,
+    pub message: String,
+}
+
+impl fmt::Display for DebugMessage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "[{:?} {:?} {:?}] {}",
+            self.severity, self.source, self.kind, self.message
+        )
+    }
+}
+
+extern "system" fn debug_callback(
+    source: GLenum,
+    kind: GLenum,
+    id: GLuint,
+    severity: GLenum,
+    _length: GLsizei,
+    message: *const GLchar,
+    _user: *mut c_void,
+) {
+    let message = DebugMessage {
+        source: DebugSource::from_gl(source),
+        kind: DebugType::from_gl(kind),
+        severity: DebugSeverity::from_gl(severity),
+        id,
+        message: unsafe { CStr::from_ptr(message) }
+            .to_string_lossy()
+            .into_owned(),
+    };
+
+    // The output is synchronous so the callback run on the thread that made the gl call
+    send(&message);
+}
+
+fn send(message: &DebugMessage) {
+    DEBUG_CALLBACK.with(|callback| {
+        if let Ok(callback) = callback.try_borrow() {
+            if let Some(ref callback) = *callback {
+                callback(message);
+            }
+        }
+    });
+}
+
+/// Send a warning of gust itself to the debug callback, beside the driver messages.
+pub(crate) fn warn(kind: DebugType, message: String) {
+    send(&DebugMessage {
+        source: DebugSource::Application,
+        kind,
+        severity: DebugSeverity::Low,
+        id: 0,
+        message,
+    });
+}
+
+/// Send the debug messages of the current gl context to the callback,
+/// with the warnings of gust like the uniforms missing from a shader.
+/// Return false if the driver doesn't support the debug output (gl 4.3 or KHR_debug).
+pub fn enable_debug<F: Fn(&DebugMessage) + 'static>(callback: F) -> bool {
+    if !gl::DebugMessageCallback::is_loaded() {
+        return false;
+    }
+
+    DEBUG_CALLBACK.with(|current| *current.borrow_mut() = Some(Box::new(callback)));
+    unsafe {
+        gl::Enable(gl::DEBUG_OUTPUT);
+        gl::Enable(gl::DEBUG_OUTPUT_SYNCHRONOUS);
+        gl::DebugMessageCallback(debug_callback, ptr::null());
+        gl::DebugMessageControl(
+            gl::DONT_CARE,
+            gl::DONT_CARE,
+            gl::DONT_CARE,
+            0,
+            ptr::null(),
+            gl::TRUE,
+        );
+    }
+    true
+}
+
+/// Stop the debug output of the current gl context.
+pub fn disable_debug() {
+    if gl::DebugMessageCallback::is_loaded() {
+        unsafe {
+            gl::Disable(gl::DEBUG_OUTPUT);
+        }
+    }
+    DEBUG_CALLBACK.with(|current| *current.borrow_mut() = None);
+}
+
+#[cfg(test)]
+mod test {
+    use super::{check_with, debug_callback, take_errors, DebugMessage, DebugSeverity};
+    use super::{DebugSource, DebugType, GlError, DEBUG_CALLBACK};
+    use device::{self, NullDevice};
+    use gl;
+    use gl::types::*;
+    use shader::Shader;
+    use std::cell::RefCell;
+    use std::error::Error;
+    use std::ptr;
+    use std::rc::Rc;
+
+    thread_local! {
+        /// Errors returned by fake_get_error, the last one first.
+        static PENDING: RefCell<Vec<GLenum>> = RefCell::new(Vec::new());
+    }
+
+    fn fake_get_error() -> GLenum {
+        PENDING.with(|pending| pending.borrow_mut().pop().unwrap_or(gl::NO_ERROR))
+    }
+
+    #[test]
+    fn operation_error_name_the_operation() {
+        let error = GlError::Operation("create_texture", Box::new(GlError::InvalidValue));
+
+        assert_eq!(error.to_string(), "create_texture: Bad value argument");
+        assert_eq!(error.cause().unwrap().to_string(), "Bad value argument");
+        assert!(take_errors().is_empty());
+    }
+
+    #[test]
+    fn checked_errors_are_kept_until_taken() {
+        PENDING.with(|pending| *pending.borrow_mut() = vec![gl::OUT_OF_MEMORY, gl::INVALID_ENUM]);
+
+        check_with("bind_texture", fake_get_error);
+        check_with("clear", fake_get_error);
+        let errors: Vec<String> = take_errors().iter().map(|e| e.to_string()).collect();
+        if cfg!(debug_assertions) {
+            assert_eq!(
+                errors,
+                vec![
+                    "bind_texture: Bad enum argument",
+                    "bind_texture: No more gl memory"
+                ]
+            );
         }
+        assert!(take_errors().is_empty());
+    }
+
+    #[test]
+    fn debug_messages_reach_the_callback() {
+        let received: Rc<RefCell<Vec<DebugMessage>>> = Rc::new(RefCell::new(Vec::new()));
+        let sink = Rc::clone(&received);
+        DEBUG_CALLBACK.with(|callback| {
+            *callback.borrow_mut() = Some(Box::new(move |message: &DebugMessage| {
+                sink.borrow_mut().push(message.clone())
+            }))
+        });
+
+        debug_callback(
+            gl::DEBUG_SOURCE_SHADER_COMPILER,
+            gl::DEBUG_TYPE_PERFORMANCE,
+            7,
+            gl::DEBUG_SEVERITY_MEDIUM,
+            -1,
+            b"slow path\0".as_ptr() as *const GLchar,
+            ptr::null_mut(),
+        );
+        DEBUG_CALLBACK.with(|callback| *callback.borrow_mut() = None);
+
+        assert_eq!(
+            *received.borrow(),
+            vec![DebugMessage {
+                source: DebugSource::ShaderCompiler,
+                kind: DebugType::Performance,
+                severity: DebugSeverity::Medium,
+                id: 7,
+                message: "slow path".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn missing_uniforms_are_warned_once() {
+        let _device = device::scoped(Rc::new(NullDevice::new()));
+        let shader = Shader::from_source("", "").unwrap();
+        let received: Rc<RefCell<Vec<DebugMessage>>> = Rc::new(RefCell::new(Vec::new()));
+        let sink = Rc::clone(&received);
+        DEBUG_CALLBACK.with(|callback| {
+            *callback.borrow_mut() = Some(Box::new(move |message: &DebugMessage| {
+                sink.borrow_mut().push(message.clone())
+            }))
+        });
+
+        shader.uniform_f("time", 1.0);
+        shader.uniform_f("time", 2.0);
+        DEBUG_CALLBACK.with(|callback| *callback.borrow_mut() = None);
+
+        let received = received.borrow();
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].source, DebugSource::Application);
+        assert_eq!(received[0].kind, DebugType::UndefinedBehavior);
+        assert!(received[0].message.contains("time"));
     }
 }
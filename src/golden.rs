@@ -0,0 +1,391 @@
+//
+//  Rust file | 2018
+//  Author: Alexandre Fourcat
+//  golden.rs
+//  module:
+//! Golden image system used to test what the drawables render.
+//! A scene is rendered offscreen then compared to a reference png with a tolerance
+//! per channel. When they differ a diff image is written next to the reference.
+//! ```no_run
+//! use gust::golden::{self, Scene};
+//! use gust::prelude::*;
+//! use std::rc::Rc;
+//!
+//! let mut scene = Scene::new(64, 64).unwrap();
+//! let texture = Rc::new(Texture::from_color(Color::red(), Vector::new(32, 32)));
+//! let sprite = Sprite::from(&texture);
+//!
+//! let image = scene.render(|target| target.draw(&sprite)).unwrap();
+//! golden::assert_golden(&image, "tests/golden/sprite.png", 2).unwrap();
+//! ```
+//! Setting the `GUST_BLESS` environment variable rewrite the references with what is rendered.
+//! The scenes need a gl driver, Mesa llvmpipe under xvfb is enough on machines without a gpu.
+
+use color::Color;
+use image;
+use image::{ImageBuffer, Rgba, RgbaImage};
+use render_texture::{RenderTexture, RenderTextureError};
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use window::{Window, WindowError};
+
+/// Environment variable that make assert_golden write references instead of comparing.
+pub static BLESS_VAR: &'static str = "GUST_BLESS";
+
+/// A Scene is an headless window holding the gl context and an offscreen target.
+/// Each scene has its own context, the built-in shaders are made again inside it.
+pub struct Scene {
+    target: RenderTexture,
+    window: Window,
+}
+
+impl Scene {
+    /// Create a new scene of width * height pixels.
+    pub fn new(width: u32, height: u32) -> Result<Scene, GoldenError> {
+        let window = Window::headless(width, height)?;
+        let mut target = RenderTexture::new(width, height)?;
+
+        target.set_clear_color(Color::black());
+        Ok(Scene { target, window })
+    }
+
+    /// Get the offscreen target to change the view or the clear color.
+    pub fn target(&mut self) -> &mut RenderTexture {
+        &mut self.target
+    }
+
+    /// Clear the target, draw with the given closure and return the result.
+    pub fn render<F>(&mut self, draw: F) -> Result<RgbaImage, GoldenError>
+    where
+        F: FnOnce(&mut RenderTexture),
+    {
+        self.window.active();
+        self.target.clear();
+        draw(&mut self.target);
+        self.target.display();
+        Ok(self.target.capture()?)
+    }
+}
+
+/// Result of the comparison of two images.
+#[derive(Debug)]
+pub struct Diff {
+    /// Number of pixels that are out of the tolerance.
+    pub mismatched: usize,
+    /// The biggest difference found on a channel.
+    pub max_delta: u8,
+    /// Image with mismatched pixels in red over the dimmed reference.
+    pub image: RgbaImage,
+}
+
+impl Diff {
+    /// Check if both images are the same with the given tolerance.
+    pub fn is_same(&self) -> bool {
+        self.mismatched == 0
+    }
+}
+
+/// Compare two images with a tolerance on each channel.
+pub fn compare(
+    actual: &RgbaImage,
+    expected: &RgbaImage,
+    tolerance: u8,
+) -> Result<Diff, GoldenError> {
+    if actual.dimensions() != expected.dimensions() {
+        return Err(GoldenError::Sizes(
+            actual.dimensions(),
+            expected.dimensions(),
+        ));
+    }
+
+    let (width, height) = expected.dimensions();
+    let mut mismatched = 0;
+    let mut max_delta = 0;
+    let image = ImageBuffer::from_fn(width, height, |x, y| {
+        let a = actual.get_pixel(x, y);
+        let e = expected.get_pixel(x, y);
+        let delta = a
+            .data
+            .iter()
+            .zip(e.data.iter())
+            .map(|(a, e)| if a > e { a - e } else { e - a })
+            .max()
+            .unwrap_or(0);
+
+        max_delta = max_delta.max(delta);
+        if delta > tolerance {
+            mismatched += 1;
+            Rgba([255, 0, 0, 255])
+        } else {
+            Rgba([e.data[0] / 3, e.data[1] / 3, e.data[2] / 3, 255])
+        }
+    });
+
+    Ok(Diff {
+        mismatched,
+        max_delta,
+        image,
+    })
+}
+
+/// Compare an image with the reference stored at `path`.
+/// On mismatch `name.actual.png` and `name.diff.png` are written next to the reference.
+pub fn assert_golden<P: AsRef<Path>>(
+    actual: &RgbaImage,
+    path: P,
+    tolerance: u8,
+) -> Result<(), GoldenError> {
+    let path = path.as_ref();
+
+    if env::var_os(BLESS_VAR).is_some() {
+        actual.save(path)?;
+        return Ok(());
+    }
+    if !path.exists() {
+        return Err(GoldenError::Missing(path.to_path_buf()));
+    }
+
+    let expected = image::open(path)?.to_rgba();
+    let diff = compare(actual, &expected, tolerance)?;
+
+    if diff.is_same() {
+        Ok(())
+    } else {
+        let diff_path = path.with_extension("diff.png");
+
+        actual.save(path.with_extension("actual.png"))?;
+        diff.image.save(&diff_path)?;
+        Err(GoldenError::Mismatch(
+            diff.mismatched,
+            diff.max_delta,
+            diff_path,
+        ))
+    }
+}
+
+#[derive(Debug)]
+/// All error trigerable while checking a golden image
+pub enum GoldenError {
+    /// The reference image doesn't exist.
+    Missing(PathBuf),
+    /// Images haven't the same sizes (actual, expected).
+    Sizes((u32, u32), (u32, u32)),
+    /// Number of mismatched pixels, biggest delta and path of the diff image.
+    Mismatch(usize, u8, PathBuf),
+    Image(image::ImageError),
+    Io(::std::io::Error),
+    Window(WindowError),
+    Target(RenderTextureError),
+}
+
+impl fmt::Display for GoldenError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GoldenError::Missing(path) => write!(
+                f,
+                "No reference image at {}, set {} to create it.",
+                path.display(),
+                BLESS_VAR
+            ),
+            GoldenError::Sizes(actual, expected) => write!(
+                f,
+                "Image sizes differ: {:?} rendered, {:?} expected.",
+                actual, expected
+            ),
+            GoldenError::Mismatch(count, delta, path) => write!(
+                f,
+                "{} pixels differ (max delta {}), diff written to {}.",
+                count,
+                delta,
+                path.display()
+            ),
+            GoldenError::Image(err) => write!(f, "Image error: {}", err),
+            GoldenError::Io(err) => write!(f, "Io error: {}", err),
+            GoldenError::Window(err) => write!(f, "{}", err),
+            GoldenError::Target(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl Error for GoldenError {
+    fn cause(&self) -> Option<&Error> {
+        match self {
+            GoldenError::Image(err) => Some(err),
+            GoldenError::Io(err) => Some(err),
+            GoldenError::Window(err) => Some(err),
+            GoldenError::Target(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<image::ImageError> for GoldenError {
+    fn from(err: image::ImageError) -> GoldenError {
+        GoldenError::Image(err)
+    }
+}
+
+impl From<::std::io::Error> for GoldenError {
+    fn from(err: ::std::io::Error) -> GoldenError {
+        GoldenError::Io(err)
+    }
+}
+
+impl From<WindowError> for GoldenError {
+    fn from(err: WindowError) -> GoldenError {
+        GoldenError::Window(err)
+    }
+}
+
+impl From<RenderTextureError> for GoldenError {
+    fn from(err: RenderTextureError) -> GoldenError {
+        GoldenError::Target(err)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{assert_golden, compare, Scene};
+    use color::Color;
+    use draw::Drawable;
+    use draw::Drawer;
+    use font::Font;
+    use image::{ImageBuffer, Rgba, RgbaImage};
+    use rect::Rect;
+    use sprite::Sprite;
+    use spritebatch::{SpriteBatch, SpriteData};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use text::Text;
+    use texture::Texture;
+    use transform::{Movable, Rotable};
+    use view::View;
+    use Vector;
+
+    fn plain(width: u32, height: u32, color: [u8; 4]) -> RgbaImage {
+        ImageBuffer::from_pixel(width, height, Rgba(color))
+    }
+
+    /// Path of a reference image committed in tests/golden.
+    fn reference(name: &str) -> String {
+        format!("{}/tests/golden/{}.png", env!("CARGO_MANIFEST_DIR"), name)
+    }
+
+    #[test]
+    fn compare_within_tolerance() {
+        let a = plain(4, 4, [100, 100, 100, 255]);
+        let b = plain(4, 4, [102, 99, 100, 255]);
+        let diff = compare(&a, &b, 2).unwrap();
+
+        assert!(diff.is_same());
+        assert_eq!(diff.max_delta, 2);
+    }
+
+    #[test]
+    fn compare_out_of_tolerance() {
+        let a = plain(4, 4, [100, 100, 100, 255]);
+        let mut b = plain(4, 4, [100, 100, 100, 255]);
+        b.put_pixel(1, 2, Rgba([0, 100, 100, 255]));
+        let diff = compare(&a, &b, 2).unwrap();
+
+        assert_eq!(diff.mismatched, 1);
+        assert_eq!(*diff.image.get_pixel(1, 2), Rgba([255, 0, 0, 255]));
+    }
+
+    #[test]
+    fn compare_bad_sizes() {
+        assert!(compare(&plain(4, 4, [0; 4]), &plain(4, 5, [0; 4]), 0).is_err());
+    }
+
+    #[test]
+    fn sprite_is_drawn_at_its_position() {
+        let mut scene = Scene::new(16, 16).unwrap();
+        let texture = Rc::new(Texture::from_color(Color::red(), Vector::new(8, 8)));
+        let mut sprite = Sprite::from(&texture);
+        sprite.set_position(Vector::new(4.0, 4.0));
+        sprite.update();
+
+        let image = scene.render(|target| target.draw(&sprite)).unwrap();
+        let expected = ImageBuffer::from_fn(16, 16, |x, y| {
+            if x >= 4 && x < 12 && y >= 4 && y < 12 {
+                Rgba([255, 0, 0, 255])
+            } else {
+                Rgba([0, 0, 0, 255])
+            }
+        });
+
+        assert!(compare(&image, &expected, 2).unwrap().is_same());
+    }
+
+    #[test]
+    fn text_matches_reference() {
+        let mut scene = Scene::new(32, 32).unwrap();
+        let font = Rc::new(RefCell::new(
+            Font::from_path(concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/examples/font/terminus.ttf"
+            ))
+            .unwrap(),
+        ));
+        let mut text = Text::from_str(&font, "A");
+        // The position is the baseline of the text
+        text.set_position(Vector::new(8.0, 26.0));
+        text.update();
+
+        let image = scene.render(|target| target.draw(&text)).unwrap();
+        // Glyphs are antialiased, the freetype versions may round them differently
+        assert_golden(&image, reference("text"), 4).unwrap();
+    }
+
+    #[test]
+    fn spritebatch_matches_reference() {
+        let mut scene = Scene::new(16, 16).unwrap();
+        let red = Rc::new(Texture::from_color(Color::red(), Vector::new(4, 4)));
+        let blue = Rc::new(Texture::from_color(Color::blue(), Vector::new(4, 4)));
+        let mut batch = SpriteBatch::from(&red);
+        let mut over = SpriteData::new(Vector::new(4.0, 4.0));
+
+        over.set_texture_index(batch.add_texture(&blue));
+        batch.push_sprite(SpriteData::new(Vector::new(2.0, 2.0)));
+        // Drawn over the first sprite in the order of the batch
+        batch.push_sprite(over);
+        batch.push_sprite(SpriteData::new(Vector::new(10.0, 10.0)));
+
+        let image = scene.render(|target| target.draw_mut(&mut batch)).unwrap();
+        assert_golden(&image, reference("spritebatch"), 2).unwrap();
+    }
+
+    #[test]
+    fn rotated_sprite_matches_reference() {
+        let mut scene = Scene::new(32, 32).unwrap();
+        let texture = Rc::new(Texture::from_color(Color::red(), Vector::new(12, 12)));
+        let mut sprite = Sprite::from(&texture);
+        // Turned by 30 degrees around its center
+        sprite.set_origin_to_center().unwrap();
+        sprite.set_position(Vector::new(16.0, 16.0));
+        sprite.set_rotation(30.0);
+        sprite.update();
+
+        let image = scene.render(|target| target.draw(&sprite)).unwrap();
+        assert_golden(&image, reference("sprite_rotated"), 2).unwrap();
+    }
+
+    #[test]
+    fn view_zoom_and_translate_match_reference() {
+        let mut scene = Scene::new(16, 16).unwrap();
+        let texture = Rc::new(Texture::from_color(Color::red(), Vector::new(4, 4)));
+        let mut sprite = Sprite::from(&texture);
+        let mut view = View::from(Rect::new(0.0, 0.0, 16.0, 16.0));
+        sprite.set_position(Vector::new(6.0, 4.0));
+        sprite.update();
+        // The view show the 8x8 pixels from (2, 2) on the 16x16 target
+        view.zoom(2.0);
+        view.translate(Vector::new(2.0, 2.0));
+        scene.target().set_view(view);
+
+        let image = scene.render(|target| target.draw(&sprite)).unwrap();
+        assert_golden(&image, reference("view"), 2).unwrap();
+    }
+}
@@ -0,0 +1,176 @@
+//
+//  Rust file | 2018
+//  Author: Alexandre Fourcat
+//  handle.rs
+//  module:
+//! Refcounted ownership of the gpu objects.
+//! Cloning a Handle share the object, it's deleted from the device that created it
+//! exactly once when the last Handle is dropped.
+//! ```no_run
+//! use gust::prelude::*;
+//!
+//! let _window = Window::new(800, 600, "Handles");
+//! let texture = Texture::from_path("texture.png").unwrap();
+//!
+//! // Same gl texture, deleted once both are dropped
+//! let shared = texture.clone();
+//! assert_eq!(shared.id(), texture.id());
+//! // New gl texture holding a copy of the pixels
+//! let copy = texture.duplicate();
+//! assert_ne!(copy.id(), texture.id());
+//! ```
+
+use device;
+use device::RenderDevice;
+use std::fmt;
+use std::rc::Rc;
+
+/// Kind of gpu object owned by a Handle, tell how it's deleted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Kind {
+    Buffer,
+    VertexArray,
+    Texture,
+    Framebuffer,
+}
+
+struct Object {
+    kind: Kind,
+    id: u32,
+    /// Device the object was created on, the current one may have changed since.
+    device: Rc<RenderDevice>,
+}
+
+impl PartialEq for Object {
+    fn eq(&self, other: &Object) -> bool {
+        self.kind == other.kind && self.id == other.id && Rc::ptr_eq(&self.device, &other.device)
+    }
+}
+
+impl Eq for Object {}
+
+impl Drop for Object {
+    fn drop(&mut self) {
+        // 0 is never a valid object
+        if self.id == 0 {
+            return;
+        }
+
+        match self.kind {
+            Kind::Buffer => self.device.delete_buffer(self.id),
+            Kind::VertexArray => self.device.delete_vertex_array(self.id),
+            Kind::Texture => self.device.delete_texture(self.id),
+            Kind::Framebuffer => self.device.delete_framebuffer(self.id),
+        }
+    }
+}
+
+/// Shared owner of a gpu object.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Handle(Rc<Object>);
+
+impl Handle {
+    /// Take the ownership of the object `id` created on the current device.
+    pub fn new(kind: Kind, id: u32) -> Handle {
+        Handle(Rc::new(Object {
+            kind,
+            id,
+            device: device::current(),
+        }))
+    }
+
+    /// Id of the object inside the device.
+    pub fn id(&self) -> u32 {
+        self.0.id
+    }
+
+    pub fn kind(&self) -> Kind {
+        self.0.kind
+    }
+
+    /// Device the object was created on.
+    pub(crate) fn device(&self) -> &Rc<RenderDevice> {
+        &self.0.device
+    }
+
+    /// Number of handles sharing the object.
+    pub fn owners(&self) -> usize {
+        Rc::strong_count(&self.0)
+    }
+}
+
+impl Default for Handle {
+    /// A handle owning nothing.
+    fn default() -> Handle {
+        Handle::new(Kind::VertexArray, 0)
+    }
+}
+
+impl fmt::Debug for Handle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}({})", self.0.kind, self.0.id)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Handle, Kind};
+    use device::{self, NullDevice, RenderDevice};
+    use render_texture::RenderTexture;
+    use shader::Shader;
+    use std::rc::Rc;
+    use stream_buffer::StreamBuffer;
+    use vertex::Vertex;
+
+    #[test]
+    fn object_deleted_once_by_the_last_owner() {
+        let null = Rc::new(NullDevice::new());
+        let _device = device::scoped(null.clone());
+
+        let handle = Handle::new(Kind::Buffer, null.create_buffer());
+        let shared = handle.clone();
+        assert_eq!(shared.owners(), 2);
+
+        drop(handle);
+        assert_eq!(null.deleted(), 0);
+        drop(shared);
+        assert_eq!(null.deleted(), 1);
+    }
+
+    #[test]
+    fn object_deleted_by_the_device_that_created_it() {
+        let null = Rc::new(NullDevice::new());
+        let other = Rc::new(NullDevice::new());
+        let handle = {
+            let _device = device::scoped(null.clone());
+            Handle::new(Kind::Buffer, null.create_buffer())
+        };
+
+        let _device = device::scoped(other.clone());
+        drop(handle);
+        assert_eq!((null.deleted(), other.deleted()), (1, 0));
+    }
+
+    #[test]
+    fn objects_outside_handles_follow_their_device_too() {
+        let null = Rc::new(NullDevice::new());
+        let other = Rc::new(NullDevice::new());
+        let (target, shader, buffer) = {
+            let _device = device::scoped(null.clone());
+            let target = RenderTexture::new(4, 4).unwrap();
+            let shader = Shader::from_source("", "").unwrap();
+            let mut buffer = StreamBuffer::ring(3);
+
+            buffer.upload(&[Vertex::default(); 3], 0, 3);
+            buffer.fence();
+            (target, shader, buffer)
+        };
+        let deleted = null.deleted();
+
+        let _device = device::scoped(other.clone());
+        drop((target, shader, buffer));
+        // The texture and framebuffer, the program, the buffer and its fence
+        assert_eq!(null.deleted(), deleted + 5);
+        assert_eq!(other.deleted(), 0);
+    }
+}
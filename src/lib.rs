@@ -80,17 +80,30 @@ extern crate lazy_static;
 extern crate alga;
 extern crate image;
 
+pub mod batch;
 pub mod color;
+pub mod command;
+pub mod device;
 pub mod draw;
+pub mod draw_list;
 pub mod event;
 pub mod font;
 pub mod gl_error;
+pub mod golden;
+pub mod handle;
+pub mod post_process;
+pub mod preprocessor;
+pub mod rasterizer;
 pub mod rect;
+pub mod render_texture;
 pub mod resources;
 pub mod shader;
+pub mod shader_watcher;
 pub mod shared_window;
 pub mod sprite;
 pub mod spritebatch;
+pub mod state_cache;
+pub mod stream_buffer;
 pub mod text;
 pub mod texture;
 pub mod transform;
@@ -102,11 +115,14 @@ pub mod window;
 pub mod prelude {
     pub use super::{Action, Coord, Key, MouseButtonLeft, MouseButtonRight, Point, Vector};
     pub use color::Color;
+    pub use command::CommandList;
     pub use draw::{Context, Drawable, DrawableMut, Drawer};
+    pub use draw_list::DrawList;
     pub use event::{Event, EventHandler, Events};
     pub use font::Font;
+    pub use render_texture::RenderTexture;
     pub use sprite::Sprite;
-    pub use spritebatch::{SpriteBatch, SpriteData};
+    pub use spritebatch::{SpriteBatch, SpriteData, SpriteHandle};
     pub use text::Text;
     pub use texture::Texture;
     pub use transform::{Movable, Rotable, Scalable, Transformable};
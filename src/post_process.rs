@@ -0,0 +1,700 @@
+//
+//  Rust file | 2018
+//  Author: Alexandre Fourcat
+//  post_process.rs
+//  module:
+//! Post-processing effects.
+//! An effect is made of fullscreen fragment shader passes, each pass read the result of the
+//! previous one from an offscreen texture and the last pass of the chain write on the target.
+//! The passes receive the uniforms declared by `#include "gust/effect.glsl"`:
+//! `previous` (result of the previous pass), `source` (what the effect received),
+//! `scene` (what was drawn), `resolution` (in pixels) and `time` (seconds since the creation
+//! of the chain).
+//! ```no_run
+//! use gust::post_process::Effect;
+//! use gust::prelude::*;
+//!
+//! let mut window = Window::new(800, 600, "Effects");
+//! window.add_effect(Effect::bloom(0.8, 1.5)).unwrap();
+//! window.add_effect(Effect::vignette(0.5)).unwrap();
+//!
+//! while window.is_open() {
+//!     window.clear();
+//!     // draw the scene
+//!     window.display();
+//! }
+//! ```
+//! A custom effect only need a fragment shader:
+//! ```no_run
+//! use gust::post_process::Effect;
+//! use gust::prelude::*;
+//!
+//! let mut window = Window::new(800, 600, "Grayscale");
+//! let mut gray = Effect::new(
+//!     "gray",
+//!     "#version 330 core
+//!     #include \"gust/effect.glsl\"
+//!     uniform float amount;
+//!
+//!     void main()
+//!     {
+//!         vec4 color = texture(previous, TexCoord);
+//!         float gray = dot(color.rgb, vec3(0.299, 0.587, 0.114));
+//!         FragColor = vec4(mix(color.rgb, vec3(gray), amount), color.a);
+//!     }",
+//! ).unwrap();
+//!
+//! gray.set_uniform("amount", 0.8);
+//! window.add_effect(gray).unwrap();
+//! ```
+
+use color::Color;
+use device;
+use draw::{BlendMode, Context, Drawable};
+use nalgebra::Vector2;
+use preprocessor::Preprocessor;
+use render_texture::{RenderTexture, RenderTextureError};
+use resources::Resource;
+use shader::{Shader, ShaderError, UniformValue, Uniforms};
+use std::path::Path;
+use std::slice;
+use std::time::Instant;
+use texture::{Filter, Texture, Wrap};
+use vertex::{Vertex, VertexArray};
+use vertex_buffer::{Primitive, VertexBuffer};
+
+/// Vertex shader of the passes, covering the whole target.
+pub static EFFECT_VS: &'static str = "#version 330 core
+#include \"gust/layout.glsl\"
+out vec2 TexCoord;
+
+void main()
+{
+   gl_Position = vec4(aPos.xy, 0.0, 1.0);
+   TexCoord = aTexCoord;
+}
+";
+
+static COPY_FS: &'static str = "#version 330 core
+#include \"gust/effect.glsl\"
+
+void main()
+{
+   FragColor = texture(previous, TexCoord);
+}
+";
+
+/// Gaussian blur along direction, radius spread the samples.
+static BLUR_FS: &'static str = "#version 330 core
+#include \"gust/effect.glsl\"
+uniform vec2 direction;
+uniform float radius;
+
+const float weights[5] = float[](0.227027, 0.1945946, 0.1216216, 0.054054, 0.016216);
+
+void main()
+{
+   vec2 offset = direction * radius / resolution;
+   vec4 color = texture(previous, TexCoord) * weights[0];
+
+   for (int i = 1; i < 5; i++) {
+      color += texture(previous, TexCoord + offset * float(i)) * weights[i];
+      color += texture(previous, TexCoord - offset * float(i)) * weights[i];
+   }
+   FragColor = color;
+}
+";
+
+static BRIGHT_FS: &'static str = "#version 330 core
+#include \"gust/effect.glsl\"
+uniform float threshold;
+
+void main()
+{
+   vec4 color = texture(previous, TexCoord);
+   float brightness = dot(color.rgb, vec3(0.2126, 0.7152, 0.0722));
+   FragColor = brightness > threshold ? color : vec4(0.0, 0.0, 0.0, color.a);
+}
+";
+
+static BLOOM_FS: &'static str = "#version 330 core
+#include \"gust/effect.glsl\"
+uniform float intensity;
+
+void main()
+{
+   vec4 color = texture(source, TexCoord);
+   FragColor = vec4(color.rgb + texture(previous, TexCoord).rgb * intensity, color.a);
+}
+";
+
+static VIGNETTE_FS: &'static str = "#version 330 core
+#include \"gust/effect.glsl\"
+uniform float strength;
+
+void main()
+{
+   vec4 color = texture(previous, TexCoord);
+   float distance = length(TexCoord - vec2(0.5)) * 1.4142135;
+   FragColor = vec4(color.rgb * (1.0 - strength * smoothstep(0.4, 1.0, distance)), color.a);
+}
+";
+
+static CRT_FS: &'static str = "#version 330 core
+#include \"gust/effect.glsl\"
+uniform float curvature;
+uniform float scanlines;
+
+void main()
+{
+   // Bend the screen like an old tube
+   vec2 uv = TexCoord * 2.0 - 1.0;
+   uv += uv * (uv.yx * uv.yx) * curvature;
+   uv = uv * 0.5 + 0.5;
+   if (uv.x < 0.0 || uv.x > 1.0 || uv.y < 0.0 || uv.y > 1.0) {
+      FragColor = vec4(0.0, 0.0, 0.0, 1.0);
+      return;
+   }
+
+   vec4 color = texture(previous, uv);
+   float line = sin(uv.y * resolution.y * 3.1415926) * 0.5 + 0.5;
+   float flicker = 1.0 - 0.02 * sin(time * 60.0);
+   FragColor = vec4(color.rgb * (1.0 - scanlines * line) * flicker, color.a);
+}
+";
+
+/// The lut is a strip of lut_size slices of lut_size * lut_size pixels, one per blue level.
+static LUT_FS: &'static str = "#version 330 core
+#include \"gust/effect.glsl\"
+uniform sampler2D lut;
+uniform float lut_size;
+
+vec3 grade(vec3 color)
+{
+   float blue = color.b * (lut_size - 1.0);
+   float slice = floor(blue);
+   vec2 texel = vec2(0.5 / (lut_size * lut_size), 0.5 / lut_size);
+   vec2 uv = vec2(color.r / lut_size, color.g) * (lut_size - 1.0) / lut_size + texel;
+   vec3 low = texture(lut, uv + vec2(slice / lut_size, 0.0)).rgb;
+   vec3 high = texture(lut, uv + vec2(min(slice + 1.0, lut_size - 1.0) / lut_size, 0.0)).rgb;
+
+   return mix(low, high, blue - slice);
+}
+
+void main()
+{
+   vec4 color = texture(previous, TexCoord);
+   FragColor = vec4(grade(clamp(color.rgb, 0.0, 1.0)), color.a);
+}
+";
+
+/// Texture units of the gust samplers, the textures of the effects come after.
+const PREVIOUS_UNIT: u32 = 0;
+const SOURCE_UNIT: u32 = 1;
+const SCENE_UNIT: u32 = 2;
+
+//----------------------------------------------------------------------------
+//
+//
+//                             EFFECT : STRUCT
+//
+//
+//----------------------------------------------------------------------------
+
+/// Fullscreen pass of an effect.
+#[derive(Debug, Clone)]
+struct Pass {
+    shader: Resource<Shader>,
+    uniforms: Uniforms,
+}
+
+/// Post-processing effect made of one or several fullscreen passes.
+/// The uniforms of the effect are given to each pass using them.
+#[derive(Debug, Clone)]
+pub struct Effect {
+    name: String,
+    passes: Vec<Pass>,
+    uniforms: Uniforms,
+    textures: Vec<(String, Resource<Texture>)>,
+    enabled: bool,
+}
+
+impl Effect {
+    /// Effect of a single pass made of the fragment shader source.
+    pub fn new(name: &str, fragment: &str) -> Result<Effect, ShaderError> {
+        let shader = Shader::from_source(EFFECT_VS, fragment)?;
+
+        Ok(Effect::from_shader(name, Resource::new(shader)))
+    }
+
+    /// Same as new with the fragment shader file.
+    pub fn from_file<P: AsRef<Path>>(name: &str, path: P) -> Result<Effect, ShaderError> {
+        let preprocessor = Preprocessor::new();
+        let shader = Shader::from_preprocessed(
+            &preprocessor.process("gust/effect.vert", EFFECT_VS)?,
+            &preprocessor.load(path)?,
+        )?;
+
+        Ok(Effect::from_shader(name, Resource::new(shader)))
+    }
+
+    /// Effect of a single pass, the shader has to use `EFFECT_VS` as vertex shader.
+    pub fn from_shader(name: &str, shader: Resource<Shader>) -> Effect {
+        Effect {
+            name: name.to_string(),
+            passes: vec![Pass {
+                shader,
+                uniforms: Uniforms::new(),
+            }],
+            uniforms: Uniforms::new(),
+            textures: Vec::new(),
+            enabled: true,
+        }
+    }
+
+    /// Built-in effects are made of gust sources, they always compile.
+    fn builtin(fragment: &str) -> Resource<Shader> {
+        Resource::new(Shader::from_source(EFFECT_VS, fragment).unwrap())
+    }
+
+    /// Two passes gaussian blur, radius is the distance in pixels between the samples.
+    pub fn blur(radius: f32) -> Effect {
+        let blur = Effect::builtin(BLUR_FS);
+        let mut effect = Effect::from_shader("blur", blur.clone());
+
+        effect.passes[0]
+            .uniforms
+            .set("direction", Vector2::new(1.0, 0.0));
+        effect.add_pass(blur, Effect::direction(0.0, 1.0));
+        effect.set_uniform("radius", radius);
+        effect
+    }
+
+    fn direction(x: f32, y: f32) -> Uniforms {
+        let mut uniforms = Uniforms::new();
+
+        uniforms.set("direction", Vector2::new(x, y));
+        uniforms
+    }
+
+    /// Make the parts brighter than threshold glow.
+    pub fn bloom(threshold: f32, intensity: f32) -> Effect {
+        let blur = Effect::builtin(BLUR_FS);
+        let mut effect = Effect::from_shader("bloom", Effect::builtin(BRIGHT_FS));
+
+        effect.add_pass(blur.clone(), Effect::direction(1.0, 0.0));
+        effect.add_pass(blur, Effect::direction(0.0, 1.0));
+        effect.add_pass(Effect::builtin(BLOOM_FS), Uniforms::new());
+        effect.set_uniform("threshold", threshold);
+        effect.set_uniform("intensity", intensity);
+        effect.set_uniform("radius", 2.0);
+        effect
+    }
+
+    /// Darken the corners, strength goes from 0 to 1.
+    pub fn vignette(strength: f32) -> Effect {
+        let mut effect = Effect::from_shader("vignette", Effect::builtin(VIGNETTE_FS));
+
+        effect.set_uniform("strength", strength);
+        effect
+    }
+
+    /// Old monitor look with a curved screen and scanlines.
+    pub fn crt() -> Effect {
+        let mut effect = Effect::from_shader("crt", Effect::builtin(CRT_FS));
+
+        effect.set_uniform("curvature", 0.1);
+        effect.set_uniform("scanlines", 0.25);
+        effect
+    }
+
+    /// Replace the colors by the ones of a lookup table.
+    /// The lut is a strip of N slices of N * N pixels (256 * 16 for 16 levels), the red
+    /// grows to the right of a slice, the green to the bottom and the blue from slice to slice.
+    pub fn color_grading(lut: &Resource<Texture>) -> Effect {
+        let mut effect = Effect::from_shader("color_grading", Effect::builtin(LUT_FS));
+
+        lut.set_filter(Filter::Linear);
+        lut.set_wrap(Wrap::ClampToEdge);
+        effect.set_texture("lut", lut);
+        effect.set_uniform("lut_size", lut.height() as f32);
+        effect
+    }
+
+    /// Add a pass run after the others, its uniforms come on top of the effect ones.
+    pub fn add_pass(&mut self, shader: Resource<Shader>, uniforms: Uniforms) {
+        self.passes.push(Pass { shader, uniforms });
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Number of passes of the effect.
+    pub fn passes(&self) -> usize {
+        self.passes.len()
+    }
+
+    /// Set an uniform of the effect, the passes not using it ignore it.
+    pub fn set_uniform<T: Into<UniformValue>>(&mut self, name: &str, value: T) {
+        self.uniforms.set(name, value);
+    }
+
+    pub fn uniforms(&self) -> &Uniforms {
+        &self.uniforms
+    }
+
+    pub fn uniforms_mut(&mut self) -> &mut Uniforms {
+        &mut self.uniforms
+    }
+
+    /// Give a texture to the sampler2D name of the passes.
+    pub fn set_texture(&mut self, name: &str, texture: &Resource<Texture>) {
+        let texture = Resource::clone(texture);
+
+        match self.textures.iter_mut().find(|(key, _)| key == name) {
+            Some(entry) => entry.1 = texture,
+            None => self.textures.push((name.to_string(), texture)),
+        }
+    }
+
+    /// A disabled effect is skipped by the chain.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+}
+
+//----------------------------------------------------------------------------
+//
+//
+//                             POSTPROCESS : STRUCT
+//
+//
+//----------------------------------------------------------------------------
+
+/// Ordered effects applied to what's drawn on a target.
+/// What's drawn goes inside the scene texture, then the passes ping-pong between
+/// offscreen textures of the same size, created when needed (3 at most).
+#[derive(Debug)]
+pub struct PostProcess {
+    effects: Vec<Effect>,
+    scene: RenderTexture,
+    targets: Vec<RenderTexture>,
+    quad: VertexBuffer,
+    copy: Effect,
+    start: Instant,
+}
+
+impl PostProcess {
+    /// Chain without effects for scenes of width * height pixels.
+    pub fn new(width: u32, height: u32) -> Result<PostProcess, RenderTextureError> {
+        let white = Color::white();
+        let quad = [
+            Vertex::new(Vector2::new(-1.0, -1.0), Vector2::new(0.0, 0.0), white),
+            Vertex::new(Vector2::new(1.0, -1.0), Vector2::new(1.0, 0.0), white),
+            Vertex::new(Vector2::new(-1.0, 1.0), Vector2::new(0.0, 1.0), white),
+            Vertex::new(Vector2::new(1.0, 1.0), Vector2::new(1.0, 1.0), white),
+        ];
+
+        Ok(PostProcess {
+            effects: Vec::new(),
+            scene: PostProcess::target(width, height)?,
+            targets: Vec::new(),
+            quad: VertexBuffer::new(Primitive::TrianglesStrip, VertexArray::from(&quad[..])),
+            copy: Effect::from_shader("copy", Effect::builtin(COPY_FS)),
+            start: Instant::now(),
+        })
+    }
+
+    fn target(width: u32, height: u32) -> Result<RenderTexture, RenderTextureError> {
+        let target = RenderTexture::new(width, height)?;
+
+        target.texture().set_filter(Filter::Linear);
+        target.texture().set_wrap(Wrap::ClampToEdge);
+        Ok(target)
+    }
+
+    /// Add an effect at the end of the chain.
+    pub fn push(&mut self, effect: Effect) {
+        self.effects.push(effect);
+    }
+
+    pub fn insert(&mut self, index: usize, effect: Effect) {
+        self.effects.insert(index, effect);
+    }
+
+    pub fn remove(&mut self, index: usize) -> Effect {
+        self.effects.remove(index)
+    }
+
+    /// Remove every effect, what's drawn is copied as is.
+    pub fn clear(&mut self) {
+        self.effects.clear();
+    }
+
+    pub fn effects(&self) -> &[Effect] {
+        &self.effects
+    }
+
+    /// First effect named name.
+    pub fn effect(&self, name: &str) -> Option<&Effect> {
+        self.effects.iter().find(|effect| effect.name == name)
+    }
+
+    pub fn effect_mut(&mut self, name: &str) -> Option<&mut Effect> {
+        self.effects.iter_mut().find(|effect| effect.name == name)
+    }
+
+    pub fn len(&self) -> usize {
+        self.effects.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.effects.is_empty()
+    }
+
+    /// Texture receiving what's drawn before the effects.
+    pub fn scene(&self) -> &Resource<Texture> {
+        self.scene.texture()
+    }
+
+    pub fn width(&self) -> u32 {
+        self.scene.width()
+    }
+
+    pub fn height(&self) -> u32 {
+        self.scene.height()
+    }
+
+    /// Recreate the textures if the size changed, the scene is lost.
+    pub fn resize(&mut self, width: u32, height: u32) -> Result<(), RenderTextureError> {
+        if width == self.width() && height == self.height() {
+            return Ok(());
+        }
+
+        self.scene = PostProcess::target(width, height)?;
+        self.targets.clear();
+        Ok(())
+    }
+
+    /// Send the next draws to the scene texture.
+    pub fn bind_scene(&self) {
+        device::current().bind_framebuffer(self.scene.framebuffer());
+    }
+
+    /// Run the effects on the content of the render texture, the result replace it.
+    pub fn apply(&mut self, target: &mut RenderTexture) -> Result<(), RenderTextureError> {
+        let (width, height) = (target.width(), target.height());
+        let texture = target.texture();
+
+        self.resize(width, height)?;
+        device::current().copy_texture(
+            texture.id(),
+            self.scene().id(),
+            *texture.rgb_mode(),
+            width,
+            height,
+        );
+        self.render(target.framebuffer(), [0, 0, width as i32, height as i32])
+    }
+
+    /// Run the effects on the scene, the last pass is drawn on framebuffer inside viewport.
+    /// The framebuffer and the viewport stay bound.
+    pub fn render(
+        &mut self,
+        framebuffer: u32,
+        viewport: [i32; 4],
+    ) -> Result<(), RenderTextureError> {
+        let elapsed = self.start.elapsed();
+        let mut gust = Uniforms::new();
+
+        gust.set("previous", UniformValue::Sampler(PREVIOUS_UNIT));
+        gust.set("source", UniformValue::Sampler(SOURCE_UNIT));
+        gust.set("scene", UniformValue::Sampler(SCENE_UNIT));
+        gust.set(
+            "resolution",
+            Vector2::new(self.width() as f32, self.height() as f32),
+        );
+        gust.set(
+            "time",
+            elapsed.as_secs() as f32 + elapsed.subsec_nanos() as f32 * 1e-9,
+        );
+
+        // Without effects the scene is copied as is
+        let total: usize = self
+            .effects
+            .iter()
+            .filter(|effect| effect.enabled)
+            .map(|effect| effect.passes.len())
+            .sum();
+        let (effects, total) = if total == 0 {
+            (slice::from_ref(&self.copy), 1)
+        } else {
+            (&self.effects[..], total)
+        };
+        let device = device::current();
+        // None is the scene, Some(i) the target i
+        let mut previous = None;
+        let mut drawn = 0;
+
+        device.front_face(false);
+        for effect in effects.iter().filter(|effect| effect.enabled) {
+            let source = previous;
+
+            for pass in &effect.passes {
+                drawn += 1;
+                let output = if drawn == total {
+                    device.bind_framebuffer(framebuffer);
+                    device.set_viewport(viewport);
+                    None
+                } else {
+                    let (width, height) = (self.scene.width(), self.scene.height());
+                    let index = PostProcess::free_target(
+                        &mut self.targets,
+                        width,
+                        height,
+                        &[source, previous],
+                    )?;
+
+                    device.bind_framebuffer(self.targets[index].framebuffer());
+                    device.set_viewport([0, 0, width as i32, height as i32]);
+                    Some(index)
+                };
+
+                self.texture(source).active(SOURCE_UNIT as i32);
+                self.scene.texture().active(SCENE_UNIT as i32);
+
+                let mut uniforms = Uniforms::new();
+                for (name, value) in gust
+                    .iter()
+                    .chain(effect.uniforms.iter())
+                    .chain(pass.uniforms.iter())
+                {
+                    if pass.shader.has_uniform(name) {
+                        uniforms.set(name, *value);
+                    }
+                }
+                for (unit, (name, texture)) in effect.textures.iter().enumerate() {
+                    let unit = SCENE_UNIT + 1 + unit as u32;
+
+                    texture.active(unit as i32);
+                    if pass.shader.has_uniform(name) {
+                        uniforms.set(name, UniformValue::Sampler(unit));
+                    }
+                }
+
+                let mut context = Context::new(
+                    Some(self.texture(previous)),
+                    &pass.shader,
+                    Vec::new(),
+                    BlendMode::None,
+                );
+                context.set_uniforms(&uniforms);
+                self.quad.draw_with_context(&mut context);
+                previous = output;
+            }
+        }
+        Ok(())
+    }
+
+    /// Scene for None, target index otherwise.
+    fn texture(&self, index: Option<usize>) -> &Resource<Texture> {
+        match index {
+            Some(index) => self.targets[index].texture(),
+            None => self.scene.texture(),
+        }
+    }
+
+    /// Index of a target not in use, created if every target is.
+    fn free_target(
+        targets: &mut Vec<RenderTexture>,
+        width: u32,
+        height: u32,
+        used: &[Option<usize>],
+    ) -> Result<usize, RenderTextureError> {
+        if let Some(index) = (0..targets.len()).find(|index| !used.contains(&Some(*index))) {
+            return Ok(index);
+        }
+
+        targets.push(PostProcess::target(width, height)?);
+        Ok(targets.len() - 1)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Effect, PostProcess, EFFECT_VS};
+    use color::Color;
+    use device::{self, NullDevice};
+    use golden::{compare, Scene};
+    use image::{ImageBuffer, Rgba};
+    use resources::Resource;
+    use shader::{Shader, Uniforms};
+    use std::rc::Rc;
+
+    static HALF_FS: &str = "#version 330 core
+#include \"gust/effect.glsl\"
+
+void main()
+{
+   FragColor = vec4(texture(previous, TexCoord).rgb * 0.5, 1.0);
+}
+";
+
+    static INVERT_FS: &str = "#version 330 core
+#include \"gust/effect.glsl\"
+
+void main()
+{
+   FragColor = vec4(1.0 - texture(previous, TexCoord).rgb, 1.0);
+}
+";
+
+    #[test]
+    fn every_enabled_pass_is_drawn() {
+        let null = Rc::new(NullDevice::new());
+        let _device = device::scoped(null.clone());
+        let mut chain = PostProcess::new(64, 32).unwrap();
+
+        chain.render(0, [0, 0, 64, 32]).unwrap();
+        assert_eq!(null.draw_calls(), 1);
+
+        chain.push(Effect::bloom(0.5, 1.0));
+        chain.push(Effect::vignette(0.5));
+        chain.render(0, [0, 0, 64, 32]).unwrap();
+        assert_eq!(null.draw_calls(), 1 + 5);
+
+        chain.effect_mut("bloom").unwrap().set_enabled(false);
+        chain.render(0, [0, 0, 64, 32]).unwrap();
+        assert_eq!(null.draw_calls(), 1 + 5 + 1);
+    }
+
+    #[test]
+    fn passes_are_chained_in_order_on_the_target() {
+        let mut scene = Scene::new(8, 8).unwrap();
+        let mut chain = PostProcess::new(8, 8).unwrap();
+        let mut half_invert = Effect::new("half_invert", HALF_FS).unwrap();
+        let invert = Shader::from_source(EFFECT_VS, INVERT_FS).unwrap();
+
+        // Three passes so the offscreen targets are swapped twice
+        half_invert.add_pass(Resource::new(invert), Uniforms::new());
+        chain.push(half_invert);
+        chain.push(Effect::new("half", HALF_FS).unwrap());
+
+        let image = scene
+            .render(|target| {
+                target.set_clear_color(Color::red());
+                target.clear();
+                chain.apply(target).unwrap();
+            })
+            .unwrap();
+        // Red halved, inverted then halved, any other order give another color
+        let expected = ImageBuffer::from_pixel(8, 8, Rgba([64, 128, 128, 255]));
+
+        assert!(compare(&image, &expected, 2).unwrap().is_same());
+    }
+}
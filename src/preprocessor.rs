@@ -0,0 +1,755 @@
+//
+//  Rust file | 2018
+//  Author: Alexandre Fourcat
+//  preprocessor.rs
+//  module:
+//! GLSL preprocessor.
+//! Resolve the `#include "file"` of the shaders and inject defines right after the `#version`.
+//! An included path is looked up next to the file including it, then inside the virtual
+//! sources where gust put its snippets (`gust/layout.glsl`, `gust/vertex.glsl`,
+//! `gust/instanced.glsl`, `gust/fragment.glsl` and `gust/effect.glsl`).
+//! The conditional directives are left to the glsl compiler, so a file included inside
+//! an `#ifdef` is always read. A file is included every time, unless it declares
+//! `#pragma once` (include guards are left to the compiler too).
+//! Every line of the result remember where it comes from, the errors of the compiler
+//! are given with the original files and lines.
+//! ```no_run
+//! use gust::preprocessor::{Preprocessor, ShaderVariants};
+//! use gust::window::Window;
+//!
+//! let _window = Window::new(800, 600, "Variants");
+//! let mut variants = ShaderVariants::new(Preprocessor::new(), "light.vert", "light.frag");
+//!
+//! // light.frag: #include "common.glsl" ... #ifdef SHADOWS ...
+//! let simple = variants.get(&[]).unwrap();
+//! let shadowed = variants.get(&[("SHADOWS", "1"), ("LIGHTS", "4")]).unwrap();
+//! ```
+
+use resources::Resource;
+use shader::{Shader, ShaderError};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Attributes of gust::vertex::Vertex given to the vertex shaders.
+static GUST_LAYOUT: &'static str = "#pragma once
+layout (location = 0) in vec2 aPos;
+layout (location = 1) in vec2 aTexCoord;
+layout (location = 2) in vec3 aColor;
+uniform mat4 projection;
+";
+
+/// Vertex shader of gust, MODEL is the name of the model matrix (transform by default).
+static GUST_VERTEX: &'static str = "#include \"gust/layout.glsl\"
+#ifndef MODEL
+#define MODEL transform
+#endif
+out vec3 ourColor;
+out vec2 TexCoord;
+uniform mat4 MODEL;
+#ifdef MULTI_TEXTURE
+layout (location = 9) in float aTextureIndex;
+flat out int TextureIndex;
+#endif
+
+void main()
+{
+   gl_Position = projection * MODEL * vec4(aPos.xy, 0.0, 1.0);
+   ourColor = aColor;
+   TexCoord = aTexCoord;
+#ifdef MULTI_TEXTURE
+   TextureIndex = int(aTextureIndex);
+#endif
+}
+";
+
+/// Vertex shader of the instanced draws, the unit quad is placed by the attributes of
+/// gust::vertex::Instance. MODEL is the name of the model matrix (transform by default).
+static GUST_INSTANCED: &'static str = "#include \"gust/layout.glsl\"
+#ifndef MODEL
+#define MODEL transform
+#endif
+layout (location = 3) in vec2 iPos;
+layout (location = 4) in vec2 iOrigin;
+layout (location = 5) in vec2 iScale;
+layout (location = 6) in float iRotation;
+layout (location = 7) in vec4 iTexRect;
+layout (location = 8) in vec4 iColor;
+out vec3 ourColor;
+out vec2 TexCoord;
+uniform mat4 MODEL;
+#ifdef MULTI_TEXTURE
+layout (location = 10) in float iTextureIndex;
+flat out int TextureIndex;
+#endif
+
+void main()
+{
+   vec2 local = aPos * iScale - iOrigin;
+   float c = cos(iRotation);
+   float s = sin(iRotation);
+   vec2 world = vec2(local.x * c - local.y * s, local.x * s + local.y * c) + iPos;
+
+   gl_Position = projection * MODEL * vec4(world, 0.0, 1.0);
+   ourColor = aColor * iColor.rgb;
+   TexCoord = iTexRect.xy + aTexCoord * iTexRect.zw;
+#ifdef MULTI_TEXTURE
+   TextureIndex = int(iTextureIndex);
+#endif
+}
+";
+
+/// Fragment shader of gust, the texture is ignored if NO_TEXTURE is defined.
+/// With ALPHA_TEST the fragments less opaque than the `threshold` uniform are discarded.
+/// With MULTI_TEXTURE the texture is chosen per vertex between TEXTURE_UNITS units (16 by default).
+static GUST_FRAGMENT: &'static str = "out vec4 FragColor;
+in vec3 ourColor;
+in vec2 TexCoord;
+#if defined(MULTI_TEXTURE)
+#ifndef TEXTURE_UNITS
+#define TEXTURE_UNITS 16
+#endif
+uniform sampler2D textures[TEXTURE_UNITS];
+flat in int TextureIndex;
+
+// Samplers can only be indexed by constants in glsl 330
+vec4 texel(vec2 uv)
+{
+   switch (TextureIndex) {
+   case 0: return texture(textures[0], uv);
+#if TEXTURE_UNITS > 1
+   case 1: return texture(textures[1], uv);
+#endif
+#if TEXTURE_UNITS > 2
+   case 2: return texture(textures[2], uv);
+#endif
+#if TEXTURE_UNITS > 3
+   case 3: return texture(textures[3], uv);
+#endif
+#if TEXTURE_UNITS > 4
+   case 4: return texture(textures[4], uv);
+#endif
+#if TEXTURE_UNITS > 5
+   case 5: return texture(textures[5], uv);
+#endif
+#if TEXTURE_UNITS > 6
+   case 6: return texture(textures[6], uv);
+#endif
+#if TEXTURE_UNITS > 7
+   case 7: return texture(textures[7], uv);
+#endif
+#if TEXTURE_UNITS > 8
+   case 8: return texture(textures[8], uv);
+#endif
+#if TEXTURE_UNITS > 9
+   case 9: return texture(textures[9], uv);
+#endif
+#if TEXTURE_UNITS > 10
+   case 10: return texture(textures[10], uv);
+#endif
+#if TEXTURE_UNITS > 11
+   case 11: return texture(textures[11], uv);
+#endif
+#if TEXTURE_UNITS > 12
+   case 12: return texture(textures[12], uv);
+#endif
+#if TEXTURE_UNITS > 13
+   case 13: return texture(textures[13], uv);
+#endif
+#if TEXTURE_UNITS > 14
+   case 14: return texture(textures[14], uv);
+#endif
+#if TEXTURE_UNITS > 15
+   case 15: return texture(textures[15], uv);
+#endif
+   }
+   return vec4(0.0);
+}
+#elif !defined(NO_TEXTURE)
+uniform sampler2D ourTexture;
+#endif
+#if defined(ALPHA_TEST)
+uniform float threshold;
+#endif
+
+void main()
+{
+#if defined(NO_TEXTURE)
+   FragColor = vec4(ourColor, 1.0);
+#elif defined(MULTI_TEXTURE)
+   FragColor = texel(TexCoord) * vec4(ourColor, 1.0);
+#else
+   FragColor = texture(ourTexture, TexCoord) * vec4(ourColor, 1.0);
+#endif
+#if defined(ALPHA_TEST)
+   if (FragColor.a < threshold)
+      discard;
+#endif
+}
+";
+
+/// Inputs of the post-processing passes, see gust::post_process.
+static GUST_EFFECT: &'static str = "in vec2 TexCoord;
+out vec4 FragColor;
+uniform sampler2D previous;
+uniform sampler2D source;
+uniform sampler2D scene;
+uniform vec2 resolution;
+uniform float time;
+";
+
+/// Line of an original file, file is None when unknown.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SourceLine {
+    pub file: Option<String>,
+    pub line: u32,
+}
+
+impl fmt::Display for SourceLine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.file {
+            Some(ref file) => write!(f, "{}:{}", file, self.line),
+            None => write!(f, "line {}", self.line),
+        }
+    }
+}
+
+//----------------------------------------------------------------------------
+//
+//
+//                             SOURCE : STRUCT
+//
+//
+//----------------------------------------------------------------------------
+
+/// Preprocessed glsl code ready to be compiled.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Source {
+    code: String,
+    lines: Vec<SourceLine>,
+    files: Vec<PathBuf>,
+    /// Files declaring `#pragma once`, their next includes are skipped.
+    /// The files on disk are known by their canonical path.
+    once: HashSet<String>,
+}
+
+impl Source {
+    fn push(&mut self, code: &str, file: &str, line: usize) {
+        self.code.push_str(code);
+        self.code.push('\n');
+        self.lines.push(SourceLine {
+            file: Some(file.to_string()),
+            line: line as u32,
+        });
+    }
+
+    pub fn code(&self) -> &str {
+        &self.code
+    }
+
+    /// Where the line of the code (starting at 1) comes from.
+    pub fn origin(&self, line: u32) -> Option<&SourceLine> {
+        if line == 0 {
+            return None;
+        }
+        self.lines.get(line as usize - 1)
+    }
+
+    /// Files read on disk to make the code.
+    pub fn files(&self) -> &[PathBuf] {
+        &self.files
+    }
+}
+
+//----------------------------------------------------------------------------
+//
+//
+//                             PREPROCESSOR : STRUCT
+//
+//
+//----------------------------------------------------------------------------
+
+/// Defines and virtual sources used to preprocess shaders.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Preprocessor {
+    defines: Vec<(String, String)>,
+    sources: HashMap<String, String>,
+}
+
+impl Preprocessor {
+    /// A preprocessor without defines knowing the gust snippets.
+    pub fn new() -> Preprocessor {
+        let mut preprocessor = Preprocessor {
+            defines: Vec::new(),
+            sources: HashMap::new(),
+        };
+
+        preprocessor.add_source("gust/layout.glsl", GUST_LAYOUT);
+        preprocessor.add_source("gust/vertex.glsl", GUST_VERTEX);
+        preprocessor.add_source("gust/instanced.glsl", GUST_INSTANCED);
+        preprocessor.add_source("gust/fragment.glsl", GUST_FRAGMENT);
+        preprocessor.add_source("gust/effect.glsl", GUST_EFFECT);
+        preprocessor
+    }
+
+    /// Inject `#define name value` in every shader, replacing the previous value.
+    pub fn define<T: ToString>(&mut self, name: &str, value: T) {
+        let value = value.to_string();
+
+        match self.defines.iter_mut().find(|define| define.0 == name) {
+            Some(define) => define.1 = value,
+            None => self.defines.push((name.to_string(), value)),
+        }
+    }
+
+    pub fn undefine(&mut self, name: &str) {
+        self.defines.retain(|define| define.0 != name);
+    }
+
+    pub fn defines(&self) -> &[(String, String)] {
+        &self.defines
+    }
+
+    /// Add a file that can be included without being on disk.
+    pub fn add_source(&mut self, name: &str, code: &str) {
+        self.sources.insert(name.to_string(), code.to_string());
+    }
+
+    /// Preprocess a file.
+    pub fn load<P: AsRef<Path>>(&self, path: P) -> Result<Source, ShaderError> {
+        let path = path.as_ref();
+        let code = fs::read_to_string(path)?;
+        let mut source = Source::default();
+
+        source.files.push(path.to_path_buf());
+        self.expand(
+            &path.display().to_string(),
+            &fs::canonicalize(path)?.display().to_string(),
+            path.parent(),
+            &code,
+            &mut source,
+            &mut Vec::new(),
+        )?;
+        Ok(source)
+    }
+
+    /// Preprocess code, name is the file given in the errors.
+    /// The includes are looked up in the virtual sources then in the current directory.
+    pub fn process(&self, name: &str, code: &str) -> Result<Source, ShaderError> {
+        let mut source = Source::default();
+
+        self.expand(name, name, None, code, &mut source, &mut Vec::new())?;
+        Ok(source)
+    }
+
+    /// Expand the includes of code, name is given in the origins and key in the include checks.
+    fn expand(
+        &self,
+        name: &str,
+        key: &str,
+        dir: Option<&Path>,
+        code: &str,
+        source: &mut Source,
+        stack: &mut Vec<String>,
+    ) -> Result<(), ShaderError> {
+        // The defines go after the #version that has to be first, past the comments
+        let start = if stack.is_empty() {
+            Some(first_code_line(code).unwrap_or((0, false)))
+        } else {
+            None
+        };
+        stack.push(key.to_string());
+
+        for (index, line) in code.lines().enumerate() {
+            let number = index + 1;
+
+            if let Some((_, version)) = start.filter(|start| start.0 == index) {
+                if version {
+                    source.push(line, name, number);
+                }
+                for (define, value) in &self.defines {
+                    source.push(&format!("#define {} {}", define, value), name, number);
+                }
+                if version {
+                    continue;
+                }
+            }
+
+            // Dropped from the code, the glsl compilers don't know it
+            if is_pragma_once(line) {
+                source.once.insert(key.to_string());
+                continue;
+            }
+            let include = match parse_include(line) {
+                Some(include) => include,
+                None => {
+                    source.push(line, name, number);
+                    continue;
+                }
+            };
+            let error = || ShaderError::Include {
+                file: name.to_string(),
+                line: number as u32,
+                include: include.to_string(),
+            };
+            let (path, code) = self.resolve(dir, include).ok_or_else(error)?;
+            // The same file can be reached by several paths, like `a/../b.glsl` and `b.glsl`
+            let (included, key) = match path {
+                Some(ref path) => (
+                    path.display().to_string(),
+                    fs::canonicalize(path)?.display().to_string(),
+                ),
+                None => (include.to_string(), include.to_string()),
+            };
+
+            if source.once.contains(&key) {
+                continue;
+            }
+            if stack.contains(&key) {
+                return Err(error());
+            }
+            if let Some(ref path) = path {
+                if !source.files.contains(path) {
+                    source.files.push(path.clone());
+                }
+            }
+            let dir = path.as_ref().and_then(|path| path.parent());
+            self.expand(&included, &key, dir, &code, source, stack)?;
+        }
+        stack.pop();
+        Ok(())
+    }
+
+    /// Find an included file next to the current one or inside the virtual sources.
+    fn resolve(&self, dir: Option<&Path>, include: &str) -> Option<(Option<PathBuf>, String)> {
+        let path = dir.unwrap_or_else(|| Path::new("")).join(include);
+
+        match (dir, self.sources.get(include)) {
+            (None, Some(code)) => Some((None, code.clone())),
+            (Some(_), Some(code)) if !path.is_file() => Some((None, code.clone())),
+            _ => fs::read_to_string(&path)
+                .ok()
+                .map(|code| (Some(path), code)),
+        }
+    }
+}
+
+impl Default for Preprocessor {
+    fn default() -> Preprocessor {
+        Preprocessor::new()
+    }
+}
+
+/// Index of the first line that isn't blank or a comment, and if it's the `#version`.
+fn first_code_line(code: &str) -> Option<(usize, bool)> {
+    let mut comment = false;
+
+    for (index, line) in code.lines().enumerate() {
+        let mut rest = line.trim();
+        loop {
+            if comment {
+                match rest.find("*/") {
+                    Some(end) => rest = rest[end + 2..].trim_start(),
+                    None => break,
+                }
+                comment = false;
+            } else if rest.is_empty() || rest.starts_with("//") {
+                break;
+            } else if rest.starts_with("/*") {
+                rest = &rest[2..];
+                comment = true;
+            } else {
+                return Some((index, rest.starts_with("#version")));
+            }
+        }
+    }
+    None
+}
+
+fn is_pragma_once(line: &str) -> bool {
+    let line = line.trim();
+
+    line.starts_with('#')
+        && line[1..]
+            .split_whitespace()
+            .eq(["pragma", "once"].iter().cloned())
+}
+
+/// Path of an `#include "path"` or `#include <path>` line.
+fn parse_include(line: &str) -> Option<&str> {
+    let rest = line.trim_start();
+    if !rest.starts_with('#') {
+        return None;
+    }
+    let rest = rest[1..].trim_start();
+    if !rest.starts_with("include") {
+        return None;
+    }
+    let rest = rest["include".len()..].trim();
+    let close = match rest.chars().next() {
+        Some('"') => '"',
+        Some('<') => '>',
+        _ => return None,
+    };
+
+    rest[1..].find(close).map(|end| &rest[1..=end])
+}
+
+//----------------------------------------------------------------------------
+//
+//
+//                             SHADERVARIANTS : STRUCT
+//
+//
+//----------------------------------------------------------------------------
+
+#[derive(Debug, Clone)]
+enum Input {
+    File(PathBuf),
+    Code(String),
+}
+
+/// Shaders made of the same sources with different defines.
+/// Each set of defines is compiled once, the first time it's asked.
+#[derive(Debug)]
+pub struct ShaderVariants {
+    preprocessor: Preprocessor,
+    vert: Input,
+    frag: Input,
+    shaders: HashMap<Vec<(String, String)>, Resource<Shader>>,
+}
+
+impl ShaderVariants {
+    /// Variants of the shader files.
+    pub fn new<P: AsRef<Path>, Q: AsRef<Path>>(
+        preprocessor: Preprocessor,
+        vert: P,
+        frag: Q,
+    ) -> ShaderVariants {
+        ShaderVariants {
+            preprocessor,
+            vert: Input::File(vert.as_ref().to_path_buf()),
+            frag: Input::File(frag.as_ref().to_path_buf()),
+            shaders: HashMap::new(),
+        }
+    }
+
+    /// Variants of the shader code.
+    pub fn from_source(preprocessor: Preprocessor, vert: &str, frag: &str) -> ShaderVariants {
+        ShaderVariants {
+            preprocessor,
+            vert: Input::Code(vert.to_string()),
+            frag: Input::Code(frag.to_string()),
+            shaders: HashMap::new(),
+        }
+    }
+
+    /// The shader compiled with the defines added to the ones of the preprocessor.
+    pub fn get(&mut self, defines: &[(&str, &str)]) -> Result<Resource<Shader>, ShaderError> {
+        let mut key: Vec<(String, String)> = defines
+            .iter()
+            .map(|&(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        key.sort();
+
+        if let Some(shader) = self.shaders.get(&key) {
+            return Ok(Resource::clone(shader));
+        }
+
+        let mut preprocessor = self.preprocessor.clone();
+        for &(name, value) in defines {
+            preprocessor.define(name, value);
+        }
+        let process = |input: &Input, name: &str| match input {
+            Input::File(path) => preprocessor.load(path),
+            Input::Code(code) => preprocessor.process(name, code),
+        };
+        let shader = Resource::new(Shader::from_preprocessed(
+            &process(&self.vert, "vertex")?,
+            &process(&self.frag, "fragment")?,
+        )?);
+
+        self.shaders.insert(key, Resource::clone(&shader));
+        Ok(shader)
+    }
+
+    /// Number of variants compiled.
+    pub fn len(&self) -> usize {
+        self.shaders.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.shaders.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Preprocessor, SourceLine};
+    use shader::ShaderError;
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn include_and_defines_keep_the_origin() {
+        let mut preprocessor = Preprocessor::new();
+        preprocessor.add_source(
+            "common.glsl",
+            "float twice(float x) {\n    return x * 2.0;\n}",
+        );
+        preprocessor.define("LIGHTS", 4);
+
+        let source = preprocessor
+            .process(
+                "main.frag",
+                "#version 330 core\n#include \"common.glsl\"\nvoid main() {}",
+            )
+            .unwrap();
+
+        assert_eq!(
+            source.code(),
+            "#version 330 core\n#define LIGHTS 4\nfloat twice(float x) {\n    return x * 2.0;\n}\nvoid main() {}\n"
+        );
+        assert_eq!(
+            source.origin(4),
+            Some(&SourceLine {
+                file: Some("common.glsl".to_string()),
+                line: 2,
+            })
+        );
+        assert_eq!(
+            source.origin(6),
+            Some(&SourceLine {
+                file: Some("main.frag".to_string()),
+                line: 3,
+            })
+        );
+    }
+
+    #[test]
+    fn defines_go_after_a_version_behind_comments() {
+        let mut preprocessor = Preprocessor::new();
+        preprocessor.define("LIGHTS", 4);
+
+        let source = preprocessor
+            .process(
+                "main.frag",
+                "// MIT licence\n\n/* light\n   shader */\n#version 330 core\nvoid main() {}",
+            )
+            .unwrap();
+        assert_eq!(
+            source.code(),
+            "// MIT licence\n\n/* light\n   shader */\n#version 330 core\n#define LIGHTS 4\nvoid main() {}\n"
+        );
+
+        // Without #version the defines go first
+        let source = preprocessor.process("main.frag", "void main() {}").unwrap();
+        assert_eq!(source.code(), "#define LIGHTS 4\nvoid main() {}\n");
+    }
+
+    #[test]
+    fn pragma_once_files_are_included_once() {
+        let mut preprocessor = Preprocessor::new();
+        preprocessor.add_source("common.glsl", "#pragma once\nfloat twice(float x);");
+        preprocessor.add_source("light.glsl", "#include \"common.glsl\"\nvec3 light();");
+        preprocessor.add_source("shadow.glsl", "#include \"common.glsl\"\nfloat shadow();");
+
+        let source = preprocessor
+            .process(
+                "main.frag",
+                "#include \"light.glsl\"\n#include \"shadow.glsl\"\nvoid main() {}",
+            )
+            .unwrap();
+        assert_eq!(
+            source.code(),
+            "float twice(float x);\nvec3 light();\nfloat shadow();\nvoid main() {}\n"
+        );
+        assert_eq!(
+            source.origin(3),
+            Some(&SourceLine {
+                file: Some("shadow.glsl".to_string()),
+                line: 2,
+            })
+        );
+    }
+
+    #[test]
+    fn includes_inside_conditionals_dont_hide_the_next_ones() {
+        let mut preprocessor = Preprocessor::new();
+        preprocessor.add_source("a.glsl", "float a();");
+
+        let source = preprocessor
+            .process(
+                "main.frag",
+                "#ifdef X\n#include \"a.glsl\"\n#endif\n#include \"a.glsl\"\nvoid main() {}",
+            )
+            .unwrap();
+        assert_eq!(
+            source.code(),
+            "#ifdef X\nfloat a();\n#endif\nfloat a();\nvoid main() {}\n"
+        );
+    }
+
+    #[test]
+    fn missing_and_recursive_includes_fail() {
+        let mut preprocessor = Preprocessor::new();
+        preprocessor.add_source("loop.glsl", "#include \"loop.glsl\"");
+
+        match preprocessor.process("main.vert", "\n#include <missing.glsl>") {
+            Err(ShaderError::Include {
+                file,
+                line,
+                include,
+            }) => {
+                assert_eq!(
+                    (file.as_str(), line, include.as_str()),
+                    ("main.vert", 2, "missing.glsl")
+                );
+            }
+            result => panic!("Unexpected {:?}", result),
+        }
+        assert!(preprocessor
+            .process("main.vert", "#include \"loop.glsl\"")
+            .is_err());
+    }
+
+    #[test]
+    fn includes_are_known_by_their_canonical_path() {
+        let dir = env::temp_dir().join("gust_includes");
+        fs::create_dir_all(dir.join("lib")).unwrap();
+        fs::write(
+            dir.join("common.glsl"),
+            "#pragma once\nfloat twice(float x);",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("lib/light.glsl"),
+            "#include \"../common.glsl\"\nvec3 light();",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("main.frag"),
+            "#include \"common.glsl\"\n#include \"lib/light.glsl\"\nvoid main() {}",
+        )
+        .unwrap();
+        fs::write(dir.join("lib/loop.glsl"), "#include \"../lib/loop.glsl\"").unwrap();
+        fs::write(dir.join("loop.frag"), "#include \"lib/loop.glsl\"").unwrap();
+
+        let preprocessor = Preprocessor::new();
+        let source = preprocessor.load(dir.join("main.frag")).unwrap();
+        assert_eq!(
+            source.code(),
+            "float twice(float x);\nvec3 light();\nvoid main() {}\n"
+        );
+        assert_eq!(source.files().len(), 3);
+        match preprocessor.load(dir.join("loop.frag")) {
+            Err(ShaderError::Include { line, include, .. }) => {
+                assert_eq!((line, include.as_str()), (1, "../lib/loop.glsl"))
+            }
+            result => panic!("Unexpected {:?}", result),
+        }
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
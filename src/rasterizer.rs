@@ -0,0 +1,1583 @@
+//
+//  Rust file | 2018
+//  Author: Alexandre Fourcat
+//  rasterizer.rs
+//  module:
+//! Software rasterizer, draw into an RGBA buffer without OpenGl.
+//! A Rasterizer is a Drawer backed by a RasterDevice keeping every object in memory,
+//! so Sprite, Text or SpriteBatch can be rendered on a server without any gpu to make
+//! thumbnails, or inside deterministic tests. Like with a window, the objects drawn have
+//! to be made while the rasterizer is active.
+//! ```no_run
+//! use gust::prelude::*;
+//! use gust::rasterizer::Rasterizer;
+//! use std::rc::Rc;
+//!
+//! let mut raster = Rasterizer::new(64, 64);
+//! let _active = raster.active();
+//! let texture = Rc::new(Texture::from_path("examples/texture/Dirt.png").unwrap());
+//! let sprite = Sprite::from(&texture);
+//!
+//! raster.clear();
+//! raster.draw(&sprite);
+//! raster.image().save("thumbnail.png").unwrap();
+//! ```
+//! Plain vertices can be drawn too with draw_vertices, without any gpu object.
+
+use color::Color;
+use command::Uniform;
+use device;
+use device::{
+    ActiveVariable, DepthTest, DeviceGuard, RenderDevice, ShaderStage, StencilTest,
+};
+use draw::{BlendEquation, BlendFactor, BlendFunc, BlendMode, Context, Drawable, DrawableMut, Drawer};
+use gl;
+use image::imageops;
+use image::{Rgba, RgbaImage};
+use nalgebra::{Matrix4, Vector4};
+use rect::Rect;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ffi::CStr;
+use std::mem;
+use std::rc::Rc;
+use texture::{Filter, RgbMode, Wrap};
+use vertex::{Instance, Vertex};
+use vertex_buffer::{BufferUsage, Primitive};
+use view::View;
+use Vector;
+
+//----------------------------------------------------------------------------
+//
+//
+//                             RASTERIZER : STRUCT
+//
+//
+//----------------------------------------------------------------------------
+
+/// Rasterizer is a drawing target living in memory.
+#[derive(Debug)]
+pub struct Rasterizer {
+    device: Rc<RasterDevice>,
+    /// Context of the built-in shaders made on the device.
+    context: usize,
+    view: View,
+    clear_color: Color,
+    blend_mode: BlendMode,
+}
+
+impl Rasterizer {
+    /// Create a rasterizer of width * height pixels with a view covering it.
+    pub fn new(width: u32, height: u32) -> Rasterizer {
+        let device = Rc::new(RasterDevice::new(width, height));
+
+        device.init(width, height);
+        Rasterizer {
+            device,
+            context: device::new_context(),
+            view: View::from(Rect::new(0.0, 0.0, width as f32, height as f32)),
+            clear_color: Color::black(),
+            blend_mode: BlendMode::Alpha,
+        }
+    }
+
+    /// Make the device of the rasterizer the current one until the guard is dropped.
+    /// The textures, sprites... drawn on the rasterizer have to be made meanwhile,
+    /// declaring them after the guard is enough.
+    pub fn active(&self) -> RasterGuard {
+        let context = device::context();
+
+        device::set_context(self.context);
+        RasterGuard {
+            context,
+            _device: device::scoped(self.device.clone()),
+        }
+    }
+
+    /// Set clear color
+    pub fn set_clear_color(&mut self, new_color: Color) {
+        self.clear_color = new_color;
+    }
+
+    /// Fill the whole buffer with the clear color
+    pub fn clear(&mut self) {
+        self.device.state.borrow_mut().clear(0, self.clear_color);
+    }
+
+    /// Set the blend mode used by the next draw_vertices, same factors as the gl ones.
+    /// The drawables keep their own.
+    pub fn set_blend_mode(&mut self, blend_mode: BlendMode) {
+        self.blend_mode = blend_mode;
+    }
+
+    pub fn blend_mode(&self) -> BlendMode {
+        self.blend_mode
+    }
+
+    pub fn set_view(&mut self, mut view: View) {
+        view.update();
+        self.view = view;
+    }
+
+    pub fn view(&self) -> &View {
+        &self.view
+    }
+
+    /// Return the rendered image (top-left origin like Window::capture).
+    pub fn image(&self) -> RgbaImage {
+        imageops::flip_vertical(&self.device.state.borrow().screen)
+    }
+
+    /// Consume the rasterizer and return the rendered image.
+    pub fn into_image(self) -> RgbaImage {
+        self.image()
+    }
+
+    /// Draw vertices with a primitive type, a model transform and maybe a texture.
+    /// Texture coordinates are sampled like the gl textures (linear filter, repeat wrap).
+    pub fn draw_vertices(
+        &mut self,
+        vertice: &[Vertex],
+        primitive: Primitive,
+        transform: &Matrix4<f32>,
+        texture: Option<&RgbaImage>,
+    ) {
+        let mut state = self.device.state.borrow_mut();
+        let (w, h) = state.screen.dimensions();
+        let viewport = [0, 0, w as i32, h as i32];
+        let mvp = self.view.projection() * transform;
+        let frags: Vec<Fragment> = vertice
+            .iter()
+            .map(|v| Fragment::new(project(&mvp, v.pos, viewport), v.tex, v.color, 0.0))
+            .collect();
+        let shade = |frag: &Fragment| {
+            let texel =
+                texture.map_or([1.0; 4], |t| sample(t, frag.tex, Filter::Linear, Wrap::Repeat));
+
+            Some(modulate(texel, frag.color))
+        };
+
+        Pipeline {
+            pixels: &mut state.screen,
+            planes: None,
+            clip: viewport,
+            blend: self.blend_mode.func(),
+            front: None,
+            stencil: None,
+            depth: None,
+            shade: &shade,
+        }
+        .primitives(&frags, primitive);
+    }
+}
+
+impl Drawer for Rasterizer {
+    fn draw<T: Drawable>(&mut self, drawable: &T) {
+        let _active = self.active();
+        drawable.draw(self);
+    }
+
+    fn draw_mut<T: DrawableMut>(&mut self, drawable: &mut T) {
+        let _active = self.active();
+        drawable.draw_mut(self);
+    }
+
+    fn draw_with_context<T: Drawable>(&mut self, drawable: &mut T, context: &mut Context) {
+        let _active = self.active();
+        context.set_projection(self.view.projection());
+        drawable.draw_with_context(context);
+    }
+
+    fn draw_with_context_mut<T: DrawableMut>(&mut self, drawable: &mut T, context: &mut Context) {
+        let _active = self.active();
+        context.set_projection(self.view.projection());
+        drawable.draw_with_context_mut(context);
+    }
+
+    fn get_sizes(&self) -> Vector<f32> {
+        let (w, h) = self.device.state.borrow().screen.dimensions();
+
+        Vector::new(w as f32, h as f32)
+    }
+
+    fn get_center(&self) -> Vector<f32> {
+        let sizes = self.get_sizes();
+        let view_pos = self.view.postition();
+        let view_zoom = self.view.get_zoom();
+
+        Vector::new(
+            (sizes.x / (2.0 * (1.0 / view_zoom))) + view_pos.x,
+            (sizes.y / (2.0 * (1.0 / view_zoom))) + view_pos.y,
+        )
+    }
+
+    fn projection(&self) -> &Matrix4<f32> {
+        self.view.projection()
+    }
+}
+
+impl Drop for Rasterizer {
+    /// The built-in shaders are deleted through the device.
+    fn drop(&mut self) {
+        let _active = self.active();
+        device::drop_context(self.context);
+    }
+}
+
+/// Give back the previous device and context when dropped, see Rasterizer::active.
+pub struct RasterGuard {
+    context: usize,
+    _device: DeviceGuard,
+}
+
+impl Drop for RasterGuard {
+    fn drop(&mut self) {
+        device::set_context(self.context);
+    }
+}
+
+//----------------------------------------------------------------------------
+//
+//
+//                             RASTERDEVICE : STRUCT
+//
+//
+//----------------------------------------------------------------------------
+
+/// A device drawing in memory, the framebuffer 0 is its screen.
+/// Glsl isn't run: a program is drawn like the built-in shader it's made of, read from
+/// the defines of its sources (MODEL, MULTI_TEXTURE, NO_TEXTURE and ALPHA_TEST) and the
+/// instanced snippet. So the effects of the custom shaders are ignored.
+/// Rows are stored from the bottom like gl does.
+#[derive(Debug)]
+pub struct RasterDevice {
+    state: RefCell<State>,
+}
+
+impl RasterDevice {
+    /// Create a device with a screen of width * height pixels.
+    pub fn new(width: u32, height: u32) -> RasterDevice {
+        RasterDevice {
+            state: RefCell::new(State {
+                next_id: 0,
+                buffers: HashMap::new(),
+                vertex_arrays: HashMap::new(),
+                textures: HashMap::new(),
+                shaders: HashMap::new(),
+                programs: HashMap::new(),
+                framebuffers: HashMap::new(),
+                planes: HashMap::new(),
+                screen: RgbaImage::new(width, height),
+                units: HashMap::new(),
+                program: 0,
+                framebuffer: 0,
+                viewport: [0, 0, width as i32, height as i32],
+                clockwise: false,
+                blend: None,
+                scissor: None,
+                stencil: None,
+                depth: None,
+            }),
+        }
+    }
+
+    fn draw(
+        &self,
+        vertex_array: u32,
+        primitive: Primitive,
+        first: usize,
+        count: usize,
+        instances: Option<usize>,
+    ) {
+        let state = &mut *self.state.borrow_mut();
+        let framebuffer = state.framebuffer;
+        // Taken out of the state to read the textures while drawing
+        let mut pixels = match state.target() {
+            Some(target) => mem::replace(target, RgbaImage::new(0, 0)),
+            None => return,
+        };
+        let mut planes = state.planes.remove(&framebuffer).unwrap_or_default();
+
+        planes.fit(pixels.width() as usize * pixels.height() as usize);
+        state.rasterize(
+            &mut pixels,
+            &mut planes,
+            vertex_array,
+            primitive,
+            first..first + count,
+            instances,
+        );
+        state.planes.insert(framebuffer, planes);
+        if let Some(target) = state.target() {
+            *target = pixels;
+        }
+    }
+}
+
+/// Everything made on a RasterDevice and its states.
+#[derive(Debug)]
+struct State {
+    next_id: u32,
+    buffers: HashMap<u32, Buffer>,
+    vertex_arrays: HashMap<u32, Layout>,
+    textures: HashMap<u32, Surface>,
+    /// Sources of the shaders, until they are linked.
+    shaders: HashMap<u32, String>,
+    programs: HashMap<u32, Program>,
+    /// Texture drawn on by each framebuffer.
+    framebuffers: HashMap<u32, u32>,
+    /// Depth and stencil buffers, by framebuffer.
+    planes: HashMap<u32, Planes>,
+    screen: RgbaImage,
+    /// Texture bound on each unit.
+    units: HashMap<u32, u32>,
+    program: u32,
+    framebuffer: u32,
+    viewport: [i32; 4],
+    clockwise: bool,
+    blend: Option<BlendFunc>,
+    scissor: Option<[i32; 4]>,
+    stencil: Option<StencilTest>,
+    depth: Option<DepthTest>,
+}
+
+impl State {
+    fn id(&mut self) -> u32 {
+        self.next_id += 1;
+        self.next_id
+    }
+
+    /// Pixels of the framebuffer bound.
+    fn target(&mut self) -> Option<&mut RgbaImage> {
+        match self.framebuffer {
+            0 => Some(&mut self.screen),
+            framebuffer => {
+                let texture = self.framebuffers.get(&framebuffer)?;
+                self.textures.get_mut(texture).map(|surface| &mut surface.pixels)
+            }
+        }
+    }
+
+    fn clear(&mut self, framebuffer: u32, color: Color) {
+        let Color(r, g, b, a) = color;
+        let color = Rgba([to_u8(r), to_u8(g), to_u8(b), to_u8(a)]);
+        let previous = mem::replace(&mut self.framebuffer, framebuffer);
+
+        if let Some(pixels) = self.target() {
+            for pixel in pixels.pixels_mut() {
+                *pixel = color;
+            }
+        }
+        self.planes.remove(&framebuffer);
+        self.framebuffer = previous;
+    }
+
+    /// Draw the vertices of the range with the program used, the states and the textures.
+    fn rasterize(
+        &self,
+        pixels: &mut RgbaImage,
+        planes: &mut Planes,
+        vertex_array: u32,
+        primitive: Primitive,
+        range: ::std::ops::Range<usize>,
+        instances: Option<usize>,
+    ) {
+        let (program, layout) = match (
+            self.programs.get(&self.program),
+            self.vertex_arrays.get(&vertex_array),
+        ) {
+            (Some(program), Some(layout)) => (program, layout),
+            _ => return,
+        };
+        let vertices = match layout.vertices.and_then(|buffer| self.buffers.get(&buffer)) {
+            Some(Buffer::Vertices(vertices)) => vertices,
+            _ => return,
+        };
+        let vertices = &vertices[range.start.min(vertices.len())..range.end.min(vertices.len())];
+        let indices = match layout.indices.and_then(|buffer| self.buffers.get(&buffer)) {
+            Some(Buffer::Indices(indices)) => indices.as_slice(),
+            _ => &[],
+        };
+        let mvp = program.matrix("projection") * program.matrix(&program.model);
+        let threshold = program.float("threshold").unwrap_or(0.0);
+        let shade = |frag: &Fragment| {
+            let texel = if program.textured {
+                let unit = if program.multi_texture {
+                    frag.flat as u32
+                } else {
+                    0
+                };
+                self.units
+                    .get(&unit)
+                    .and_then(|texture| self.textures.get(texture))
+                    .map_or([0.0, 0.0, 0.0, 1.0], |surface| surface.sample(frag.tex))
+            } else {
+                [1.0; 4]
+            };
+            let color = modulate(texel, frag.color);
+
+            if program.alpha_test && color[3] < threshold {
+                None
+            } else {
+                Some(color)
+            }
+        };
+        let (w, h) = pixels.dimensions();
+        let mut clip = intersect(self.viewport, [0, 0, w as i32, h as i32]);
+        if let Some(scissor) = self.scissor {
+            clip = intersect(clip, scissor);
+        }
+        let mut pipeline = Pipeline {
+            pixels,
+            planes: Some(planes),
+            clip,
+            blend: self.blend,
+            front: Some(self.clockwise),
+            stencil: self.stencil,
+            depth: self.depth,
+            shade: &shade,
+        };
+
+        let (buffer, first) = match (instances, program.instanced, layout.instances) {
+            (Some(_), true, Some(instancing)) => instancing,
+            (None, false, _) => {
+                let frags: Vec<Fragment> = vertices
+                    .iter()
+                    .enumerate()
+                    .map(|(i, v)| {
+                        let index = indices.get(range.start + i).cloned().unwrap_or(0.0);
+                        Fragment::new(project(&mvp, v.pos, self.viewport), v.tex, v.color, index)
+                    })
+                    .collect();
+                return pipeline.primitives(&frags, primitive);
+            }
+            _ => return,
+        };
+        let instances = match self.buffers.get(&buffer) {
+            Some(Buffer::Instances(data)) => data.iter().skip(first).take(instances.unwrap_or(0)),
+            _ => return,
+        };
+        for instance in instances {
+            let (s, c) = instance.rotation.sin_cos();
+            let rect = instance.tex_rect;
+            let frags: Vec<Fragment> = vertices
+                .iter()
+                .map(|v| {
+                    // Same placement as the instanced snippet of the preprocessor
+                    let local = v.pos.component_mul(&instance.scale) - instance.origin;
+                    let world = Vector::new(local.x * c - local.y * s, local.x * s + local.y * c);
+                    let tex = Vector::new(rect.x + v.tex.x * rect.z, rect.y + v.tex.y * rect.w);
+                    let color = Color(
+                        v.color.0 * instance.color.0,
+                        v.color.1 * instance.color.1,
+                        v.color.2 * instance.color.2,
+                        1.0,
+                    );
+
+                    Fragment::new(
+                        project(&mvp, world + instance.pos, self.viewport),
+                        tex,
+                        color,
+                        instance.texture_index,
+                    )
+                })
+                .collect();
+            pipeline.primitives(&frags, primitive);
+        }
+    }
+}
+
+/// Data of a buffer, given by the first upload.
+#[derive(Debug, Clone)]
+enum Buffer {
+    Vertices(Vec<Vertex>),
+    Instances(Vec<Instance>),
+    Indices(Vec<f32>),
+}
+
+/// Buffers linked to a vertex array.
+#[derive(Debug, Clone, Copy, Default)]
+struct Layout {
+    vertices: Option<u32>,
+    /// Texture index of each vertex.
+    indices: Option<u32>,
+    /// Buffer of the instances and the first one drawn.
+    instances: Option<(u32, usize)>,
+}
+
+/// Pixels of a texture with the way they are sampled.
+#[derive(Debug, Clone)]
+struct Surface {
+    pixels: RgbaImage,
+    wrap: Wrap,
+    filter: Filter,
+}
+
+impl Surface {
+    fn sample(&self, coord: Vector<f32>) -> [f32; 4] {
+        sample(&self.pixels, coord, self.filter, self.wrap)
+    }
+}
+
+/// Depth and stencil buffers of a target.
+#[derive(Debug, Clone, Default)]
+struct Planes {
+    depth: Vec<f32>,
+    stencil: Vec<u8>,
+}
+
+impl Planes {
+    /// Give cleared buffers of len pixels if they don't have this size.
+    fn fit(&mut self, len: usize) {
+        if self.depth.len() != len {
+            self.depth = vec![1.0; len];
+            self.stencil = vec![0; len];
+        }
+    }
+
+    /// Run the stencil and the depth tests on a pixel like GlDevice set them,
+    /// and write the pixel if it pass them.
+    fn test(&mut self, index: usize, stencil: Option<StencilTest>, depth: Option<DepthTest>) -> bool {
+        if let Some(test) = stencil {
+            if self.stencil[index] & test.mask != test.reference & test.mask {
+                return false;
+            }
+        }
+        if let Some(test) = depth {
+            if test.depth > self.depth[index] {
+                return false;
+            }
+            if test.write {
+                self.depth[index] = test.depth;
+            }
+        }
+        if let Some(test) = stencil {
+            let bits = &mut self.stencil[index];
+            *bits = (*bits & !test.write_mask) | (test.reference & test.write_mask);
+        }
+        true
+    }
+}
+
+/// A program known by the built-in shader it's made of.
+#[derive(Debug, Clone, Default)]
+struct Program {
+    /// The unit quad is placed by the instances.
+    instanced: bool,
+    /// Name of the model matrix.
+    model: String,
+    textured: bool,
+    multi_texture: bool,
+    alpha_test: bool,
+    /// Names of the uniforms by location, a location is given to every name asked.
+    locations: Vec<String>,
+    uniforms: HashMap<String, Uniform>,
+}
+
+impl Program {
+    fn new(vert: &str, frag: &str) -> Program {
+        Program {
+            instanced: vert.contains(" iPos;"),
+            model: define(vert, "MODEL").unwrap_or("transform").to_string(),
+            textured: define(frag, "NO_TEXTURE").is_none(),
+            multi_texture: define(frag, "MULTI_TEXTURE").is_some(),
+            alpha_test: define(frag, "ALPHA_TEST").is_some(),
+            ..Program::default()
+        }
+    }
+
+    fn set(&mut self, location: i32, value: Uniform) {
+        if location < 0 {
+            return;
+        }
+        if let Some(name) = self.locations.get(location as usize) {
+            self.uniforms.insert(name.clone(), value);
+        }
+    }
+
+    /// Value of a mat4 uniform, the identity if it's not set.
+    fn matrix(&self, name: &str) -> Matrix4<f32> {
+        match self.uniforms.get(name) {
+            Some(Uniform::Matrix(values)) if values.len() == 16 => {
+                Matrix4::from_column_slice(values)
+            }
+            _ => Matrix4::identity(),
+        }
+    }
+
+    fn float(&self, name: &str) -> Option<f32> {
+        match self.uniforms.get(name) {
+            Some(Uniform::Floats(values)) => values.first().cloned(),
+            _ => None,
+        }
+    }
+}
+
+/// Value of a `#define` of the source, an empty one if it has no value.
+fn define<'a>(source: &'a str, name: &str) -> Option<&'a str> {
+    source.lines().find_map(|line| {
+        let mut words = line.split_whitespace();
+
+        if words.next() == Some("#define") && words.next() == Some(name) {
+            Some(words.next().unwrap_or(""))
+        } else {
+            None
+        }
+    })
+}
+
+/// Expand pixels of the mode to RGBA like gl does, the missing channels are 0 and alpha 1.
+fn to_rgba(data: &[u8], mode: RgbMode) -> Vec<u8> {
+    let mut rgba = Vec::with_capacity(data.len() / mode.channels() * 4);
+
+    for texel in data.chunks(mode.channels()) {
+        let mut pixel = [0, 0, 0, 255];
+        pixel[..texel.len()].copy_from_slice(texel);
+        rgba.extend_from_slice(&pixel);
+    }
+    rgba
+}
+
+impl RenderDevice for RasterDevice {
+    fn create_buffer(&self) -> u32 {
+        self.state.borrow_mut().id()
+    }
+
+    fn buffer_data(&self, buffer: u32, data: &[Vertex], _usage: BufferUsage) {
+        self.state
+            .borrow_mut()
+            .buffers
+            .insert(buffer, Buffer::Vertices(data.to_vec()));
+    }
+
+    fn buffer_sub_data(&self, buffer: u32, offset: usize, data: &[Vertex]) {
+        if let Some(Buffer::Vertices(vertices)) = self.state.borrow_mut().buffers.get_mut(&buffer) {
+            if vertices.len() < offset + data.len() {
+                vertices.resize(offset + data.len(), Vertex::default());
+            }
+            vertices[offset..offset + data.len()].copy_from_slice(data);
+        }
+    }
+
+    fn orphan_buffer(&self, buffer: u32, len: usize, _usage: BufferUsage) {
+        self.state
+            .borrow_mut()
+            .buffers
+            .insert(buffer, Buffer::Vertices(vec![Vertex::default(); len]));
+    }
+
+    /// Nothing is drawn in the background, it's a simple write.
+    fn buffer_write_unsynchronized(&self, buffer: u32, offset: usize, data: &[Vertex]) {
+        self.buffer_sub_data(buffer, offset, data);
+    }
+
+    fn instance_data(&self, buffer: u32, data: &[Instance], _usage: BufferUsage) {
+        self.state
+            .borrow_mut()
+            .buffers
+            .insert(buffer, Buffer::Instances(data.to_vec()));
+    }
+
+    fn instance_sub_data(&self, buffer: u32, offset: usize, data: &[Instance]) {
+        if let Some(Buffer::Instances(instances)) =
+            self.state.borrow_mut().buffers.get_mut(&buffer)
+        {
+            if instances.len() < offset + data.len() {
+                instances.resize(offset + data.len(), Instance::default());
+            }
+            instances[offset..offset + data.len()].copy_from_slice(data);
+        }
+    }
+
+    fn texture_index_data(&self, buffer: u32, data: &[f32]) {
+        self.state
+            .borrow_mut()
+            .buffers
+            .insert(buffer, Buffer::Indices(data.to_vec()));
+    }
+
+    fn bind_buffer(&self, _buffer: u32) {}
+
+    fn delete_buffer(&self, buffer: u32) {
+        self.state.borrow_mut().buffers.remove(&buffer);
+    }
+
+    fn create_vertex_array(&self) -> u32 {
+        let mut state = self.state.borrow_mut();
+        let id = state.id();
+
+        state.vertex_arrays.insert(id, Layout::default());
+        id
+    }
+
+    fn vertex_layout(&self, vertex_array: u32, buffer: u32) {
+        if let Some(layout) = self.state.borrow_mut().vertex_arrays.get_mut(&vertex_array) {
+            layout.vertices = Some(buffer);
+        }
+    }
+
+    fn texture_index_layout(&self, vertex_array: u32, buffer: u32) {
+        if let Some(layout) = self.state.borrow_mut().vertex_arrays.get_mut(&vertex_array) {
+            layout.indices = Some(buffer);
+        }
+    }
+
+    fn instance_layout(&self, vertex_array: u32, buffer: u32, first: usize) {
+        if let Some(layout) = self.state.borrow_mut().vertex_arrays.get_mut(&vertex_array) {
+            layout.instances = Some((buffer, first));
+        }
+    }
+
+    fn bind_vertex_array(&self, _vertex_array: u32) {}
+
+    fn delete_vertex_array(&self, vertex_array: u32) {
+        self.state.borrow_mut().vertex_arrays.remove(&vertex_array);
+    }
+
+    /// Draws are done when they return, every fence is already signaled.
+    fn fence(&self) -> usize {
+        self.state.borrow_mut().id() as usize
+    }
+
+    fn wait_fence(&self, _fence: usize, _timeout: u64) -> bool {
+        true
+    }
+
+    fn delete_fence(&self, _fence: usize) {}
+
+    fn create_texture(&self, data: &[u8], mode: RgbMode, width: u32, height: u32) -> u32 {
+        let mut state = self.state.borrow_mut();
+        let id = state.id();
+        let pixels = RgbaImage::from_raw(width, height, to_rgba(data, mode))
+            .unwrap_or_else(|| RgbaImage::new(width, height));
+
+        state.textures.insert(
+            id,
+            Surface {
+                pixels,
+                wrap: Wrap::Repeat,
+                filter: Filter::Linear,
+            },
+        );
+        id
+    }
+
+    fn update_texture(
+        &self,
+        texture: u32,
+        data: &[u8],
+        mode: RgbMode,
+        pos: Vector<u32>,
+        sizes: Vector<u32>,
+    ) {
+        let mut state = self.state.borrow_mut();
+        let surface = match state.textures.get_mut(&texture) {
+            Some(surface) => surface,
+            None => return,
+        };
+        let (w, h) = surface.pixels.dimensions();
+        let rgba = to_rgba(data, mode);
+
+        for (i, texel) in rgba.chunks(4).enumerate().take((sizes.x * sizes.y) as usize) {
+            let x = pos.x + i as u32 % sizes.x;
+            let y = pos.y + i as u32 / sizes.x;
+
+            if x < w && y < h {
+                surface.pixels.get_pixel_mut(x, y).data.copy_from_slice(texel);
+            }
+        }
+    }
+
+    fn read_texture(&self, texture: u32, mode: RgbMode, data: &mut [u8]) {
+        if let Some(surface) = self.state.borrow().textures.get(&texture) {
+            for (pixel, out) in surface
+                .pixels
+                .pixels()
+                .zip(data.chunks_mut(mode.channels()))
+            {
+                out.copy_from_slice(&pixel.data[..out.len()]);
+            }
+        }
+    }
+
+    fn copy_texture(&self, src: u32, dst: u32, mode: RgbMode, width: u32, height: u32) {
+        let mut data = vec![0; (width * height) as usize * mode.channels()];
+
+        self.read_texture(src, mode, &mut data);
+        self.update_texture(
+            dst,
+            &data,
+            mode,
+            Vector::new(0, 0),
+            Vector::new(width, height),
+        );
+    }
+
+    fn texture_wrap(&self, texture: u32, wrap: Wrap) {
+        if let Some(surface) = self.state.borrow_mut().textures.get_mut(&texture) {
+            surface.wrap = wrap;
+        }
+    }
+
+    fn texture_filter(&self, texture: u32, filter: Filter) {
+        if let Some(surface) = self.state.borrow_mut().textures.get_mut(&texture) {
+            surface.filter = filter;
+        }
+    }
+
+    fn bind_texture(&self, unit: u32, texture: u32) {
+        self.state.borrow_mut().units.insert(unit, texture);
+    }
+
+    /// The minimum given by opengl 3.3.
+    fn max_texture_units(&self) -> usize {
+        16
+    }
+
+    fn delete_texture(&self, texture: u32) {
+        self.state.borrow_mut().textures.remove(&texture);
+    }
+
+    /// The source is kept to know the built-in shader it's made of, nothing is compiled.
+    fn create_shader(&self, _stage: ShaderStage, source: &CStr) -> u32 {
+        let mut state = self.state.borrow_mut();
+        let id = state.id();
+
+        state
+            .shaders
+            .insert(id, source.to_string_lossy().into_owned());
+        id
+    }
+
+    fn shader_status(&self, _shader: u32) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn create_program(&self, vert: u32, frag: u32) -> u32 {
+        let mut state = self.state.borrow_mut();
+        let id = state.id();
+        let program = {
+            let source = |shader| state.shaders.get(&shader).map_or("", |s| s.as_str());
+            Program::new(source(vert), source(frag))
+        };
+
+        state.programs.insert(id, program);
+        id
+    }
+
+    fn program_status(&self, _program: u32) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn use_program(&self, program: u32) {
+        self.state.borrow_mut().program = program;
+    }
+
+    fn uniform_location(&self, program: u32, name: &str) -> i32 {
+        let mut state = self.state.borrow_mut();
+        let program = match state.programs.get_mut(&program) {
+            Some(program) => program,
+            None => return -1,
+        };
+
+        match program.locations.iter().position(|known| known == name) {
+            Some(location) => location as i32,
+            None => {
+                program.locations.push(name.to_string());
+                program.locations.len() as i32 - 1
+            }
+        }
+    }
+
+    /// Glsl isn't parsed, no variable is known.
+    fn active_uniforms(&self, _program: u32) -> Vec<ActiveVariable> {
+        Vec::new()
+    }
+
+    fn active_attributes(&self, _program: u32) -> Vec<ActiveVariable> {
+        Vec::new()
+    }
+
+    fn uniform_floats(&self, location: i32, values: &[f32]) {
+        let state = &mut *self.state.borrow_mut();
+
+        if let Some(program) = state.programs.get_mut(&state.program) {
+            program.set(location, Uniform::Floats(values.to_vec()));
+        }
+    }
+
+    fn uniform_ints(&self, location: i32, values: &[i32]) {
+        let state = &mut *self.state.borrow_mut();
+
+        if let Some(program) = state.programs.get_mut(&state.program) {
+            program.set(location, Uniform::Ints(values.to_vec()));
+        }
+    }
+
+    fn uniform_matrix(&self, location: i32, values: &[f32]) {
+        let state = &mut *self.state.borrow_mut();
+
+        if let Some(program) = state.programs.get_mut(&state.program) {
+            program.set(location, Uniform::Matrix(values.to_vec()));
+        }
+    }
+
+    fn delete_shader(&self, shader: u32) {
+        self.state.borrow_mut().shaders.remove(&shader);
+    }
+
+    fn delete_program(&self, program: u32) {
+        self.state.borrow_mut().programs.remove(&program);
+    }
+
+    fn create_framebuffer(&self, texture: u32) -> Result<u32, u32> {
+        let mut state = self.state.borrow_mut();
+
+        if !state.textures.contains_key(&texture) {
+            return Err(gl::FRAMEBUFFER_INCOMPLETE_ATTACHMENT);
+        }
+        let id = state.id();
+        state.framebuffers.insert(id, texture);
+        Ok(id)
+    }
+
+    fn bind_framebuffer(&self, framebuffer: u32) {
+        self.state.borrow_mut().framebuffer = framebuffer;
+    }
+
+    fn framebuffer(&self) -> u32 {
+        self.state.borrow().framebuffer
+    }
+
+    fn read_pixels(&self, width: u32, height: u32, data: &mut [u8]) {
+        let mut state = self.state.borrow_mut();
+        let pixels = match state.target() {
+            Some(pixels) => pixels,
+            None => return,
+        };
+        let rows = data.chunks_mut(width as usize * 4).take(height as usize);
+
+        for (y, row) in rows.enumerate() {
+            for (x, out) in row.chunks_mut(4).enumerate() {
+                if (x as u32) < pixels.width() && (y as u32) < pixels.height() {
+                    out.copy_from_slice(&pixels.get_pixel(x as u32, y as u32).data);
+                }
+            }
+        }
+    }
+
+    fn delete_framebuffer(&self, framebuffer: u32) {
+        let mut state = self.state.borrow_mut();
+
+        state.framebuffers.remove(&framebuffer);
+        state.planes.remove(&framebuffer);
+        if state.framebuffer == framebuffer {
+            state.framebuffer = 0;
+        }
+    }
+
+    fn init(&self, width: u32, height: u32) {
+        let mut state = self.state.borrow_mut();
+
+        state.viewport = [0, 0, width as i32, height as i32];
+        state.blend = BlendMode::Alpha.func();
+    }
+
+    fn viewport(&self) -> [i32; 4] {
+        self.state.borrow().viewport
+    }
+
+    fn set_viewport(&self, viewport: [i32; 4]) {
+        self.state.borrow_mut().viewport = viewport;
+    }
+
+    /// The back faces are always culled, like the gl context set by init.
+    fn front_face(&self, clockwise: bool) {
+        self.state.borrow_mut().clockwise = clockwise;
+    }
+
+    fn is_clockwise(&self) -> bool {
+        self.state.borrow().clockwise
+    }
+
+    fn blend(&self, mode: &BlendMode) {
+        self.state.borrow_mut().blend = mode.func();
+    }
+
+    fn disable_blend(&self) {
+        self.state.borrow_mut().blend = None;
+    }
+
+    fn scissor(&self, rect: Option<[i32; 4]>) {
+        self.state.borrow_mut().scissor = rect;
+    }
+
+    fn stencil(&self, test: Option<StencilTest>) {
+        self.state.borrow_mut().stencil = test;
+    }
+
+    fn depth(&self, test: Option<DepthTest>) {
+        self.state.borrow_mut().depth = test;
+    }
+
+    fn clear_stencil(&self, bits: u8) {
+        let state = &mut *self.state.borrow_mut();
+
+        state.scissor = None;
+        if let Some(planes) = state.planes.get_mut(&state.framebuffer) {
+            for stencil in &mut planes.stencil {
+                *stencil &= !bits;
+            }
+        }
+    }
+
+    fn clear(&self, color: Color) {
+        let mut state = self.state.borrow_mut();
+        let framebuffer = state.framebuffer;
+
+        state.scissor = None;
+        state.clear(framebuffer, color);
+    }
+
+    fn draw_arrays(&self, vertex_array: u32, primitive: Primitive, first: usize, count: usize) {
+        self.draw(vertex_array, primitive, first, count, None);
+    }
+
+    fn draw_arrays_instanced(
+        &self,
+        vertex_array: u32,
+        primitive: Primitive,
+        first: usize,
+        count: usize,
+        instances: usize,
+    ) {
+        self.draw(vertex_array, primitive, first, count, Some(instances));
+    }
+
+    fn flush(&self) {}
+}
+
+//----------------------------------------------------------------------------
+//
+//
+//                             PIPELINE : STRUCT
+//
+//
+//----------------------------------------------------------------------------
+
+/// A vertex once projected in pixel space, from the bottom left corner.
+#[derive(Debug, Clone, Copy)]
+struct Fragment {
+    pos: Vector<f32>,
+    tex: Vector<f32>,
+    color: [f32; 4],
+    /// Not interpolated, the value of the last vertex of the primitive is used.
+    flat: f32,
+}
+
+impl Fragment {
+    /// The alpha of the vertices is ignored like in the gust shaders.
+    fn new(pos: Vector<f32>, tex: Vector<f32>, color: Color, flat: f32) -> Fragment {
+        Fragment {
+            pos,
+            tex,
+            color: [color.0, color.1, color.2, 1.0],
+            flat,
+        }
+    }
+}
+
+/// Where and how the fragments of a draw are written.
+struct Pipeline<'a> {
+    pixels: &'a mut RgbaImage,
+    planes: Option<&'a mut Planes>,
+    /// Only the pixels inside [x, y, width, height] are drawn.
+    clip: [i32; 4],
+    blend: Option<BlendFunc>,
+    /// Winding of the front faces, clockwise or not, the back ones are culled.
+    /// None draw both.
+    front: Option<bool>,
+    stencil: Option<StencilTest>,
+    depth: Option<DepthTest>,
+    /// Color of a fragment, None if it's discarded.
+    shade: &'a Fn(&Fragment) -> Option<[f32; 4]>,
+}
+
+impl<'a> Pipeline<'a> {
+    fn primitives(&mut self, frags: &[Fragment], primitive: Primitive) {
+        match primitive {
+            Primitive::Triangles => {
+                for tri in frags.chunks(3).filter(|x| x.len() == 3) {
+                    self.triangle(&tri[0], &tri[1], &tri[2]);
+                }
+            }
+            Primitive::TrianglesStrip => {
+                // Every other triangle is reversed to keep the winding of the strip
+                for (i, tri) in frags.windows(3).enumerate() {
+                    if i % 2 == 0 {
+                        self.triangle(&tri[0], &tri[1], &tri[2]);
+                    } else {
+                        self.triangle(&tri[1], &tri[0], &tri[2]);
+                    }
+                }
+            }
+            Primitive::TriangleFan => {
+                if let Some((center, rest)) = frags.split_first() {
+                    for pair in rest.windows(2) {
+                        self.triangle(center, &pair[0], &pair[1]);
+                    }
+                }
+            }
+            Primitive::Quads => {
+                for quad in frags.chunks(4).filter(|x| x.len() == 4) {
+                    self.triangle(&quad[0], &quad[1], &quad[2]);
+                    self.triangle(&quad[0], &quad[2], &quad[3]);
+                }
+            }
+            Primitive::Lines => {
+                for line in frags.chunks(2).filter(|x| x.len() == 2) {
+                    self.line(&line[0], &line[1]);
+                }
+            }
+            Primitive::Points => {
+                for frag in frags {
+                    self.plot(frag.pos.x as i64, frag.pos.y as i64, frag);
+                }
+            }
+        }
+    }
+
+    /// Fill a triangle, only its front face if the back ones are culled.
+    /// Pixels centers on a shared edge are only drawn once (top-left rule)
+    /// so the blending of a quad diagonal stays right.
+    fn triangle(&mut self, a: &Fragment, b: &Fragment, c: &Fragment) {
+        let area = edge(a.pos, b.pos, c.pos);
+        if area == 0.0 {
+            return;
+        }
+        // The y axis goes up so the counter clockwise faces have a positive area
+        if self.front == Some(area > 0.0) {
+            return;
+        }
+        let flat = c.flat;
+        let (b, c, area) = if area < 0.0 {
+            (c, b, -area)
+        } else {
+            (b, c, area)
+        };
+
+        let (w, h) = self.pixels.dimensions();
+        let min_x = a.pos.x.min(b.pos.x).min(c.pos.x).floor().max(0.0) as u32;
+        let min_y = a.pos.y.min(b.pos.y).min(c.pos.y).floor().max(0.0) as u32;
+        let max_x = (a.pos.x.max(b.pos.x).max(c.pos.x).ceil().max(0.0) as u32).min(w);
+        let max_y = (a.pos.y.max(b.pos.y).max(c.pos.y).ceil().max(0.0) as u32).min(h);
+
+        for y in min_y..max_y {
+            for x in min_x..max_x {
+                let p = Vector::new(x as f32 + 0.5, y as f32 + 0.5);
+                let e0 = edge(b.pos, c.pos, p);
+                let e1 = edge(c.pos, a.pos, p);
+                let e2 = edge(a.pos, b.pos, p);
+
+                if !inside(e0, c.pos - b.pos)
+                    || !inside(e1, a.pos - c.pos)
+                    || !inside(e2, b.pos - a.pos)
+                {
+                    continue;
+                }
+                let (w0, w1, w2) = (e0 / area, e1 / area, e2 / area);
+                let frag = Fragment {
+                    pos: p,
+                    tex: a.tex * w0 + b.tex * w1 + c.tex * w2,
+                    color: [
+                        a.color[0] * w0 + b.color[0] * w1 + c.color[0] * w2,
+                        a.color[1] * w0 + b.color[1] * w1 + c.color[1] * w2,
+                        a.color[2] * w0 + b.color[2] * w1 + c.color[2] * w2,
+                        1.0,
+                    ],
+                    flat,
+                };
+                self.plot(i64::from(x), i64::from(y), &frag);
+            }
+        }
+    }
+
+    /// Draw a line with a simple DDA interpolating fragments.
+    fn line(&mut self, a: &Fragment, b: &Fragment) {
+        let delta = b.pos - a.pos;
+        let steps = delta.x.abs().max(delta.y.abs()).ceil().max(1.0) as usize;
+
+        for i in 0..=steps {
+            let t = i as f32 / steps as f32;
+            let frag = Fragment {
+                pos: a.pos + delta * t,
+                tex: a.tex + (b.tex - a.tex) * t,
+                color: [
+                    a.color[0] + (b.color[0] - a.color[0]) * t,
+                    a.color[1] + (b.color[1] - a.color[1]) * t,
+                    a.color[2] + (b.color[2] - a.color[2]) * t,
+                    1.0,
+                ],
+                flat: b.flat,
+            };
+            self.plot(frag.pos.x as i64, frag.pos.y as i64, &frag);
+        }
+    }
+
+    /// Shade a fragment, test it and blend it with the blend mode.
+    fn plot(&mut self, x: i64, y: i64, frag: &Fragment) {
+        let [left, bottom, width, height] = self.clip;
+        if x < i64::from(left.max(0))
+            || y < i64::from(bottom.max(0))
+            || x >= i64::from(left + width).min(i64::from(self.pixels.width()))
+            || y >= i64::from(bottom + height).min(i64::from(self.pixels.height()))
+        {
+            return;
+        }
+
+        let src = match (self.shade)(frag) {
+            Some(color) => color,
+            None => return,
+        };
+        let index = y as usize * self.pixels.width() as usize + x as usize;
+        if let Some(ref mut planes) = self.planes {
+            if !planes.test(index, self.stencil, self.depth) {
+                return;
+            }
+        }
+        // Only the stencil is written while drawing a mask
+        if self.stencil.filter(|test| test.write_mask != 0).is_some() {
+            return;
+        }
+
+        let pixel = self.pixels.get_pixel_mut(x as u32, y as u32);
+        let mut dst = [0.0; 4];
+
+        for (dst, channel) in dst.iter_mut().zip(pixel.data.iter()) {
+            *dst = f32::from(*channel) / 255.0;
+        }
+        for (i, channel) in pixel.data.iter_mut().enumerate() {
+            let out = self.blend.map_or(src[i], |func| blend(&func, &src, &dst, i));
+            *channel = to_u8(out);
+        }
+    }
+}
+
+/// Place a point in pixel space, from the bottom left corner of the viewport.
+fn project(mvp: &Matrix4<f32>, pos: Vector<f32>, viewport: [i32; 4]) -> Vector<f32> {
+    let ndc = mvp * Vector4::new(pos.x, pos.y, 0.0, 1.0);
+
+    Vector::new(
+        viewport[0] as f32 + (ndc.x / ndc.w + 1.0) / 2.0 * viewport[2] as f32,
+        viewport[1] as f32 + (ndc.y / ndc.w + 1.0) / 2.0 * viewport[3] as f32,
+    )
+}
+
+/// Intersection of two [x, y, width, height] rectangles.
+fn intersect(a: [i32; 4], b: [i32; 4]) -> [i32; 4] {
+    let x = a[0].max(b[0]);
+    let y = a[1].max(b[1]);
+    let right = (a[0] + a[2]).min(b[0] + b[2]);
+    let top = (a[1] + a[3]).min(b[1] + b[3]);
+
+    [x, y, (right - x).max(0), (top - y).max(0)]
+}
+
+/// Multiply a texel by the color of the fragment.
+fn modulate(texel: [f32; 4], color: [f32; 4]) -> [f32; 4] {
+    [
+        texel[0] * color[0],
+        texel[1] * color[1],
+        texel[2] * color[2],
+        texel[3] * color[3],
+    ]
+}
+
+/// Round a channel to the nearest u8 like the gl does.
+fn to_u8(value: f32) -> u8 {
+    (value.max(0.0).min(1.0) * 255.0).round() as u8
+}
+
+/// Blend the channel i of src and dst like glBlendFuncSeparate / glBlendEquationSeparate.
+fn blend(func: &BlendFunc, src: &[f32; 4], dst: &[f32; 4], i: usize) -> f32 {
+    let (src_factor, dst_factor, equation) = if i < 3 {
+        (func.color_src, func.color_dst, func.color_equation)
+    } else {
+        (func.alpha_src, func.alpha_dst, func.alpha_equation)
+    };
+    let factor = |factor: BlendFactor| match factor {
+        BlendFactor::Zero => 0.0,
+        BlendFactor::One => 1.0,
+        BlendFactor::SrcColor => src[i],
+        BlendFactor::OneMinusSrcColor => 1.0 - src[i],
+        BlendFactor::DstColor => dst[i],
+        BlendFactor::OneMinusDstColor => 1.0 - dst[i],
+        BlendFactor::SrcAlpha => src[3],
+        BlendFactor::OneMinusSrcAlpha => 1.0 - src[3],
+        BlendFactor::DstAlpha => dst[3],
+        BlendFactor::OneMinusDstAlpha => 1.0 - dst[3],
+    };
+    let s = src[i] * factor(src_factor);
+    let d = dst[i] * factor(dst_factor);
+
+    // Min and max ignore the factors
+    match equation {
+        BlendEquation::Add => s + d,
+        BlendEquation::Subtract => s - d,
+        BlendEquation::ReverseSubtract => d - s,
+        BlendEquation::Min => src[i].min(dst[i]),
+        BlendEquation::Max => src[i].max(dst[i]),
+    }
+}
+
+/// Signed area of the parallelogram (a, b, p).
+fn edge(a: Vector<f32>, b: Vector<f32>, p: Vector<f32>) -> f32 {
+    (b.x - a.x) * (p.y - a.y) - (b.y - a.y) * (p.x - a.x)
+}
+
+/// Check the side of a point regarding an edge, points on the edge
+/// are inside only for one of the two triangles sharing it.
+fn inside(value: f32, edge: Vector<f32>) -> bool {
+    value > 0.0 || (value == 0.0 && (edge.y > 0.0 || (edge.y == 0.0 && edge.x < 0.0)))
+}
+
+/// Index of the texel i of a row or a column of len texels with the wrap mode.
+fn wrap_texel(i: i64, len: i64, wrap: Wrap) -> u32 {
+    let index = match wrap {
+        Wrap::Repeat => (i % len + len) % len,
+        Wrap::MirroredRepeat => {
+            let i = (i % (2 * len) + 2 * len) % (2 * len);
+            if i < len {
+                i
+            } else {
+                2 * len - 1 - i
+            }
+        }
+        Wrap::ClampToEdge => i.max(0).min(len - 1),
+    };
+    index as u32
+}
+
+/// Sample like gl::LINEAR or gl::NEAREST with the wrap mode.
+fn sample(texture: &RgbaImage, coord: Vector<f32>, filter: Filter, wrap: Wrap) -> [f32; 4] {
+    let (w, h) = texture.dimensions();
+    if w == 0 || h == 0 {
+        return [1.0; 4];
+    }
+
+    let texel = |tx: f32, ty: f32| {
+        let tx = wrap_texel(tx as i64, i64::from(w), wrap);
+        let ty = wrap_texel(ty as i64, i64::from(h), wrap);
+        texture.get_pixel(tx, ty).data
+    };
+    let mut out = [0.0; 4];
+    if filter == Filter::Nearest {
+        let nearest = texel((coord.x * w as f32).floor(), (coord.y * h as f32).floor());
+
+        for (out, channel) in out.iter_mut().zip(nearest.iter()) {
+            *out = f32::from(*channel) / 255.0;
+        }
+        return out;
+    }
+
+    let x = coord.x * w as f32 - 0.5;
+    let y = coord.y * h as f32 - 0.5;
+    let (x0, y0) = (x.floor(), y.floor());
+    let (fx, fy) = (x - x0, y - y0);
+    let (p00, p10) = (texel(x0, y0), texel(x0 + 1.0, y0));
+    let (p01, p11) = (texel(x0, y0 + 1.0), texel(x0 + 1.0, y0 + 1.0));
+
+    for (i, out) in out.iter_mut().enumerate() {
+        let top = f32::from(p00[i]) * (1.0 - fx) + f32::from(p10[i]) * fx;
+        let bottom = f32::from(p01[i]) * (1.0 - fx) + f32::from(p11[i]) * fx;
+        *out = (top * (1.0 - fy) + bottom * fy) / 255.0;
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::Rasterizer;
+    use color::Color;
+    use draw::{BlendMode, Drawer, IDENTITY};
+    use image::{Rgba, RgbaImage};
+    use rect::Rect;
+    use render_texture::RenderTexture;
+    use sprite::Sprite;
+    use spritebatch::{SpriteBatch, SpriteData};
+    use std::rc::Rc;
+    use texture::Texture;
+    use transform::Movable;
+    use vertex::Vertex;
+    use vertex_buffer::Primitive;
+    use view::View;
+    use Vector;
+
+    fn quad(x: f32, y: f32, size: f32, color: Color) -> [Vertex; 4] {
+        [
+            Vertex::new(Vector::new(x, y), Vector::new(0.0, 0.0), color),
+            Vertex::new(Vector::new(x, y + size), Vector::new(0.0, 1.0), color),
+            Vertex::new(Vector::new(x + size, y), Vector::new(1.0, 0.0), color),
+            Vertex::new(
+                Vector::new(x + size, y + size),
+                Vector::new(1.0, 1.0),
+                color,
+            ),
+        ]
+    }
+
+    /// Pixels of the image inside [x, x + size[ * [y, y + size[ are red, the others black.
+    fn red_square(image: &RgbaImage, x: u32, y: u32, size: u32) -> bool {
+        image.enumerate_pixels().all(|(px, py, pixel)| {
+            let inside = px >= x && px < x + size && py >= y && py < y + size;
+
+            *pixel
+                == if inside {
+                    Rgba([255, 0, 0, 255])
+                } else {
+                    Rgba([0, 0, 0, 255])
+                }
+        })
+    }
+
+    #[test]
+    fn clear_fill_buffer() {
+        let mut raster = Rasterizer::new(4, 4);
+        raster.set_clear_color(Color::blue());
+        raster.clear();
+
+        assert!(raster
+            .image()
+            .pixels()
+            .all(|p| *p == Rgba([0, 0, 255, 255])));
+    }
+
+    #[test]
+    fn quad_cover_its_pixels() {
+        let mut raster = Rasterizer::new(8, 8);
+        raster.clear();
+        raster.draw_vertices(
+            &quad(2.0, 2.0, 4.0, Color::red()),
+            Primitive::TrianglesStrip,
+            &IDENTITY,
+            None,
+        );
+
+        assert!(red_square(&raster.image(), 2, 2, 4));
+    }
+
+    #[test]
+    fn texture_is_sampled_and_alpha_blended() {
+        let mut raster = Rasterizer::new(2, 2);
+        let texture = RgbaImage::from_pixel(2, 2, Rgba([255, 255, 255, 128]));
+        raster.clear();
+        raster.draw_vertices(
+            &quad(0.0, 0.0, 2.0, Color::green()),
+            Primitive::TrianglesStrip,
+            &IDENTITY,
+            Some(&texture),
+        );
+
+        let image = raster.image();
+        let pixel = image.get_pixel(0, 0);
+        assert_eq!(pixel.data[0], 0);
+        assert!(pixel.data[1] >= 127 && pixel.data[1] <= 129);
+    }
+
+    #[test]
+    fn view_translate_move_the_drawing() {
+        let mut raster = Rasterizer::new(8, 8);
+        let mut view = View::from(Rect::new(0.0, 0.0, 8.0, 8.0));
+        view.translate(Vector::new(2.0, 0.0));
+        raster.set_view(view);
+        raster.clear();
+        raster.draw_vertices(
+            &quad(2.0, 0.0, 2.0, Color::white()),
+            Primitive::TrianglesStrip,
+            &IDENTITY,
+            None,
+        );
+
+        let image = raster.image();
+        assert_eq!(*image.get_pixel(0, 0), Rgba([255, 255, 255, 255]));
+        assert_eq!(*image.get_pixel(2, 0), Rgba([0, 0, 0, 255]));
+    }
+
+    #[test]
+    fn blend_modes_combine_with_the_destination() {
+        let mut raster = Rasterizer::new(1, 1);
+        raster.set_clear_color(Color::new(0.5, 0.5, 0.5));
+
+        raster.clear();
+        raster.set_blend_mode(BlendMode::Add);
+        raster.draw_vertices(
+            &quad(0.0, 0.0, 1.0, Color::new(0.25, 0.0, 1.0)),
+            Primitive::TrianglesStrip,
+            &IDENTITY,
+            None,
+        );
+        assert_eq!(&raster.image().get_pixel(0, 0).data[..3], &[192, 128, 255]);
+
+        raster.clear();
+        raster.set_blend_mode(BlendMode::Multiply);
+        raster.draw_vertices(
+            &quad(0.0, 0.0, 1.0, Color::new(0.5, 0.0, 1.0)),
+            Primitive::TrianglesStrip,
+            &IDENTITY,
+            None,
+        );
+        assert_eq!(&raster.image().get_pixel(0, 0).data[..3], &[64, 0, 128]);
+    }
+
+    #[test]
+    fn sprite_is_drawn_without_gl() {
+        let mut raster = Rasterizer::new(8, 8);
+        let _active = raster.active();
+        let texture = Rc::new(Texture::from_color(Color::red(), Vector::new(4, 4)));
+        let mut sprite = Sprite::from(&texture);
+
+        sprite.set_position(Vector::new(2.0, 3.0));
+        raster.clear();
+        raster.draw_mut(&mut sprite);
+
+        assert!(red_square(&raster.image(), 2, 3, 4));
+    }
+
+    #[test]
+    fn spritebatch_is_drawn_without_gl() {
+        let mut raster = Rasterizer::new(8, 8);
+        let _active = raster.active();
+        let red = Rc::new(Texture::from_color(Color::red(), Vector::new(2, 2)));
+        let mut batch = SpriteBatch::from(&red);
+
+        batch.push_sprite(SpriteData::new(Vector::new(1.0, 5.0)));
+        raster.clear();
+        raster.draw_mut(&mut batch);
+
+        assert!(red_square(&raster.image(), 1, 5, 2));
+    }
+
+    #[test]
+    fn render_texture_is_drawn_then_shown() {
+        let mut raster = Rasterizer::new(8, 8);
+        let _active = raster.active();
+        let red = Rc::new(Texture::from_color(Color::red(), Vector::new(2, 2)));
+        let mut target = RenderTexture::new(4, 4).unwrap();
+        let mut square = Sprite::from(&red);
+        let mut shown = Sprite::from(target.texture());
+
+        // Drawn at the top left corner of the texture, with the flipped faces
+        target.set_clear_color(Color::black());
+        target.clear();
+        target.draw_mut(&mut square);
+        assert!(red_square(&target.capture().unwrap(), 0, 0, 2));
+
+        shown.set_position(Vector::new(4.0, 4.0));
+        raster.clear();
+        raster.draw_mut(&mut shown);
+        let image = raster.image();
+        assert_eq!(*image.get_pixel(4, 4), Rgba([255, 0, 0, 255]));
+        assert_eq!(*image.get_pixel(6, 6), Rgba([0, 0, 0, 255]));
+        assert_eq!(*image.get_pixel(3, 3), Rgba([0, 0, 0, 255]));
+    }
+}
@@ -0,0 +1,292 @@
+//
+//  Rust file | 2018
+//  Author: Alexandre Fourcat
+//  render_texture.rs
+//  module:
+//! RenderTexture is an offscreen target that can be drawn on like a window.
+//! The result is a simple texture that can be given to a Sprite or a SpriteBatch.
+//! ```no_run
+//! use gust::prelude::*;
+//! use gust::render_texture::RenderTexture;
+//!
+//! let mut window = Window::new(800, 600, "Minimap");
+//! let mut minimap = RenderTexture::new(200, 150).unwrap();
+//! let map = Sprite::from(minimap.texture());
+//!
+//! minimap.clear();
+//! // minimap.draw(&world);
+//! minimap.display();
+//!
+//! window.clear();
+//! window.draw(&map);
+//! window.display();
+//! ```
+
+use color::Color;
+use device;
+use draw;
+use draw::{Drawable, DrawableMut, Drawer};
+use handle::{Handle, Kind};
+use image;
+use image::ImageBuffer;
+use nalgebra::{Matrix4, Vector3};
+use rect::Rect;
+use resources::Resource;
+use std::error::Error;
+use std::fmt;
+use texture::Texture;
+use view::View;
+use Vector;
+
+/// A RenderTexture is a framebuffer object linked to a texture.
+/// Everything drawn on it end up inside the texture.
+#[derive(Debug)]
+pub struct RenderTexture {
+    fbo: Handle,
+    texture: Resource<Texture>,
+    view: View,
+    projection: Matrix4<f32>,
+    clear_color: Color,
+    /// Target and states bound before active, given back by release.
+    previous: (u32, [i32; 4], bool),
+}
+
+impl RenderTexture {
+    /// Create a new RenderTexture of width * height pixels.
+    pub fn new(width: u32, height: u32) -> Result<RenderTexture, RenderTextureError> {
+        let texture = Texture::from_color(
+            Color::new_alpha(0.0, 0.0, 0.0, 0.0),
+            Vector::new(width, height),
+        );
+        let fbo = device::current()
+            .create_framebuffer(texture.id())
+            .map_err(RenderTextureError::Incomplete)?;
+        let fbo = Handle::new(Kind::Framebuffer, fbo);
+
+        let mut render_texture = RenderTexture {
+            fbo,
+            texture: Resource::new(texture),
+            view: View::from(Rect::new(0.0, 0.0, width as f32, height as f32)),
+            projection: Matrix4::identity(),
+            clear_color: Color::new_alpha(0.0, 0.0, 0.0, 0.0),
+            previous: (0, [0; 4], false),
+        };
+        render_texture.update_projection();
+        Ok(render_texture)
+    }
+
+    /// Return the texture the RenderTexture is drawing on.
+    /// It can be given to a Sprite or a SpriteBatch.
+    pub fn texture(&self) -> &Resource<Texture> {
+        &self.texture
+    }
+
+    /// Framebuffer object of the texture.
+    pub(crate) fn framebuffer(&self) -> u32 {
+        self.fbo.id()
+    }
+
+    /// Simple getter for width
+    pub fn width(&self) -> u32 {
+        self.texture.width()
+    }
+
+    /// Simple getter for height
+    pub fn height(&self) -> u32 {
+        self.texture.height()
+    }
+
+    /// Set clear color
+    pub fn set_clear_color(&mut self, new_color: Color) {
+        self.clear_color = new_color;
+    }
+
+    /// Clear the texture with the clear color
+    pub fn clear(&mut self) {
+        self.active();
+        device::current().clear(self.clear_color);
+        self.release();
+    }
+
+    /// Make sure everything drawn is inside the texture.
+    pub fn display(&mut self) {
+        device::current().flush();
+    }
+
+    /// Read the content of the texture into an image with a top-left origin.
+    pub fn capture(&self) -> Result<image::RgbaImage, RenderTextureError> {
+        let data = self.texture.get_data();
+        let len = data.len();
+
+        ImageBuffer::from_vec(self.width(), self.height(), data)
+            .ok_or(RenderTextureError::Readback(len))
+    }
+
+    /// Set a new view and update the projection
+    pub fn set_view(&mut self, mut view: View) {
+        view.update();
+        self.view = view;
+        self.update_projection();
+    }
+
+    pub fn view(&self) -> &View {
+        &self.view
+    }
+
+    /// Bind the framebuffer and the viewport of the texture.
+    pub fn active(&mut self) {
+        let device = device::current();
+
+        self.previous = (device.framebuffer(), device.viewport(), device.is_clockwise());
+        device.bind_framebuffer(self.fbo.id());
+        device.set_viewport([0, 0, self.width() as i32, self.height() as i32]);
+        // The projection is flipped so the winding of the faces is too
+        device.front_face(true);
+    }
+
+    /// Give back the framebuffer, the viewport and the winding bound before active,
+    /// so a RenderTexture can be drawn on while another target is active.
+    pub fn release(&mut self) {
+        let device = device::current();
+        let (framebuffer, viewport, clockwise) = self.previous;
+
+        device.front_face(clockwise);
+        device.bind_framebuffer(framebuffer);
+        device.set_viewport(viewport);
+    }
+
+    /// OpenGl store textures from bottom to top so the view is flipped
+    /// to keep the texture in the same direction as the window.
+    fn update_projection(&mut self) {
+        self.projection =
+            Matrix4::new_nonuniform_scaling(&Vector3::new(1.0, -1.0, 1.0)) * self.view.projection();
+    }
+}
+
+impl Drawer for RenderTexture {
+    fn draw<T: Drawable>(&mut self, drawable: &T) {
+        self.active();
+        drawable.draw(self);
+        self.release();
+    }
+
+    #[inline]
+    fn draw_mut<T: DrawableMut>(&mut self, drawable: &mut T) {
+        self.active();
+        drawable.draw_mut(self);
+        self.release();
+    }
+
+    #[inline]
+    fn draw_with_context<T: Drawable>(&mut self, drawable: &mut T, context: &mut draw::Context) {
+        self.active();
+        context.set_projection(&self.projection);
+        drawable.draw_with_context(context);
+        self.release();
+    }
+
+    #[inline]
+    fn draw_with_context_mut<T: DrawableMut>(
+        &mut self,
+        drawable: &mut T,
+        context: &mut draw::Context,
+    ) {
+        self.active();
+        context.set_projection(&self.projection);
+        drawable.draw_with_context_mut(context);
+        self.release();
+    }
+
+    #[inline]
+    fn get_sizes(&self) -> Vector<f32> {
+        Vector::new(self.width() as f32, self.height() as f32)
+    }
+
+    #[inline]
+    fn get_center(&self) -> Vector<f32> {
+        let view_pos = self.view.postition();
+        let view_zoom = self.view.get_zoom();
+
+        Vector::new(
+            (self.width() as f32 / (2.0 * (1.0 / view_zoom))) + view_pos.x,
+            (self.height() as f32 / (2.0 * (1.0 / view_zoom))) + view_pos.y,
+        )
+    }
+
+    fn projection(&self) -> &Matrix4<f32> {
+        &self.projection
+    }
+}
+
+#[derive(Debug)]
+/// All error trigerable in RenderTexture
+pub enum RenderTextureError {
+    /// The framebuffer status returned by OpenGl
+    Incomplete(u32),
+    /// The bytes read back don't fill the image
+    Readback(usize),
+}
+
+impl fmt::Display for RenderTextureError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RenderTextureError::Incomplete(status) => write!(
+                f,
+                "The framebuffer of the RenderTexture is incomplete (status: {:#x}).",
+                status
+            ),
+            RenderTextureError::Readback(len) => write!(
+                f,
+                "Only {} bytes were read back from the RenderTexture.",
+                len
+            ),
+        }
+    }
+}
+
+impl Error for RenderTextureError {
+    fn cause(&self) -> Option<&Error> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::RenderTexture;
+    use device::{self, NullDevice, RenderDevice};
+    use std::rc::Rc;
+
+    #[test]
+    fn capture_keep_the_rows_of_the_texture() {
+        let _device = device::scoped(Rc::new(NullDevice::new()));
+        let target = RenderTexture::new(5, 3).unwrap();
+        let image = target.capture().unwrap();
+
+        // The projection is already flipped, rows stay in the texture order
+        let rows: Vec<u8> = (0..3).map(|y| image.get_pixel(4, y)[0]).collect();
+        assert_eq!(rows, vec![0, 1, 2]);
+        assert_eq!(image.dimensions(), (5, 3));
+        assert_eq!(image.into_raw().len(), 5 * 3 * 4);
+    }
+
+    #[test]
+    fn release_give_back_the_previous_target() {
+        let device = Rc::new(NullDevice::new());
+        let _device = device::scoped(device.clone());
+        let mut outer = RenderTexture::new(4, 4).unwrap();
+        let mut inner = RenderTexture::new(2, 2).unwrap();
+
+        device.set_viewport([0, 0, 8, 8]);
+        outer.active();
+        inner.active();
+        assert_eq!(device.framebuffer(), inner.framebuffer());
+        inner.release();
+        assert_eq!(device.framebuffer(), outer.framebuffer());
+        assert_eq!(device.viewport(), [0, 0, 4, 4]);
+        assert!(device.is_clockwise());
+        outer.release();
+        assert_eq!(device.framebuffer(), 0);
+        assert_eq!(device.viewport(), [0, 0, 8, 8]);
+        assert!(!device.is_clockwise());
+    }
+}
@@ -1,166 +1,157 @@
 //! Shader module
 
-use gl;
-use gl::types::*;
+use device;
+use device::{ActiveVariable, GlslType, RenderDevice, ShaderStage};
+use gl_error::{self, DebugType};
 use nalgebra::{Matrix2, Matrix3, Matrix4, Vector2, Vector3, Vector4};
-use std;
+use preprocessor::{Preprocessor, Source, SourceLine};
+use resources::Resource;
+use spritebatch::SpriteBatch;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::error::Error;
 use std::ffi::CString;
+use std::fmt;
 use std::fs::File;
 use std::io;
 use std::io::Read;
-use std::ptr;
-
-lazy_static! {
-    pub static ref DEFAULT_SHADER: Shader = Shader::default();
+use std::rc::Rc;
+use texture::Texture;
+
+pub static DEFAULT_SHADER: Builtin = Builtin {
+    vert: VS,
+    frag: || FS.to_string(),
+};
+
+pub static SPRITE_SHADER: Builtin = Builtin {
+    vert: VS,
+    frag: || FS.to_string(),
+};
+
+pub static BATCH_SHADER: Builtin = Builtin {
+    vert: BATCH_VS,
+    frag: || FS.to_string(),
+};
+
+pub static INSTANCED_SHADER: Builtin = Builtin {
+    vert: INSTANCED_VS,
+    frag: || FS.to_string(),
+};
+
+pub static MULTI_BATCH_SHADER: Builtin = Builtin {
+    vert: MULTI_BATCH_VS,
+    frag: multi_fs,
+};
+
+pub static MULTI_INSTANCED_SHADER: Builtin = Builtin {
+    vert: MULTI_INSTANCED_VS,
+    frag: multi_fs,
+};
+
+pub static NO_TEXTURE_SHADER: Builtin = Builtin {
+    vert: VS,
+    frag: || NO_TEXTURE_FS.to_string(),
+};
+
+/// Draw the stencil masks, the transparent texels are discarded.
+pub static MASK_SHADER: Builtin = Builtin {
+    vert: VS,
+    frag: || MASK_FS.to_string(),
+};
+
+/// Shader of gust compiled the first time it's used in each gl context, a program
+/// can't be used by another context. They are deleted with the window of the context.
+/// ```no_run
+/// use gust::shader::DEFAULT_SHADER;
+/// use gust::window::Window;
+///
+/// let _window = Window::headless(10, 10).unwrap();
+/// let shader = DEFAULT_SHADER.get();
+/// shader.activate();
+/// ```
+pub struct Builtin {
+    vert: &'static str,
+    frag: fn() -> String,
 }
 
-lazy_static! {
-    pub static ref SPRITE_SHADER: Shader = {
-        let (vert, frag, id);
-        unsafe {
-            let (v, f, i) = Shader::do_shader(
-                &CString::new(SPRITE_VS.as_bytes()).unwrap(),
-                &CString::new(SPRITE_FS.as_bytes()).unwrap(),
-            )
-            .unwrap();
-            frag = f;
-            vert = v;
-            id = i;
-        }
-        Shader { id, frag, vert }
-    };
-}
+impl Builtin {
+    /// Return the shader made inside the current context.
+    pub fn get(&self) -> Rc<Shader> {
+        let key = self as *const Builtin as usize;
 
-lazy_static! {
-    pub static ref BATCH_SHADER: Shader = {
-        let (vert, frag, id);
-        unsafe {
-            let (v, f, i) = Shader::do_shader(
-                &CString::new(BATCH_VS.as_bytes()).unwrap(),
-                &CString::new(FS.as_bytes()).unwrap(),
-            )
-            .unwrap();
-            vert = v;
-            frag = f;
-            id = i;
-        }
-        Shader {
-            id: id,
-            frag: frag,
-            vert: vert,
+        if let Some(shader) = device::with_objects(|objects| objects.shaders.get(&key).cloned()) {
+            return shader;
         }
-    };
-}
-
-lazy_static! {
-    pub static ref NO_TEXTURE_SHADER: Shader = {
-        let (vert, frag, id);
-        unsafe {
-            let (v, f, i) = Shader::do_shader(
-                &CString::new(VS.as_bytes()).unwrap(),
-                &CString::new(NO_TEXTURE_FS.as_bytes()).unwrap(),
-            )
-            .unwrap();
-            vert = v;
-            frag = f;
-            id = i;
-        }
-        Shader {
-            id: id,
-            frag: frag,
-            vert: vert,
-        }
-    };
+        // Made outside of the table, the fragment shader may need the texture units
+        let shader = Rc::new(Shader::from_source(self.vert, &(self.frag)()).unwrap());
+        device::with_objects(|objects| objects.shaders.insert(key, Rc::clone(&shader)));
+        shader
+    }
 }
 
 /// Shader object that abstract openGl type
-#[derive(Debug)]
 pub struct Shader {
-    id: u32,
-    vert: u32,
-    frag: u32,
+    /// Program id, changed by reload.
+    id: Cell<u32>,
+    /// Locations already asked.
+    locations: RefCell<HashMap<String, i32>>,
+    /// Device the programs were made on, the current one may have changed since.
+    device: Rc<RenderDevice>,
 }
 
-static SPRITE_VS: &'static str = "#version 330 core
-layout (location = 0) in vec2 aPos;
-layout (location = 1) in vec2 aTexCoord;
-layout (location = 2) in vec3 aColor;
-out vec3 ourColor;
-out vec2 TexCoord;
-uniform mat4 transform;
-uniform mat4 model;
-uniform mat4 projection;
-
-void main()
-{
-    gl_Position = projection * transform * vec4(aPos.xy, 0.0, 1.0);
-    ourColor = aColor;
-    TexCoord = aTexCoord;
-}";
-
-static SPRITE_FS: &'static str = "#version 330 core
-out vec4 FragColor;
-in vec3 ourColor;
-in vec2 TexCoord;
-uniform sampler2D ourTexture;
-
-void main()
-{
-   FragColor = texture(ourTexture, TexCoord) * vec4(ourColor, 1.0);
-}";
+// The built-in shaders are made of the gust snippets of the preprocessor
+
+static VS: &'static str = "#version 330 core
+#include \"gust/vertex.glsl\"
+";
 
 static BATCH_VS: &'static str = "#version 330 core
-layout (location = 0) in vec2 aPos;
-layout (location = 1) in vec2 aTexCoord;
-layout (location = 2) in vec3 aColor;
-out vec3 ourColor;
-out vec2 TexCoord;
-uniform mat4 glob_model;
-uniform mat4 projection;
-
-void main()
-{
-   gl_Position = projection * glob_model * vec4(aPos.xy, 0.0, 1.0);
-   ourColor = aColor;
-   TexCoord = aTexCoord;
-}
+#define MODEL glob_model
+#include \"gust/vertex.glsl\"
 ";
 
-static VS: &'static str = "#version 330 core
-layout (location = 0) in vec2 aPos;
-layout (location = 1) in vec2 aTexCoord;
-layout (location = 2) in vec3 aColor;
-out vec3 ourColor;
-out vec2 TexCoord;
-uniform mat4 transform;
-uniform mat4 projection;
-
-void main()
-{
-   gl_Position = projection * transform * vec4(aPos.xy, 0.0, 1.0);
-   ourColor = aColor;
-   TexCoord = aTexCoord;
-}";
+static INSTANCED_VS: &'static str = "#version 330 core
+#define MODEL glob_model
+#include \"gust/instanced.glsl\"
+";
+
+static MULTI_BATCH_VS: &'static str = "#version 330 core
+#define MODEL glob_model
+#define MULTI_TEXTURE
+#include \"gust/vertex.glsl\"
+";
+
+static MULTI_INSTANCED_VS: &'static str = "#version 330 core
+#define MODEL glob_model
+#define MULTI_TEXTURE
+#include \"gust/instanced.glsl\"
+";
 
 static FS: &'static str = "#version 330 core
-out vec4 FragColor;
-in vec3 ourColor;
-in vec2 TexCoord;
-uniform sampler2D ourTexture;
+#include \"gust/fragment.glsl\"
+";
 
-void main()
-{
-   FragColor = texture(ourTexture, TexCoord) * vec4(ourColor, 1.0);
-}";
+/// The sampler array is as big as the units bound by the sprite batches.
+fn multi_fs() -> String {
+    format!(
+        "#version 330 core
+#define MULTI_TEXTURE
+#define TEXTURE_UNITS {}
+#include \"gust/fragment.glsl\"
+",
+        SpriteBatch::texture_units()
+    )
+}
 
 static NO_TEXTURE_FS: &'static str = "#version 330 core
-out vec4 FragColor;
-in vec3 ourColor;
+#define NO_TEXTURE
+#include \"gust/fragment.glsl\"
+";
 
-void main()
-{
-   FragColor = vec4(ourColor, 1.0);
-}";
+static MASK_FS: &'static str = "#version 330 core
+#define ALPHA_TEST
+#include \"gust/fragment.glsl\"
+";
 
 /// Return a string from a filename
 pub fn file_to_cstring(name: &str) -> Result<CString, io::Error> {
@@ -173,197 +164,713 @@ impl Shader {
     // Constructors ---------------------------------------------------------------
 
     /// Create a new Shader from a filename of vertex and frag
-    pub fn new(vert: &str, frag: &str) -> Result<Shader, io::Error> {
-        let vert_source = file_to_cstring(vert)?;
-        let frag_source = file_to_cstring(frag)?;
-        let (vert_id, frag_id, id);
-
-        unsafe {
-            let (v, f, i) = Shader::do_shader(&vert_source, &frag_source)?;
-            vert_id = v;
-            frag_id = f;
-            id = i;
-        }
+    /// The files are preprocessed, see the preprocessor module.
+    pub fn new(vert: &str, frag: &str) -> Result<Shader, ShaderError> {
+        let preprocessor = Preprocessor::new();
+
+        Shader::from_preprocessed(&preprocessor.load(vert)?, &preprocessor.load(frag)?)
+    }
+
+    /// Create a new Shader from the glsl sources of vertex and frag
+    /// ```no_run
+    /// use gust::shader::Shader;
+    /// use gust::window::Window;
+    ///
+    /// let _window = Window::headless(10, 10).unwrap();
+    /// match Shader::from_source("void main() {}", "void main() { oops }") {
+    ///     Ok(_) => println!("Compiled"),
+    ///     Err(error) => println!("Error at lines {:?}: {}", error.lines(), error),
+    /// }
+    /// ```
+    pub fn from_source(vert: &str, frag: &str) -> Result<Shader, ShaderError> {
+        let (vert, frag) = Shader::process(vert, frag)?;
+
+        Shader::from_preprocessed(&vert, &frag)
+    }
 
+    /// Create a new Shader from sources given by a Preprocessor.
+    pub fn from_preprocessed(vert: &Source, frag: &Source) -> Result<Shader, ShaderError> {
+        let id = Shader::do_shader(vert, frag)?;
         Ok(Shader {
-            id,
-            frag: frag_id,
-            vert: vert_id,
+            id: Cell::new(id),
+            locations: RefCell::new(Shader::active_locations(id)),
+            device: device::current(),
         })
     }
 
-    /// Do all everything needed for shaders
-    unsafe fn do_shader(
-        vert_code: &CString,
-        frag_code: &CString,
-    ) -> Result<(u32, u32, u32), io::Error> {
-        let id = gl::CreateProgram();
-        let vert_id = Shader::compile_shader(&vert_code, gl::VERTEX_SHADER);
-        let frag_id = Shader::compile_shader(&frag_code, gl::FRAGMENT_SHADER);
-
-        gl::AttachShader(id, vert_id);
-        gl::AttachShader(id, frag_id);
-        gl::LinkProgram(id);
-
-        let mut status: i32 = 0;
-        let s: String = std::iter::repeat(' ').take(512).collect();
-        let info_log: CString = CString::new(s.as_bytes()).unwrap();
-        gl::GetProgramiv(id, gl::LINK_STATUS, &mut status);
-        if status == 0 {
-            gl::GetProgramInfoLog(id, 512, ptr::null_mut(), info_log.as_ptr() as *mut _);
-            println!("Could not link shaders {}", info_log.into_string().unwrap());
-        }
-        gl::DeleteShader(vert_id);
-        gl::DeleteShader(frag_id);
-        Ok((vert_id, frag_id, id))
-    }
-
-    /// Compile all shaders
-    unsafe fn compile_shader(code: &CString, gl_type: GLenum) -> u32 {
-        let id = gl::CreateShader(gl_type);
-        gl::ShaderSource(id, 1, &code.as_ptr(), ptr::null());
-        gl::CompileShader(id);
-        let mut success: i32 = 0;
-        let s: String = std::iter::repeat(' ').take(512).collect();
-        let info_log: CString = CString::new(s.as_bytes()).unwrap();
-        gl::GetShaderiv(id, gl::COMPILE_STATUS, &mut success);
-        if success == 0 {
-            gl::GetShaderInfoLog(id, 512, ptr::null_mut(), info_log.as_ptr() as *mut _);
-            println!(
-                "Could not compile shaders {}",
-                info_log.into_string().unwrap()
-            );
+    /// Replace the program by a new one made of the sources, every holder of the shader use it.
+    /// On failure the current program is kept.
+    pub fn reload(&self, vert: &str, frag: &str) -> Result<(), ShaderError> {
+        let (vert, frag) = Shader::process(vert, frag)?;
+
+        self.reload_preprocessed(&vert, &frag)
+    }
+
+    /// Same as reload with sources given by a Preprocessor.
+    pub fn reload_preprocessed(&self, vert: &Source, frag: &Source) -> Result<(), ShaderError> {
+        let id = Shader::do_shader(vert, frag)?;
+        let old = self.id.replace(id);
+
+        self.locations.replace(Shader::active_locations(id));
+        self.device.delete_program(old);
+        Ok(())
+    }
+
+    fn active_locations(id: u32) -> HashMap<String, i32> {
+        device::current()
+            .active_uniforms(id)
+            .into_iter()
+            .map(|uniform| (uniform.name, uniform.location))
+            .collect()
+    }
+
+    /// Compile and link the sources without keeping the program.
+    /// Useful to validate shaders in tests, a gl context is still needed.
+    pub fn check(vert: &str, frag: &str) -> Result<(), ShaderError> {
+        let (vert, frag) = Shader::process(vert, frag)?;
+        let id = Shader::do_shader(&vert, &frag)?;
+
+        device::current().delete_program(id);
+        Ok(())
+    }
+
+    fn process(vert: &str, frag: &str) -> Result<(Source, Source), ShaderError> {
+        let preprocessor = Preprocessor::new();
+
+        Ok((
+            preprocessor.process("vertex", vert)?,
+            preprocessor.process("fragment", frag)?,
+        ))
+    }
+
+    /// Compile both stages and link them in a program.
+    fn do_shader(vert: &Source, frag: &Source) -> Result<u32, ShaderError> {
+        let device = device::current();
+        let vert_id = Shader::compile_shader(vert, ShaderStage::Vertex)?;
+        let frag_id = match Shader::compile_shader(frag, ShaderStage::Fragment) {
+            Ok(id) => id,
+            Err(error) => {
+                device.delete_shader(vert_id);
+                return Err(error);
+            }
         };
-        id
+        let id = device.create_program(vert_id, frag_id);
+        let status = device.program_status(id);
+
+        // The program keep what it needs from the shaders
+        device.delete_shader(vert_id);
+        device.delete_shader(frag_id);
+        if let Err(log) = status {
+            device.delete_program(id);
+            return Err(ShaderError::Link {
+                lines: parse_lines(&log)
+                    .into_iter()
+                    .map(|line| SourceLine { file: None, line })
+                    .collect(),
+                log,
+            });
+        }
+        Ok(id)
+    }
+
+    /// Compile a stage
+    fn compile_shader(source: &Source, stage: ShaderStage) -> Result<u32, ShaderError> {
+        let code = CString::new(source.code().as_bytes()).map_err(|_| ShaderError::Nul(stage))?;
+        let device = device::current();
+        let id = device.create_shader(stage, &code);
+
+        if let Err(log) = device.shader_status(id) {
+            device.delete_shader(id);
+            // The lines of the log are the ones of the preprocessed code
+            let lines = parse_lines(&log)
+                .into_iter()
+                .map(|line| match source.origin(line) {
+                    Some(origin) => origin.clone(),
+                    None => SourceLine { file: None, line },
+                })
+                .collect();
+            return Err(ShaderError::Compile { stage, lines, log });
+        }
+        Ok(id)
     }
 
     // Usable ---------------------------------------------------------------------
 
+    /// Id of the program inside the device.
+    pub fn id(&self) -> u32 {
+        self.id.get()
+    }
+
     /// Activate the program
     pub fn activate(&self) {
-        unsafe {
-            gl::UseProgram(self.id);
+        device::current().use_program(self.id());
+    }
+
+    /// Location of the uniform, asked to the device only the first time.
+    fn location(&self, name: &str) -> i32 {
+        if let Some(location) = self.locations.borrow().get(name) {
+            return *location;
+        }
+        let location = device::current().uniform_location(self.id(), name);
+        if location == -1 {
+            gl_error::warn(
+                DebugType::UndefinedBehavior,
+                format!("Uniform {} doesn't exist in shader {}.", name, self.id()),
+            );
+        }
+        self.locations.borrow_mut().insert(name.to_string(), location);
+        location
+    }
+
+    /// Uniforms used by the program, the ones unused by the glsl code are removed by the driver.
+    pub fn uniforms(&self) -> Vec<ActiveVariable> {
+        device::current().active_uniforms(self.id())
+    }
+
+    /// Check if the program use the uniform, without the warning of the setters.
+    pub fn has_uniform(&self, name: &str) -> bool {
+        if let Some(location) = self.locations.borrow().get(name) {
+            return *location != -1;
         }
+        let location = device::current().uniform_location(self.id(), name);
+
+        self.locations.borrow_mut().insert(name.to_string(), location);
+        location != -1
+    }
+
+    /// Vertex attributes used by the program.
+    pub fn attributes(&self) -> Vec<ActiveVariable> {
+        device::current().active_attributes(self.id())
+    }
+
+    /// Set an uniform of the program, a warning is printed once if it doesn't exist.
+    /// ```no_run
+    /// use gust::prelude::*;
+    /// use gust::shader::{Shader, UniformValue};
+    ///
+    /// let _window = Window::new(800, 600, "Uniforms");
+    /// let shader = Shader::new("wave.vert", "wave.frag").unwrap();
+    /// shader.activate();
+    /// shader.set_uniform("time", 1.5);
+    /// shader.set_uniform("offset", Vector::new(1.0, 0.5));
+    /// shader.set_uniform("noise", UniformValue::Sampler(1));
+    /// ```
+    pub fn set_uniform<T: Into<UniformValue>>(&self, name: &str, value: T) {
+        let location = self.location(name);
+        value.into().apply(location);
     }
 
     // Uniform setter Vector
 
-    pub fn uniform_f4(&mut self, name: &str, value: Vector4<f32>) {
-        unsafe {
-            let pos = gl::GetUniformLocation(self.id, name.as_ptr() as *const _);
-            gl::Uniform4f(pos, value.x, value.y, value.z, value.w);
+    pub fn uniform_f4(&self, name: &str, value: Vector4<f32>) {
+        self.set_uniform(name, value);
+    }
+
+    pub fn uniform_f3(&self, name: &str, value: Vector3<f32>) {
+        self.set_uniform(name, value);
+    }
+
+    pub fn uniform_f2(&self, name: &str, value: Vector2<f32>) {
+        self.set_uniform(name, value);
+    }
+
+    pub fn uniform_f(&self, name: &str, value: f32) {
+        self.set_uniform(name, value);
+    }
+
+    // Uniform setter integer
+
+    pub fn uniform_bool(&self, name: &str, value: bool) {
+        self.set_uniform(name, value);
+    }
+
+    pub fn uniform_int(&self, name: &str, value: i32) {
+        self.set_uniform(name, value);
+    }
+
+    pub fn uniform_int2(&self, name: &str, value: Vector2<i32>) {
+        self.set_uniform(name, value);
+    }
+
+    pub fn uniform_int3(&self, name: &str, value: Vector3<i32>) {
+        self.set_uniform(name, value);
+    }
+
+    pub fn uniform_int4(&self, name: &str, value: Vector4<i32>) {
+        self.set_uniform(name, value);
+    }
+
+    // Uniform setter for matrix
+
+    pub fn uniform_mat4f(&self, name: &str, value: &Matrix4<f32>) {
+        self.set_uniform(name, *value);
+    }
+
+    pub fn uniform_mat3f(&self, name: &str, value: Matrix3<f32>) {
+        self.set_uniform(name, value);
+    }
+
+    pub fn uniform_mat2f(&self, name: &str, value: Matrix2<f32>) {
+        self.set_uniform(name, value);
+    }
+}
+
+impl Default for Shader {
+    /// Default shader mode
+    fn default() -> Shader {
+        Shader::from_source(VS, FS).unwrap()
+    }
+}
+
+impl PartialEq for Shader {
+    fn eq(&self, other: &Shader) -> bool {
+        self.id() == other.id()
+    }
+}
+
+impl Drop for Shader {
+    fn drop(&mut self) {
+        self.device.delete_program(self.id());
+    }
+}
+
+impl fmt::Debug for Shader {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Shader({})", self.id())
+    }
+}
+
+//----------------------------------------------------------------------------
+//
+//
+//                             UNIFORMVALUE : ENUM
+//
+//
+//----------------------------------------------------------------------------
+
+/// Typed value of an uniform.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UniformValue {
+    Float(f32),
+    Vec2(Vector2<f32>),
+    Vec3(Vector3<f32>),
+    Vec4(Vector4<f32>),
+    Int(i32),
+    IVec2(Vector2<i32>),
+    IVec3(Vector3<i32>),
+    IVec4(Vector4<i32>),
+    Bool(bool),
+    Mat2(Matrix2<f32>),
+    Mat3(Matrix3<f32>),
+    Mat4(Matrix4<f32>),
+    /// Texture unit read by a sampler2D.
+    Sampler(u32),
+}
+
+impl UniformValue {
+    /// Send the value to the uniform at location of the program in use.
+    pub fn apply(&self, location: i32) {
+        let device = device::current();
+
+        match self {
+            UniformValue::Float(v) => device.uniform_floats(location, &[*v]),
+            UniformValue::Vec2(v) => device.uniform_floats(location, &[v.x, v.y]),
+            UniformValue::Vec3(v) => device.uniform_floats(location, &[v.x, v.y, v.z]),
+            UniformValue::Vec4(v) => device.uniform_floats(location, &[v.x, v.y, v.z, v.w]),
+            UniformValue::Int(v) => device.uniform_ints(location, &[*v]),
+            UniformValue::IVec2(v) => device.uniform_ints(location, &[v.x, v.y]),
+            UniformValue::IVec3(v) => device.uniform_ints(location, &[v.x, v.y, v.z]),
+            UniformValue::IVec4(v) => device.uniform_ints(location, &[v.x, v.y, v.z, v.w]),
+            UniformValue::Bool(v) => device.uniform_ints(location, &[*v as i32]),
+            UniformValue::Mat2(v) => device.uniform_matrix(location, v.as_slice()),
+            UniformValue::Mat3(v) => device.uniform_matrix(location, v.as_slice()),
+            UniformValue::Mat4(v) => device.uniform_matrix(location, v.as_slice()),
+            UniformValue::Sampler(unit) => device.uniform_ints(location, &[*unit as i32]),
         }
     }
 
-    pub fn uniform_f3(&mut self, name: &str, value: Vector3<f32>) {
-        unsafe {
-            let pos = gl::GetUniformLocation(self.id, name.as_ptr() as *const _);
-            gl::Uniform3f(pos, value.x, value.y, value.z);
+    /// Glsl type receiving the value.
+    pub fn glsl_type(&self) -> GlslType {
+        match self {
+            UniformValue::Float(_) => GlslType::Float,
+            UniformValue::Vec2(_) => GlslType::Vec2,
+            UniformValue::Vec3(_) => GlslType::Vec3,
+            UniformValue::Vec4(_) => GlslType::Vec4,
+            UniformValue::Int(_) => GlslType::Int,
+            UniformValue::IVec2(_) => GlslType::IVec2,
+            UniformValue::IVec3(_) => GlslType::IVec3,
+            UniformValue::IVec4(_) => GlslType::IVec4,
+            UniformValue::Bool(_) => GlslType::Bool,
+            UniformValue::Mat2(_) => GlslType::Mat2,
+            UniformValue::Mat3(_) => GlslType::Mat3,
+            UniformValue::Mat4(_) => GlslType::Mat4,
+            UniformValue::Sampler(_) => GlslType::Sampler2D,
         }
     }
+}
+
+macro_rules! uniform_from {
+    ($($kind:ty => $variant:ident),*) => {
+        $(
+            impl From<$kind> for UniformValue {
+                fn from(value: $kind) -> UniformValue {
+                    UniformValue::$variant(value)
+                }
+            }
+        )*
+    };
+}
 
-    pub fn uniform_f2(&mut self, name: &str, value: Vector2<f32>) {
-        unsafe {
-            let pos = gl::GetUniformLocation(self.id, name.as_ptr() as *const _);
-            gl::Uniform2f(pos, value.x, value.y);
+uniform_from!(
+    f32 => Float,
+    Vector2<f32> => Vec2,
+    Vector3<f32> => Vec3,
+    Vector4<f32> => Vec4,
+    i32 => Int,
+    Vector2<i32> => IVec2,
+    Vector3<i32> => IVec3,
+    Vector4<i32> => IVec4,
+    bool => Bool,
+    Matrix2<f32> => Mat2,
+    Matrix3<f32> => Mat3,
+    Matrix4<f32> => Mat4
+);
+
+//----------------------------------------------------------------------------
+//
+//
+//                             UNIFORMS : STRUCT
+//
+//
+//----------------------------------------------------------------------------
+
+/// Named uniform values given to a shader before a draw.
+/// The textures are bound from the unit 1, the unit 0 being the one of the drawable.
+/// ```no_run
+/// use gust::prelude::*;
+/// use gust::shader::Shader;
+/// use std::rc::Rc;
+///
+/// let _window = Window::new(800, 600, "Palette swap");
+/// let hero = Rc::new(Texture::from_path("hero.png").unwrap());
+/// let palette = Rc::new(Texture::from_path("palette.png").unwrap());
+/// let mut sprite = Sprite::from(&hero);
+///
+/// sprite.set_shader(Rc::new(Shader::new("palette.vert", "palette.frag").unwrap()));
+/// sprite.uniforms_mut().set_texture("palette", &palette);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Uniforms {
+    values: Vec<(String, UniformValue)>,
+    textures: Vec<(String, Resource<Texture>)>,
+}
+
+impl Uniforms {
+    pub fn new() -> Uniforms {
+        Uniforms {
+            values: Vec::new(),
+            textures: Vec::new(),
         }
     }
 
-    pub fn uniform_f(&mut self, name: &str, value: f32) {
-        unsafe {
-            let pos = gl::GetUniformLocation(self.id, name.as_ptr() as *const _);
-            gl::Uniform1f(pos, value);
+    /// Set the uniform name, replacing the previous value.
+    pub fn set<T: Into<UniformValue>>(&mut self, name: &str, value: T) {
+        let value = value.into();
+
+        match self.values.iter_mut().find(|(key, _)| key == name) {
+            Some(entry) => entry.1 = value,
+            None => self.values.push((name.to_owned(), value)),
         }
     }
 
-    // Uniform setter integer
+    pub fn get(&self, name: &str) -> Option<UniformValue> {
+        self.values
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| *value)
+    }
 
-    pub fn uniform_bool(&mut self, name: &str, value: bool) {
-        self.uniform_int(name, value as i32);
+    /// Remove the uniform name, return its value if it was set.
+    pub fn remove(&mut self, name: &str) -> Option<UniformValue> {
+        let index = self.values.iter().position(|(key, _)| key == name)?;
+        Some(self.values.remove(index).1)
     }
 
-    pub fn uniform_int(&mut self, name: &str, value: i32) {
-        unsafe {
-            let pos = gl::GetUniformLocation(self.id, name.as_ptr() as *const _);
-            gl::Uniform1i(pos, value);
+    /// Bind the texture to the sampler2D name, replacing the previous texture.
+    /// Each texture get its own unit after the unit 0.
+    pub fn set_texture(&mut self, name: &str, texture: &Resource<Texture>) {
+        match self.textures.iter_mut().find(|(key, _)| key == name) {
+            Some(entry) => entry.1 = Rc::clone(texture),
+            None => self.textures.push((name.to_owned(), Rc::clone(texture))),
         }
     }
 
-    pub fn uniform_int2(&mut self, name: &str, value: Vector2<i32>) {
-        unsafe {
-            let pos = gl::GetUniformLocation(self.id, name.as_ptr() as *const _);
-            gl::Uniform2i(pos, value.x, value.y);
-        }
+    /// Unit the texture name is bound to.
+    pub fn texture_unit(&self, name: &str) -> Option<u32> {
+        self.textures
+            .iter()
+            .position(|(key, _)| key == name)
+            .map(|index| index as u32 + 1)
     }
 
-    pub fn uniform_int3(&mut self, name: &str, value: Vector3<i32>) {
-        unsafe {
-            let pos = gl::GetUniformLocation(self.id, name.as_ptr() as *const _);
-            gl::Uniform3i(pos, value.x, value.y, value.z);
-        }
+    pub fn remove_texture(&mut self, name: &str) -> Option<Resource<Texture>> {
+        let index = self.textures.iter().position(|(key, _)| key == name)?;
+        Some(self.textures.remove(index).1)
+    }
+
+    /// Number of values and textures.
+    pub fn len(&self) -> usize {
+        self.values.len() + self.textures.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty() && self.textures.is_empty()
     }
 
-    pub fn uniform_int4(&mut self, name: &str, value: Vector4<i32>) {
-        unsafe {
-            let pos = gl::GetUniformLocation(self.id, name.as_ptr() as *const _);
-            gl::Uniform4i(pos, value.x, value.y, value.z, value.w);
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &UniformValue)> {
+        self.values
+            .iter()
+            .map(|(name, value)| (name.as_str(), value))
+    }
+
+    /// Send every value to the shader and bind the textures, the shader has to be active.
+    pub fn apply(&self, shader: &Shader) {
+        for (name, value) in &self.values {
+            shader.set_uniform(name, *value);
+        }
+        for (unit, (name, texture)) in self.textures.iter().enumerate() {
+            let unit = unit as u32 + 1;
+
+            texture.active(unit as i32);
+            shader.set_uniform(name, UniformValue::Sampler(unit));
         }
     }
+}
 
-    // Uniform setter for matrix
+/// Error of a shader creation.
+#[derive(Debug)]
+pub enum ShaderError {
+    /// A source file couldn't be read.
+    Io(io::Error),
+    /// The source of the stage contain a nul byte.
+    Nul(ShaderStage),
+    /// An include of the file at line couldn't be found or include itself.
+    Include {
+        file: String,
+        line: u32,
+        include: String,
+    },
+    /// The stage didn't compile, lines are the ones given by the log mapped to the files.
+    Compile {
+        stage: ShaderStage,
+        log: String,
+        lines: Vec<SourceLine>,
+    },
+    /// The stages compiled but couldn't be linked.
+    Link { log: String, lines: Vec<SourceLine> },
+}
 
-    pub fn uniform_mat4f(&self, name: &str, value: &Matrix4<f32>) {
-        unsafe {
-            let string = CString::new(name.as_bytes()).unwrap();
-            let pos = gl::GetUniformLocation(self.id, string.as_ptr());
-            gl::UniformMatrix4fv(pos, 1, gl::FALSE, value.as_slice().as_ptr());
+impl ShaderError {
+    /// Stage that failed, None for the io and link errors.
+    pub fn stage(&self) -> Option<ShaderStage> {
+        match self {
+            ShaderError::Nul(stage) | ShaderError::Compile { stage, .. } => Some(*stage),
+            _ => None,
         }
     }
 
-    pub fn uniform_mat3f(&mut self, name: &str, value: Matrix3<f32>) {
-        unsafe {
-            let pos = gl::GetUniformLocation(self.id, name.as_ptr() as *const _);
-            gl::UniformMatrix3fv(pos, 1, gl::FALSE, value.as_slice().as_ptr());
+    /// Full info log of the driver.
+    pub fn log(&self) -> Option<&str> {
+        match self {
+            ShaderError::Compile { log, .. } | ShaderError::Link { log, .. } => Some(log),
+            _ => None,
         }
     }
 
-    pub fn uniform_mat2f(&mut self, name: &str, value: Matrix2<f32>) {
-        unsafe {
-            let pos = gl::GetUniformLocation(self.id, name.as_ptr() as *const _);
-            gl::UniformMatrix2fv(pos, 1, gl::FALSE, value.as_slice().as_ptr());
+    /// Source lines quoted by the log.
+    pub fn lines(&self) -> &[SourceLine] {
+        match self {
+            ShaderError::Compile { lines, .. } | ShaderError::Link { lines, .. } => lines,
+            _ => &[],
         }
     }
 }
 
-impl Default for Shader {
-    /// Default shader mode
-    fn default() -> Shader {
-        let vert_source = CString::new(VS.as_bytes()).unwrap();
-        let frag_source = CString::new(FS.as_bytes()).unwrap();
-        let (vert_id, frag_id, id);
-
-        unsafe {
-            let (v, f, i) = Shader::do_shader(&vert_source, &frag_source).unwrap();
-            vert_id = v;
-            frag_id = f;
-            id = i;
+impl From<io::Error> for ShaderError {
+    fn from(error: io::Error) -> ShaderError {
+        ShaderError::Io(error)
+    }
+}
+
+impl fmt::Display for ShaderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ShaderError::Io(error) => write!(f, "Could not read shader: {}", error),
+            ShaderError::Nul(stage) => write!(f, "The {:?} shader contain a nul byte.", stage),
+            ShaderError::Include {
+                file,
+                line,
+                include,
+            } => write!(
+                f,
+                "Could not include {} at {}:{}, the file is missing or include itself.",
+                include, file, line
+            ),
+            ShaderError::Compile { stage, log, lines } => {
+                write!(f, "Could not compile {:?} shader", stage)?;
+                for (i, line) in lines.iter().enumerate() {
+                    write!(f, "{} {}", if i == 0 { " at" } else { "," }, line)?;
+                }
+                write!(f, ":\n{}", log)
+            }
+            ShaderError::Link { log, .. } => write!(f, "Could not link shaders:\n{}", log),
         }
+    }
+}
 
-        Shader {
-            id,
-            frag: frag_id,
-            vert: vert_id,
+impl Error for ShaderError {
+    fn cause(&self) -> Option<&Error> {
+        match self {
+            ShaderError::Io(error) => Some(error),
+            _ => None,
         }
     }
 }
 
-impl Drop for Shader {
-    fn drop(&mut self) {
-        unsafe {
-            gl::DeleteShader(self.vert);
-            gl::DeleteShader(self.frag);
-            gl::DeleteProgram(self.id);
+/// Find the line numbers inside a glsl info log.
+/// Mesa write `0:12(5): error`, Nvidia `0(12) : error` and Amd `ERROR: 0:12: error`.
+fn parse_lines(log: &str) -> Vec<u32> {
+    let mut lines: Vec<u32> = log.lines().filter_map(parse_line).collect();
+
+    lines.sort();
+    lines.dedup();
+    lines
+}
+
+/// Line number of a log line: the number after the first `<file>:` or `<file>(`.
+fn parse_line(line: &str) -> Option<u32> {
+    let bytes = line.as_bytes();
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if !bytes[i].is_ascii_digit() || (i > 0 && bytes[i - 1].is_ascii_alphanumeric()) {
+            i += 1;
+            continue;
+        }
+        let file_end = i + line[i..]
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(line.len() - i);
+        let rest = &line[file_end..];
+
+        if rest.starts_with(':') || rest.starts_with('(') {
+            let number: String = rest[1..]
+                .chars()
+                .take_while(|c| c.is_ascii_digit())
+                .collect();
+            if let Ok(number) = number.parse() {
+                return Some(number);
+            }
         }
-        println!("Shaders {} deleted.", self.id);
+        i = file_end;
+    }
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_lines, Shader, UniformValue, Uniforms, DEFAULT_SHADER};
+    use command::{record, Uniform};
+    use device::{self, GlslType, NullDevice};
+    use draw::Drawer;
+    use nalgebra::Vector2;
+    use render_texture::RenderTexture;
+    use sprite::Sprite;
+    use std::rc::Rc;
+    use texture::Texture;
+
+    #[test]
+    fn builtins_are_made_once_per_context() {
+        let null = Rc::new(NullDevice::new());
+        let _device = device::scoped(null.clone());
+        let first = DEFAULT_SHADER.get().id();
+
+        assert_eq!(DEFAULT_SHADER.get().id(), first);
+        let context = device::new_context();
+        device::set_context(context);
+        let second = DEFAULT_SHADER.get().id();
+        assert_ne!(second, first);
+        assert_eq!(DEFAULT_SHADER.get().id(), second);
+        // The programs of a context are deleted with it
+        let deleted = null.deleted();
+        device::drop_context(context);
+        assert_eq!(null.deleted(), deleted + 1);
+        device::set_context(0);
+        assert_eq!(DEFAULT_SHADER.get().id(), first);
+    }
+
+    #[test]
+    fn locations_are_cached() {
+        let _device = device::scoped(Rc::new(NullDevice::new()));
+        let shader = Shader::from_source("", "").unwrap();
+
+        shader.set_uniform("offset", Vector2::new(1.0, 2.0));
+        shader.set_uniform("offset", Vector2::new(2.0, 3.0));
+        assert_eq!(shader.locations.borrow().get("offset"), Some(&-1));
+        assert_eq!(
+            UniformValue::from(Vector2::new(1.0, 2.0)).glsl_type(),
+            GlslType::Vec2
+        );
+    }
+
+    #[test]
+    fn parse_lines_of_every_vendor() {
+        let mesa = "0:12(5): error: `oops' undeclared\n0:3(1): error: syntax error";
+        let nvidia = "0(7) : error C0000: syntax error, unexpected '}'";
+        let amd = "ERROR: 0:21: 'oops' : undeclared identifier\nERROR: 1 compilation errors.";
+
+        assert_eq!(parse_lines(mesa), vec![3, 12]);
+        assert_eq!(parse_lines(nvidia), vec![7]);
+        assert_eq!(parse_lines(amd), vec![21]);
+        assert!(parse_lines("Link error: missing main").is_empty());
+    }
+
+    #[test]
+    fn uniforms_replace_by_name() {
+        let mut uniforms = Uniforms::new();
+
+        uniforms.set("time", 1.0);
+        uniforms.set("tint", Vector2::new(0.5, 0.5));
+        uniforms.set("time", 2.0);
+        assert_eq!(uniforms.len(), 2);
+        assert_eq!(uniforms.get("time"), Some(UniformValue::Float(2.0)));
+        assert_eq!(uniforms.remove("time"), Some(UniformValue::Float(2.0)));
+        assert_eq!(uniforms.get("time"), None);
+    }
+
+    #[test]
+    fn custom_program_and_uniforms_reach_the_draw() {
+        let _device = device::scoped(Rc::new(NullDevice::new()));
+        let hero = Rc::new(Texture::from_size(Vector2::new(4, 4)));
+        let palette = Rc::new(Texture::from_size(Vector2::new(16, 1)));
+        let shader = Rc::new(Shader::from_source("", "").unwrap());
+        let mut target = RenderTexture::new(8, 8).unwrap();
+        let mut sprite = Sprite::from(&hero);
+
+        sprite.set_shader(Rc::clone(&shader));
+        sprite.set_uniform("time", 2.0);
+        sprite.uniforms_mut().set_texture("palette", &palette);
+        let list = record(|| target.draw(&sprite));
+        let draw = list.draws().next().unwrap();
+
+        // The palette is bound after the unit 0 of the sprite texture
+        assert_eq!(draw.program, shader.id());
+        assert_eq!(draw.textures, vec![(0, hero.id()), (1, palette.id())]);
+        assert!(draw
+            .uniforms
+            .contains(&("time".to_string(), Uniform::Floats(vec![2.0]))));
+        assert!(draw
+            .uniforms
+            .contains(&("palette".to_string(), Uniform::Ints(vec![1]))));
+        assert_eq!(sprite.uniforms().texture_unit("palette"), Some(1));
     }
 }
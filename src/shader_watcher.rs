@@ -0,0 +1,170 @@
+//
+//  Rust file | 2018
+//  Author: Alexandre Fourcat
+//  shader_watcher.rs
+//  module:
+//! Shader hot-reload.
+//! The watcher poll the modification time of the sources and of the files they include
+//! and reload the shader in place,
+//! everything holding the shader draw with the new program right away.
+//! If the new sources don't compile the last good program is kept.
+//! ```no_run
+//! use gust::prelude::*;
+//! use gust::shader_watcher::ShaderWatcher;
+//!
+//! let mut window = Window::new(800, 600, "Hot reload");
+//! let mut watcher = ShaderWatcher::new("effect.vert", "effect.frag").unwrap();
+//!
+//! while window.is_open() {
+//!     match watcher.poll() {
+//!         Ok(true) => println!("Shader reloaded"),
+//!         Ok(false) => {}
+//!         Err(error) => println!("{}", error),
+//!     }
+//!     window.clear();
+//!     // draw things with watcher.shader()
+//!     window.display();
+//! }
+//! ```
+
+use preprocessor::{Preprocessor, Source};
+use resources::Resource;
+use shader::{Shader, ShaderError};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// What's compared to know if a file changed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Stamp {
+    modified: SystemTime,
+    len: u64,
+}
+
+impl Stamp {
+    fn of(path: &Path) -> Result<Stamp, io::Error> {
+        let metadata = fs::metadata(path)?;
+
+        Ok(Stamp {
+            modified: metadata.modified()?,
+            len: metadata.len(),
+        })
+    }
+}
+
+/// Shader reloaded when its source files or the files they include change.
+#[derive(Debug)]
+pub struct ShaderWatcher {
+    shader: Resource<Shader>,
+    preprocessor: Preprocessor,
+    vert: PathBuf,
+    frag: PathBuf,
+    stamps: Vec<(PathBuf, Stamp)>,
+}
+
+impl ShaderWatcher {
+    /// Create the shader from the files, they have to compile the first time.
+    pub fn new<P: AsRef<Path>, Q: AsRef<Path>>(
+        vert: P,
+        frag: Q,
+    ) -> Result<ShaderWatcher, ShaderError> {
+        ShaderWatcher::with_preprocessor(vert, frag, Preprocessor::new())
+    }
+
+    /// Same as new with the defines and sources of the preprocessor.
+    pub fn with_preprocessor<P: AsRef<Path>, Q: AsRef<Path>>(
+        vert: P,
+        frag: Q,
+        preprocessor: Preprocessor,
+    ) -> Result<ShaderWatcher, ShaderError> {
+        let (vert, frag) = (vert.as_ref().to_path_buf(), frag.as_ref().to_path_buf());
+        let (vert_source, frag_source) = (preprocessor.load(&vert)?, preprocessor.load(&frag)?);
+        let shader = Shader::from_preprocessed(&vert_source, &frag_source)?;
+
+        Ok(ShaderWatcher {
+            shader: Resource::new(shader),
+            preprocessor,
+            vert,
+            frag,
+            stamps: ShaderWatcher::stamps(&vert_source, &frag_source)?,
+        })
+    }
+
+    fn stamps(vert: &Source, frag: &Source) -> Result<Vec<(PathBuf, Stamp)>, io::Error> {
+        vert.files()
+            .iter()
+            .chain(frag.files())
+            .map(|path| Ok((path.clone(), Stamp::of(path)?)))
+            .collect()
+    }
+
+    /// The shader watched, always holding the last program that compiled.
+    pub fn shader(&self) -> &Resource<Shader> {
+        &self.shader
+    }
+
+    /// Reload the shader if a file changed since the last poll.
+    /// Return true if the shader was reloaded, the compilation errors are returned
+    /// once per change and the previous program stay in use.
+    pub fn poll(&mut self) -> Result<bool, ShaderError> {
+        let mut stamps = Vec::with_capacity(self.stamps.len());
+
+        for (path, _) in &self.stamps {
+            stamps.push((path.clone(), Stamp::of(path)?));
+        }
+        if stamps == self.stamps {
+            return Ok(false);
+        }
+        // Set first so a broken file isn't compiled again on every poll
+        self.stamps = stamps;
+
+        let (vert, frag) = (
+            self.preprocessor.load(&self.vert)?,
+            self.preprocessor.load(&self.frag)?,
+        );
+        // The includes may have changed
+        self.stamps = ShaderWatcher::stamps(&vert, &frag)?;
+        self.shader.reload_preprocessed(&vert, &frag)?;
+        Ok(true)
+    }
+
+    /// Reload the shader from the files whether they changed or not.
+    pub fn reload(&self) -> Result<(), ShaderError> {
+        self.shader.reload_preprocessed(
+            &self.preprocessor.load(&self.vert)?,
+            &self.preprocessor.load(&self.frag)?,
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ShaderWatcher;
+    use device::{self, NullDevice};
+    use std::env;
+    use std::fs;
+    use std::rc::Rc;
+
+    #[test]
+    fn poll_reload_on_change() {
+        let _device = device::scoped(Rc::new(NullDevice::new()));
+        let dir = env::temp_dir();
+        let (vert, frag) = (dir.join("gust_watch.vert"), dir.join("gust_watch.frag"));
+        fs::write(&vert, "void main() {}").unwrap();
+        fs::write(&frag, "void main() {}").unwrap();
+
+        let mut watcher = ShaderWatcher::new(&vert, &frag).unwrap();
+        let id = watcher.shader().id();
+        assert!(!watcher.poll().unwrap());
+
+        fs::write(&frag, "void main() { discard; }").unwrap();
+        assert!(watcher.poll().unwrap());
+        assert_ne!(watcher.shader().id(), id);
+        assert!(!watcher.poll().unwrap());
+
+        drop(watcher);
+        fs::remove_file(vert).unwrap();
+        fs::remove_file(frag).unwrap();
+    }
+}
@@ -1,5 +1,6 @@
 use crate::Vector;
 use color::Color;
+use device;
 use draw::*;
 use glfw::Context;
 use nalgebra::Matrix4;
@@ -9,6 +10,8 @@ use window::Window;
 /// Shared Window is a window that can be shared between thread.
 pub struct SharedWindow {
     context: glfw::RenderContext,
+    /// Id of the gl context shared with the window.
+    id: usize,
     view: View,
 }
 
@@ -17,11 +20,17 @@ impl SharedWindow {
         SharedWindow {
             view: window.view().clone(),
             context: window.win.render_context(),
+            id: window.context,
         }
     }
 
     pub fn active(&mut self) -> bool {
-        self.context.make_current();
+        if !self.context.is_current() {
+            self.context.make_current();
+            device::set_context(self.id);
+            // The states known by the device belong to the previous context
+            device::current().reset_state();
+        }
         self.context.is_current()
     }
 
@@ -31,10 +40,7 @@ impl SharedWindow {
     }
 
     pub fn clear(&self, color: Color) {
-        unsafe {
-            gl::ClearColor(color.0, color.1, color.2, color.3);
-            gl::Clear(gl::COLOR_BUFFER_BIT);
-        }
+        device::current().clear(color);
     }
 }
 
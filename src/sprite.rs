@@ -1,11 +1,12 @@
 //! Module to handle drawable texture that are called Sprite
 
+use batch::Batch;
 use color::Color;
 use draw::{BlendMode, Context, Drawable, DrawableMut, Drawer};
 use nalgebra;
 use nalgebra::*;
 use resources::Resource;
-use shader::DEFAULT_SHADER;
+use shader::{Shader, UniformValue, Uniforms, DEFAULT_SHADER};
 use std::convert::From;
 use std::error::Error;
 use std::fmt;
@@ -28,7 +29,7 @@ use vertex_buffer::{Primitive, VertexBuffer};
 /// sprite.set_position(Vector2::new(100.0, 200.0));
 /// ```
 /// > A sprite is just attributes for textures to become printable ...
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, PartialEq)]
 pub struct Sprite {
     pos: Vector2<f32>,
     scale: Vector2<f32>,
@@ -37,6 +38,10 @@ pub struct Sprite {
     vertice: VertexBuffer,
     texture: Option<Resource<Texture>>,
     model: Matrix4<f32>,
+    blend_mode: BlendMode,
+    layer: f32,
+    shader: Option<Resource<Shader>>,
+    uniforms: Uniforms,
     need_update: bool,
 }
 
@@ -64,6 +69,10 @@ impl Sprite {
             origin: Vector2::new(0.0, 0.0),
             model: Matrix4::identity(),
             rotation: 0.0,
+            blend_mode: BlendMode::Alpha,
+            layer: 0.0,
+            shader: None,
+            uniforms: Uniforms::new(),
         }
     }
 
@@ -106,6 +115,50 @@ impl Sprite {
         self.texture = Some(Resource::clone(texture));
         self.need_update = true;
     }
+
+    /// Set the blend mode used to draw the sprite.
+    pub fn set_blend_mode(&mut self, blend_mode: BlendMode) {
+        self.blend_mode = blend_mode;
+    }
+
+    pub fn blend_mode(&self) -> BlendMode {
+        self.blend_mode
+    }
+
+    /// Set the layer used to sort the sprite inside a DrawList.
+    pub fn set_layer(&mut self, layer: f32) {
+        self.layer = layer;
+    }
+
+    /// Draw with the shader instead of the built-in one,
+    /// `transform` and `projection` stay set by gust.
+    /// A sprite with a custom shader or uniforms isn't batched.
+    pub fn set_shader(&mut self, shader: Resource<Shader>) {
+        self.shader = Some(shader);
+    }
+
+    /// Go back to the built-in shader.
+    pub fn reset_shader(&mut self) {
+        self.shader = None;
+    }
+
+    /// The custom shader, None if the built-in one is used.
+    pub fn shader(&self) -> Option<&Resource<Shader>> {
+        self.shader.as_ref()
+    }
+
+    /// Set an uniform given to the shader before each draw.
+    pub fn set_uniform<T: Into<UniformValue>>(&mut self, name: &str, value: T) {
+        self.uniforms.set(name, value);
+    }
+
+    pub fn uniforms(&self) -> &Uniforms {
+        &self.uniforms
+    }
+
+    pub fn uniforms_mut(&mut self) -> &mut Uniforms {
+        &mut self.uniforms
+    }
 }
 
 impl<'a> From<&'a Resource<Texture>> for Sprite {
@@ -158,6 +211,10 @@ impl<'a> From<&'a Resource<Texture>> for Sprite {
             model: Matrix4::identity().append_translation(&Vector3::new(pos.x, pos.y, 0.0)),
             rotation: 0.0,
             origin: Vector2::new(0.0, 0.0),
+            blend_mode: BlendMode::Alpha,
+            layer: 0.0,
+            shader: None,
+            uniforms: Uniforms::new(),
         }
     }
 }
@@ -257,6 +314,26 @@ impl Movable for Sprite {
     }
 }
 
+/// The clone has its own vertices, changing its texture rect doesn't touch this sprite.
+impl Clone for Sprite {
+    fn clone(&self) -> Sprite {
+        Sprite {
+            pos: self.pos,
+            scale: self.scale,
+            rotation: self.rotation,
+            origin: self.origin,
+            vertice: self.vertice.duplicate(),
+            texture: self.texture.clone(),
+            model: self.model,
+            blend_mode: self.blend_mode,
+            layer: self.layer,
+            shader: self.shader.clone(),
+            uniforms: self.uniforms.clone(),
+            need_update: self.need_update,
+        }
+    }
+}
+
 impl Default for Sprite {
     fn default() -> Self {
         Sprite {
@@ -267,6 +344,10 @@ impl Default for Sprite {
             vertice: VertexBuffer::default(),
             texture: Some(Resource::new(Texture::default())),
             model: Matrix4::<f32>::identity(),
+            blend_mode: BlendMode::Alpha,
+            layer: 0.0,
+            shader: None,
+            uniforms: Uniforms::new(),
             need_update: false,
         }
     }
@@ -295,18 +376,45 @@ impl Drawable for Sprite {
             None
         };
 
+        let shader = self.shader.clone().unwrap_or_else(|| DEFAULT_SHADER.get());
         let mut context = Context::new(
             texture,
-            &*DEFAULT_SHADER,
+            &shader,
             vec![
                 ("transform".to_string(), &self.model),
                 ("projection".to_string(), window.projection()),
             ],
-            BlendMode::Alpha,
+            self.blend_mode,
         );
+        context.set_uniforms(&self.uniforms);
         self.vertice.draw_with_context(&mut context);
     }
 
+    fn layer(&self) -> f32 {
+        self.layer
+    }
+
+    /// Sprites with a texture are merged with the ones sharing it,
+    /// the ones with their own shader or uniforms are drawn alone.
+    fn batch(&self, batch: &mut Batch, projection: &Matrix4<f32>) -> bool {
+        if self.shader.is_some() || !self.uniforms.is_empty() {
+            return false;
+        }
+        if let Some(ref texture) = self.texture {
+            batch.push_quad(
+                self.vertice.array().array(),
+                &self.model,
+                texture,
+                DEFAULT_SHADER.get(),
+                self.blend_mode,
+                projection,
+            );
+            true
+        } else {
+            false
+        }
+    }
+
     /// Draw the actual sprite with your own context.
     fn draw_with_context<'a>(&self, context: &'a mut Context) {
         self.vertice.draw_with_context(context);
@@ -330,11 +438,11 @@ impl Drawable for Sprite {
         if self.origin.x != 0.0 && self.origin.y != 0.0 {
             self.model
                 .append_translation_mut(&Vector3::new(self.origin.x, self.origin.y, 0.0));
-            self.model *= Matrix4::from_euler_angles(0.0, 0.0, self.rotation * (3.14116 * 180.0));
+            self.model *= Matrix4::from_euler_angles(0.0, 0.0, self.rotation.to_radians());
             self.model
                 .prepend_translation_mut(&Vector3::new(-self.origin.x, -self.origin.y, 0.0));
         } else {
-            self.model *= Matrix4::from_euler_angles(0.0, 0.0, self.rotation * (3.14116 * 180.0));
+            self.model *= Matrix4::from_euler_angles(0.0, 0.0, self.rotation.to_radians());
         }
         self.model
             .append_nonuniform_scaling_mut(&Vector3::new(self.scale.x, self.scale.y, 0.0));
@@ -6,19 +6,23 @@
 
 use super::Vector;
 use color::Color;
+use device;
 use draw::*;
-use gl;
-use gl::types::*;
+use handle::{Handle, Kind};
 use nalgebra::{Matrix4, Vector3};
 use nalgebra::{Scalar, Vector4};
 use rect::Rect;
-use shader::BATCH_SHADER;
-use std::mem;
-use std::ptr;
+use resources::Resource;
+use shader::{
+    Shader, UniformValue, Uniforms, BATCH_SHADER, INSTANCED_SHADER, MULTI_BATCH_SHADER,
+    MULTI_INSTANCED_SHADER,
+};
 use std::rc::Rc;
+use stream_buffer::StreamBuffer;
 use texture::Texture;
 use transform::*;
-use vertex::Vertex;
+use vertex::{Instance, Vertex};
+use vertex_buffer::{BufferUsage, Primitive};
 
 pub enum BatchError {
     BadTextureRect,
@@ -29,10 +33,16 @@ pub enum BatchError {
 pub struct SpriteData {
     pos: Vector<f32>,
     rotation: f32,
+    scale: Vector<f32>,
+    /// Point of the frame placed at pos, in pixels from its top left corner.
+    origin: Vector<f32>,
     model: Matrix4<f32>,
     need_update: bool,
+    /// Top left corner and size of the frame in texture coordinates.
     text_coord: [Vector<f32>; 2],
     color: Option<Color>,
+    /// Index of the texture inside the batch.
+    texture: usize,
 }
 
 impl SpriteData {
@@ -44,9 +54,20 @@ impl SpriteData {
         }
     }
 
-    /// Set texture_coord Raw (gl like)
+    /// Set texture_coord Raw (gl like), the top left corner then the size.
     pub fn set_texture_raw(&mut self, text_coord: [Vector<f32>; 2]) {
         self.text_coord = text_coord;
+        self.need_update = true;
+    }
+
+    /// Choose the texture of the batch drawn by the sprite, see SpriteBatch::add_texture.
+    pub fn set_texture_index(&mut self, index: usize) {
+        self.texture = index;
+        self.need_update = true;
+    }
+
+    pub fn texture_index(&self) -> usize {
+        self.texture
     }
 
     /// Set texture rect.
@@ -61,6 +82,15 @@ impl SpriteData {
                 text_rect.height as f32 / texture_size as f32,
             ),
         ];
+        self.need_update = true;
+    }
+
+    /// Size in pixels of the frame inside a texture of texture_size, before the scale.
+    pub fn size(&self, texture_size: Vector<f32>) -> Vector<f32> {
+        Vector::new(
+            self.text_coord[1].x * texture_size.x,
+            self.text_coord[1].y * texture_size.y,
+        )
     }
 
     /// Get texture rect.
@@ -82,10 +112,13 @@ impl Default for SpriteData {
         SpriteData {
             pos: Vector::new(0.0, 0.0),
             rotation: 0.0,
+            scale: Vector::new(1.0, 1.0),
+            origin: Vector::new(0.0, 0.0),
             model: Matrix4::identity(),
             need_update: true,
             text_coord: [Vector::new(0.0, 0.0), Vector::new(1.0, 1.0)],
             color: None,
+            texture: 0,
         }
     }
 }
@@ -98,35 +131,42 @@ impl Transformable for SpriteData {
         true
     }
 
-    fn set_origin<T>(&mut self, _origin: Vector<T>)
+    /// The origin is the center of the rotation and the scale, in pixels of the frame.
+    fn set_origin<T>(&mut self, origin: Vector<T>)
     where
         T: Scalar + Into<f32>,
     {
-        unimplemented!("No origin for now in SpriteData.");
+        self.origin.x = origin.x.into();
+        self.origin.y = origin.y.into();
+        self.need_update = true;
     }
 
     fn get_origin(&self) -> Vector<f32> {
-        unimplemented!("No origin for now in SpriteData.");
+        self.origin
     }
 }
 
 impl Scalable for SpriteData {
-    fn set_scale<T>(&mut self, _vec: Vector<T>)
+    fn set_scale<T>(&mut self, vec: Vector<T>)
     where
         T: Scalar + Into<f32>,
     {
-        unimplemented!("For instance no scale to SpriteData.");
+        self.scale.x = vec.x.into();
+        self.scale.y = vec.y.into();
+        self.need_update = true;
     }
 
     fn get_scale(&self) -> Vector<f32> {
-        unimplemented!("For instance no scale to SpriteData.");
+        self.scale
     }
 
-    fn scale<T>(&mut self, _factor: Vector<T>)
+    fn scale<T>(&mut self, factor: Vector<T>)
     where
         T: Scalar + Into<f32>,
     {
-        unimplemented!("For instance no scale to SpriteData.");
+        self.scale.x += factor.x.into();
+        self.scale.y += factor.y.into();
+        self.need_update = true;
     }
 }
 
@@ -176,7 +216,7 @@ impl Movable for SpriteData {
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Debug)]
 /// SpriteBatch is a datastructure that handle all sprites that have the same texture.
 /// And make only 1 drawCall to draw them all. this way you can highly optimise data sended to
 /// GPU.
@@ -192,17 +232,147 @@ impl Movable for SpriteData {
 /// The idea behind SpriteBatch is to limit draw calls. Even if your sprites havn't the same texture
 /// can pack textures. And give your Vertex text_coord the actual texture coordinate that you want to be drawn.
 pub struct SpriteBatch {
-    texture: Option<Rc<Texture>>,
+    /// Textures chosen by the sprites, the first one is the default.
+    textures: Vec<Rc<Texture>>,
     sprites: Vec<SpriteData>,
+    /// Slot of each sprite, in the same order as sprites.
+    owners: Vec<usize>,
+    slots: Vec<Slot>,
+    free_slots: Vec<usize>,
     vertice: Vec<Vertex>,
-    gl_objects: (u32, u32),
+    vertex_array: Handle,
+    buffer: StreamBuffer,
+    texture_indices: TextureIndices,
     glob_origin: Vector<f32>,
     glob_pos: Vector<f32>,
     glob_scale: Vector<f32>,
     glob_rotation: f32,
-    len: usize,
     need_update: bool,
     model: Matrix4<f32>,
+    blend_mode: BlendMode,
+    layer: f32,
+    shader: Option<Resource<Shader>>,
+    uniforms: Uniforms,
+    instancing: Option<Instancing>,
+}
+
+/// Textures bound by a sub-draw at most, the size of the sampler array of the shaders.
+pub const MAX_TEXTURES: usize = 16;
+
+/// Stable reference to a sprite of a SpriteBatch, it stays valid when other sprites are removed.
+/// The handle of a removed sprite is never given back to another one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SpriteHandle {
+    slot: u32,
+    generation: u32,
+}
+
+/// Where a handle point to, the sprite index is None once removed.
+#[derive(Clone, Debug)]
+struct Slot {
+    generation: u32,
+    sprite: Option<usize>,
+}
+
+/// Gpu objects of the instanced mode, a unit quad and one Instance per sprite.
+#[derive(Debug)]
+struct Instancing {
+    vertex_array: Handle,
+    quad: Handle,
+    buffer: Handle,
+    instances: Vec<Instance>,
+    /// Number of instances the buffer can hold.
+    len: usize,
+}
+
+impl Instancing {
+    fn new() -> Instancing {
+        let device = device::current();
+        let (vertex_array, quad, buffer) = (
+            device.create_vertex_array(),
+            device.create_buffer(),
+            device.create_buffer(),
+        );
+
+        // Same corners order as the quads of the classic mode
+        device.buffer_data(
+            quad,
+            &[
+                Vertex::new(Vector::new(0.0, 0.0), Vector::new(0.0, 0.0), Color::white()),
+                Vertex::new(Vector::new(0.0, 1.0), Vector::new(0.0, 1.0), Color::white()),
+                Vertex::new(Vector::new(1.0, 0.0), Vector::new(1.0, 0.0), Color::white()),
+                Vertex::new(Vector::new(1.0, 1.0), Vector::new(1.0, 1.0), Color::white()),
+            ],
+            BufferUsage::Static,
+        );
+        device.vertex_layout(vertex_array, quad);
+        device.instance_layout(vertex_array, buffer, 0);
+        Instancing {
+            vertex_array: Handle::new(Kind::VertexArray, vertex_array),
+            quad: Handle::new(Kind::Buffer, quad),
+            buffer: Handle::new(Kind::Buffer, buffer),
+            instances: Vec::new(),
+            len: 0,
+        }
+    }
+}
+
+/// Texture index of each vertex of the classic mode, inside the group of textures of its
+/// sub-draw. It has its own buffer so only the multi-texture batches pay for it, and is
+/// repeated in every region of the vertex buffer to follow the ring buffers.
+#[derive(Debug)]
+struct TextureIndices {
+    buffer: Handle,
+    indices: Vec<f32>,
+    /// Texture units the indices were computed for.
+    units: usize,
+    /// The indices changed since the last upload.
+    dirty: bool,
+    /// Vertices of a region and number of regions of the vertex buffer when uploaded.
+    layout: (usize, usize),
+}
+
+impl TextureIndices {
+    fn new() -> TextureIndices {
+        TextureIndices {
+            buffer: Handle::new(Kind::Buffer, device::current().create_buffer()),
+            indices: Vec::new(),
+            units: 0,
+            dirty: false,
+            layout: (0, 0),
+        }
+    }
+
+    /// Follow the sprites pushed or removed since the last update, the indices of the
+    /// sprites changed are set afterward.
+    fn resize(&mut self, sprites: &[SpriteData], units: usize) {
+        if units != self.units {
+            self.indices.clear();
+            self.units = units;
+        }
+        let len = self.indices.len() / 4;
+        if len != sprites.len() {
+            self.dirty = true;
+        }
+        self.indices.truncate(sprites.len() * 4);
+        for sprite in sprites.iter().skip(len) {
+            self.indices
+                .extend_from_slice(&[(sprite.texture % units) as f32; 4]);
+        }
+    }
+
+    /// Set the index of the 4 vertices of the sprite.
+    fn set(&mut self, sprite: usize, texture: usize) {
+        let index = (texture % self.units) as f32;
+        let indices = &mut self.indices[sprite * 4..sprite * 4 + 4];
+
+        if indices[0] != index {
+            for i in indices.iter_mut() {
+                *i = index;
+            }
+            self.dirty = true;
+        }
+    }
 }
 
 // For maximum efficiency we will not use the previously implemented abstraction of VertexBuffer
@@ -213,46 +383,234 @@ impl SpriteBatch {
     }
 
     pub fn extend_from_slice(&mut self, slice: &mut [SpriteData]) {
-        {
-            let vertice = &mut self.vertice;
-
-            let (w, h) = self
-                .texture
-                .as_ref()
-                .map_or((0.0, 0.0), |x| (x.width() as f32, x.height() as f32));
-
-            for x in slice.iter_mut() {
-                x.need_update = true;
-                vertice.extend_from_slice(&[
-                    Vertex::new(
-                        Vector::new(0.0, 0.0),
-                        Vector::new(x.text_coord[0].x, x.text_coord[0].y),
-                        x.color.unwrap_or(Color::white()),
-                    ),
-                    Vertex::new(
-                        Vector::new(0.0, h),
-                        Vector::new(x.text_coord[0].x, x.text_coord[1].y),
-                        x.color.unwrap_or(Color::white()),
-                    ),
-                    Vertex::new(
-                        Vector::new(w, 0.0),
-                        Vector::new(x.text_coord[1].x, x.text_coord[0].y),
-                        x.color.unwrap_or(Color::white()),
-                    ),
-                    Vertex::new(
-                        Vector::new(w, h),
-                        Vector::new(x.text_coord[1].x, x.text_coord[1].y),
-                        x.color.unwrap_or(Color::white()),
-                    ),
-                ]);
+        for x in slice.iter_mut() {
+            x.need_update = true;
+            // The instanced mode only need the sprites
+            if self.instancing.is_none() {
+                self.vertice
+                    .extend_from_slice(&SpriteBatch::quad(x, &self.textures));
             }
         }
+        for _ in 0..slice.len() {
+            let index = self.owners.len();
+            self.new_slot(index);
+        }
         self.sprites.extend_from_slice(slice);
         self.need_update = true;
     }
 
-    /// Clear data.
+    /// Give a slot pointing to the sprite at index, reusing the free ones.
+    fn new_slot(&mut self, index: usize) -> SpriteHandle {
+        let slot = match self.free_slots.pop() {
+            Some(slot) => slot,
+            None => {
+                self.slots.push(Slot {
+                    generation: 0,
+                    sprite: None,
+                });
+                self.slots.len() - 1
+            }
+        };
+
+        self.slots[slot].sprite = Some(index);
+        self.owners.push(slot);
+        SpriteHandle {
+            slot: slot as u32,
+            generation: self.slots[slot].generation,
+        }
+    }
+
+    /// Invalidate the handles of a slot and make it reusable.
+    fn free_slot(&mut self, slot: usize) {
+        self.slots[slot].sprite = None;
+        self.slots[slot].generation = self.slots[slot].generation.wrapping_add(1);
+        self.free_slots.push(slot);
+    }
+
+    /// Index of the sprite pointed by handle if it's still inside the batch.
+    fn index(&self, handle: SpriteHandle) -> Option<usize> {
+        self.slots
+            .get(handle.slot as usize)
+            .filter(|slot| slot.generation == handle.generation)
+            .and_then(|slot| slot.sprite)
+    }
+
+    /// Add a texture the sprites can choose with SpriteData::set_texture_index,
+    /// return its index. A texture already inside the batch isn't added twice.
+    /// Above the texture units of the driver the batch is drawn in several sub-draws.
+    pub fn add_texture(&mut self, texture: &Rc<Texture>) -> usize {
+        match self.textures.iter().position(|t| Rc::ptr_eq(t, texture)) {
+            Some(index) => index,
+            None => {
+                self.textures.push(Rc::clone(texture));
+                self.textures.len() - 1
+            }
+        }
+    }
+
+    pub fn textures(&self) -> &[Rc<Texture>] {
+        &self.textures
+    }
+
+    /// Number of textures bound by each sub-draw, the units of the driver up to MAX_TEXTURES.
+    /// The driver is only asked once per gl context.
+    pub fn texture_units() -> usize {
+        device::with_objects(|objects| {
+            *objects.texture_units.get_or_insert_with(|| {
+                device::current()
+                    .max_texture_units()
+                    .min(MAX_TEXTURES)
+                    .max(1)
+            })
+        })
+    }
+
+    /// Size of the texture of a sprite, zero if it doesn't exist.
+    fn texture_size(data: &SpriteData, textures: &[Rc<Texture>]) -> Vector<f32> {
+        textures
+            .get(data.texture)
+            .map_or(Vector::new(0.0, 0.0), |texture| {
+                Vector::new(texture.width() as f32, texture.height() as f32)
+            })
+    }
+
+    /// Vertices of the sprite frame before its transformation.
+    fn quad(data: &SpriteData, textures: &[Rc<Texture>]) -> [Vertex; 4] {
+        let size = data.size(SpriteBatch::texture_size(data, textures));
+        let start = data.text_coord[0];
+        let end = start + data.text_coord[1];
+        let color = data.color.unwrap_or(Color::white());
+        [
+            Vertex::new(Vector::new(0.0, 0.0), start, color),
+            Vertex::new(Vector::new(0.0, size.y), Vector::new(start.x, end.y), color),
+            Vertex::new(Vector::new(size.x, 0.0), Vector::new(end.x, start.y), color),
+            Vertex::new(size, end, color),
+        ]
+    }
+
+    /// Instance drawing the same quad as the vertices of the classic mode.
+    fn instance(data: &SpriteData, textures: &[Rc<Texture>], units: usize) -> Instance {
+        let (start, size) = (data.text_coord[0], data.text_coord[1]);
+        let texture_size = SpriteBatch::texture_size(data, textures);
+
+        Instance {
+            pos: data.pos,
+            // The shader doesn't know the scale, it's already applied
+            origin: data.origin.component_mul(&data.scale),
+            scale: data.size(texture_size).component_mul(&data.scale),
+            rotation: data.rotation.to_radians(),
+            tex_rect: Vector4::new(start.x, start.y, size.x, size.y),
+            color: data.color.unwrap_or(Color::white()),
+            texture_index: (data.texture % units) as f32,
+        }
+    }
+
+    /// Draw the sprites with a single unit quad and one Instance per sprite, placed by the
+    /// vertex shader. A change of sprite only upload its Instance instead of 4 vertices
+    /// transformed on the cpu, big batches should use it.
+    /// Custom shaders have to include `gust/instanced.glsl` with `#define MODEL glob_model`.
+    pub fn set_instanced(&mut self, instanced: bool) {
+        if instanced == self.is_instanced() {
+            return;
+        }
+
+        self.vertice.clear();
+        if instanced {
+            self.instancing = Some(Instancing::new());
+        } else {
+            self.instancing = None;
+            for sprite in &self.sprites {
+                self.vertice
+                    .extend_from_slice(&SpriteBatch::quad(sprite, &self.textures));
+            }
+        }
+        for sprite in &mut self.sprites {
+            sprite.need_update = true;
+        }
+    }
+
+    pub fn is_instanced(&self) -> bool {
+        self.instancing.is_some()
+    }
+
+    /// Change how the sprites are uploaded, Dynamic by default.
+    /// Batches rewritten every frame should use Stream to stop waiting for the previous draws.
+    pub fn set_usage(&mut self, usage: BufferUsage) {
+        self.set_buffer(StreamBuffer::new(usage));
+    }
+
+    /// Upload the vertices inside a ring buffer of regions guarded by fences,
+    /// see StreamBuffer::ring. The instanced mode orphans its buffer instead.
+    pub fn set_ring(&mut self, regions: usize) {
+        self.set_buffer(StreamBuffer::ring(regions));
+    }
+
+    pub fn usage(&self) -> BufferUsage {
+        self.buffer.usage()
+    }
+
+    fn set_buffer(&mut self, buffer: StreamBuffer) {
+        self.vertex_array = SpriteBatch::create_vbo(&buffer, &self.texture_indices);
+        self.buffer = buffer;
+        // Uploaded again for the regions of the new buffer
+        self.texture_indices.layout = (0, 0);
+        if let Some(ref mut instancing) = self.instancing {
+            // Reallocated with the new usage
+            instancing.len = 0;
+        }
+        for sprite in &mut self.sprites {
+            sprite.need_update = true;
+        }
+    }
+
+    /// Set the blend mode used to draw the sprites.
+    pub fn set_blend_mode(&mut self, blend_mode: BlendMode) {
+        self.blend_mode = blend_mode;
+    }
+
+    pub fn blend_mode(&self) -> BlendMode {
+        self.blend_mode
+    }
+
+    /// Set the layer used to sort the batch inside a DrawList.
+    pub fn set_layer(&mut self, layer: f32) {
+        self.layer = layer;
+    }
+
+    /// Draw with the shader instead of the built-in one,
+    /// `glob_model` and `projection` stay set by gust.
+    pub fn set_shader(&mut self, shader: Resource<Shader>) {
+        self.shader = Some(shader);
+    }
+
+    /// Go back to the built-in shader.
+    pub fn reset_shader(&mut self) {
+        self.shader = None;
+    }
+
+    /// The custom shader, None if the built-in one is used.
+    pub fn shader(&self) -> Option<&Resource<Shader>> {
+        self.shader.as_ref()
+    }
+
+    /// Set an uniform given to the shader before each draw.
+    pub fn set_uniform<T: Into<UniformValue>>(&mut self, name: &str, value: T) {
+        self.uniforms.set(name, value);
+    }
+
+    pub fn uniforms(&self) -> &Uniforms {
+        &self.uniforms
+    }
+
+    pub fn uniforms_mut(&mut self) -> &mut Uniforms {
+        &mut self.uniforms
+    }
+
+    /// Clear data, every handle become invalid.
     pub fn clear(&mut self) {
+        for slot in ::std::mem::take(&mut self.owners) {
+            self.free_slot(slot);
+        }
         self.sprites.clear();
         self.vertice.clear();
     }
@@ -274,6 +632,7 @@ impl SpriteBatch {
     }
 
     /// Return maybe a mutable Slice.
+    /// Indexes change when a sprite is removed, keep a SpriteHandle instead.
     pub fn get_sprite_mut(&mut self, idx: usize) -> Option<&mut SpriteData> {
         self.sprites.get_mut(idx)
     }
@@ -283,135 +642,161 @@ impl SpriteBatch {
         self.sprites.get(idx)
     }
 
-    pub fn push_sprite(&mut self, mut sprites: SpriteData) {
+    /// Push a sprite at the end of the batch.
+    pub fn push_sprite(&mut self, mut sprites: SpriteData) -> SpriteHandle {
         sprites.need_update = true;
-        let (w, h) = if let Some(ref texture) = self.texture {
-            (texture.width() as f32, texture.height() as f32)
-        } else {
-            (0.0, 0.0)
-        };
-
-        self.vertice.extend_from_slice(&[
-            Vertex::new(
-                Vector::new(0.0, 0.0),
-                Vector::new(sprites.text_coord[0].x, sprites.text_coord[0].y),
-                Color::white(),
-            ),
-            Vertex::new(
-                Vector::new(0.0, h),
-                Vector::new(sprites.text_coord[0].x, sprites.text_coord[1].y),
-                Color::white(),
-            ),
-            Vertex::new(
-                Vector::new(w, 0.0),
-                Vector::new(sprites.text_coord[1].x, sprites.text_coord[0].y),
-                Color::white(),
-            ),
-            Vertex::new(
-                Vector::new(w, h),
-                Vector::new(sprites.text_coord[1].x, sprites.text_coord[1].y),
-                Color::white(),
-            ),
-        ]);
+        if self.instancing.is_none() {
+            let quad = SpriteBatch::quad(&sprites, &self.textures);
+            self.vertice.extend_from_slice(&quad);
+        }
+        let index = self.sprites.len();
         self.sprites.push(sprites);
+        self.new_slot(index)
+    }
+
+    /// Add a sprite and return the handle to get it back, even after removals.
+    pub fn insert(&mut self, sprite: SpriteData) -> SpriteHandle {
+        self.push_sprite(sprite)
+    }
+
+    /// Remove the sprite of handle, the last sprite take its place so nothing else move.
+    /// Return None if the sprite was already removed.
+    pub fn remove(&mut self, handle: SpriteHandle) -> Option<SpriteData> {
+        let index = self.index(handle)?;
+        let last = self.sprites.len() - 1;
+
+        self.free_slot(handle.slot as usize);
+        self.owners.swap_remove(index);
+        let removed = self.sprites.swap_remove(index);
+        if index != last {
+            self.slots[self.owners[index]].sprite = Some(index);
+            // Its vertices have to be rebuilt at its new place
+            self.sprites[index].need_update = true;
+        }
+        if self.instancing.is_none() {
+            self.vertice.truncate(last * 4);
+        }
+        Some(removed)
+    }
+
+    /// Return true if the sprite of handle is still inside the batch.
+    pub fn contains(&self, handle: SpriteHandle) -> bool {
+        self.index(handle).is_some()
+    }
+
+    /// Return the sprite of handle.
+    pub fn get(&self, handle: SpriteHandle) -> Option<&SpriteData> {
+        self.index(handle).map(move |index| &self.sprites[index])
+    }
+
+    /// Return the sprite of handle mutably, changes are uploaded at the next update.
+    pub fn get_mut(&mut self, handle: SpriteHandle) -> Option<&mut SpriteData> {
+        let index = self.index(handle)?;
+        Some(&mut self.sprites[index])
     }
 
     /// Pop a sprite and return it's data.
     pub fn pop_sprite(&mut self) -> Option<SpriteData> {
-        self.need_update = true;
-        self.vertice.truncate(self.len - 4);
-        self.sprites.pop()
-    }
+        let sprite = self.sprites.pop()?;
 
-    fn update_vbo(&mut self) {
-        unsafe {
-            gl::BindBuffer(gl::ARRAY_BUFFER, self.gl_objects.1);
+        if let Some(slot) = self.owners.pop() {
+            self.free_slot(slot);
+        }
+        if self.instancing.is_none() {
+            let len = self.sprites.len() * 4;
+            self.vertice.truncate(len);
+        }
+        Some(sprite)
+    }
 
-            if self.len != self.vertice.len() / 4 {
-                gl::BufferData(
-                    gl::ARRAY_BUFFER,
-                    (std::mem::size_of::<GLfloat>() * self.vertice.len() * 8) as GLsizeiptr,
-                    self.vertice.as_ptr() as *const GLvoid,
-                    gl::STATIC_DRAW,
-                );
-                self.len = self.vertice.len() / 4;
+    /// Update the instances of the sprites changed, only the range changed is uploaded.
+    fn update_instances(&mut self) {
+        let usage = self.buffer.usage();
+        let instancing = match self.instancing {
+            Some(ref mut instancing) => instancing,
+            None => return,
+        };
+        let (mut first, mut last) = (self.sprites.len(), 0);
+        let units = SpriteBatch::texture_units();
+
+        instancing
+            .instances
+            .resize(self.sprites.len(), Instance::default());
+        for (i, sprite) in self.sprites.iter_mut().enumerate() {
+            if sprite.need_update {
+                instancing.instances[i] = SpriteBatch::instance(sprite, &self.textures, units);
+                sprite.need_update = false;
+                first = first.min(i);
+                last = i + 1;
             }
-            gl::BufferSubData(
-                gl::ARRAY_BUFFER,
-                0,
-                (std::mem::size_of::<GLfloat>() * self.vertice.len() * 8) as GLsizeiptr,
-                self.vertice.as_ptr() as *const GLvoid,
+        }
+
+        let device = device::current();
+        // The buffer is only reallocated when it grows or orphaned for Stream,
+        // removals keep it
+        if instancing.len < instancing.instances.len()
+            || (usage == BufferUsage::Stream && first < last)
+        {
+            device.instance_data(instancing.buffer.id(), &instancing.instances, usage);
+            instancing.len = instancing.instances.len();
+        } else if first < last {
+            device.instance_sub_data(
+                instancing.buffer.id(),
+                first,
+                &instancing.instances[first..last],
             );
-            self.update_vao();
-            gl::BindBuffer(gl::ARRAY_BUFFER, 0);
         }
     }
 
-    fn create_vbo() -> (u32, u32) {
-        let (mut vao, mut vbo) = (0, 0);
-        unsafe {
-            gl::GenVertexArrays(1, &mut vao);
+    /// Upload the vertices of the sprites between first and last.
+    fn update_vbo(&mut self, first: usize, last: usize) {
+        self.buffer.upload(&self.vertice, first * 4, last * 4);
+    }
 
-            gl::GenBuffers(1, &mut vbo);
+    /// Vertex array reading the buffer, the buffer id never change so it's linked once.
+    fn create_vbo(buffer: &StreamBuffer, texture_indices: &TextureIndices) -> Handle {
+        let device = device::current();
+        let vertex_array = device.create_vertex_array();
 
-            gl::BindVertexArray(0);
-        }
-        (vao, vbo)
+        device.vertex_layout(vertex_array, buffer.id());
+        device.texture_index_layout(vertex_array, texture_indices.buffer.id());
+        Handle::new(Kind::VertexArray, vertex_array)
     }
 
-    fn update_vao(&mut self) {
-        unsafe {
-            gl::BindVertexArray(self.gl_objects.0);
-            // position (of each vertex)
-            gl::VertexAttribPointer(
-                0,
-                2,
-                gl::FLOAT,
-                gl::FALSE,
-                (8 * mem::size_of::<GLfloat>()) as GLsizei,
-                ptr::null(),
-            );
-            gl::EnableVertexAttribArray(0);
-            // texture coord (of each vertex)
-            gl::VertexAttribPointer(
-                1,
-                2,
-                gl::FLOAT,
-                gl::FALSE,
-                (8 * mem::size_of::<GLfloat>()) as GLsizei,
-                (2 * mem::size_of::<GLfloat>()) as *const _,
-            );
-            gl::EnableVertexAttribArray(1);
-            // color (of each vertex)
-            gl::VertexAttribPointer(
-                2,
-                3,
-                gl::FLOAT,
-                gl::FALSE,
-                (8 * mem::size_of::<GLfloat>()) as GLsizei,
-                (4 * mem::size_of::<GLfloat>()) as *const _,
-            );
-            gl::EnableVertexAttribArray(2);
+    /// Upload the texture indices of the classic mode when they or the regions of the
+    /// vertex buffer changed.
+    fn upload_texture_indices(&mut self) {
+        let layout = (self.buffer.capacity(), self.buffer.regions());
+        let indices = &mut self.texture_indices;
 
-            gl::BindVertexArray(0);
+        if !indices.dirty && layout == indices.layout {
+            return;
         }
+        let mut data = Vec::with_capacity(layout.0 * layout.1);
+        for region in 0..layout.1 {
+            data.extend_from_slice(&indices.indices);
+            data.resize((region + 1) * layout.0, 0.0);
+        }
+        device::current().texture_index_data(indices.buffer.id(), &data);
+        indices.dirty = false;
+        indices.layout = layout;
     }
 
-    fn fill_vbo(&mut self) {
-        unsafe {
-            gl::BindVertexArray(self.gl_objects.0);
-            gl::BindBuffer(gl::ARRAY_BUFFER, self.gl_objects.1);
-
-            gl::BufferData(
-                gl::ARRAY_BUFFER,
-                (std::mem::size_of::<GLfloat>() * self.vertice.len() * 8) as GLsizeiptr,
-                self.vertice.as_ptr() as *const GLvoid,
-                gl::STATIC_DRAW,
-            );
-            self.update_vao();
-
-            gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+    /// Contiguous runs of sprites whose textures are in the same group of units,
+    /// as (group, first sprite, number of sprites). Each run is one sub-draw, in the
+    /// order of the sprites so they still overlap the same way.
+    fn texture_ranges(&self) -> Vec<(usize, usize, usize)> {
+        let units = SpriteBatch::texture_units();
+        let mut ranges: Vec<(usize, usize, usize)> = Vec::new();
+
+        for (i, sprite) in self.sprites.iter().enumerate() {
+            let group = sprite.texture / units;
+            match ranges.last_mut() {
+                Some(range) if range.0 == group => range.2 += 1,
+                _ => ranges.push((group, i, 1)),
+            }
         }
+        ranges
     }
 
     fn update_model(&mut self) {
@@ -427,30 +812,92 @@ impl SpriteBatch {
                 self.glob_origin.y,
                 0.0,
             ));
-            self.model *=
-                Matrix4::from_euler_angles(0.0, 0.0, self.glob_rotation * (3.14116 * 180.0));
+            self.model *= Matrix4::from_euler_angles(0.0, 0.0, self.glob_rotation.to_radians());
             self.model.prepend_translation_mut(&Vector3::new(
                 -self.glob_origin.x,
                 -self.glob_origin.y,
                 0.0,
             ));
         } else {
-            self.model *=
-                Matrix4::from_euler_angles(0.0, 0.0, self.glob_rotation * (3.14116 * 180.0));
+            self.model *= Matrix4::from_euler_angles(0.0, 0.0, self.glob_rotation.to_radians());
         }
         self.model.append_nonuniform_scaling_mut(&Vector3::new(
             self.glob_scale.x,
             self.glob_scale.y,
             0.0,
         ));
-        if self.glob_rotation > 360.0 {
-            self.glob_rotation = 0.0;
-        }
+        self.glob_rotation %= 360.0;
         self.need_update = false;
     }
 
-    fn len(&self) -> usize {
-        self.len
+    /// Draw every sprites with the context, binding the textures of the sub-draws
+    /// on the samplers of the shader when the batch holds several textures.
+    fn draw_in(&self, context: &mut Context, shader: Option<&Shader>) {
+        self.setup_draw(context);
+        if self.textures.len() <= 1 {
+            self.draw_sprites(0, self.sprites.len());
+            self.buffer.fence();
+            return;
+        }
+
+        // One sub-draw per run of sprites whose textures fit inside the same units
+        let units = SpriteBatch::texture_units();
+        if let Some(shader) = shader {
+            for unit in 0..units {
+                let name = format!("textures[{}]", unit);
+                if shader.has_uniform(&name) {
+                    shader.set_uniform(&name, UniformValue::Sampler(unit as u32));
+                }
+            }
+        }
+        let groups: Vec<_> = self.textures.chunks(units).collect();
+        for (group, first, count) in self.texture_ranges() {
+            // A sprite without texture has a zero size, nothing is bound for it
+            for (unit, texture) in groups
+                .get(group)
+                .into_iter()
+                .flat_map(|textures| textures.iter())
+                .enumerate()
+            {
+                texture.active(unit as i32);
+            }
+            self.draw_sprites(first, count);
+        }
+        self.buffer.fence();
+    }
+
+    /// Draw call of every sprites, with the shader and the textures already bound.
+    /// Draw count sprites from the sprite first.
+    fn draw_sprites(&self, first: usize, count: usize) {
+        let device = device::current();
+
+        match self.instancing {
+            Some(ref instancing) => {
+                if first != 0 {
+                    device.instance_layout(
+                        instancing.vertex_array.id(),
+                        instancing.buffer.id(),
+                        first,
+                    );
+                }
+                device.draw_arrays_instanced(
+                    instancing.vertex_array.id(),
+                    Primitive::TrianglesStrip,
+                    0,
+                    4,
+                    count,
+                );
+                if first != 0 {
+                    device.instance_layout(instancing.vertex_array.id(), instancing.buffer.id(), 0);
+                }
+            }
+            None => device.draw_arrays(
+                self.vertex_array.id(),
+                Primitive::TrianglesStrip,
+                self.buffer.first() + first * 4,
+                count * 4,
+            ),
+        }
     }
 }
 
@@ -549,102 +996,170 @@ impl DrawableMut for SpriteBatch {
 
 impl Drawable for SpriteBatch {
     fn draw<T: Drawer>(&self, target: &mut T) {
-        let texture = if let Some(ref rc_texture) = self.texture {
-            Some(rc_texture.as_ref())
-        } else {
+        let multi_texture = self.textures.len() > 1;
+        let texture = if multi_texture {
             None
+        } else {
+            self.textures.first().map(|texture| texture.as_ref())
         };
 
+        let shader = match self.shader {
+            Some(ref shader) => Rc::clone(shader),
+            None if multi_texture && self.instancing.is_some() => MULTI_INSTANCED_SHADER.get(),
+            None if multi_texture => MULTI_BATCH_SHADER.get(),
+            None if self.instancing.is_some() => INSTANCED_SHADER.get(),
+            None => BATCH_SHADER.get(),
+        };
         let mut context = Context::new(
             texture,
-            &*BATCH_SHADER,
+            &shader,
             vec![
                 ("projection".to_string(), target.projection()),
                 ("glob_model".to_string(), &self.model),
             ],
-            BlendMode::Alpha,
+            self.blend_mode,
         );
+        context.set_uniforms(&self.uniforms);
+        self.draw_in(&mut context, Some(&shader));
+    }
 
-        self.setup_draw(&mut context);
-        unsafe {
-            gl::BindVertexArray(self.gl_objects.0);
-            gl::BindBuffer(gl::ARRAY_BUFFER, self.gl_objects.1);
-
-            gl::DrawArrays(gl::TRIANGLE_STRIP, 0, self.vertice.len() as i32);
-
-            gl::BindVertexArray(0);
-            gl::BindBuffer(gl::ARRAY_BUFFER, 0);
-        }
+    fn layer(&self) -> f32 {
+        self.layer
     }
 
-    fn draw_with_context(&self, _context: &mut Context) {
-        unimplemented!(
-        "Put an issue here please if I forgot to implement it https://github.com/Afourcat/Gust/issues");
+    fn draw_with_context(&self, context: &mut Context) {
+        let shader = context.shader();
+        self.draw_in(context, shader);
     }
 
     fn update(&mut self) {
         //use std::sync::mpsc;
         //let (rec, sen) = mpsc::channel();
-        let mut sprite_mod = false;
-        {
+        let (mut first, mut last) = (self.sprites.len(), 0);
+        if self.instancing.is_some() {
+            self.update_instances();
+        } else {
             //let rex = Mutex::new(rec);
+            let textures = &self.textures;
             let sprites = &mut self.sprites;
             //let vertices = Mutex::new(&mut self.vertice);
             let vertices = &mut self.vertice;
+            // Only the batches of several textures need the indices
+            let indices = &mut self.texture_indices;
+            let multi_texture = textures.len() > 1;
 
+            if multi_texture {
+                indices.resize(sprites, SpriteBatch::texture_units());
+            }
             for (i, mut elem) in sprites.iter_mut().enumerate() {
                 if elem.need_update {
                     let vert = &mut vertices[(i * 4)..(i * 4 + 4)];
-                    self::update_sprite(&mut elem, Vector::new(0.0, 0.0), vert);
-                    sprite_mod = true;
+                    self::update_sprite(&mut elem, textures, vert);
+                    if multi_texture {
+                        indices.set(i, elem.texture);
+                    }
+                    first = first.min(i);
+                    last = i + 1;
                 }
             }
+            self.update_vbo(first, last);
+            if multi_texture {
+                self.upload_texture_indices();
+            }
         }
 
-        if sprite_mod {
-            self.update_vbo();
-        }
         if self.need_update {
             self.update_model();
         }
     }
 }
 
-fn update_sprite(data: &mut SpriteData, origin: Vector<f32>, vertice: &mut [Vertex]) {
-    data.model = Matrix4::identity().append_translation(&Vector3::new(
-        data.pos.x - origin.x,
-        data.pos.y - origin.y,
-        0.0,
-    ));
-
-    data.model *= Matrix4::from_euler_angles(0.0, 0.0, data.rotation * (3.14116 * 180.0));
-
-    for vertex in vertice {
-        let b = data.model * Vector4::new(vertex.pos.x, vertex.pos.y, 0.0, 1.0);
+/// Rebuild the vertices of a sprite from its frame then move them in the world.
+fn update_sprite(data: &mut SpriteData, textures: &[Rc<Texture>], vertice: &mut [Vertex]) {
+    data.model = Matrix4::new_translation(&Vector3::new(data.pos.x, data.pos.y, 0.0))
+        * Matrix4::from_euler_angles(0.0, 0.0, data.rotation.to_radians())
+        * Matrix4::new_nonuniform_scaling(&Vector3::new(data.scale.x, data.scale.y, 1.0))
+        * Matrix4::new_translation(&Vector3::new(-data.origin.x, -data.origin.y, 0.0));
+
+    let quad = SpriteBatch::quad(data, textures);
+    for (vertex, corner) in vertice.iter_mut().zip(quad.iter()) {
+        let b = data.model * Vector4::new(corner.pos.x, corner.pos.y, 0.0, 1.0);
+        *vertex = *corner;
         vertex.pos = Vector::new(b.x, b.y);
     }
 
-    if data.rotation > 360.0 {
-        data.rotation = 0.0;
-    }
+    data.rotation %= 360.0;
 
     data.need_update = false;
 }
 
 impl Default for SpriteBatch {
     fn default() -> Self {
+        let buffer = StreamBuffer::new(BufferUsage::Dynamic);
+        let texture_indices = TextureIndices::new();
+        let vertex_array = Self::create_vbo(&buffer, &texture_indices);
+
         SpriteBatch {
-            texture: None,
+            textures: Vec::new(),
             sprites: Vec::new(),
+            owners: Vec::new(),
+            slots: Vec::new(),
+            free_slots: Vec::new(),
             vertice: Vec::new(),
-            gl_objects: Self::create_vbo(),
+            vertex_array,
+            buffer,
+            texture_indices,
             glob_origin: Vector::new(0.0, 0.0),
             glob_pos: Vector::new(0.0, 0.0),
             glob_scale: Vector::new(0.0, 0.0),
             glob_rotation: 0.0,
-            len: 0,
             need_update: false,
             model: Matrix4::identity(),
+            blend_mode: BlendMode::Alpha,
+            layer: 0.0,
+            shader: None,
+            uniforms: Uniforms::new(),
+            instancing: None,
+        }
+    }
+}
+
+/// A deep copy with its own gl objects, the sprites are uploaded again at the next update.
+impl Clone for SpriteBatch {
+    fn clone(&self) -> SpriteBatch {
+        let buffer = if self.buffer.is_ring() {
+            StreamBuffer::ring(self.buffer.regions())
+        } else {
+            StreamBuffer::new(self.usage())
+        };
+        let texture_indices = TextureIndices::new();
+        let vertex_array = Self::create_vbo(&buffer, &texture_indices);
+        let mut sprites = self.sprites.clone();
+
+        for sprite in &mut sprites {
+            sprite.need_update = true;
+        }
+        SpriteBatch {
+            textures: self.textures.clone(),
+            sprites,
+            owners: self.owners.clone(),
+            slots: self.slots.clone(),
+            free_slots: self.free_slots.clone(),
+            vertice: self.vertice.clone(),
+            vertex_array,
+            buffer,
+            texture_indices,
+            glob_origin: self.glob_origin,
+            glob_pos: self.glob_pos,
+            glob_scale: self.glob_scale,
+            glob_rotation: self.glob_rotation,
+            need_update: self.need_update,
+            model: self.model,
+            blend_mode: self.blend_mode,
+            layer: self.layer,
+            shader: self.shader.clone(),
+            uniforms: self.uniforms.clone(),
+            instancing: self.instancing.as_ref().map(|_| Instancing::new()),
         }
     }
 }
@@ -652,19 +1167,31 @@ impl Default for SpriteBatch {
 impl From<&Rc<Texture>> for SpriteBatch {
     fn from(what: &Rc<Texture>) -> SpriteBatch {
         let (width, height) = (what.width(), what.height());
+        let buffer = StreamBuffer::new(BufferUsage::Dynamic);
+        let texture_indices = TextureIndices::new();
+        let vertex_array = Self::create_vbo(&buffer, &texture_indices);
 
         SpriteBatch {
-            texture: Some(Rc::clone(what)),
+            textures: vec![Rc::clone(what)],
             sprites: Vec::new(),
+            owners: Vec::new(),
+            slots: Vec::new(),
+            free_slots: Vec::new(),
             vertice: Vec::new(),
-            gl_objects: Self::create_vbo(),
+            vertex_array,
+            buffer,
+            texture_indices,
             glob_origin: Vector::new((width / 2) as f32, (height / 2) as f32),
             glob_pos: Vector::new(0.0, 0.0),
             glob_scale: Vector::new(1.0, 1.0),
             glob_rotation: 0.0,
-            len: 0,
             need_update: false,
             model: Matrix4::identity(),
+            blend_mode: BlendMode::Alpha,
+            layer: 0.0,
+            shader: None,
+            uniforms: Uniforms::new(),
+            instancing: None,
         }
     }
 }
@@ -675,15 +1202,274 @@ mod test {
 
     use self::test::Bencher;
     use super::{SpriteBatch, SpriteData};
-    use draw::Drawable;
+    use color::Color;
+    use device::{self, NullDevice};
+    use draw::{BlendMode, Context, Drawable, Drawer};
+    use nalgebra::Matrix4;
+    use render_texture::RenderTexture;
+    use shader::MULTI_BATCH_SHADER;
     use std::rc::Rc;
-    use transform::Movable;
+    use transform::{Movable, Rotable, Scalable, Transformable};
     use window::Window;
     use {texture::Texture, Vector};
 
+    #[test]
+    fn instanced_batch_is_one_draw_without_vertices() {
+        let null = Rc::new(NullDevice::new());
+        let _device = device::scoped(null.clone());
+        let texture = Rc::new(Texture::from_color(Color::white(), Vector::new(4, 4)));
+        let mut target = RenderTexture::new(8, 8).unwrap();
+        let mut batch = SpriteBatch::from(&texture);
+
+        batch.set_instanced(true);
+        for i in 0..1000 {
+            batch.push_sprite(SpriteData::new(Vector::new(i as f32, 0.0)));
+        }
+        target.draw_mut(&mut batch);
+        assert_eq!(null.draw_calls(), 1);
+        assert!(batch.vertice.is_empty());
+        assert_eq!(batch.instancing.as_ref().unwrap().len, 1000);
+
+        batch.set_instanced(false);
+        assert_eq!(batch.vertice.len(), 4000);
+    }
+
+    #[test]
+    fn sprite_quad_follows_rect_scale_and_origin() {
+        let null = Rc::new(NullDevice::new());
+        let _device = device::scoped(null.clone());
+        let texture = Rc::new(Texture::from_color(Color::white(), Vector::new(8, 8)));
+        let mut batch = SpriteBatch::from(&texture);
+        let mut data = SpriteData::new(Vector::new(10.0, 10.0));
+
+        data.set_texture_raw([Vector::new(0.5, 0.0), Vector::new(0.5, 0.5)]);
+        data.set_scale(Vector::new(2.0, 2.0));
+        data.set_origin(Vector::new(2.0, 2.0));
+        batch.push_sprite(data.clone());
+        batch.update();
+        assert_eq!(batch.vertice[0].pos, Vector::new(6.0, 6.0));
+        assert_eq!(batch.vertice[3].pos, Vector::new(14.0, 14.0));
+        assert_eq!(batch.vertice[3].tex, Vector::new(1.0, 0.5));
+
+        // Updating twice must not move the vertices again
+        batch
+            .get_sprite_mut(0)
+            .unwrap()
+            .set_position(Vector::new(10.0, 10.0));
+        batch.update();
+        assert_eq!(batch.vertice[0].pos, Vector::new(6.0, 6.0));
+
+        let instance = SpriteBatch::instance(&data, batch.textures(), 16);
+        assert_eq!(instance.scale, Vector::new(8.0, 8.0));
+        assert_eq!(instance.origin, Vector::new(4.0, 4.0));
+
+        // The rotation is in degrees, a quarter turn around the origin
+        batch.get_sprite_mut(0).unwrap().set_rotation(90.0);
+        batch.update();
+        let corner = batch.vertice[0].pos;
+        assert!((corner.x - 14.0).abs() < 1e-4 && (corner.y - 6.0).abs() < 1e-4);
+
+        // A full turn more lands on the same quad and keeps the remainder
+        batch.get_sprite_mut(0).unwrap().set_rotation(450.0);
+        batch.update();
+        assert_eq!(batch.get_sprite(0).unwrap().get_rotation(), 90.0);
+        let corner = batch.vertice[0].pos;
+        assert!((corner.x - 14.0).abs() < 1e-4 && (corner.y - 6.0).abs() < 1e-4);
+        data.set_rotation(90.0);
+        let instance = SpriteBatch::instance(&data, batch.textures(), 16);
+        assert_eq!(instance.rotation, 90f32.to_radians());
+    }
+
+    #[test]
+    fn clone_owns_its_own_buffers() {
+        let _device = device::scoped(Rc::new(NullDevice::new()));
+        let texture = Rc::new(Texture::from_color(Color::white(), Vector::new(4, 4)));
+        let mut batch = SpriteBatch::from(&texture);
+
+        batch.set_ring(3);
+        batch.push_sprite(SpriteData::new(Vector::new(0.0, 0.0)));
+        batch.update();
+        let mut clone = batch.clone();
+
+        assert_ne!(clone.buffer.id(), batch.buffer.id());
+        assert_ne!(clone.vertex_array.id(), batch.vertex_array.id());
+        assert!(clone.buffer.is_ring() && clone.buffer.is_empty());
+        clone.update();
+        assert_eq!((clone.buffer.len(), batch.buffer.len()), (4, 4));
+    }
+
+    #[test]
+    fn handles_stay_valid_across_removals() {
+        let null = Rc::new(NullDevice::new());
+        let _device = device::scoped(null.clone());
+        let texture = Rc::new(Texture::from_color(Color::white(), Vector::new(4, 4)));
+        let mut batch = SpriteBatch::from(&texture);
+        let handles: Vec<_> = (0..4)
+            .map(|i| batch.insert(SpriteData::new(Vector::new(i as f32, 0.0))))
+            .collect();
+
+        batch.update();
+        assert_eq!(batch.remove(handles[1]).unwrap().get_position().x, 1.0);
+        assert!(batch.remove(handles[1]).is_none());
+        assert!(!batch.contains(handles[1]));
+        assert_eq!(batch.get(handles[3]).unwrap().get_position().x, 3.0);
+        assert_eq!(batch.get(handles[0]).unwrap().get_position().x, 0.0);
+        assert_eq!(batch.vertice.len(), 12);
+
+        // The moved sprite is rebuilt at its new place
+        batch.update();
+        assert_eq!(batch.vertice[4].pos, Vector::new(3.0, 0.0));
+
+        // A new sprite reuse the slot but not the handle
+        let new = batch.insert(SpriteData::new(Vector::new(5.0, 0.0)));
+        assert_ne!(new, handles[1]);
+        assert!(batch.get(handles[1]).is_none());
+        assert_eq!(batch.pop_sprite().unwrap().get_position().x, 5.0);
+        assert!(!batch.contains(new));
+        batch.clear();
+        assert!(!batch.contains(handles[0]));
+    }
+
+    #[test]
+    fn textures_beyond_the_units_are_split_in_sub_draws() {
+        let null = Rc::new(NullDevice::new());
+        let _device = device::scoped(null.clone());
+        let textures: Vec<_> = (0..20)
+            .map(|i| Rc::new(Texture::from_color(Color::white(), Vector::new(i + 1, 1))))
+            .collect();
+        let mut target = RenderTexture::new(8, 8).unwrap();
+        let mut batch = SpriteBatch::from(&textures[0]);
+
+        for texture in &textures {
+            let mut data = SpriteData::new(Vector::new(0.0, 0.0));
+            data.set_texture_index(batch.add_texture(texture));
+            batch.push_sprite(data);
+        }
+        assert_eq!(batch.add_texture(&textures[3]), 3);
+        assert_eq!(batch.textures().len(), 20);
+
+        batch.update();
+        assert_eq!(batch.vertice[3 * 4 + 3].pos, Vector::new(4.0, 1.0));
+        // The indices are inside the group of 16 units of the null device
+        assert_eq!(batch.texture_indices.indices[3 * 4], 3.0);
+        assert_eq!(batch.texture_indices.indices[17 * 4], 1.0);
+        assert!(!batch.texture_indices.dirty);
+
+        // Only the sprites changed or moved are set again
+        batch.get_sprite_mut(3).unwrap().set_texture_index(5);
+        let removed = batch.pop_sprite().unwrap();
+        batch.update();
+        assert_eq!(batch.texture_indices.indices.len(), 19 * 4);
+        assert_eq!(batch.texture_indices.indices[3 * 4 + 3], 5.0);
+        assert!(!batch.texture_indices.dirty);
+        batch.push_sprite(removed);
+
+        target.draw_mut(&mut batch);
+        assert_eq!(null.draw_calls(), 2);
+    }
+
+    #[test]
+    fn sub_draws_keep_the_order_of_the_sprites() {
+        let null = Rc::new(NullDevice::new());
+        let _device = device::scoped(null.clone());
+        let textures: Vec<_> = (0..20)
+            .map(|_| Rc::new(Texture::from_color(Color::white(), Vector::new(1, 1))))
+            .collect();
+        let mut target = RenderTexture::new(8, 8).unwrap();
+        let mut batch = SpriteBatch::from(&textures[0]);
+
+        for texture in &textures {
+            batch.add_texture(texture);
+        }
+        for &index in &[0, 17, 1, 2, 18] {
+            let mut data = SpriteData::new(Vector::new(0.0, 0.0));
+            data.set_texture_index(index);
+            batch.push_sprite(data);
+        }
+        let group = |index: usize| Some(textures[index].id());
+
+        // One sub-draw per run of sprites of the same group, in their order
+        let list = target.record(|target| target.draw_mut(&mut batch));
+        let draws: Vec<_> = list
+            .draws()
+            .map(|draw| (draw.first, draw.count, draw.texture()))
+            .collect();
+        assert_eq!(
+            draws,
+            vec![
+                (0, 4, group(0)),
+                (4, 4, group(16)),
+                (8, 8, group(0)),
+                (16, 4, group(16)),
+            ]
+        );
+
+        batch.set_instanced(true);
+        let list = target.record(|target| target.draw_mut(&mut batch));
+        let instances: Vec<_> = list.draws().map(|draw| draw.instances).collect();
+        assert_eq!(instances, vec![Some(1), Some(1), Some(2), Some(1)]);
+    }
+
+    #[test]
+    fn draw_with_context_uses_the_shader_of_the_context() {
+        let _device = device::scoped(Rc::new(NullDevice::new()));
+        let textures: Vec<_> = (0..20)
+            .map(|_| Rc::new(Texture::from_color(Color::white(), Vector::new(1, 1))))
+            .collect();
+        let mut target = RenderTexture::new(8, 8).unwrap();
+        let mut batch = SpriteBatch::from(&textures[0]);
+        for texture in &textures {
+            batch.add_texture(texture);
+        }
+        for &index in &[0, 17] {
+            let mut data = SpriteData::new(Vector::new(0.0, 0.0));
+            data.set_texture_index(index);
+            batch.push_sprite(data);
+        }
+        batch.update();
+
+        let shader = MULTI_BATCH_SHADER.get();
+        let identity = Matrix4::identity();
+        let list = target.record(|target| {
+            let mut context = Context::new(
+                None,
+                &shader,
+                vec![("glob_model".to_string(), &identity)],
+                BlendMode::Alpha,
+            );
+            target.draw_with_context(&mut batch, &mut context);
+        });
+        let draws: Vec<_> = list
+            .draws()
+            .map(|draw| (draw.first, draw.program, draw.texture()))
+            .collect();
+        assert_eq!(
+            draws,
+            vec![
+                (0, shader.id(), Some(textures[0].id())),
+                (4, shader.id(), Some(textures[16].id())),
+            ]
+        );
+    }
+
+    #[test]
+    fn texture_units_are_queried_once_per_context() {
+        let _device = device::scoped(Rc::new(NullDevice::new()));
+        let previous = device::context();
+        let context = device::new_context();
+
+        device::set_context(context);
+        assert_eq!(SpriteBatch::texture_units(), 16);
+        device::with_objects(|objects| objects.texture_units = Some(4));
+        assert_eq!(SpriteBatch::texture_units(), 4);
+        device::set_context(previous);
+        assert_eq!(SpriteBatch::texture_units(), 16);
+        device::drop_context(context);
+    }
+
     #[bench]
     fn sprite_batch_create(bencher: &mut Bencher) {
-        Window::new(100, 100, "Loader");
+        let _window = Window::headless(100, 100).unwrap();
 
         bencher.iter(|| {
             SpriteBatch::new();
@@ -692,7 +1478,7 @@ mod test {
 
     #[bench]
     fn batch_create_with_data(bencher: &mut Bencher) {
-        Window::new(100, 100, "Loader");
+        let _window = Window::headless(100, 100).unwrap();
         let texture = Rc::new(Texture::from_path("examples/texture/test.jpg").unwrap());
         let mut vec = Vec::with_capacity(1000);
         (0..1000)
@@ -707,7 +1493,7 @@ mod test {
 
     #[bench]
     fn batch_update_create(bencher: &mut Bencher) {
-        Window::new(100, 100, "Loader");
+        let _window = Window::headless(100, 100).unwrap();
         let texture = Rc::new(Texture::from_path("examples/texture/test.jpg").unwrap());
         let mut vec = Vec::with_capacity(1000);
         (0..1000)
@@ -723,7 +1509,7 @@ mod test {
 
     #[bench]
     fn batch_update_translation_with_bad_update(bencher: &mut Bencher) {
-        Window::new(100, 100, "Loader");
+        let _window = Window::headless(100, 100).unwrap();
         let texture = Rc::new(Texture::from_path("examples/texture/test.jpg").unwrap());
         let mut vec = Vec::with_capacity(1000);
         (0..1000)
@@ -748,7 +1534,7 @@ mod test {
 
     #[bench]
     fn batch_update_translation(bencher: &mut Bencher) {
-        Window::new(100, 100, "Loader");
+        let _window = Window::headless(100, 100).unwrap();
         let texture = Rc::new(Texture::from_path("examples/texture/test.jpg").unwrap());
         let mut vec = Vec::with_capacity(1000);
         (0..1000)
@@ -768,7 +1554,7 @@ mod test {
 
     #[bench]
     fn batch_update_content(bencher: &mut Bencher) {
-        Window::new(100, 100, "Loader");
+        let _window = Window::headless(100, 100).unwrap();
         let texture = Rc::new(Texture::from_path("examples/texture/Dirt.png").unwrap());
         let mut vec = Vec::with_capacity(100000);
         (0..100000)
@@ -0,0 +1,597 @@
+//
+//  Rust file | 2018
+//  Author: Alexandre Fourcat
+//  state_cache.rs
+//  module:
+//! Gl state cache.
+//! Every draw set its texture, blend mode, program and uniforms even if they are already there.
+//! The StateCache sit in front of a device and only forward the calls changing something.
+//! It is the default device of each thread, its counters tell how many calls were saved.
+//! ```no_run
+//! use gust::device;
+//! use gust::prelude::*;
+//!
+//! let mut window = Window::new(800, 600, "Stats");
+//! // draw things...
+//! window.display();
+//!
+//! let stats = device::current().stats();
+//! println!("{} calls issued, {} skipped", stats.issued(), stats.skipped());
+//! device::current().reset_stats();
+//! ```
+
+use color::Color;
+use command::Uniform;
+use device::{ActiveVariable, DepthTest, RenderDevice, ShaderStage, StencilTest};
+use draw::BlendMode;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::ffi::CStr;
+use std::rc::Rc;
+use texture::{Filter, RgbMode, Wrap};
+use vertex::{Instance, Vertex};
+use vertex_buffer::{BufferUsage, Primitive};
+use Vector;
+
+/// Number of calls forwarded to the device and skipped because nothing changed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Counter {
+    pub issued: usize,
+    pub skipped: usize,
+}
+
+impl Counter {
+    fn count(&mut self, issued: bool) -> bool {
+        if issued {
+            self.issued += 1;
+        } else {
+            self.skipped += 1;
+        }
+        issued
+    }
+}
+
+/// Counters of a StateCache by kind of state.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StateStats {
+    pub textures: Counter,
+    pub programs: Counter,
+    pub blends: Counter,
+    pub uniforms: Counter,
+    pub framebuffers: Counter,
+    /// Viewport, front face, scissor, stencil and depth.
+    pub states: Counter,
+}
+
+impl StateStats {
+    fn counters(&self) -> [Counter; 6] {
+        [
+            self.textures,
+            self.programs,
+            self.blends,
+            self.uniforms,
+            self.framebuffers,
+            self.states,
+        ]
+    }
+
+    /// Total of calls forwarded.
+    pub fn issued(&self) -> usize {
+        self.counters().iter().map(|c| c.issued).sum()
+    }
+
+    /// Total of calls skipped.
+    pub fn skipped(&self) -> usize {
+        self.counters().iter().map(|c| c.skipped).sum()
+    }
+}
+
+/// Last known value of each state, None when unknown.
+#[derive(Debug, Default)]
+struct State {
+    textures: HashMap<u32, u32>,
+    program: Option<u32>,
+    blend: Option<Option<BlendMode>>,
+    uniforms: HashMap<(u32, i32), Uniform>,
+    framebuffer: Option<u32>,
+    viewport: Option<[i32; 4]>,
+    front_face: Option<bool>,
+    scissor: Option<Option<[i32; 4]>>,
+    stencil: Option<Option<StencilTest>>,
+    depth: Option<Option<DepthTest>>,
+}
+
+/// Store value in slot and return true if it changed.
+fn update<T: PartialEq>(slot: &mut Option<T>, value: T) -> bool {
+    if slot.as_ref() == Some(&value) {
+        false
+    } else {
+        *slot = Some(value);
+        true
+    }
+}
+
+/// Device skipping the redundant state changes of another one.
+pub struct StateCache {
+    inner: Rc<RenderDevice>,
+    state: RefCell<State>,
+    stats: Cell<StateStats>,
+}
+
+impl StateCache {
+    pub fn new(inner: Rc<RenderDevice>) -> StateCache {
+        StateCache {
+            inner,
+            state: RefCell::new(State::default()),
+            stats: Cell::new(StateStats::default()),
+        }
+    }
+
+    /// Count a call and return if it has to be forwarded.
+    fn count<F: FnOnce(&mut StateStats) -> &mut Counter>(&self, counter: F, issued: bool) -> bool {
+        let mut stats = self.stats.get();
+        counter(&mut stats).count(issued);
+        self.stats.set(stats);
+        issued
+    }
+
+    /// The inner device bind textures itself while working on them.
+    fn forget_textures(&self) {
+        self.state.borrow_mut().textures.clear();
+    }
+
+    fn set_uniform(&self, location: i32, value: Uniform) -> bool {
+        let mut state = self.state.borrow_mut();
+        let program = state.program;
+        let changed = match program {
+            Some(program) if location != -1 => {
+                if state.uniforms.get(&(program, location)) == Some(&value) {
+                    false
+                } else {
+                    state.uniforms.insert((program, location), value);
+                    true
+                }
+            }
+            _ => true,
+        };
+
+        drop(state);
+        self.count(|s| &mut s.uniforms, changed)
+    }
+}
+
+impl RenderDevice for StateCache {
+    fn create_buffer(&self) -> u32 {
+        self.inner.create_buffer()
+    }
+
+    fn buffer_data(&self, buffer: u32, data: &[Vertex], usage: BufferUsage) {
+        self.inner.buffer_data(buffer, data, usage);
+    }
+
+    fn buffer_sub_data(&self, buffer: u32, offset: usize, data: &[Vertex]) {
+        self.inner.buffer_sub_data(buffer, offset, data);
+    }
+
+    fn orphan_buffer(&self, buffer: u32, len: usize, usage: BufferUsage) {
+        self.inner.orphan_buffer(buffer, len, usage);
+    }
+
+    fn buffer_write_unsynchronized(&self, buffer: u32, offset: usize, data: &[Vertex]) {
+        self.inner.buffer_write_unsynchronized(buffer, offset, data);
+    }
+
+    fn instance_data(&self, buffer: u32, data: &[Instance], usage: BufferUsage) {
+        self.inner.instance_data(buffer, data, usage);
+    }
+
+    fn instance_sub_data(&self, buffer: u32, offset: usize, data: &[Instance]) {
+        self.inner.instance_sub_data(buffer, offset, data);
+    }
+
+    fn texture_index_data(&self, buffer: u32, data: &[f32]) {
+        self.inner.texture_index_data(buffer, data);
+    }
+
+    fn bind_buffer(&self, buffer: u32) {
+        self.inner.bind_buffer(buffer);
+    }
+
+    fn delete_buffer(&self, buffer: u32) {
+        self.inner.delete_buffer(buffer);
+    }
+
+    fn create_vertex_array(&self) -> u32 {
+        self.inner.create_vertex_array()
+    }
+
+    fn vertex_layout(&self, vertex_array: u32, buffer: u32) {
+        self.inner.vertex_layout(vertex_array, buffer);
+    }
+
+    fn texture_index_layout(&self, vertex_array: u32, buffer: u32) {
+        self.inner.texture_index_layout(vertex_array, buffer);
+    }
+
+    fn instance_layout(&self, vertex_array: u32, buffer: u32, first: usize) {
+        self.inner.instance_layout(vertex_array, buffer, first);
+    }
+
+    fn bind_vertex_array(&self, vertex_array: u32) {
+        self.inner.bind_vertex_array(vertex_array);
+    }
+
+    fn delete_vertex_array(&self, vertex_array: u32) {
+        self.inner.delete_vertex_array(vertex_array);
+    }
+
+    fn fence(&self) -> usize {
+        self.inner.fence()
+    }
+
+    fn wait_fence(&self, fence: usize, timeout: u64) -> bool {
+        self.inner.wait_fence(fence, timeout)
+    }
+
+    fn delete_fence(&self, fence: usize) {
+        self.inner.delete_fence(fence);
+    }
+
+    fn create_texture(&self, data: &[u8], mode: RgbMode, width: u32, height: u32) -> u32 {
+        self.forget_textures();
+        self.inner.create_texture(data, mode, width, height)
+    }
+
+    fn update_texture(
+        &self,
+        texture: u32,
+        data: &[u8],
+        mode: RgbMode,
+        pos: Vector<u32>,
+        sizes: Vector<u32>,
+    ) {
+        self.forget_textures();
+        self.inner.update_texture(texture, data, mode, pos, sizes);
+    }
+
+    fn read_texture(&self, texture: u32, mode: RgbMode, data: &mut [u8]) {
+        self.forget_textures();
+        self.inner.read_texture(texture, mode, data);
+    }
+
+    fn copy_texture(&self, src: u32, dst: u32, mode: RgbMode, width: u32, height: u32) {
+        self.forget_textures();
+        self.inner.copy_texture(src, dst, mode, width, height);
+    }
+
+    fn texture_wrap(&self, texture: u32, wrap: Wrap) {
+        self.forget_textures();
+        self.inner.texture_wrap(texture, wrap);
+    }
+
+    fn texture_filter(&self, texture: u32, filter: Filter) {
+        self.forget_textures();
+        self.inner.texture_filter(texture, filter);
+    }
+
+    fn bind_texture(&self, unit: u32, texture: u32) {
+        let changed = self.state.borrow_mut().textures.insert(unit, texture) != Some(texture);
+
+        if self.count(|s| &mut s.textures, changed) {
+            self.inner.bind_texture(unit, texture);
+        }
+    }
+
+    fn max_texture_units(&self) -> usize {
+        self.inner.max_texture_units()
+    }
+
+    fn delete_texture(&self, texture: u32) {
+        // Gl unbind a deleted texture, and its id can be given again
+        self.state
+            .borrow_mut()
+            .textures
+            .retain(|_, bound| *bound != texture);
+        self.inner.delete_texture(texture);
+    }
+
+    fn create_shader(&self, stage: ShaderStage, source: &CStr) -> u32 {
+        self.inner.create_shader(stage, source)
+    }
+
+    fn shader_status(&self, shader: u32) -> Result<(), String> {
+        self.inner.shader_status(shader)
+    }
+
+    fn create_program(&self, vert: u32, frag: u32) -> u32 {
+        self.inner.create_program(vert, frag)
+    }
+
+    fn program_status(&self, program: u32) -> Result<(), String> {
+        self.inner.program_status(program)
+    }
+
+    fn use_program(&self, program: u32) {
+        let changed = update(&mut self.state.borrow_mut().program, program);
+
+        if self.count(|s| &mut s.programs, changed) {
+            self.inner.use_program(program);
+        }
+    }
+
+    fn uniform_location(&self, program: u32, name: &str) -> i32 {
+        self.inner.uniform_location(program, name)
+    }
+
+    fn active_uniforms(&self, program: u32) -> Vec<ActiveVariable> {
+        self.inner.active_uniforms(program)
+    }
+
+    fn active_attributes(&self, program: u32) -> Vec<ActiveVariable> {
+        self.inner.active_attributes(program)
+    }
+
+    fn uniform_floats(&self, location: i32, values: &[f32]) {
+        if self.set_uniform(location, Uniform::Floats(values.to_vec())) {
+            self.inner.uniform_floats(location, values);
+        }
+    }
+
+    fn uniform_ints(&self, location: i32, values: &[i32]) {
+        if self.set_uniform(location, Uniform::Ints(values.to_vec())) {
+            self.inner.uniform_ints(location, values);
+        }
+    }
+
+    fn uniform_matrix(&self, location: i32, values: &[f32]) {
+        if self.set_uniform(location, Uniform::Matrix(values.to_vec())) {
+            self.inner.uniform_matrix(location, values);
+        }
+    }
+
+    fn delete_shader(&self, shader: u32) {
+        self.inner.delete_shader(shader);
+    }
+
+    fn delete_program(&self, program: u32) {
+        {
+            let mut state = self.state.borrow_mut();
+
+            state.uniforms.retain(|key, _| key.0 != program);
+            if state.program == Some(program) {
+                state.program = None;
+            }
+        }
+        self.inner.delete_program(program);
+    }
+
+    fn create_framebuffer(&self, texture: u32) -> Result<u32, u32> {
+        // The texture is bound on the active unit, the framebuffer binding is given back
+        self.state.borrow_mut().textures.clear();
+        self.inner.create_framebuffer(texture)
+    }
+
+    fn bind_framebuffer(&self, framebuffer: u32) {
+        let changed = update(&mut self.state.borrow_mut().framebuffer, framebuffer);
+
+        if self.count(|s| &mut s.framebuffers, changed) {
+            self.inner.bind_framebuffer(framebuffer);
+        }
+    }
+
+    fn framebuffer(&self) -> u32 {
+        if let Some(framebuffer) = self.state.borrow().framebuffer {
+            return framebuffer;
+        }
+
+        let framebuffer = self.inner.framebuffer();
+        self.state.borrow_mut().framebuffer = Some(framebuffer);
+        framebuffer
+    }
+
+    fn read_pixels(&self, width: u32, height: u32, data: &mut [u8]) {
+        self.inner.read_pixels(width, height, data);
+    }
+
+    fn delete_framebuffer(&self, framebuffer: u32) {
+        {
+            let mut state = self.state.borrow_mut();
+
+            if state.framebuffer == Some(framebuffer) {
+                state.framebuffer = Some(0);
+            }
+        }
+        self.inner.delete_framebuffer(framebuffer);
+    }
+
+    fn init(&self, width: u32, height: u32) {
+        self.reset_state();
+        self.inner.init(width, height);
+    }
+
+    fn viewport(&self) -> [i32; 4] {
+        if let Some(viewport) = self.state.borrow().viewport {
+            return viewport;
+        }
+
+        let viewport = self.inner.viewport();
+        self.state.borrow_mut().viewport = Some(viewport);
+        viewport
+    }
+
+    fn set_viewport(&self, viewport: [i32; 4]) {
+        let changed = update(&mut self.state.borrow_mut().viewport, viewport);
+
+        if self.count(|s| &mut s.states, changed) {
+            self.inner.set_viewport(viewport);
+        }
+    }
+
+    fn front_face(&self, clockwise: bool) {
+        let changed = update(&mut self.state.borrow_mut().front_face, clockwise);
+
+        if self.count(|s| &mut s.states, changed) {
+            self.inner.front_face(clockwise);
+        }
+    }
+
+    fn is_clockwise(&self) -> bool {
+        if let Some(clockwise) = self.state.borrow().front_face {
+            return clockwise;
+        }
+
+        let clockwise = self.inner.is_clockwise();
+        self.state.borrow_mut().front_face = Some(clockwise);
+        clockwise
+    }
+
+    fn blend(&self, mode: &BlendMode) {
+        let changed = update(&mut self.state.borrow_mut().blend, Some(*mode));
+
+        if self.count(|s| &mut s.blends, changed) {
+            self.inner.blend(mode);
+        }
+    }
+
+    fn disable_blend(&self) {
+        let changed = update(&mut self.state.borrow_mut().blend, None);
+
+        if self.count(|s| &mut s.blends, changed) {
+            self.inner.disable_blend();
+        }
+    }
+
+    fn scissor(&self, rect: Option<[i32; 4]>) {
+        let changed = update(&mut self.state.borrow_mut().scissor, rect);
+
+        if self.count(|s| &mut s.states, changed) {
+            self.inner.scissor(rect);
+        }
+    }
+
+    fn stencil(&self, test: Option<StencilTest>) {
+        let changed = update(&mut self.state.borrow_mut().stencil, test);
+
+        if self.count(|s| &mut s.states, changed) {
+            self.inner.stencil(test);
+        }
+    }
+
+    fn depth(&self, test: Option<DepthTest>) {
+        let changed = update(&mut self.state.borrow_mut().depth, test);
+
+        if self.count(|s| &mut s.states, changed) {
+            self.inner.depth(test);
+        }
+    }
+
+    fn clear_stencil(&self, bits: u8) {
+        // The scissor and the stencil mask are changed to clear
+        {
+            let mut state = self.state.borrow_mut();
+
+            state.scissor = None;
+            state.stencil = None;
+        }
+        self.inner.clear_stencil(bits);
+    }
+
+    fn clear(&self, color: Color) {
+        {
+            let mut state = self.state.borrow_mut();
+
+            state.scissor = None;
+            state.stencil = None;
+            state.depth = None;
+        }
+        self.inner.clear(color);
+    }
+
+    fn draw_arrays(&self, vertex_array: u32, primitive: Primitive, first: usize, count: usize) {
+        self.inner
+            .draw_arrays(vertex_array, primitive, first, count);
+    }
+
+    fn draw_arrays_instanced(
+        &self,
+        vertex_array: u32,
+        primitive: Primitive,
+        first: usize,
+        count: usize,
+        instances: usize,
+    ) {
+        self.inner
+            .draw_arrays_instanced(vertex_array, primitive, first, count, instances);
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+
+    fn reset_state(&self) {
+        *self.state.borrow_mut() = State::default();
+        self.inner.reset_state();
+    }
+
+    fn stats(&self) -> StateStats {
+        self.stats.get()
+    }
+
+    fn reset_stats(&self) {
+        self.stats.set(StateStats::default());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::StateCache;
+    use device::{NullDevice, RenderDevice};
+    use draw::BlendMode;
+    use std::rc::Rc;
+
+    #[test]
+    fn redundant_calls_are_skipped() {
+        let cache = StateCache::new(Rc::new(NullDevice::new()));
+
+        for _ in 0..3 {
+            cache.use_program(1);
+            cache.bind_texture(0, 4);
+            cache.blend(&BlendMode::Alpha);
+            cache.uniform_floats(2, &[1.0, 2.0]);
+        }
+        cache.uniform_floats(2, &[1.0, 3.0]);
+        cache.blend(&BlendMode::Add);
+
+        let stats = cache.stats();
+        assert_eq!(stats.programs.issued, 1);
+        assert_eq!(stats.programs.skipped, 2);
+        assert_eq!(stats.textures.skipped, 2);
+        assert_eq!(stats.blends.issued, 2);
+        assert_eq!(stats.uniforms.issued, 2);
+        assert_eq!(stats.skipped(), 8);
+
+        cache.reset_state();
+        cache.use_program(1);
+        assert_eq!(cache.stats().programs.issued, 2);
+    }
+
+    #[test]
+    fn framebuffer_binding_is_kept_around_creation() {
+        let null = Rc::new(NullDevice::new());
+        let cache = StateCache::new(null.clone());
+
+        cache.bind_framebuffer(3);
+        let fbo = cache.create_framebuffer(1).unwrap();
+        cache.bind_framebuffer(3);
+        assert_eq!(cache.stats().framebuffers.skipped, 1);
+
+        cache.delete_framebuffer(fbo);
+        cache.bind_framebuffer(3);
+        assert_eq!(cache.stats().framebuffers.skipped, 2);
+
+        cache.delete_framebuffer(3);
+        assert_eq!(null.framebuffer(), 0);
+        cache.bind_framebuffer(0);
+        assert_eq!(cache.stats().framebuffers.skipped, 3);
+    }
+}
@@ -0,0 +1,280 @@
+//
+//  Rust file | 2018
+//  Author: Alexandre Fourcat
+//  stream_buffer.rs
+//  module:
+//! Vertex buffers rewritten often.
+//! A StreamBuffer own a gl buffer and upload the vertices the way fitting its usage:
+//! - Static and Dynamic buffers are updated in place, only the changed range is sent.
+//! - Stream buffers are orphaned before each upload, the driver give them a new storage
+//!   instead of waiting for the draws still reading the old one.
+//! - Ring buffers are split in regions written in turn without synchronization. A fence is
+//!   put after the draws of a region, if the gpu still reads it when it's written again the
+//!   buffer is orphaned instead of waiting.
+//!
+//! The storage only grows, removing vertices never reallocate it.
+//! ```no_run
+//! use gust::device::{self, RenderDevice};
+//! use gust::stream_buffer::StreamBuffer;
+//! use gust::vertex::Vertex;
+//! use gust::vertex_buffer::Primitive;
+//!
+//! let mut buffer = StreamBuffer::ring(3);
+//! let vertex_array = device::current().create_vertex_array();
+//! let vertices = vec![Vertex::default(); 3000];
+//!
+//! device::current().vertex_layout(vertex_array, buffer.id());
+//! loop {
+//!     buffer.upload(&vertices, 0, vertices.len());
+//!     device::current().draw_arrays(vertex_array, Primitive::Triangles, buffer.first(), 3000);
+//!     buffer.fence();
+//! }
+//! ```
+
+use device;
+use handle::{Handle, Kind};
+use std::cell::Cell;
+use vertex::Vertex;
+use vertex_buffer::BufferUsage;
+
+/// Gl buffer uploading vertices without stalling on the draws reading it.
+/// It can't be cloned, its regions and fences belong to the one writing it.
+#[derive(Debug, PartialEq)]
+pub struct StreamBuffer {
+    handle: Handle,
+    usage: BufferUsage,
+    /// Vertices a region can hold.
+    capacity: usize,
+    /// Vertices of the last upload.
+    len: usize,
+    /// Region holding the last upload.
+    region: usize,
+    /// One fence per region, 0 when the region isn't read by the gpu.
+    /// They are made and deleted on the device of the buffer.
+    fences: Vec<Cell<usize>>,
+}
+
+impl Drop for StreamBuffer {
+    fn drop(&mut self) {
+        self.clear_fences();
+    }
+}
+
+impl StreamBuffer {
+    /// A buffer of one region, uploads are made in place or by orphaning for Stream.
+    pub fn new(usage: BufferUsage) -> StreamBuffer {
+        StreamBuffer::with_regions(usage, 1)
+    }
+
+    /// A ring buffer of regions, 3 is enough to never wait for the gpu.
+    pub fn ring(regions: usize) -> StreamBuffer {
+        StreamBuffer::with_regions(BufferUsage::Stream, regions.max(1))
+    }
+
+    fn with_regions(usage: BufferUsage, regions: usize) -> StreamBuffer {
+        StreamBuffer {
+            handle: Handle::new(Kind::Buffer, device::current().create_buffer()),
+            usage,
+            capacity: 0,
+            len: 0,
+            region: 0,
+            fences: vec![Cell::new(0); regions],
+        }
+    }
+
+    /// Id of the gl buffer, it never change so a vertex array can be linked once.
+    pub fn id(&self) -> u32 {
+        self.handle.id()
+    }
+
+    pub fn usage(&self) -> BufferUsage {
+        self.usage
+    }
+
+    pub fn is_ring(&self) -> bool {
+        self.fences.len() > 1
+    }
+
+    /// Number of regions written in turn, 1 if it's not a ring.
+    pub fn regions(&self) -> usize {
+        self.fences.len()
+    }
+
+    /// Vertices a region can hold before growing.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Vertices of the last upload.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// First vertex of the last upload inside the buffer, to give to the draw calls.
+    pub fn first(&self) -> usize {
+        self.region * self.capacity
+    }
+
+    /// Upload data, every vertex to draw, where data[first..last] changed since the last
+    /// upload. The vertices added since the last upload are always written, stream and
+    /// ring buffers always write the whole data.
+    pub fn upload(&mut self, data: &[Vertex], first: usize, last: usize) {
+        if first >= last && data.len() <= self.len {
+            self.len = data.len();
+            return;
+        }
+        let device = device::current();
+        let regions = self.fences.len();
+        let old_len = self.len;
+
+        self.len = data.len();
+        if self.capacity < data.len() {
+            // The old storage is orphaned, its fences are useless
+            self.capacity = data.len().next_power_of_two();
+            self.region = 0;
+            self.clear_fences();
+            device.orphan_buffer(self.id(), self.capacity * regions, self.usage);
+            device.buffer_sub_data(self.id(), 0, data);
+        } else if self.is_ring() {
+            self.region = (self.region + 1) % regions;
+            // The gpu is late, a new storage is taken instead of waiting for it
+            if !self.poll(self.region) {
+                self.region = 0;
+                self.clear_fences();
+                device.orphan_buffer(self.id(), self.capacity * regions, self.usage);
+            }
+            device.buffer_write_unsynchronized(self.id(), self.first(), data);
+        } else if self.usage == BufferUsage::Stream {
+            device.orphan_buffer(self.id(), self.capacity, self.usage);
+            device.buffer_sub_data(self.id(), 0, data);
+        } else {
+            let (first, last) = if data.len() > old_len {
+                (first.min(old_len), data.len())
+            } else {
+                (first, last.min(data.len()))
+            };
+            let first = first.min(last);
+            device.buffer_sub_data(self.id(), first, &data[first..last]);
+        }
+    }
+
+    /// Mark the region of the last upload as read by the draws sent until now.
+    /// Only ring buffers need it, it does nothing for the others.
+    pub fn fence(&self) {
+        if !self.is_ring() {
+            return;
+        }
+        let device = self.handle.device();
+        let fence = &self.fences[self.region];
+
+        if fence.get() != 0 {
+            device.delete_fence(fence.get());
+        }
+        fence.set(device.fence());
+    }
+
+    /// Check without waiting if the gpu is done with the region, false if it still reads it.
+    /// The fence is kept until it is signaled.
+    fn poll(&self, region: usize) -> bool {
+        let device = self.handle.device();
+        let fence = &self.fences[region];
+
+        if fence.get() == 0 {
+            return true;
+        }
+        if !device.wait_fence(fence.get(), 0) {
+            return false;
+        }
+        device.delete_fence(fence.get());
+        fence.set(0);
+        true
+    }
+
+    fn clear_fences(&self) {
+        let device = self.handle.device();
+
+        for fence in &self.fences {
+            if fence.get() != 0 {
+                device.delete_fence(fence.get());
+                fence.set(0);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::StreamBuffer;
+    use device::{self, NullDevice};
+    use std::rc::Rc;
+    use vertex::Vertex;
+    use vertex_buffer::BufferUsage;
+
+    #[test]
+    fn ring_regions_are_written_in_turn() {
+        let null = Rc::new(NullDevice::new());
+        let _device = device::scoped(null.clone());
+        let vertices = vec![Vertex::default(); 6];
+        let mut buffer = StreamBuffer::ring(3);
+
+        buffer.upload(&vertices, 0, 6);
+        assert_eq!(buffer.capacity(), 8);
+        let firsts: Vec<usize> = (0..4)
+            .map(|_| {
+                buffer.fence();
+                buffer.upload(&vertices, 0, 6);
+                buffer.first()
+            })
+            .collect();
+        assert_eq!(firsts, vec![8, 16, 0, 8]);
+
+        // Growing orphan the storage and start again from the first region
+        buffer.upload(&vec![Vertex::default(); 9], 0, 9);
+        assert_eq!((buffer.first(), buffer.capacity()), (0, 16));
+
+        let mut dynamic = StreamBuffer::new(BufferUsage::Dynamic);
+        dynamic.upload(&vertices, 0, 6);
+        dynamic.upload(&vertices[..2], 0, 0);
+        assert_eq!(
+            (dynamic.first(), dynamic.len(), dynamic.capacity()),
+            (0, 2, 8)
+        );
+        // Growing inside the capacity write the new vertices whatever the range
+        dynamic.upload(&vertices[..5], 4, 1);
+        dynamic.upload(&vertices, 0, 0);
+        assert_eq!((dynamic.len(), dynamic.capacity()), (6, 8));
+    }
+
+    #[test]
+    fn busy_regions_orphan_the_buffer() {
+        let null = Rc::new(NullDevice::new());
+        let _device = device::scoped(null.clone());
+        let vertices = vec![Vertex::default(); 6];
+        let mut buffer = StreamBuffer::ring(2);
+
+        buffer.upload(&vertices, 0, 6);
+        buffer.fence();
+        buffer.upload(&vertices, 0, 6);
+        buffer.fence();
+
+        // The buffer is orphaned instead of waiting, the fences of the old storage go away
+        null.set_busy(true);
+        let deleted = null.deleted();
+        buffer.upload(&vertices, 0, 6);
+        assert_eq!((buffer.first(), null.deleted()), (0, deleted + 2));
+        buffer.fence();
+
+        // The next region has no fence in the new storage
+        buffer.upload(&vertices, 0, 6);
+        assert_eq!((buffer.first(), null.deleted()), (8, deleted + 2));
+        buffer.fence();
+
+        null.set_busy(false);
+        buffer.upload(&vertices, 0, 6);
+        assert_eq!((buffer.first(), null.deleted()), (0, deleted + 3));
+    }
+}
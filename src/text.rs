@@ -8,7 +8,8 @@ use color::Color;
 use draw::{BlendMode, Context, Drawable, DrawableMut, Drawer, IDENTITY};
 use font::{CharInfo, Font};
 use nalgebra::Scalar;
-use shader;
+use resources::Resource;
+use shader::{self, Shader, UniformValue, Uniforms};
 use std::cell::RefCell;
 use std::{error::Error, rc::Rc};
 /// # How to use
@@ -34,8 +35,8 @@ use std::{error::Error, rc::Rc};
 /// It's made from the Text system of the C++ library SFML.
 use texture::Texture;
 use transform::*;
-use vertex::Vertex;
-use vertex_buffer::VertexBuffer;
+use vertex::{Vertex, VertexArray};
+use vertex_buffer::{BufferUsage, Primitive, VertexBuffer};
 use {Point, Vector};
 
 extern crate freetype as ft;
@@ -52,6 +53,10 @@ pub struct Text {
     vertex_buffer: VertexBuffer,
     need_update: bool,
     pos: Vector<f32>,
+    blend_mode: BlendMode,
+    layer: f32,
+    shader: Option<Resource<Shader>>,
+    uniforms: Uniforms,
 }
 
 impl Text {
@@ -71,9 +76,17 @@ impl Text {
             font: Rc::clone(font),
             content: String::new(),
             actual_size: 14,
-            vertex_buffer: VertexBuffer::default(),
+            vertex_buffer: VertexBuffer::with_usage(
+                Primitive::Triangles,
+                VertexArray::new(),
+                BufferUsage::Dynamic,
+            ),
             need_update: true,
             pos: Vector::new(0.0, 0.0),
+            blend_mode: BlendMode::Alpha,
+            layer: 0.0,
+            shader: None,
+            uniforms: Uniforms::new(),
         }
     }
 
@@ -83,12 +96,63 @@ impl Text {
             font: Rc::clone(font),
             content: String::from(content),
             actual_size: 14,
-            vertex_buffer: VertexBuffer::default(),
+            vertex_buffer: VertexBuffer::with_usage(
+                Primitive::Triangles,
+                VertexArray::new(),
+                BufferUsage::Dynamic,
+            ),
             need_update: true,
             pos: Vector::new(0.0, 0.0),
+            blend_mode: BlendMode::Alpha,
+            layer: 0.0,
+            shader: None,
+            uniforms: Uniforms::new(),
         }
     }
 
+    /// Set the blend mode used to draw the text.
+    pub fn set_blend_mode(&mut self, blend_mode: BlendMode) {
+        self.blend_mode = blend_mode;
+    }
+
+    pub fn blend_mode(&self) -> BlendMode {
+        self.blend_mode
+    }
+
+    /// Set the layer used to sort the text inside a DrawList.
+    pub fn set_layer(&mut self, layer: f32) {
+        self.layer = layer;
+    }
+
+    /// Draw with the shader instead of the built-in one,
+    /// `transform` and `projection` stay set by gust.
+    pub fn set_shader(&mut self, shader: Resource<Shader>) {
+        self.shader = Some(shader);
+    }
+
+    /// Go back to the built-in shader.
+    pub fn reset_shader(&mut self) {
+        self.shader = None;
+    }
+
+    /// The custom shader, None if the built-in one is used.
+    pub fn shader(&self) -> Option<&Resource<Shader>> {
+        self.shader.as_ref()
+    }
+
+    /// Set an uniform given to the shader before each draw.
+    pub fn set_uniform<T: Into<UniformValue>>(&mut self, name: &str, value: T) {
+        self.uniforms.set(name, value);
+    }
+
+    pub fn uniforms(&self) -> &Uniforms {
+        &self.uniforms
+    }
+
+    pub fn uniforms_mut(&mut self) -> &mut Uniforms {
+        &mut self.uniforms
+    }
+
     /// Set the content of the text
     pub fn set_content(&mut self, content: &str) {
         self.content = String::from(content);
@@ -301,20 +365,29 @@ impl Drawable for Text {
         let texture = font_ref.texture(self.actual_size).unwrap();
 
         // Create a new context with the Texture of the font
+        let shader = self
+            .shader
+            .clone()
+            .unwrap_or_else(|| shader::DEFAULT_SHADER.get());
         let mut context = Context::new(
             Some(texture),
-            &*shader::DEFAULT_SHADER,
+            &shader,
             vec![
                 ("transform".to_string(), &*IDENTITY),
                 ("projection".to_string(), target.projection()),
             ],
-            BlendMode::Alpha,
+            self.blend_mode,
         );
+        context.set_uniforms(&self.uniforms);
 
         // Draw the vertex_buffer with context
         self.vertex_buffer.draw_with_context(&mut context);
     }
 
+    fn layer(&self) -> f32 {
+        self.layer
+    }
+
     fn draw_with_context(&self, context: &mut Context) {
         self.vertex_buffer.draw_with_context(context);
     }
@@ -3,13 +3,16 @@
 //! I'm using image crate that is really useful
 
 use color::Color;
+use device;
 use gl;
 use gl::types::*;
+use handle::{Handle, Kind};
 use image;
 use image::{DynamicImage, ImageBuffer};
 use std::error::Error;
 use std::os::raw::c_void;
 use std::path::Path;
+use std::slice;
 use Vector;
 
 /// # Texture structure
@@ -25,9 +28,13 @@ use Vector;
 /// let leave = Rc::new(Texture::new("path/to/test"));
 ///	let sprite = Sprite::from(&leave);
 /// ```
+/// Cloning a texture share the same gl texture, use `duplicate` to copy it.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Texture {
+    /// Id of the texture inside the device, a copy kept for the code reading it.
+    #[deprecated(note = "use Texture::id, the texture is owned by a handle")]
     pub id: u32,
+    handle: Handle,
     width: u32,
     height: u32,
     rgb_mode: RgbMode,
@@ -38,42 +45,37 @@ impl Texture {
 
     /// Create an empty texture
     pub fn new() -> Texture {
-        let mut id = 0;
-        unsafe {
-            gl::GenTextures(1, &mut id);
-        };
-        Texture {
-            id,
-            width: 0,
-            height: 0,
-            rgb_mode: RgbMode::RGBA,
-        }
+        Texture::with_handle(
+            Handle::new(
+                Kind::Texture,
+                device::current().create_texture(&[], RgbMode::RGBA, 0, 0),
+            ),
+            0,
+            0,
+            RgbMode::RGBA,
+        )
     }
 
     /// Create a texture from a raw data pointer needed for Font handling unsafe version of
     /// from slice
     pub unsafe fn from_data(data: *mut c_void, mode: RgbMode, width: u32, height: u32) -> Texture {
-        Texture {
-            id: Self::create(data, mode.as_gl(), width as i32, height as i32),
-            width: width as u32,
-            height: height as u32,
-            rgb_mode: mode,
-        }
+        let len = (width * height) as usize * mode.channels();
+        Texture::with_handle(
+            Self::create(
+                slice::from_raw_parts(data as *const u8, len),
+                mode,
+                width,
+                height,
+            ),
+            width,
+            height,
+            mode,
+        )
     }
 
     /// Create a texture from a slice
     pub fn from_slice(data: &mut [u8], mode: RgbMode, width: u32, height: u32) -> Texture {
-        Texture {
-            id: Self::create(
-                data.as_mut_ptr() as *mut c_void,
-                mode.as_gl(),
-                width as i32,
-                height as i32,
-            ),
-            width: width as u32,
-            height: height as u32,
-            rgb_mode: mode,
-        }
+        Texture::with_handle(Self::create(data, mode, width, height), width, height, mode)
     }
 
     pub fn from_color(color: Color, sizes: Vector<u32>) -> Texture {
@@ -100,7 +102,7 @@ impl Texture {
 
     /// Create a texture from an image
     pub fn from_image(img: DynamicImage) -> Result<Texture, TextureError> {
-        let id;
+        let handle;
         let mut size = (0, 0);
         let mode;
 
@@ -108,23 +110,13 @@ impl Texture {
             DynamicImage::ImageRgba8(data) => {
                 size.0 = data.width();
                 size.1 = data.height();
-                id = Self::create(
-                    &data.into_raw()[0] as *const u8 as *mut std::ffi::c_void,
-                    gl::RGBA,
-                    size.0 as i32,
-                    size.1 as i32,
-                );
+                handle = Self::create(&data.into_raw(), RgbMode::RGBA, size.0, size.1);
                 mode = RgbMode::RGBA;
             }
             DynamicImage::ImageRgb8(data) => {
                 size.0 = data.width();
                 size.1 = data.height();
-                id = Self::create(
-                    &data.into_raw()[0] as *const u8 as *mut std::ffi::c_void,
-                    gl::RGB,
-                    size.0 as i32,
-                    size.1 as i32,
-                );
+                handle = Self::create(&data.into_raw(), RgbMode::RGB, size.0, size.1);
                 mode = RgbMode::RGB;
             }
             _ => {
@@ -132,12 +124,7 @@ impl Texture {
             }
         }
 
-        Ok(Texture {
-            id,
-            width: size.0,
-            height: size.1,
-            rgb_mode: mode,
-        })
+        Ok(Texture::with_handle(handle, size.0, size.1, mode))
     }
 
     /// Create new texture from file path
@@ -178,41 +165,48 @@ impl Texture {
         Ok(())
     }
 
+    #[allow(deprecated)]
+    fn with_handle(handle: Handle, width: u32, height: u32, rgb_mode: RgbMode) -> Texture {
+        Texture {
+            id: handle.id(),
+            handle,
+            width,
+            height,
+            rgb_mode,
+        }
+    }
+
     /// Create a texture with a
-    fn create(data: *mut c_void, rgb_mode: GLenum, width: i32, height: i32) -> u32 {
-        let mut id = 0;
-        unsafe {
-            gl::GenTextures(1, &mut id);
-            gl::BindTexture(gl::TEXTURE_2D, id);
-            gl::TexStorage2D(
-                // Create the storage
-                gl::TEXTURE_2D,
-                1,
-                if rgb_mode == gl::RGBA {
-                    gl::RGBA8
-                } else {
-                    gl::RGB8
-                },
-                width,
-                height,
-            );
-            gl::TexSubImage2D(
-                // Put pixel inside the storage
-                gl::TEXTURE_2D,
-                0,
-                0,
-                0,
-                width,
-                height,
-                rgb_mode,
-                gl::UNSIGNED_BYTE,
-                data,
+    fn create(data: &[u8], rgb_mode: RgbMode, width: u32, height: u32) -> Handle {
+        let id = device::current().create_texture(data, rgb_mode, width, height);
+        Handle::new(Kind::Texture, id)
+    }
+
+    /// Create a new gl texture holding a copy of the pixels, where clone share the same one.
+    /// The wrap and filter modes are the default ones.
+    pub fn duplicate(&self) -> Texture {
+        let texture = Texture::with_handle(
+            Self::create(
+                &vec![0; self.get_rawsize()],
+                self.rgb_mode,
+                self.width,
+                self.height,
+            ),
+            self.width,
+            self.height,
+            self.rgb_mode,
+        );
+
+        if self.get_rawsize() != 0 {
+            device::current().copy_texture(
+                self.id(),
+                texture.id(),
+                self.rgb_mode,
+                self.width,
+                self.height,
             );
-            Texture::default_param();
-            gl::GenerateMipmap(gl::TEXTURE_2D);
-            gl::BindTexture(gl::TEXTURE_2D, 0);
         }
-        id
+        texture
     }
 
     /// Update a block of a texture with an offset and a size
@@ -241,55 +235,25 @@ impl Texture {
                 pos.y + sizes.y,
             ))
         } else {
-            // Bind the texture then give it to opengl
-            unsafe {
-                gl::BindTexture(gl::TEXTURE_2D, self.id);
-                gl::TexSubImage2D(
-                    gl::TEXTURE_2D,
-                    0,
-                    pos.x as i32,
-                    pos.y as i32,
-                    sizes.x as i32,
-                    sizes.y as i32,
-                    rgb_mode.as_gl(),
-                    gl::UNSIGNED_BYTE,
-                    data as *const _ as *const c_void, //mem::transmute(data.as_ptr())
-                );
-                gl::BindTexture(gl::TEXTURE_2D, 0);
-                gl::Flush();
-            }
+            // Give it to the device
+            device::current().update_texture(self.id(), data, rgb_mode, pos, sizes);
             Ok(())
         }
     }
 
     pub fn get_rawsize(&self) -> usize {
-        match self.rgb_mode {
-            RgbMode::RGBA => (self.height * self.width * 4) as usize,
-            RgbMode::RGB => (self.height * self.width * 3) as usize,
-            RgbMode::RED => (self.height * self.width) as usize,
-        }
+        (self.height * self.width) as usize * self.rgb_mode.channels()
     }
 
     /// Get a Vec<u8> representing pixels of the texture
     pub fn get_data(&self) -> Vec<u8> {
         let size = self.get_rawsize();
-        let mut data: Vec<u8> = Vec::with_capacity(size);
 
-        if size == 0 || self.id == 0 {
+        if size == 0 || self.id() == 0 {
             Vec::new()
         } else {
-            unsafe {
-                data.set_len(size);
-                gl::BindTexture(gl::TEXTURE_2D, self.id);
-                gl::GetTexImage(
-                    gl::TEXTURE_2D,
-                    0,
-                    self.rgb_mode.as_gl(),
-                    gl::UNSIGNED_BYTE,
-                    data.as_mut_ptr() as *mut c_void,
-                );
-                gl::BindTexture(gl::TEXTURE_2D, 0);
-            };
+            let mut data: Vec<u8> = vec![0; size];
+            device::current().read_texture(self.id(), self.rgb_mode, &mut data);
             data
         }
     }
@@ -300,24 +264,10 @@ impl Texture {
         texture: &Texture,
         pos: Vector<u32>,
     ) -> Result<(), TextureError> {
-        let size = texture.get_rawsize();
-        let mut data: Vec<u8> = Vec::with_capacity(size);
-
         if self.rgb_mode != texture.rgb_mode {
             return Err(TextureError::UpdateMode(self.rgb_mode, texture.rgb_mode));
         }
-        unsafe {
-            data.set_len(size);
-            gl::BindTexture(gl::TEXTURE_2D, texture.id);
-            gl::GetTexImage(
-                gl::TEXTURE_2D,
-                0,
-                self.rgb_mode.as_gl(),
-                gl::UNSIGNED_BYTE,
-                data.as_mut_ptr() as *mut c_void,
-            );
-            gl::BindTexture(gl::TEXTURE_2D, 0);
-        }
+        let data = texture.get_data();
         let h = texture.height;
         let w = texture.width;
         let mode = texture.rgb_mode;
@@ -342,50 +292,47 @@ impl Texture {
 
     /// Repeat mode texture wrap
     pub fn repeat_mode(&self) {
-        self.active(0);
-        unsafe {
-            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::REPEAT as i32);
-
-            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::REPEAT as i32);
-        }
-        self.unbind();
+        self.set_wrap(Wrap::Repeat);
     }
 
     /// Linear mode for filter
     pub fn linear_mode(&self) {
-        self.active(0);
-        unsafe {
-            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as i32);
-            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as i32);
-        }
-        self.unbind();
+        self.set_filter(Filter::Linear);
+    }
+
+    /// Set the wrap mode of the texture
+    pub fn set_wrap(&self, wrap: Wrap) {
+        device::current().texture_wrap(self.id(), wrap);
+    }
+
+    /// Set the filter of the texture
+    pub fn set_filter(&self, filter: Filter) {
+        device::current().texture_filter(self.id(), filter);
     }
 
     #[inline]
     /// Unbind the texture
     pub fn unbind(&self) {
-        unsafe {
-            gl::BindTexture(gl::TEXTURE_2D, 0);
-        }
+        device::current().bind_texture(0, 0);
     }
 
     #[inline]
     /// Active texture num
     pub fn active(&self, num: i32) {
-        unsafe {
-            gl::ActiveTexture(gl::TEXTURE0 + num as u32);
-            gl::BindTexture(gl::TEXTURE_2D, self.id);
-        }
+        device::current().bind_texture(num as u32, self.id());
     }
 
-    unsafe fn default_param() {
-        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::REPEAT as i32);
-        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::REPEAT as i32);
-        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as i32);
-        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as i32);
+    //-------------------------GETTER-----------------------//
+
+    /// Id of the texture inside the device.
+    pub fn id(&self) -> u32 {
+        self.handle.id()
     }
 
-    //-------------------------GETTER-----------------------//
+    /// Number of textures sharing the same gl texture.
+    pub fn owners(&self) -> usize {
+        self.handle.owners()
+    }
 
     /// Getter for color mode
     pub fn rgb_mode(&self) -> &RgbMode {
@@ -406,34 +353,33 @@ impl Texture {
 impl Default for Texture {
     /// Create a 1 white pixel texture
     fn default() -> Texture {
-        let mut id = 0;
-        unsafe {
-            let data = vec![255, 255, 255, 255];
-            gl::GenTextures(1, &mut id);
-            gl::BindTexture(gl::TEXTURE_2D, id);
-            Texture::default_param();
-            gl::TexImage2D(
-                gl::TEXTURE_2D,
-                0,
-                gl::RGBA as i32,
-                1,
-                1,
-                0,
-                gl::RGBA,
-                gl::UNSIGNED_BYTE,
-                data.as_ptr() as *const c_void,
-            );
-        };
-
-        Texture {
-            id,
-            width: 1,
-            height: 1,
-            rgb_mode: RgbMode::RGBA,
-        }
+        Texture::with_handle(
+            Self::create(&[255, 255, 255, 255], RgbMode::RGBA, 1, 1),
+            1,
+            1,
+            RgbMode::RGBA,
+        )
     }
 }
 
+/// Read the pixels of the currently bound read framebuffer into an image.
+/// OpenGl give rows from bottom to top, they are flipped to have a top-left origin.
+pub(crate) fn read_framebuffer(width: u32, height: u32) -> image::RgbaImage {
+    let row = (width * 4) as usize;
+    let mut data: Vec<u8> = vec![0; row * height as usize];
+
+    device::current().read_pixels(width, height, &mut data);
+
+    let flipped = data
+        .chunks(row)
+        .rev()
+        .fold(Vec::with_capacity(data.len()), |mut acc, line| {
+            acc.extend_from_slice(line);
+            acc
+        });
+    ImageBuffer::from_vec(width, height, flipped).unwrap()
+}
+
 /// Enum to wrap gl RGB modes
 #[derive(PartialEq, Clone, Copy, Debug, Eq)]
 pub enum RgbMode {
@@ -450,6 +396,30 @@ impl RgbMode {
             RgbMode::RED => gl::RED,
         }
     }
+
+    /// Number of bytes of a pixel.
+    pub fn channels(self) -> usize {
+        match self {
+            RgbMode::RGBA => 4,
+            RgbMode::RGB => 3,
+            RgbMode::RED => 1,
+        }
+    }
+}
+
+/// How texture coordinates out of [0, 1] are handled.
+#[derive(PartialEq, Clone, Copy, Debug, Eq)]
+pub enum Wrap {
+    Repeat,
+    MirroredRepeat,
+    ClampToEdge,
+}
+
+/// How texels are sampled.
+#[derive(PartialEq, Clone, Copy, Debug, Eq)]
+pub enum Filter {
+    Linear,
+    Nearest,
 }
 
 #[derive(Debug)]
@@ -492,29 +462,34 @@ impl Error for TextureError {
     }
 }
 
-impl Drop for Texture {
-    fn drop(&mut self) {
-        unsafe {
-            println!("Texture {} deleted", self.id);
-            gl::DeleteTextures(1, &[self.id] as *const _);
-        }
-    }
-}
-
 #[cfg(test)]
 mod test {
     extern crate test;
 
     use self::test::Bencher;
-    use super::Vector;
+    use super::{read_framebuffer, Vector};
     use color::Color;
+    use device::{self, NullDevice};
+    use std::rc::Rc;
     use texture::RgbMode;
     use texture::Texture;
     use window::Window;
 
+    #[test]
+    fn framebuffer_rows_are_flipped() {
+        let _device = device::scoped(Rc::new(NullDevice::new()));
+        let image = read_framebuffer(3, 4);
+
+        // The null device number the rows from the bottom
+        let rows: Vec<u8> = (0..4).map(|y| image.get_pixel(0, y)[0]).collect();
+        assert_eq!(rows, vec![3, 2, 1, 0]);
+        assert_eq!(image.dimensions(), (3, 4));
+        assert_eq!(image.into_raw().len(), 3 * 4 * 4);
+    }
+
     #[bench]
     fn from_color(b: &mut Bencher) {
-        let _ = Window::new(200, 200, "Loader");
+        let _window = Window::headless(200, 200).unwrap();
 
         b.iter(|| {
             Texture::from_color(Color::new(1.0, 1.0, 1.0), Vector::new(100, 100));
@@ -523,7 +498,7 @@ mod test {
 
     #[bench]
     fn from_slice(b: &mut Bencher) {
-        let _ = Window::new(200, 200, "Loader");
+        let _window = Window::headless(200, 200).unwrap();
 
         b.iter(|| {
             let mut slice = vec![255; 10000];
@@ -534,7 +509,7 @@ mod test {
 
     #[bench]
     fn update_block(b: &mut Bencher) {
-        let _ = Window::new(200, 200, "Loader");
+        let _window = Window::headless(200, 200).unwrap();
 
         let mut text_host = Texture::from_color(Color::new(0.0, 1.0, 0.0), Vector::new(100, 100));
         let text_guest = Texture::from_color(Color::new(0.0, 0.0, 1.0), Vector::new(10, 10));
@@ -44,12 +44,11 @@
 //! ```
 
 use color::Color;
-use gl;
+use device;
 use gl::types::*;
-use nalgebra::Vector2;
-use std::mem;
+use handle::{Handle, Kind};
+use nalgebra::{Vector2, Vector4};
 use std::ops::{Index, IndexMut};
-use std::ptr;
 
 /// Vertex structure defined by texture coord, space coors and color
 #[derive(Debug, Clone, PartialEq, Copy)]
@@ -112,25 +111,53 @@ impl Default for Vertex {
     }
 }
 
+/// Attributes of one instance of an instanced draw, the vertex shader place the unit quad
+/// (from (0, 0) to (1, 1)) with them.
+#[repr(C)]
+#[derive(Debug, Clone, PartialEq, Copy)]
+pub struct Instance {
+    pub pos: Vector2<f32>,
+    /// Center of the rotation in pixels from the top left corner.
+    pub origin: Vector2<f32>,
+    /// Scale of the unit quad, the size in pixels.
+    pub scale: Vector2<f32>,
+    /// Rotation in radians.
+    pub rotation: f32,
+    /// Left, top, width and height of the texture coordinates.
+    pub tex_rect: Vector4<f32>,
+    pub color: Color,
+    /// Texture of the sprite among the ones bound by the multi-texture draw.
+    pub texture_index: f32,
+}
+
+impl Default for Instance {
+    fn default() -> Instance {
+        Instance {
+            pos: Vector2::new(0.0, 0.0),
+            origin: Vector2::new(0.0, 0.0),
+            scale: Vector2::new(1.0, 1.0),
+            rotation: 0.0,
+            tex_rect: Vector4::new(0.0, 0.0, 1.0, 1.0),
+            color: Color::white(),
+            texture_index: 0.0,
+        }
+    }
+}
+
 /// VertexArray is a vertex data structure that is drawable and it's the basic system
+/// Cloning a VertexArray copy the vertices but share the gl vertex array, see duplicate.
 #[derive(Clone, Debug, PartialEq, Default)]
 pub struct VertexArray {
     array: Vec<Vertex>,
-    id: u32,
+    handle: Handle,
 }
 
 impl VertexArray {
     /// Create a empty vertex array
     pub fn new() -> VertexArray {
-        let mut id = 0;
-
-        unsafe {
-            gl::GenVertexArrays(1, &mut id);
-        }
-
         VertexArray {
             array: Vec::new(),
-            id,
+            handle: Handle::new(Kind::VertexArray, device::current().create_vertex_array()),
         }
     }
 
@@ -146,41 +173,9 @@ impl VertexArray {
         &mut self.array
     }
 
-    pub fn active(&self) {
-        unsafe {
-            gl::BindVertexArray(self.id);
-
-            // Position (Of each vertex)
-            gl::VertexAttribPointer(
-                0,
-                2,
-                gl::FLOAT,
-                gl::FALSE,
-                (8 * mem::size_of::<GLfloat>()) as GLsizei,
-                ptr::null(),
-            );
-            gl::EnableVertexAttribArray(0);
-            // Texture Coord (Of each vertex)
-            gl::VertexAttribPointer(
-                1,
-                2,
-                gl::FLOAT,
-                gl::FALSE,
-                (8 * mem::size_of::<GLfloat>()) as GLsizei,
-                (2 * mem::size_of::<GLfloat>()) as *const _,
-            );
-            gl::EnableVertexAttribArray(1);
-            // Color (of each vertex)
-            gl::VertexAttribPointer(
-                2,
-                3,
-                gl::FLOAT,
-                gl::FALSE,
-                (8 * mem::size_of::<GLfloat>()) as GLsizei,
-                (4 * mem::size_of::<GLfloat>()) as *const _,
-            );
-            gl::EnableVertexAttribArray(2);
-        }
+    /// Link the buffer to this vertex array with the vertex layout.
+    pub fn active(&self, buffer: u32) {
+        device::current().vertex_layout(self.handle.id(), buffer);
     }
 
     pub fn len(&self) -> usize {
@@ -192,14 +187,23 @@ impl VertexArray {
     }
 
     pub fn bind(&self) {
-        unsafe {
-            gl::BindVertexArray(self.id);
-        }
+        device::current().bind_vertex_array(self.handle.id());
     }
 
     pub fn unbind(&self) {
-        unsafe {
-            gl::BindVertexArray(0);
+        device::current().bind_vertex_array(0);
+    }
+
+    /// Id of the vertex array inside the device.
+    pub fn id(&self) -> u32 {
+        self.handle.id()
+    }
+
+    /// Copy the vertices with a new gl vertex array, it has to be linked to a buffer.
+    pub fn duplicate(&self) -> VertexArray {
+        VertexArray {
+            array: self.array.clone(),
+            handle: Handle::new(Kind::VertexArray, device::current().create_vertex_array()),
         }
     }
 
@@ -213,11 +217,9 @@ impl<'a> From<&'a [Vertex]> for VertexArray {
         if array.is_empty() {
             VertexArray::new()
         } else {
-            let mut id = 0;
-            unsafe { gl::GenVertexArrays(1, &mut id) };
             VertexArray {
                 array: Vec::from(array),
-                id,
+                handle: Handle::new(Kind::VertexArray, device::current().create_vertex_array()),
             }
         }
     }
@@ -236,9 +238,10 @@ impl<'a> From<&'a [f32]> for VertexArray {
                     Color::new(elem[4], elem[5], elem[6]),
                 ));
             }
-            let mut id = 0;
-            unsafe { gl::GenVertexArrays(1, &mut id) };
-            VertexArray { array: arr, id }
+            VertexArray {
+                array: arr,
+                handle: Handle::new(Kind::VertexArray, device::current().create_vertex_array()),
+            }
         }
     }
 }
@@ -256,12 +259,3 @@ impl IndexMut<usize> for VertexArray {
         &mut self.array[vertex_index]
     }
 }
-
-impl Drop for VertexArray {
-    fn drop(&mut self) {
-        unsafe {
-            gl::DeleteVertexArrays(1, &[self.id] as *const _);
-        }
-        println!("VertexArray {} delete.", self.id);
-    }
-}
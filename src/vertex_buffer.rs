@@ -1,22 +1,24 @@
 //! This module encapsulate the system of vertexBuffer
 //! Here you can create a drawable object easily with a VertexArray
 
+use device;
 use draw::{BlendMode, Context, Drawable, DrawableMut, Drawer, IDENTITY};
 use gl;
 use gl::types::*;
 use resources::Resource;
 use shader::*;
-use std;
 use std::ops::{Index, IndexMut};
+use stream_buffer::StreamBuffer;
 use texture::Texture;
 use vertex::*;
 
 /// Vertex Buffer structure
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, PartialEq)]
 /// A vertexbuffer is an buffer object in OpenGl.
 /// Here it's linked with VertexArray for data.
 /// The VertexBuffer is the 'low levelest' object that is drawable.
 /// You can create it from Vertice slice or VertexArray
+/// Cloning a VertexBuffer copy its vertices inside a new gl buffer, see duplicate.
 /// ```no_run
 /// use gust::window::Window;
 /// use gust::vertex::{VertexArray, Vertex};
@@ -38,11 +40,11 @@ use vertex::*;
 /// }
 /// ```
 pub struct VertexBuffer {
-    id: u32,
+    buffer: StreamBuffer,
     texture: Option<Resource<Texture>>,
     array: VertexArray,
-    primitive: GLenum,
-    len: usize,
+    primitive: Primitive,
+    layer: f32,
 }
 
 #[derive(Debug, Clone, PartialEq, Copy, Hash)]
@@ -64,7 +66,7 @@ impl Primitive {
             Primitive::Points => gl::POINTS,
             Primitive::Lines => gl::LINES,
             Primitive::TrianglesStrip => gl::TRIANGLE_STRIP,
-            Primitive::TriangleFan => gl::TRIANGLE_STRIP,
+            Primitive::TriangleFan => gl::TRIANGLE_FAN,
         }
     }
 
@@ -86,14 +88,28 @@ impl Into<GLenum> for Primitive {
     }
 }
 
-impl VertexBuffer {
-    fn clear_gl() {
-        unsafe {
-            gl::BindBuffer(gl::ARRAY_BUFFER, 0);
-            gl::BindVertexArray(0);
+/// How often the content of a buffer is rewritten, a hint given to the driver.
+#[derive(Debug, Clone, PartialEq, Copy, Hash)]
+pub enum BufferUsage {
+    /// Written once, drawn a lot.
+    Static,
+    /// Rewritten sometimes, drawn a lot.
+    Dynamic,
+    /// Rewritten before nearly every draw.
+    Stream,
+}
+
+impl Into<GLenum> for BufferUsage {
+    fn into(self) -> GLenum {
+        match self {
+            BufferUsage::Static => gl::STATIC_DRAW,
+            BufferUsage::Dynamic => gl::DYNAMIC_DRAW,
+            BufferUsage::Stream => gl::STREAM_DRAW,
         }
     }
+}
 
+impl VertexBuffer {
     /// Clear all data from VertexArray
     pub fn clear(&mut self) {
         self.array.clear();
@@ -101,95 +117,112 @@ impl VertexBuffer {
 
     /// Create new Vertex Buffer from vertices
     pub fn new(t: Primitive, vertice: VertexArray) -> VertexBuffer {
-        let mut buffer_id: u32 = 0;
-
-        unsafe {
-            gl::GenBuffers(1, &mut buffer_id);
-            // --------------------------------
-            // Buffers generations heere
-            // we create a vertexArray and a buffer.
-            // Then we Bind the VertexArray the buffer
-            // to the openGl state machine.
-            // After we put data inside the buffer.
-            // Then we cut the data inside the buffer in 3
-            // { 1.0, 1.0, 0.0, 1.0, 3.0, 3.0 }
-            // |   pos  | texCoord |  color  |
-            // |        |          |         |
-            // With the 3 VertexAttribPointer
-            // --------------------------------
-
-            vertice.bind();
-            gl::BindBuffer(gl::ARRAY_BUFFER, buffer_id);
-            // Put data inside
-            gl::BufferData(
-                gl::ARRAY_BUFFER,
-                (std::mem::size_of::<GLfloat>() * vertice.len() * 8) as GLsizeiptr,
-                vertice.get_ptr(),
-                gl::STATIC_DRAW,
-            );
-            vertice.active();
-        };
+        VertexBuffer::with_usage(t, vertice, BufferUsage::Static)
+    }
 
-        let vertex_buffer = VertexBuffer {
-            id: buffer_id,
+    /// Create a Vertex Buffer uploading its vertices the way fitting usage.
+    pub fn with_usage(t: Primitive, vertice: VertexArray, usage: BufferUsage) -> VertexBuffer {
+        let mut buffer = StreamBuffer::new(usage);
+
+        // --------------------------------
+        // Buffers generations heere
+        // we create a vertexArray and a buffer.
+        // Then we put data inside the buffer.
+        // Then we cut the data inside the buffer in 3
+        // { 1.0, 1.0, 0.0, 1.0, 3.0, 3.0 }
+        // |   pos  | texCoord |  color  |
+        // |        |          |         |
+        // With the vertex layout of the VertexArray
+        // --------------------------------
+        buffer.upload(vertice.array(), 0, vertice.len());
+        vertice.active(buffer.id());
+
+        VertexBuffer {
+            buffer,
             texture: None,
-            primitive: Self::get_gl_type(t),
-            len: vertice.len(),
+            primitive: t,
             array: vertice,
-        };
-        Self::clear_gl();
+            layer: 0.0,
+        }
+    }
 
-        vertex_buffer
+    /// Change the way the vertices are uploaded, they are copied inside a new gl buffer.
+    pub fn set_usage(&mut self, usage: BufferUsage) {
+        self.set_buffer(StreamBuffer::new(usage));
     }
 
-    /// Append data to the actual VertexArray while be updated internaly.
-    pub fn append(&mut self, vertices: &[Vertex]) {
-        self.array.array_mut().append(&mut Vec::from(vertices));
+    /// Upload the vertices inside a ring buffer of regions, see StreamBuffer::ring.
+    pub fn set_ring(&mut self, regions: usize) {
+        self.set_buffer(StreamBuffer::ring(regions));
     }
 
-    /// Get primitive type
-    fn get_gl_type(prim: Primitive) -> GLenum {
-        match prim {
-            Primitive::Quads => gl::QUADS,
-            Primitive::Triangles => gl::TRIANGLES,
-            Primitive::Points => gl::POINTS,
-            Primitive::Lines => gl::LINES,
-            Primitive::TrianglesStrip => gl::TRIANGLE_STRIP,
-            Primitive::TriangleFan => gl::TRIANGLE_FAN,
+    /// Fill the new buffer and link it to the vertex array, it can be drawn before an update.
+    fn set_buffer(&mut self, mut buffer: StreamBuffer) {
+        buffer.upload(self.array.array(), 0, self.array.len());
+        self.array.active(buffer.id());
+        self.buffer = buffer;
+    }
+
+    pub fn usage(&self) -> BufferUsage {
+        self.buffer.usage()
+    }
+
+    /// Copy the vertices inside a new gl buffer and vertex array, the copy is drawn and
+    /// updated without touching this buffer. Same as clone.
+    pub fn duplicate(&self) -> VertexBuffer {
+        let mut buffer = if self.buffer.is_ring() {
+            StreamBuffer::ring(self.buffer.regions())
+        } else {
+            StreamBuffer::new(self.usage())
+        };
+        let array = self.array.duplicate();
+
+        buffer.upload(array.array(), 0, array.len());
+        array.active(buffer.id());
+        VertexBuffer {
+            buffer,
+            texture: self.texture.clone(),
+            array,
+            primitive: self.primitive,
+            layer: self.layer,
         }
     }
 
+    /// Append data to the actual VertexArray while be updated internaly.
+    pub fn append(&mut self, vertices: &[Vertex]) {
+        self.array.array_mut().append(&mut Vec::from(vertices));
+    }
+
     fn set_texture(&mut self, texture: &Resource<Texture>) {
         self.texture = Some(Resource::clone(texture));
     }
 
     pub fn get_primitive(&self) -> Primitive {
-        match self.primitive {
-            gl::QUADS => Primitive::Quads,
-            gl::TRIANGLES => Primitive::Triangles,
-            gl::TRIANGLE_STRIP => Primitive::TrianglesStrip,
-            gl::LINES => Primitive::Lines,
-            gl::TRIANGLE_FAN => Primitive::TriangleFan,
-            _ => Primitive::Points,
-        }
+        self.primitive
+    }
+
+    /// Get the vertices of the buffer
+    pub fn array(&self) -> &VertexArray {
+        &self.array
     }
 
     pub fn set_geometry(&mut self, vertice: &[Vertex]) {
         self.array = VertexArray::from(vertice);
     }
 
+    /// Set the layer used to sort the buffer inside a DrawList.
+    pub fn set_layer(&mut self, layer: f32) {
+        self.layer = layer;
+    }
+
     #[inline]
     pub fn bind(&self) {
-        unsafe {
-            gl::BindBuffer(gl::ARRAY_BUFFER, self.id);
-        }
+        device::current().bind_buffer(self.buffer.id());
     }
 
     #[inline]
     pub fn unbind(&self) {
-        unsafe {
-            gl::BindBuffer(gl::ARRAY_BUFFER, 0);
-        }
+        device::current().bind_buffer(0);
     }
 }
 
@@ -213,13 +246,14 @@ impl Drawable for VertexBuffer {
             None
         };
 
+        let shader = if texture.is_none() {
+            NO_TEXTURE_SHADER.get()
+        } else {
+            DEFAULT_SHADER.get()
+        };
         let mut context = Context::new(
             texture,
-            if texture.is_none() {
-                &*NO_TEXTURE_SHADER
-            } else {
-                &*DEFAULT_SHADER
-            },
+            &shader,
             vec![
                 ("transform".to_string(), &*IDENTITY),
                 ("projection".to_string(), target.projection()),
@@ -227,49 +261,36 @@ impl Drawable for VertexBuffer {
             BlendMode::Alpha,
         );
 
-        unsafe {
-            self.setup_draw(&mut context);
-            self.array.bind();
-            self.bind();
-            gl::DrawArrays(self.primitive, 0, self.array.len() as i32);
-            self.unbind();
-        }
+        self.draw_with_context(&mut context);
+    }
+
+    fn layer(&self) -> f32 {
+        self.layer
     }
 
     fn draw_with_context(&self, context: &mut Context) {
-        unsafe {
-            self.setup_draw(context);
-            self.array.bind();
-            self.bind();
-            gl::DrawArrays(self.primitive, 0, self.array.len() as i32);
-            self.unbind();
-        }
+        self.setup_draw(context);
+        device::current().draw_arrays(
+            self.array.id(),
+            self.primitive,
+            self.buffer.first(),
+            self.array.len(),
+        );
+        self.buffer.fence();
     }
 
     fn update(&mut self) {
-        unsafe {
-            self.array.bind();
-            self.bind();
-
-            if self.len != self.array.len() {
-                gl::BufferData(
-                    gl::ARRAY_BUFFER,
-                    (std::mem::size_of::<GLfloat>() * self.array.len() * 8) as GLsizeiptr,
-                    self.array.get_ptr(),
-                    gl::STATIC_DRAW,
-                );
-                self.len = self.array.len();
-            }
-            gl::BufferSubData(
-                gl::ARRAY_BUFFER,
-                0,
-                (std::mem::size_of::<GLfloat>() * self.array.len() * 8) as GLsizeiptr,
-                self.array.get_ptr(),
-            );
-            self.array.active();
-            self.unbind();
-            self.array.unbind();
-        }
+        let len = self.array.len();
+
+        self.buffer.upload(self.array.array(), 0, len);
+        self.array.active(self.buffer.id());
+    }
+}
+
+/// A deep copy, the clone is drawn and updated without touching the original.
+impl Clone for VertexBuffer {
+    fn clone(&self) -> VertexBuffer {
+        self.duplicate()
     }
 }
 
@@ -293,11 +314,68 @@ impl Default for VertexBuffer {
     }
 }
 
-impl Drop for VertexBuffer {
-    fn drop(&mut self) {
-        unsafe {
-            gl::DeleteBuffers(1, &[self.id] as *const _);
-        }
-        println!("Buffer {} deleted.", self.id);
+#[cfg(test)]
+mod test {
+    use super::{BufferUsage, Primitive, VertexBuffer};
+    use command::record;
+    use device::{self, NullDevice};
+    use draw::Drawer;
+    use render_texture::RenderTexture;
+    use std::rc::Rc;
+    use vertex::{Vertex, VertexArray};
+
+    #[test]
+    fn clone_owns_its_own_buffer() {
+        let null = Rc::new(NullDevice::new());
+        let _device = device::scoped(null.clone());
+        let mut target = RenderTexture::new(8, 8).unwrap();
+        let buffer = VertexBuffer::new(
+            Primitive::Triangles,
+            VertexArray::from(&[Vertex::default(); 3][..]),
+        );
+        let mut clone = buffer.clone();
+
+        assert_ne!(clone.buffer.id(), buffer.buffer.id());
+        assert_ne!(clone.array().id(), buffer.array().id());
+
+        // Updating the clone leaves the vertices drawn by the original alone
+        clone.append(&[Vertex::default(); 3]);
+        let list = record(|| {
+            target.draw_mut(&mut clone);
+            target.draw(&buffer);
+        });
+        let draws: Vec<_> = list
+            .draws()
+            .map(|draw| (draw.vertex_array, draw.count))
+            .collect();
+        assert_eq!(
+            draws,
+            vec![(clone.array().id(), 6), (buffer.array().id(), 3)]
+        );
+
+        let deleted = null.deleted();
+        clone.set_usage(BufferUsage::Stream);
+        assert_eq!(buffer.usage(), BufferUsage::Static);
+        assert_eq!(null.deleted(), deleted + 1);
+        drop(clone);
+        assert_eq!(null.deleted(), deleted + 3);
+    }
+
+    #[test]
+    fn new_buffers_are_filled_right_away() {
+        let _device = device::scoped(Rc::new(NullDevice::new()));
+        let mut buffer = VertexBuffer::new(
+            Primitive::Triangles,
+            VertexArray::from(&[Vertex::default(); 3][..]),
+        );
+
+        buffer.set_ring(3);
+        assert!(buffer.buffer.is_ring());
+        assert_eq!(buffer.buffer.len(), 3);
+        buffer.set_usage(BufferUsage::Stream);
+        assert_eq!(
+            (buffer.usage(), buffer.buffer.len()),
+            (BufferUsage::Stream, 3)
+        );
     }
 }
@@ -10,22 +10,39 @@ static DEFAULT_WIDTH: u32 = 1600;
 extern crate gl;
 extern crate glfw;
 
+use batch::Batch;
 use color::Color;
+use command;
+use command::CommandList;
+use device;
 use draw;
 use draw::{Drawable, DrawableMut, Drawer};
 use event::{EventReceiver, EventType};
+use gl_error;
+use gl_error::DebugMessage;
 use glfw::Context;
+use image;
 use nalgebra;
 use nalgebra::Matrix4;
+use post_process::{Effect, PostProcess};
 use rect::Rect;
+use render_texture::RenderTextureError;
+use std::cell::RefCell;
+use std::error::Error;
+use std::fmt;
 use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc::Receiver;
 use std::sync::Mutex;
+use texture;
 use view::View;
 use Vector;
 
 static DEFAULT_FPS: u32 = 60;
 
+/// Debug context asked for the next windows, see Window::request_debug_context.
+static DEBUG_CONTEXT: AtomicBool = AtomicBool::new(false);
+
 lazy_static! {
     static ref DEFAULT_DELTA: f64 = 1.0 / f64::from(DEFAULT_FPS);
 }
@@ -36,61 +53,152 @@ pub struct Window {
     pub height: u32,
     pub width: u32,
     event: Rc<Receiver<(f64, glfw::WindowEvent)>>,
+    batch: RefCell<Batch>,
+    batching: bool,
     pub(super) win: glfw::Window,
+    /// Id of the gl context of the window, see device::context.
+    pub(super) context: usize,
     clear_color: Color,
     already_init: bool,
     view: View,
     fps_limit: u32,
+    surface: Surface,
+    post_process: Option<PostProcess>,
+    /// Why the effects failed during the last display.
+    post_process_error: Option<RenderTextureError>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Surface on which a window is created.
+pub enum Surface {
+    /// A classic window shown on screen.
+    Windowed,
+    /// An invisible window, nothing is ever shown on screen.
+    Hidden,
+    /// An invisible window with a context created through EGL instead of the native api.
+    /// It still needs a window system, like xvfb on machines without a display.
+    Egl,
 }
 
 lazy_static! {
-    static ref GLFW_INSTANCE: Mutex<glfw::Glfw> =
-        Mutex::new(glfw::init(glfw::FAIL_ON_ERRORS).unwrap());
+    /// Glfw fails to init without a window system, the windows return the error.
+    static ref GLFW_INSTANCE: Mutex<Result<glfw::Glfw, glfw::InitError>> =
+        Mutex::new(glfw::init(keep_errors()));
+}
+
+thread_local! {
+    /// Last error reported by glfw on this thread.
+    static GLFW_ERROR: RefCell<Option<String>> = RefCell::new(None);
+}
+
+/// Callback keeping the glfw errors until a window is created, glfw calls it on the
+/// thread where it has been set.
+fn keep_errors() -> Option<glfw::ErrorCallback<()>> {
+    fn keep(_: glfw::Error, description: String, _: &()) {
+        GLFW_ERROR.with(|error| *error.borrow_mut() = Some(description));
+    }
+
+    Some(glfw::Callback {
+        f: keep as fn(glfw::Error, String, &()),
+        data: (),
+    })
 }
 
 /// Window structure implementation
 impl<'a> Window {
     /// Create a new window by default
     pub fn new(width: u32, height: u32, name: &str) -> Window {
+        Self::with_surface(width, height, name, Surface::Windowed).unwrap()
+    }
+
+    /// Create a window that is never shown on screen.
+    /// It can be drawn on like any other window, useful for tests and CI machines.
+    pub fn headless(width: u32, height: u32) -> Result<Window, WindowError> {
+        Self::with_surface(width, height, "Gust headless", Surface::Hidden)
+    }
+
+    /// Create a new window with the surface of your choice.
+    /// ```no_run
+    /// use gust::window::{Surface, Window};
+    ///
+    /// let window = Window::with_surface(800, 600, "Offscreen", Surface::Egl).unwrap();
+    /// ```
+    pub fn with_surface(
+        width: u32,
+        height: u32,
+        name: &str,
+        surface: Surface,
+    ) -> Result<Window, WindowError> {
         // Init the glfw system
+        let instance = GLFW_INSTANCE.lock().unwrap();
+        let mut glfw = (*instance).map_err(WindowError::Init)?;
 
-        let mut glfw = GLFW_INSTANCE.lock().unwrap();
-
+        glfw.set_error_callback(keep_errors());
+        GLFW_ERROR.with(|error| error.borrow_mut().take());
         glfw.window_hint(glfw::WindowHint::ContextVersion(3, 3));
         glfw.window_hint(glfw::WindowHint::OpenGlProfile(
             glfw::OpenGlProfileHint::Core,
         ));
+        // Hints are global to glfw so they have to be reset for each window
+        glfw.window_hint(glfw::WindowHint::Visible(surface == Surface::Windowed));
+        // Needed by the stencil masks of draw::Context
+        glfw.window_hint(glfw::WindowHint::StencilBits(Some(8)));
+        // Needed by the debug output on some drivers
+        glfw.window_hint(glfw::WindowHint::OpenGlDebugContext(
+            DEBUG_CONTEXT.load(Ordering::Relaxed),
+        ));
+        glfw.window_hint(glfw::WindowHint::ContextCreationApi(
+            if surface == Surface::Egl {
+                glfw::ContextCreationApi::Egl
+            } else {
+                glfw::ContextCreationApi::Native
+            },
+        ));
 
         // Create window from Glfw method create_window
         // Return the glfw::WindowEvent enum and a window
         // That we are trying to wrap in this code
-        let (mut win, evt) = glfw
-            .create_window(width, height, name, glfw::WindowMode::Windowed)
-            .unwrap();
+        let (mut win, evt) =
+            match glfw.create_window(width, height, name, glfw::WindowMode::Windowed) {
+                Some(window) => window,
+                None => {
+                    return Err(match GLFW_ERROR.with(|error| error.borrow_mut().take()) {
+                        Some(description) => WindowError::Glfw(surface, description),
+                        None => WindowError::Creation(surface),
+                    })
+                }
+            };
 
         // Load all the gl function from the user configuration
         gl::load_with(|s| win.get_proc_address(s) as *const _);
         win.set_cursor_mode(glfw::CursorMode::Normal);
+        let context = device::new_context();
+        device::set_context(context);
 
-        unsafe {
-            gl::Viewport(0, 0, width as i32, height as i32);
-            gl::Enable(gl::CULL_FACE);
-            gl::Enable(gl::BLEND);
-            gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
-        }
+        device::current().init(width, height);
 
-        glfw.set_swap_interval(glfw::SwapInterval::Sync(1));
+        glfw.set_swap_interval(if surface == Surface::Windowed {
+            glfw::SwapInterval::Sync(1)
+        } else {
+            glfw::SwapInterval::None
+        });
 
-        Window {
+        Ok(Window {
             view: View::from(Rect::new(0.0, 0.0, width as f32, height as f32)),
             height,
             width,
             win,
+            context,
             event: Rc::new(evt),
+            batch: RefCell::new(Batch::new()),
+            batching: true,
             clear_color: Color::new(1.0, 1.0, 1.0),
             already_init: true,
             fps_limit: self::DEFAULT_FPS,
-        }
+            surface,
+            post_process: None,
+            post_process_error: None,
+        })
     }
 
     pub fn set_mouse_pos<T: nalgebra::Scalar + Into<f32>>(&mut self, vec: Vector<T>) {
@@ -155,7 +263,9 @@ impl<'a> Window {
 
     /// Poll the event
     pub fn poll_events(&mut self) {
-        GLFW_INSTANCE.lock().unwrap().poll_events();
+        if let Ok(ref mut glfw) = *GLFW_INSTANCE.lock().unwrap() {
+            glfw.poll_events();
+        }
     }
 
     /// Set clear color
@@ -170,23 +280,46 @@ impl<'a> Window {
 
     /// Clear screen
     pub fn clear(&self) {
-        unsafe {
-            gl::ClearColor(
-                self.clear_color.0,
-                self.clear_color.1,
-                self.clear_color.2,
-                self.clear_color.3,
-            );
-            gl::Clear(gl::COLOR_BUFFER_BIT);
+        self.batch.borrow_mut().flush();
+        if let Some(ref post_process) = self.post_process {
+            post_process.bind_scene();
         }
+        device::current().clear(self.clear_color);
+    }
+
+    /// Ask for a debug context for the windows created after this call, some drivers only
+    /// send their debug output to them. It's off by default since it can slow down the driver.
+    pub fn request_debug_context(enabled: bool) {
+        DEBUG_CONTEXT.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Send the debug messages of the driver to the callback.
+    /// Return false if the driver doesn't support the debug output.
+    /// Request a debug context before creating the window to get every message.
+    pub fn enable_debug<F: Fn(&DebugMessage) + 'static>(&mut self, callback: F) -> bool {
+        self.active();
+        gl_error::enable_debug(callback)
+    }
+
+    pub fn disable_debug(&mut self) {
+        self.active();
+        gl_error::disable_debug();
     }
 
     /// Activate window on OpenGl context
     pub fn active(&mut self) -> bool {
-        GLFW_INSTANCE
-            .lock()
-            .unwrap()
-            .make_context_current(Some(&self.win));
+        if !self.win.is_current() {
+            if let Ok(ref mut glfw) = *GLFW_INSTANCE.lock().unwrap() {
+                glfw.make_context_current(Some(&self.win));
+            }
+            device::set_context(self.context);
+            // The states known by the device belong to the previous context
+            device::current().reset_state();
+        }
+        // With effects everything is drawn on the scene of the chain
+        if let Some(ref post_process) = self.post_process {
+            post_process.bind_scene();
+        }
         true
     }
 
@@ -194,9 +327,122 @@ impl<'a> Window {
         self.view = view;
     }
 
-    /// Display the screen
+    /// Display the screen, the effects are run before.
+    /// If they fail the error is kept until the next display, see post_process_error.
     pub fn display(&mut self) {
+        self.active();
+        self.flush();
+        self.post_process_error = None;
+        self.render_effects();
         self.win.swap_buffers();
+        // The framebuffer may have been resized since the chain creation
+        let (width, height) = self.win.get_framebuffer_size();
+        let resized = match self.post_process {
+            Some(ref mut post_process) => post_process.resize(width as u32, height as u32),
+            None => Ok(()),
+        };
+        if let Err(error) = resized {
+            // The chain can't follow the window anymore
+            self.post_process = None;
+            self.post_process_error = Some(error);
+        }
+    }
+
+    /// Draw the scene through the effects on the back buffer.
+    fn render_effects(&mut self) {
+        let (width, height) = self.win.get_framebuffer_size();
+
+        if let Some(ref mut post_process) = self.post_process {
+            if let Err(error) = post_process.render(0, [0, 0, width, height]) {
+                self.post_process_error = Some(error);
+            }
+        }
+    }
+
+    /// Add a fullscreen effect run at display, after the ones already added.
+    /// ```no_run
+    /// use gust::post_process::Effect;
+    /// use gust::prelude::*;
+    ///
+    /// let mut window = Window::new(800, 600, "Blur");
+    /// window.add_effect(Effect::blur(2.0)).unwrap();
+    /// ```
+    pub fn add_effect(&mut self, effect: Effect) -> Result<(), RenderTextureError> {
+        if self.post_process.is_none() {
+            let (width, height) = self.win.get_framebuffer_size();
+
+            self.flush();
+            self.post_process = Some(PostProcess::new(width as u32, height as u32)?);
+        }
+        if let Some(ref mut post_process) = self.post_process {
+            post_process.push(effect);
+        }
+        self.active();
+        Ok(())
+    }
+
+    /// Chain of effects, None if no effect was added.
+    pub fn post_process(&self) -> Option<&PostProcess> {
+        self.post_process.as_ref()
+    }
+
+    pub fn post_process_mut(&mut self) -> Option<&mut PostProcess> {
+        self.post_process.as_mut()
+    }
+
+    /// Error of the effects during the last display, None if they were drawn.
+    /// A chain that couldn't be resized with the window is removed.
+    pub fn post_process_error(&self) -> Option<&RenderTextureError> {
+        self.post_process_error.as_ref()
+    }
+
+    /// Remove every effect, the draws go back to the window.
+    pub fn clear_effects(&mut self) {
+        self.active();
+        self.flush();
+        self.post_process = None;
+        device::current().bind_framebuffer(0);
+    }
+
+    /// Draw the sprites waiting inside the batch.
+    pub fn flush(&mut self) {
+        self.batch.get_mut().flush();
+    }
+
+    /// Merge the draws of sprites sharing a texture, enabled by default.
+    pub fn set_batching(&mut self, batching: bool) {
+        if !batching {
+            self.flush();
+        }
+        self.batching = batching;
+    }
+
+    pub fn is_batching(&self) -> bool {
+        self.batching
+    }
+
+    /// Number of draw calls made by the batch since the creation of the window.
+    pub fn batch_draw_calls(&self) -> usize {
+        self.batch.borrow().draw_calls()
+    }
+
+    /// Read the back buffer (what has been drawn since the last clear) into an image.
+    /// Should be called before display.
+    /// ```no_run
+    /// use gust::window::Window;
+    ///
+    /// let mut window = Window::new(800, 600, "Screenshot");
+    /// window.clear();
+    /// window.capture().save("screenshot.png").unwrap();
+    /// ```
+    pub fn capture(&mut self) -> image::RgbaImage {
+        let (width, height) = self.win.get_framebuffer_size();
+
+        self.active();
+        self.flush();
+        self.render_effects();
+        device::current().bind_framebuffer(0);
+        texture::read_framebuffer(width as u32, height as u32)
     }
 
     /// Init basic gl modules
@@ -240,6 +486,16 @@ impl<'a> Window {
         self.fps_limit
     }
 
+    /// Return the surface the window was created on.
+    pub fn surface(&self) -> Surface {
+        self.surface
+    }
+
+    /// Check if the window is hidden from the screen.
+    pub fn is_headless(&self) -> bool {
+        self.surface != Surface::Windowed
+    }
+
     pub fn event(&self) -> &EventReceiver {
         &self.event
     }
@@ -252,18 +508,38 @@ impl<'a> Window {
 impl Drawer for Window {
     fn draw<T: Drawable>(&mut self, drawable: &T) {
         self.active();
+        if self.batching
+            && !draw::drawing_mask()
+            && drawable.batch(self.batch.get_mut(), self.view.projection())
+        {
+            return;
+        }
+        self.flush();
         drawable.draw(self);
     }
 
     #[inline]
     fn draw_mut<T: DrawableMut>(&mut self, drawable: &mut T) {
         self.active();
-        drawable.draw_mut(self);
+        if !self.batching || draw::drawing_mask() {
+            self.flush();
+            drawable.draw_mut(self);
+            return;
+        }
+        drawable.update();
+        if drawable.batch(self.batch.get_mut(), self.view.projection()) {
+            return;
+        }
+        // Already updated, draw_mut would update it again
+        self.flush();
+        drawable.draw(self);
     }
 
     #[inline]
     fn draw_with_context<T: Drawable>(&mut self, drawable: &mut T, context: &mut draw::Context) {
         self.active();
+        self.flush();
+        context.set_projection(self.view.projection());
         drawable.draw_with_context(context);
     }
 
@@ -274,6 +550,8 @@ impl Drawer for Window {
         context: &mut draw::Context,
     ) {
         self.active();
+        self.flush();
+        context.set_projection(self.view.projection());
         drawable.draw_with_context(context);
     }
 
@@ -297,42 +575,68 @@ impl Drawer for Window {
     fn projection(&self) -> &Matrix4<f32> {
         self.view.projection()
     }
+
+    fn flush(&mut self) {
+        Window::flush(self);
+    }
+
+    /// The batch is flushed before the end of the record.
+    fn record<F: FnOnce(&mut Self)>(&mut self, draw: F) -> CommandList {
+        command::record(|| {
+            draw(self);
+            self.flush();
+        })
+    }
+}
+
+impl Drop for Window {
+    /// The objects made by gust inside the context are deleted with it.
+    fn drop(&mut self) {
+        self.active();
+        device::drop_context(self.context);
+    }
 }
 
 /// Default trait implementation for window
 impl Default for Window {
     fn default() -> Window {
-        let (mut win, evt) = GLFW_INSTANCE
-            .lock()
-            .unwrap()
-            .create_window(
-                DEFAULT_HEIGHT as u32,
-                DEFAULT_WIDTH as u32,
-                "Gust",
-                glfw::WindowMode::Windowed,
-            )
-            .unwrap();
+        Window::with_surface(DEFAULT_WIDTH, DEFAULT_HEIGHT, "Gust", Surface::Windowed).unwrap()
+    }
+}
 
-        gl::load_with(|s| win.get_proc_address(s) as *const _);
+#[derive(Debug)]
+/// All error trigerable while creating a window
+pub enum WindowError {
+    /// Glfw couldn't be initialised, there is no window system most of the time.
+    Init(glfw::InitError),
+    /// Glfw didn't give the reason.
+    Creation(Surface),
+    /// The window couldn't be created for the reason given by glfw.
+    Glfw(Surface, String),
+}
 
-        Window {
-            view: View::from(Rect::new(
-                0.0,
-                0.0,
-                DEFAULT_WIDTH as f32,
-                DEFAULT_HEIGHT as f32,
-            )),
-            height: DEFAULT_HEIGHT,
-            width: DEFAULT_WIDTH,
-            win,
-            event: Rc::new(evt),
-            clear_color: Color::new(1.0, 1.0, 1.0),
-            already_init: true,
-            fps_limit: self::DEFAULT_FPS,
+impl fmt::Display for WindowError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WindowError::Init(error) => write!(f, "Glfw could not be initialised: {}.", error),
+            WindowError::Creation(surface) => {
+                write!(f, "Glfw could not create a {:?} window.", surface)
+            }
+            WindowError::Glfw(surface, description) => write!(
+                f,
+                "Glfw could not create a {:?} window: {}",
+                surface, description
+            ),
         }
     }
 }
 
+impl Error for WindowError {
+    fn cause(&self) -> Option<&Error> {
+        None
+    }
+}
+
 pub enum InputMode {
     CursorMode(InputState),
     StickMouseButtons,